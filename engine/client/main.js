@@ -1,32 +1,64 @@
 import { decompress } from 'https://unpkg.com/fzstd@0.1.1/esm/index.mjs';
 
-// Protocol Constants
-const OP_CLEAR = 0x01;
-const OP_SET_COLOR = 0x02;
-const OP_FILL_RECT = 0x03;
-const OP_DRAW_LINE = 0x04;
-const OP_DRAW_TEXT = 0x05;
-const OP_LOAD_SOUND = 0x06;
-const OP_PLAY_SOUND = 0x07;
-const OP_STOP_SOUND = 0x08;
-const OP_SET_VOLUME = 0x09;
-const OP_LOAD_IMAGE = 0x0A;
-const OP_DRAW_IMAGE = 0x0B;
+// Protocol constants, generated by the server from engine/crates/engine/src/protocol.rs
+import {
+    OP_CLEAR, OP_SET_COLOR, OP_FILL_RECT, OP_DRAW_LINE, OP_DRAW_TEXT, OP_LOAD_SOUND, OP_PLAY_SOUND,
+    OP_STOP_SOUND, OP_SET_VOLUME, OP_LOAD_IMAGE, OP_DRAW_IMAGE, OP_FILL_POLY, OP_PUSH, OP_POP,
+    OP_TRANSLATE, OP_ROTATE, OP_SCALE, OP_FILL_CIRCLE, OP_STROKE_CIRCLE, OP_STROKE_RECT, OP_ARC,
+    OP_ELLIPSE, OP_STROKE_POLY, LINE_JOINS, LINE_CAPS, OP_LOAD_FONT, OP_SET_FONT, OP_SET_TEXT_ALIGN,
+    TEXT_ALIGNS, TEXT_BASELINES, DEFAULT_FONT, DEFAULT_FONT_SIZE, FRAME_FULL, FRAME_DELTA,
+    FRAME_SAME, DELTA_COPY, DELTA_INSERT, ACK_TAG, MAX_BASE_AGE, OP_DEFINE_LIST, OP_CALL_LIST,
+    MAX_LIST_DEPTH, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, CAP_DELTA_FRAMES, CAP_DISPLAY_LISTS,
+    OP_SET_RESOLUTION, SCALE_MODES, OP_COMPACT, COORD_SCALE, IMAGE_SIZE, IMAGE_SOURCE,
+    IMAGE_ROTATION, IMAGE_ORIGIN, CAP_COMPACT_COORDS, POINTER_TAG, POINTER_LEN, POINTER_MOVE,
+    POINTER_DOWN, POINTER_UP, POINTER_WHEEL, GAMEPAD_TAG, GAMEPAD_LEN, GAMEPAD_BUTTON,
+    CAP_NAMED_KEYS, KEY_TAG, MAX_KEY_NAME, KEY_DOWN, KEY_SHIFT, KEY_CTRL, KEY_ALT, KEY_META,
+    MAX_MESSAGE_LEN, CAP_INPUT_SEQ, FRAME_INPUT_ACK, STAMP_TAG, STAMP_LEN, NO_FRAME,
+} from './protocol.js';
+
+// Capabilities this client implements
+const CAPABILITIES = [
+    CAP_DELTA_FRAMES, CAP_DISPLAY_LISTS, CAP_COMPACT_COORDS, CAP_NAMED_KEYS, CAP_INPUT_SEQ,
+];
+
+const WHEEL_NOTCH = 100; // Pixels of wheel deltaY per notch, as most browsers report a mouse wheel
+const GAMEPAD_DEADZONE = 0.15; // Sticks rest a little off centre
+const GAMEPAD_STEPS = 128; // Values are rounded to this many steps per unit
 
 // Global State
 let ctx = null;
+let canvas = null;
+let resolution = { width: 800, height: 600, mode: 'letterbox' }; // From OP_SET_RESOLUTION
+let layout = { width: 800, height: 600, sx: 1, sy: 1 }; // Visible virtual area and device pixels per unit
 let ws = null;
 let pc = null;
 let dc = null;
 let audioCtx = null;
 const sounds = {};
 const images = {};
+const fonts = {};
+const displayLists = {}; // name -> DataView, kept until redefined
+let recentFrames = []; // [{ id, bytes }] kept as delta bases
+let deltaFrames = true; // Whether the server wraps frames in the delta envelope
+let namedKeys = false; // Whether the server takes key names; until it says so keys go as legacy codes
+let inputSeq = false; // Whether inputs go stamped with a sequence number and the frame shown
+let sentSeq = 0; // Sequence number of the last stamped input
+let ackedSeq = 0; // Highest sequence number the server has acknowledged
+let shownFrame = NO_FRAME; // Id of the last frame rendered
+let rejected = false; // Set when the server refused this client; stops reconnecting
 const activeSources = {};
 let sessionId = null;
 let gameStarted = false;
 let reconnectAttempts = 0;
 let reconnectTimer = null;
 let initialServerInstanceId = null;
+let pendingMove = null; // Latest pointermove not sent yet; moves go out once per animation frame
+let wheelDelta = 0; // Scrolled pixels not yet sent as whole notches
+// Frames per second to ask the server for, from the page's URL (cleaned up once connected)
+const urlFrameRate = new URLSearchParams(window.location.search).get('fps');
+// Room to join, e.g. from a link a friend shared; reconnects go back to it
+const urlRoom = new URLSearchParams(window.location.search).get('room');
+let gamepadSent = new Map(); // "pad:control" -> last value sent, left out when 0
 
 // Stats
 let frameCount = 0;
@@ -55,9 +87,11 @@ function hideLoading() {
 
 function setupTouchListeners(container) {
     container.querySelectorAll('.touch-btn').forEach(btn => {
-        const key = parseInt(btn.dataset.key);
-        const handleStart = (e) => { e.preventDefault(); sendInput(key, true); };
-        const handleEnd = (e) => { e.preventDefault(); sendInput(key, false); };
+        // keys.json may give a legacy code (38) or a key name ("ArrowUp")
+        const key = /^\d+$/.test(btn.dataset.key) ? parseInt(btn.dataset.key) : btn.dataset.key;
+        const send = (isDown) => typeof key === 'number' ? sendInput(key, isDown) : sendNamedKey(key, isDown, 0);
+        const handleStart = (e) => { e.preventDefault(); send(true); };
+        const handleEnd = (e) => { e.preventDefault(); send(false); };
         btn.addEventListener('touchstart', handleStart, {passive: false});
         btn.addEventListener('touchend', handleEnd, {passive: false});
         btn.addEventListener('mousedown', handleStart);
@@ -132,12 +166,10 @@ function init() {
         .catch(e => console.error("Failed to load metadata.json:", e));
     
     // Setup Canvas
-    const canvas = document.getElementById('gameCanvas');
-    const dpr = window.devicePixelRatio || 1;
-    canvas.width = 800 * dpr;
-    canvas.height = 600 * dpr;
+    canvas = document.getElementById('gameCanvas');
     ctx = canvas.getContext('2d');
-    ctx.scale(dpr, dpr);
+    applyLayout();
+    window.addEventListener('resize', () => { applyLayout(); sendViewport(); });
     
     // Setup Audio
     try {
@@ -151,8 +183,10 @@ function init() {
     window.addEventListener('keydown', resumeAudio);
 
     // Input Handling setup
-    window.addEventListener('keydown', (e) => { if(!e.repeat) sendInput(e.keyCode, true); });
-    window.addEventListener('keyup', (e) => { sendInput(e.keyCode, false); });
+    window.addEventListener('keydown', (e) => { if(!e.repeat && !isTyping(e)) sendKey(e, true); });
+    window.addEventListener('keyup', (e) => { if(!isTyping(e)) sendKey(e, false); });
+    setupPointerListeners();
+    requestAnimationFrame(pollGamepads);
 
     // Connect
     connect();
@@ -174,11 +208,14 @@ async function connect() {
     const urlParams = new URLSearchParams(window.location.search);
     const urlSessionId = urlParams.get('session');
     const protocol = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
-    let wsUrl = protocol + window.location.host + getBasePath() + "/ws";
-    if (urlSessionId) { wsUrl += "?session=" + urlSessionId; sessionId = urlSessionId; }
-    if (sessionId && reconnectAttempts > 0 && !wsUrl.includes("session=")) {
-         wsUrl += (wsUrl.includes("?") ? "&" : "?") + "session=" + sessionId;
-    }
+    const params = new URLSearchParams({ protocol: PROTOCOL_VERSION, caps: CAPABILITIES.join(',') });
+    if (urlSessionId) { params.set('session', urlSessionId); sessionId = urlSessionId; }
+    else if (sessionId && reconnectAttempts > 0) { params.set('session', sessionId); }
+    // The page can also pick a rate before connecting, e.g. a lower one on phones
+    const frameRate = Math.round(Number(urlFrameRate || window.cleoselene.frameRate));
+    if (frameRate >= 1) params.set('fps', frameRate);
+    if (urlRoom) params.set('room', urlRoom);
+    const wsUrl = protocol + window.location.host + getBasePath() + "/ws?" + params;
 
     ws = new WebSocket(wsUrl);
     ws.binaryType = 'arraybuffer';
@@ -195,7 +232,7 @@ async function connect() {
         if (pc) pc.close();
         ws = null;
         gameStarted = false; // Reset to allow hiding on next first frame
-        scheduleReconnect();
+        if (!rejected) scheduleReconnect();
     };
 
     ws.onmessage = async (event) => {
@@ -203,6 +240,12 @@ async function connect() {
         if (typeof data === 'string') {
             const msg = JSON.parse(data);
             if (msg.type === 'WELCOME') {
+                if (!(msg.protocol_version >= MIN_PROTOCOL_VERSION)) {
+                    rejected = true;
+                    showLoading(`SERVER PROTOCOL ${msg.protocol_version || 1} NOT SUPPORTED`);
+                    ws.close();
+                    return;
+                }
                 console.log("Session Joined:", msg.session_id);
                 if (msg.server_instance_id) {
                     if (initialServerInstanceId === null) {
@@ -216,8 +259,19 @@ async function connect() {
                 }
                 updateLoadingStatus("ENTERING GAME...");
                 sessionId = msg.session_id;
+                recentFrames = []; // Frame ids restart with every server session
+                deltaFrames = msg.capabilities.includes('delta_frames');
+                namedKeys = msg.capabilities.includes('named_keys');
+                inputSeq = msg.capabilities.includes('input_seq');
+                sentSeq = 0; ackedSeq = 0; shownFrame = NO_FRAME; // Counted per session
+                gamepadSent = new Map(); // A new session has no gamepad state, so send held controls again
+                sendViewport();
                 const cleanUrl = window.location.protocol + "//" + window.location.host + window.location.pathname;
                 window.history.replaceState({path: cleanUrl}, '', cleanUrl);
+            } else if (msg.type === 'REJECT') {
+                console.error("Rejected by server:", msg.reason);
+                rejected = true;
+                showLoading(msg.reason.toUpperCase());
             } else if (msg.type === 'ANSWER') {
                 await pc.setRemoteDescription(new RTCSessionDescription({ type: 'answer', sdp: msg.sdp }));
             } else if (msg.type === 'CANDIDATE') {
@@ -226,6 +280,8 @@ async function connect() {
                         candidate: msg.candidate, sdpMid: msg.sdp_mid, sdpMLineIndex: msg.sdp_mline_index
                     }));
                 } catch (e) { console.error("Error adding candidate:", e); }
+            } else if (msg.type === 'MESSAGE') {
+                receiveMessage(msg.payload);
             }
         } else {
             processCompressedFrame(data);
@@ -269,36 +325,342 @@ async function processCompressedFrame(data) {
         
         // Zstd Decompression (Standard)
         const decompressed = decompress(new Uint8Array(streamData));
-        renderFrame(new DataView(decompressed.buffer));
+        if (!deltaFrames) {
+            renderFrame(new DataView(decompressed.buffer, decompressed.byteOffset, decompressed.byteLength));
+            return;
+        }
+        const frame = decodeFrame(decompressed);
+        if (!frame) return; // Not acknowledged, so the server falls back to a full frame soon
+        renderFrame(new DataView(frame.bytes.buffer, frame.bytes.byteOffset, frame.bytes.byteLength));
+        shownFrame = frame.id;
+        sendAck(frame.id);
     } catch (e) {
         console.error("Frame Error:", e);
     }
 }
 
+// Rebuilds the full command stream from a FULL, DELTA or SAME envelope
+function decodeFrame(data) {
+    const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+    let kind = view.getUint8(0);
+    const id = view.getUint32(1, true);
+    let start = 5; // End of the header before the base id
+    if (kind & FRAME_INPUT_ACK) {
+        ackedSeq = Math.max(ackedSeq, view.getUint32(5, true));
+        kind &= ~FRAME_INPUT_ACK;
+        start = 9;
+    }
+    let bytes;
+    if (kind === FRAME_FULL) {
+        bytes = data.slice(start);
+    } else {
+        const baseId = view.getUint32(start, true);
+        const base = recentFrames.find(f => f.id === baseId);
+        if (!base) { console.warn("Missing base frame", baseId); return null; }
+        if (kind === FRAME_SAME) {
+            bytes = base.bytes;
+        } else if (kind === FRAME_DELTA) {
+            const parts = [];
+            let total = 0;
+            let offset = start + 4;
+            while (offset < data.byteLength) {
+                const op = view.getUint8(offset); offset += 1;
+                if (op === DELTA_COPY) {
+                    const start = view.getUint32(offset, true); offset += 4;
+                    const len = view.getUint32(offset, true); offset += 4;
+                    if (start + len > base.bytes.byteLength) { console.warn("Bad delta copy"); return null; }
+                    parts.push(base.bytes.subarray(start, start + len)); total += len;
+                } else if (op === DELTA_INSERT) {
+                    const len = view.getUint32(offset, true); offset += 4;
+                    if (offset + len > data.byteLength) { console.warn("Truncated delta"); return null; }
+                    parts.push(data.subarray(offset, offset + len)); total += len;
+                    offset += len;
+                } else { console.warn("Unknown delta op", op); return null; }
+            }
+            bytes = new Uint8Array(total);
+            let pos = 0;
+            for (const p of parts) { bytes.set(p, pos); pos += p.byteLength; }
+        } else { console.warn("Unknown frame kind", kind); return null; }
+    }
+    // Frames can arrive out of order; keep everything the server may still use
+    const newest = recentFrames.reduce((n, f) => Math.max(n, f.id), id);
+    recentFrames = recentFrames.filter(f => newest - f.id <= MAX_BASE_AGE && f.id !== id);
+    recentFrames.push({ id, bytes });
+    return { id, bytes };
+}
+
+function sendAck(id) {
+    const buf = new Uint8Array(5);
+    buf[0] = ACK_TAG;
+    new DataView(buf.buffer).setUint32(1, id, true);
+    sendBinary(buf);
+}
+
+// Room the canvas has, in CSS pixels
+function viewportSize() {
+    const parent = canvas.parentElement;
+    if (parent && parent.clientWidth > 0 && parent.clientHeight > 0) {
+        return { width: parent.clientWidth, height: parent.clientHeight };
+    }
+    return { width: window.innerWidth, height: window.innerHeight };
+}
+
+// Sizes the canvas for the game's resolution and scaling mode. Mirrors ScaleMode in
+// protocol.rs, which the server uses for api.screen_size.
+function applyLayout() {
+    if (!canvas) return;
+    const dpr = window.devicePixelRatio || 1;
+    const vp = viewportSize();
+    const { width: rw, height: rh, mode } = resolution;
+    const fit = Math.min(vp.width / rw, vp.height / rh);
+
+    let cssW = rw * fit, cssH = rh * fit, sx = fit, sy = fit;
+    let visW = rw, visH = rh;
+    if (mode === 'stretch') {
+        cssW = vp.width; cssH = vp.height; sx = vp.width / rw; sy = vp.height / rh;
+    } else if (mode === 'integer') {
+        const s = Math.max(1, Math.floor(fit * dpr)) / dpr;
+        cssW = rw * s; cssH = rh * s; sx = s; sy = s;
+    } else if (mode === 'expand' && fit > 0) {
+        cssW = vp.width; cssH = vp.height;
+        visW = Math.max(rw, vp.width / fit); visH = Math.max(rh, vp.height / fit);
+    }
+
+    canvas.width = Math.round(cssW * dpr);
+    canvas.height = Math.round(cssH * dpr);
+    canvas.style.width = cssW + 'px';
+    canvas.style.height = cssH + 'px';
+    // Resizing resets the context, smoothing included
+    ctx.imageSmoothingEnabled = mode !== 'integer';
+    layout = { width: visW, height: visH, sx: sx * dpr, sy: sy * dpr };
+    ctx.setTransform(layout.sx, 0, 0, layout.sy, 0, 0);
+}
+
+// Tells the server how much room the canvas has, for api.screen_size
+function sendViewport() {
+    if (!canvas || !ws || ws.readyState !== WebSocket.OPEN) return;
+    const vp = viewportSize();
+    ws.send(JSON.stringify({ type: 'VIEWPORT', width: vp.width, height: vp.height }));
+}
+
+// Sends any JSON value to the game's on_message, for name entry, chat and the like.
+// Goes over the WebSocket, which unlike the data channel doesn't drop messages.
+// Returns whether the message was sent; the server also drops messages beyond its rate limit.
+function sendMessage(payload) {
+    if (!ws || ws.readyState !== WebSocket.OPEN) return false;
+    const text = JSON.stringify({ type: 'MESSAGE', payload: payload === undefined ? null : payload });
+    if (new TextEncoder().encode(text).length > MAX_MESSAGE_LEN) {
+        console.warn("Message too long, not sent");
+        return false;
+    }
+    ws.send(text);
+    return true;
+}
+
+// Passes a message from the game's api.send to the page's onMessage hook. Messages
+// that arrive before the page sets one are dropped.
+function receiveMessage(payload) {
+    const onMessage = window.cleoselene.onMessage;
+    if (typeof onMessage !== 'function') return;
+    try { onMessage(payload); } catch (e) { console.error("onMessage Error:", e); }
+}
+
+// Hooks for the page hosting the game, keeping any it set up before this script ran.
+// inputSeq tells how many inputs the server has yet to acknowledge, e.g. for showing
+// input lag; the page sets onMessage(payload) to receive api.send.
+window.cleoselene = Object.assign(window.cleoselene || {}, {
+    sendMessage,
+    inputSeq: () => ({ sent: sentSeq, acked: ackedSeq }),
+});
+
 function sendInput(code, isDown) {
     const buf = new Uint8Array(2);
     buf[0] = code; buf[1] = isDown ? 1 : 0;
-    if (dc && dc.readyState === 'open') { dc.send(buf); } 
+    sendStamped(buf);
+}
+
+// Keys typed into the page's own text fields, e.g. for sendMessage, aren't game input
+function isTyping(e) {
+    const t = e.target;
+    return t instanceof HTMLElement && (t.isContentEditable || ['INPUT', 'TEXTAREA', 'SELECT'].includes(t.tagName));
+}
+
+// Sends the physical key's name with the modifiers held, or its legacy code to servers without named keys
+function sendKey(e, isDown) {
+    if (!namedKeys || !e.code) { sendInput(e.keyCode, isDown); return; }
+    const mods = (e.shiftKey ? KEY_SHIFT : 0) | (e.ctrlKey ? KEY_CTRL : 0) | (e.altKey ? KEY_ALT : 0) | (e.metaKey ? KEY_META : 0);
+    sendNamedKey(e.code, isDown, mods);
+}
+
+function sendNamedKey(name, isDown, mods) {
+    if (!namedKeys || !/^[A-Za-z0-9]+$/.test(name) || name.length > MAX_KEY_NAME) return;
+    const buf = new Uint8Array(2 + name.length);
+    buf[0] = KEY_TAG;
+    buf[1] = (isDown ? KEY_DOWN : 0) | mods;
+    for (let i = 0; i < name.length; i++) buf[2 + i] = name.charCodeAt(i);
+    sendStamped(buf);
+}
+
+// Sends an input packet, stamped with the next sequence number and the frame on screen
+// if the server takes stamps
+function sendStamped(packet) {
+    if (!inputSeq) { sendBinary(packet); return; }
+    sentSeq = (sentSeq + 1) >>> 0;
+    const buf = new Uint8Array(STAMP_LEN + packet.length);
+    const view = new DataView(buf.buffer);
+    buf[0] = STAMP_TAG;
+    view.setUint32(1, sentSeq, true);
+    view.setUint32(5, shownFrame, true);
+    buf.set(packet, STAMP_LEN);
+    sendBinary(buf);
+}
+
+// Prefers the data channel, falling back to the WebSocket
+function sendBinary(buf) {
+    if (dc && dc.readyState === 'open') { dc.send(buf); }
     else if (ws && ws.readyState === WebSocket.OPEN) { ws.send(buf); }
 }
 
+function setupPointerListeners() {
+    canvas.style.touchAction = 'none'; // Touches are pointer events, not scrolling
+    canvas.addEventListener('pointerdown', (e) => {
+        canvas.setPointerCapture(e.pointerId); // So the up arrives even off the canvas
+        flushPointerMove();
+        sendPointer(POINTER_DOWN, e.button + 1, e);
+    });
+    canvas.addEventListener('pointerup', (e) => {
+        flushPointerMove();
+        sendPointer(POINTER_UP, e.button + 1, e);
+    });
+    canvas.addEventListener('pointermove', (e) => {
+        if (!pendingMove) requestAnimationFrame(flushPointerMove);
+        pendingMove = e;
+    });
+    canvas.addEventListener('wheel', (e) => {
+        e.preventDefault();
+        const unit = e.deltaMode === 1 ? WHEEL_NOTCH / 3 : e.deltaMode === 2 ? WHEEL_NOTCH : 1;
+        wheelDelta += e.deltaY * unit;
+        const notches = Math.max(-127, Math.min(127, Math.trunc(wheelDelta / WHEEL_NOTCH)));
+        if (notches === 0) return;
+        wheelDelta -= notches * WHEEL_NOTCH;
+        flushPointerMove();
+        sendPointer(POINTER_WHEEL, notches, e);
+    }, { passive: false });
+    canvas.addEventListener('contextmenu', (e) => e.preventDefault());
+}
+
+// Sends gamepad changes every animation frame; the Gamepad API has no change events
+function pollGamepads() {
+    requestAnimationFrame(pollGamepads);
+    const current = new Map();
+    const record = (pad, control, value) => {
+        value = Math.round(Math.max(-1, Math.min(1, value)) * GAMEPAD_STEPS) / GAMEPAD_STEPS;
+        if (value !== 0) current.set(pad + ':' + control, value);
+    };
+    for (const pad of (navigator.getGamepads ? navigator.getGamepads() : [])) {
+        if (!pad || !pad.connected) continue;
+        const number = Math.min(pad.index + 1, 255);
+        pad.axes.slice(0, GAMEPAD_BUTTON).forEach((v, i) => record(number, i, Math.abs(v) < GAMEPAD_DEADZONE ? 0 : v));
+        // Some digital buttons report being pressed with a value of 0
+        pad.buttons.slice(0, 255 - GAMEPAD_BUTTON).forEach((b, i) => record(number, GAMEPAD_BUTTON + i, b.pressed && !b.value ? 1 : b.value));
+    }
+    for (const [key, value] of current) {
+        if (gamepadSent.get(key) !== value) sendGamepad(key, value);
+    }
+    for (const key of gamepadSent.keys()) {
+        if (!current.has(key)) sendGamepad(key, 0); // Released, or the pad went away
+    }
+    gamepadSent = current;
+}
+
+function sendGamepad(key, value) {
+    const [pad, control] = key.split(':').map(Number);
+    const buf = new Uint8Array(GAMEPAD_LEN);
+    buf[0] = GAMEPAD_TAG; buf[1] = pad; buf[2] = control;
+    new DataView(buf.buffer).setFloat32(3, value, true);
+    sendStamped(buf);
+}
+
+function flushPointerMove() {
+    if (!pendingMove) return;
+    sendPointer(POINTER_MOVE, 0, pendingMove);
+    pendingMove = null;
+}
+
+// Sends a pointer event at the game's virtual coordinates. The canvas starts at the
+// virtual origin, and layout has device pixels per virtual pixel.
+function sendPointer(kind, button, e) {
+    const rect = canvas.getBoundingClientRect();
+    const dpr = window.devicePixelRatio || 1;
+    const buf = new Uint8Array(POINTER_LEN);
+    const view = new DataView(buf.buffer);
+    buf[0] = POINTER_TAG; buf[1] = kind;
+    view.setInt8(2, button);
+    view.setFloat32(3, (e.clientX - rect.left) * dpr / layout.sx, true);
+    view.setFloat32(7, (e.clientY - rect.top) * dpr / layout.sy, true);
+    sendStamped(buf);
+}
+
 function renderFrame(view) {
     if (!gameStarted) {
         console.log("First Frame Received! Hiding Overlay.");
         gameStarted = true;
         hideLoading();
     }
+    if (!ctx) return;
+    ctx.setTransform(layout.sx, 0, 0, layout.sy, 0, 0);
+    // Text style is per frame, like the transform stack
+    ctx.font = `${DEFAULT_FONT_SIZE}px ${DEFAULT_FONT}`; ctx.textAlign = 'left'; ctx.textBaseline = 'middle';
+    renderCommands(view, 0);
+}
+
+// Plays a command stream; `level` is the number of display lists it is nested in.
+function renderCommands(view, level) {
     let offset = 0;
     const len = view.byteLength;
-    if (!ctx) return;
+    let depth = 0; // Unmatched OP_PUSH count, unwound at the end of the stream
+    let compact = false; // Current command has OP_COMPACT set
+    // Unsigned LEB128
+    const varint = () => {
+        let v = 0;
+        for (let shift = 0; ; shift += 7) {
+            const b = view.getUint8(offset); offset += 1;
+            v += (b & 0x7F) * Math.pow(2, shift);
+            if (!(b & 0x80) || shift >= 28) return v;
+        }
+    };
+    const zigzag = () => { const v = varint(); return v % 2 ? -(v + 1) / 2 : v / 2; };
+    // Position, size, radius or line width
+    const coord = () => {
+        if (compact) return zigzag() / COORD_SCALE;
+        const v = view.getFloat32(offset, true); offset += 4;
+        return v;
+    };
+    // Point list; compact points are relative to the previous one
+    const points = () => {
+        const count = compact ? varint() : view.getUint16(offset, true);
+        if (!compact) offset += 2;
+        const pts = [];
+        let x = 0, y = 0;
+        for (let i = 0; i < count; i++) {
+            if (compact) { x += zigzag(); y += zigzag(); pts.push([x / COORD_SCALE, y / COORD_SCALE]); }
+            else { pts.push([view.getFloat32(offset, true), view.getFloat32(offset + 4, true)]); offset += 8; }
+        }
+        return pts;
+    };
     while (offset < len) {
-        const opcode = view.getUint8(offset);
+        const opcode = view.getUint8(offset) & ~OP_COMPACT;
+        compact = (view.getUint8(offset) & OP_COMPACT) !== 0;
         offset += 1;
+        // Payload length, so unknown opcodes and extra fields can be skipped
+        const payloadLen = varint();
+        const end = offset + payloadLen;
+        if (end > len) break;
         if (opcode === OP_CLEAR) {
             const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2);
             offset += 3;
             ctx.fillStyle = `rgb(${r},${g},${b})`;
-            ctx.fillRect(0, 0, 800, 600);
+            ctx.fillRect(0, 0, layout.width, layout.height);
         } 
         else if (opcode === OP_SET_COLOR) {
             const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
@@ -307,28 +669,28 @@ function renderFrame(view) {
             ctx.fillStyle = color; ctx.strokeStyle = color;
         }
         else if (opcode === OP_FILL_RECT) {
-            const x = view.getFloat32(offset, true); offset += 4;
-            const y = view.getFloat32(offset, true); offset += 4;
-            const w = view.getFloat32(offset, true); offset += 4;
-            const h = view.getFloat32(offset, true); offset += 4;
+            const x = coord();
+            const y = coord();
+            const w = coord();
+            const h = coord();
             ctx.fillRect(x, y, w, h);
         }
         else if (opcode === OP_DRAW_LINE) {
-            const x1 = view.getFloat32(offset, true); offset += 4;
-            const y1 = view.getFloat32(offset, true); offset += 4;
-            const x2 = view.getFloat32(offset, true); offset += 4;
-            const y2 = view.getFloat32(offset, true); offset += 4;
-            const w = view.getFloat32(offset, true); offset += 4;
+            const x1 = coord();
+            const y1 = coord();
+            const x2 = coord();
+            const y2 = coord();
+            const w = coord();
             ctx.lineWidth = w; ctx.beginPath(); ctx.moveTo(x1, y1); ctx.lineTo(x2, y2); ctx.stroke(); ctx.lineWidth = 1;
         }
         else if (opcode === OP_DRAW_TEXT) {
-            const x = view.getFloat32(offset, true); offset += 4;
-            const y = view.getFloat32(offset, true); offset += 4;
+            const x = coord();
+            const y = coord();
             const textLen = view.getUint16(offset, true); offset += 2;
             const textBuffer = new Uint8Array(view.buffer, view.byteOffset + offset, textLen);
             offset += textLen;
             const text = new TextDecoder().decode(textBuffer);
-            ctx.font = "14px monospace"; ctx.textBaseline = "middle"; ctx.fillText(text, x, y);
+            ctx.fillText(text, x, y);
         }
         else if (opcode === OP_LOAD_SOUND) {
             const nameLen = view.getUint16(offset, true); offset += 2;
@@ -418,19 +780,15 @@ function renderFrame(view) {
             const nameLen = view.getUint16(offset, true); offset += 2;
             const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
             
-            const dx = view.getFloat32(offset, true); offset += 4;
-            const dy = view.getFloat32(offset, true); offset += 4;
-            const dw = view.getFloat32(offset, true); offset += 4;
-            const dh = view.getFloat32(offset, true); offset += 4;
-            
-            const sx = view.getFloat32(offset, true); offset += 4;
-            const sy = view.getFloat32(offset, true); offset += 4;
-            const sw = view.getFloat32(offset, true); offset += 4;
-            const sh = view.getFloat32(offset, true); offset += 4;
-            
-            const rot = view.getFloat32(offset, true); offset += 4;
-            const ox = view.getFloat32(offset, true); offset += 4;
-            const oy = view.getFloat32(offset, true); offset += 4;
+            // Compact images only carry the fields that differ from the defaults
+            const flags = compact ? view.getUint8(offset++) : 0xFF;
+            const dx = coord();
+            const dy = coord();
+            const [dw, dh] = (flags & IMAGE_SIZE) ? [coord(), coord()] : [-1, -1];
+            const [sx, sy, sw, sh] = (flags & IMAGE_SOURCE) ? [coord(), coord(), coord(), coord()] : [0, 0, -1, -1];
+            let rot = 0;
+            if (flags & IMAGE_ROTATION) { rot = view.getFloat32(offset, true); offset += 4; }
+            const [ox, oy] = (flags & IMAGE_ORIGIN) ? [coord(), coord()] : [0, 0];
 
             const img = images[name];
             if (img && typeof img !== "string") {
@@ -452,7 +810,164 @@ function renderFrame(view) {
                 ctx.restore();
             }
         }
-        else { break; }
+        else if (opcode === OP_FILL_POLY) {
+            const pts = points();
+            if (pts.length > 0) {
+                ctx.beginPath();
+                pts.forEach(([x, y], i) => { if (i === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y); });
+                ctx.closePath();
+                ctx.fill();
+            }
+        }
+        else if (opcode === OP_PUSH) {
+            ctx.save(); depth++;
+        }
+        else if (opcode === OP_POP) {
+            if (depth > 0) { ctx.restore(); depth--; }
+        }
+        else if (opcode === OP_TRANSLATE) {
+            const x = coord();
+            const y = coord();
+            ctx.translate(x, y);
+        }
+        else if (opcode === OP_ROTATE) {
+            const angle = view.getFloat32(offset, true); offset += 4;
+            ctx.rotate(angle);
+        }
+        else if (opcode === OP_SCALE) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            ctx.scale(x, y);
+        }
+        else if (opcode === OP_FILL_CIRCLE) {
+            const x = coord();
+            const y = coord();
+            const r = coord();
+            ctx.beginPath(); ctx.arc(x, y, Math.abs(r), 0, Math.PI * 2); ctx.fill();
+        }
+        else if (opcode === OP_STROKE_CIRCLE) {
+            const x = coord();
+            const y = coord();
+            const r = coord();
+            const w = coord();
+            ctx.beginPath(); ctx.arc(x, y, Math.abs(r), 0, Math.PI * 2);
+            ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1;
+        }
+        else if (opcode === OP_STROKE_RECT) {
+            const x = coord();
+            const y = coord();
+            const w = coord();
+            const h = coord();
+            const lw = coord();
+            ctx.lineWidth = lw; ctx.strokeRect(x, y, w, h); ctx.lineWidth = 1;
+        }
+        else if (opcode === OP_ARC) {
+            const x = coord();
+            const y = coord();
+            const r = coord();
+            const start = view.getFloat32(offset, true); offset += 4;
+            const end = view.getFloat32(offset, true); offset += 4;
+            const w = coord();
+            ctx.beginPath();
+            if (w > 0) {
+                ctx.arc(x, y, Math.abs(r), start, end);
+                ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1;
+            } else {
+                // Width 0 = filled pie slice
+                ctx.moveTo(x, y); ctx.arc(x, y, Math.abs(r), start, end); ctx.closePath(); ctx.fill();
+            }
+        }
+        else if (opcode === OP_ELLIPSE) {
+            const x = coord();
+            const y = coord();
+            const rx = coord();
+            const ry = coord();
+            const rot = view.getFloat32(offset, true); offset += 4;
+            const w = coord();
+            ctx.beginPath(); ctx.ellipse(x, y, Math.abs(rx), Math.abs(ry), rot, 0, Math.PI * 2);
+            if (w > 0) { ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1; } else { ctx.fill(); }
+        }
+        else if (opcode === OP_STROKE_POLY) {
+            const w = coord();
+            const join = LINE_JOINS[view.getUint8(offset)] || 'miter'; offset += 1;
+            const cap = LINE_CAPS[view.getUint8(offset)] || 'butt'; offset += 1;
+            const closed = view.getUint8(offset) !== 0; offset += 1;
+            const pts = points();
+            ctx.beginPath();
+            pts.forEach(([x, y], i) => { if (i === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y); });
+            if (closed) ctx.closePath();
+            if (pts.length > 0) {
+                ctx.lineWidth = w; ctx.lineJoin = join; ctx.lineCap = cap;
+                ctx.stroke();
+                ctx.lineWidth = 1; ctx.lineJoin = 'miter'; ctx.lineCap = 'butt';
+            }
+        }
+        else if (opcode === OP_LOAD_FONT) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const urlLen = view.getUint16(offset, true); offset += 2;
+            let url = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, urlLen)); offset += urlLen;
+
+            if (url.startsWith('/') && !url.startsWith('//')) {
+                const bp = getBasePath();
+                if (bp && !url.startsWith(bp)) {
+                    url = bp + url;
+                }
+            }
+
+            if (!fonts[name]) {
+                fonts[name] = new FontFace(name, `url(${url})`);
+                fonts[name].load()
+                    .then(face => document.fonts.add(face))
+                    .catch(e => console.error("Font load failed:", name, e));
+            }
+        }
+        else if (opcode === OP_SET_FONT) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const size = view.getFloat32(offset, true); offset += 4;
+            ctx.font = `${size}px ${name}`;
+        }
+        else if (opcode === OP_SET_TEXT_ALIGN) {
+            ctx.textAlign = TEXT_ALIGNS[view.getUint8(offset)] || 'left'; offset += 1;
+            ctx.textBaseline = TEXT_BASELINES[view.getUint8(offset)] || 'middle'; offset += 1;
+        }
+        else if (opcode === OP_DEFINE_LIST) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const listLen = view.getUint32(offset, true); offset += 4;
+            // Copy, the frame buffer is reused as a delta base
+            const bytes = new Uint8Array(view.buffer, view.byteOffset + offset, listLen).slice(); offset += listLen;
+            displayLists[name] = new DataView(bytes.buffer);
+        }
+        else if (opcode === OP_CALL_LIST) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const x = coord();
+            const y = coord();
+            const rotation = view.getFloat32(offset, true); offset += 4;
+            const sx = view.getFloat32(offset, true); offset += 4;
+            const sy = view.getFloat32(offset, true); offset += 4;
+            // Unknown lists draw nothing; so does nesting past the limit
+            const list = displayLists[name];
+            if (list && level < MAX_LIST_DEPTH) {
+                ctx.save();
+                ctx.translate(x, y); ctx.rotate(rotation); ctx.scale(sx, sy);
+                renderCommands(list, level + 1);
+                ctx.restore();
+            }
+        }
+        else if (opcode === OP_SET_RESOLUTION) {
+            const width = view.getUint16(offset, true); offset += 2;
+            const height = view.getUint16(offset, true); offset += 2;
+            const mode = SCALE_MODES[view.getUint8(offset)] || 'letterbox'; offset += 1;
+            if (width !== resolution.width || height !== resolution.height || mode !== resolution.mode) {
+                resolution = { width, height, mode };
+                applyLayout();
+            }
+        }
+        offset = end;
     }
+    while (depth > 0) { ctx.restore(); depth--; }
 }
 document.addEventListener('DOMContentLoaded', init);
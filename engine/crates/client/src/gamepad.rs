@@ -0,0 +1,88 @@
+use engine::input::{Input, GAMEPAD_BUTTON};
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
+use web_sys::{Gamepad, GamepadButton};
+
+// Sticks rest a little off centre; values closer to it than this count as 0
+const DEADZONE: f64 = 0.15;
+// Values are rounded to this many steps per unit, so noise doesn't flood the channel
+const STEPS: f64 = 128.0;
+
+/// Polls the browser's gamepads and turns changes into input packets.
+#[derive(Default)]
+pub struct Gamepads {
+    // Last value sent for each pad and control, left out when 0
+    sent: HashMap<(u8, u8), f32>,
+}
+
+impl Gamepads {
+    /// Inputs for every control that changed since the last poll. A pad that went
+    /// away has its controls released.
+    pub fn poll(&mut self) -> Vec<Input> {
+        let mut current = HashMap::new();
+        let pads = web_sys::window()
+            .and_then(|w| w.navigator().get_gamepads().ok())
+            .map(|pads| pads.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for pad in pads {
+            let Ok(pad) = pad.dyn_into::<Gamepad>() else {
+                continue;
+            };
+            if !pad.connected() {
+                continue;
+            }
+            let number = (pad.index() + 1).min(u8::MAX as u32) as u8;
+            for (i, axis) in pad.axes().iter().enumerate().take(GAMEPAD_BUTTON as usize) {
+                let value = axis.as_f64().unwrap_or(0.0);
+                let value = if value.abs() < DEADZONE { 0.0 } else { value };
+                record(&mut current, number, i as u8, value);
+            }
+            let buttons = pad.buttons();
+            for (i, button) in buttons
+                .iter()
+                .enumerate()
+                .take((u8::MAX - GAMEPAD_BUTTON) as usize)
+            {
+                let Ok(button) = button.dyn_into::<GamepadButton>() else {
+                    continue;
+                };
+                // Some digital buttons report being pressed with a value of 0
+                let value = if button.pressed() && button.value() == 0.0 {
+                    1.0
+                } else {
+                    button.value()
+                };
+                record(&mut current, number, GAMEPAD_BUTTON + i as u8, value);
+            }
+        }
+
+        let mut inputs = Vec::new();
+        for (&(pad, control), &value) in &current {
+            if self.sent.get(&(pad, control)) != Some(&value) {
+                inputs.push(Input::Gamepad {
+                    pad,
+                    control,
+                    value,
+                });
+            }
+        }
+        for &(pad, control) in self.sent.keys() {
+            if !current.contains_key(&(pad, control)) {
+                inputs.push(Input::Gamepad {
+                    pad,
+                    control,
+                    value: 0.0,
+                });
+            }
+        }
+        self.sent = current;
+        inputs
+    }
+}
+
+fn record(values: &mut HashMap<(u8, u8), f32>, pad: u8, control: u8, value: f64) {
+    let value = ((value.clamp(-1.0, 1.0) * STEPS).round() / STEPS) as f32;
+    if value != 0.0 {
+        values.insert((pad, control), value);
+    }
+}
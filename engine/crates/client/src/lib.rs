@@ -1,28 +1,47 @@
 mod render;
 mod audio;
 mod predictor;
+mod gamepad;
 
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{console, WebSocket, RtcPeerConnection, RtcDataChannel, RtcConfiguration, RtcIceServer, MessageEvent, BinaryType, RtcSdpType, RtcSessionDescriptionInit, RtcIceCandidateInit, RtcSessionDescription};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use render::Renderer;
 use audio::AudioManager;
 use predictor::Predictor;
+use gamepad::Gamepads;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use ruzstd::decoding::dictionary::Dictionary;
 use ruzstd::StreamingDecoder;
+use engine::delta::{self, FrameDecoder};
+use engine::input::{Input, Modifiers, PointerKind, Stamp, MAX_KEY_NAME, NO_FRAME};
+use engine::protocol::{Capabilities, MAX_MESSAGE_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 use std::io::Read;
 
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum SignalMessage {
-    WELCOME { session_id: String, server_instance_id: String },
+    WELCOME {
+        session_id: String,
+        server_instance_id: String,
+        // Absent from servers that predate versioning
+        #[serde(default)]
+        protocol_version: u16,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        #[serde(default)]
+        dictionary_id: Option<u32>,
+    },
+    REJECT { reason: String, protocol_version: u16 },
     OFFER { sdp: String },
     ANSWER { sdp: String },
     CANDIDATE { candidate: String, sdp_mid: Option<String>, sdp_mline_index: Option<u16> },
+    // From the game's `api.send`
+    MESSAGE { payload: serde_json::Value },
 }
 
 struct ClientState {
@@ -35,6 +54,20 @@ struct ClientState {
     session_id: Option<String>,
     game_started: bool,
     frame_count: u32,
+    frames: FrameDecoder,
+    // What the server agreed to use, from WELCOME
+    caps: Capabilities,
+    // Holds the game's zstd dictionary once loaded
+    zstd: ruzstd::FrameDecoder,
+    dictionary_id: Option<u32>,
+    // Latest pointer move not sent yet; moves go out once per animation frame
+    pending_move: Option<(f32, f32)>,
+    // Scrolled pixels not yet sent as whole notches
+    wheel_delta: f64,
+    gamepads: Gamepads,
+    // Sequence number of the last stamped input, and the id of the last frame shown
+    input_seq: Cell<u32>,
+    shown_frame: u32,
 }
 
 #[wasm_bindgen(start)]
@@ -48,13 +81,8 @@ pub fn start() -> Result<(), JsValue> {
     let canvas = document.get_element_by_id("gameCanvas")
         .expect("Canvas not found")
         .dyn_into::<web_sys::HtmlCanvasElement>()?;
-    
-    let dpr = window.device_pixel_ratio();
-    canvas.set_width((800.0 * dpr) as u32);
-    canvas.set_height((600.0 * dpr) as u32);
-    let ctx = canvas.get_context("2d")?.unwrap().dyn_into::<web_sys::CanvasRenderingContext2d>()?;
-    ctx.scale(dpr, dpr)?;
 
+    // Sized by the renderer, once it knows the game's resolution
     let renderer = Renderer::new(canvas).map_err(|e| JsValue::from_str(&e))?;
     let audio = AudioManager::new().map_err(|e| JsValue::from_str(&e))?;
 
@@ -68,12 +96,29 @@ pub fn start() -> Result<(), JsValue> {
         session_id: None,
         game_started: false,
         frame_count: 0,
+        frames: FrameDecoder::new(),
+        caps: Capabilities::default(),
+        zstd: ruzstd::FrameDecoder::new(),
+        dictionary_id: None,
+        pending_move: None,
+        wheel_delta: 0.0,
+        gamepads: Gamepads::default(),
+        input_seq: Cell::new(0),
+        shown_frame: NO_FRAME,
     }));
 
-    connect(state.clone())?;
     setup_input(state.clone())?;
+    setup_hooks(state.clone())?;
     load_predictor(state.clone());
 
+    // The server only uses its dictionary if we have it when connecting
+    wasm_bindgen_futures::spawn_local(async move {
+        load_dictionary(&state).await;
+        if let Err(e) = connect(state) {
+            console::error_1(&e);
+        }
+    });
+
     Ok(())
 }
 
@@ -82,7 +127,23 @@ fn connect(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
     let location = window.location();
     let protocol = if location.protocol()? == "https:" { "wss:" } else { "ws:" };
     let host = location.host()?;
-    let url = format!("{}//{}/ws", protocol, host);
+    let mut url = format!(
+        "{}//{}{}/ws?protocol={}&caps={}",
+        protocol,
+        host,
+        base_path(),
+        PROTOCOL_VERSION,
+        Capabilities::ALL.names().join(",")
+    );
+    if let Some(id) = state.borrow().dictionary_id {
+        url.push_str(&format!("&dict={}", id));
+    }
+    if let Some(fps) = frame_rate(&window) {
+        url.push_str(&format!("&fps={}", fps));
+    }
+    if let Some(room) = room(&window) {
+        url.push_str(&format!("&room={}", String::from(js_sys::encode_uri_component(&room))));
+    }
 
     let ws = WebSocket::new(&url)?;
     ws.set_binary_type(BinaryType::Arraybuffer);
@@ -111,8 +172,21 @@ fn handle_signal_message(state: Rc<RefCell<ClientState>>, msg: &str) {
     };
 
     match signal {
-        SignalMessage::WELCOME { session_id, .. } => {
+        SignalMessage::WELCOME { session_id, protocol_version, capabilities, dictionary_id, .. } => {
+            if protocol_version < MIN_PROTOCOL_VERSION {
+                show_error(&format!(
+                    "Server protocol version {} is not supported (client speaks {}).",
+                    protocol_version, PROTOCOL_VERSION
+                ));
+                if let Some(ws) = &state.borrow().ws {
+                    let _ = ws.close();
+                }
+                return;
+            }
             console::log_1(&format!("Joined Session: {}", session_id).into());
+            if let Some(id) = dictionary_id {
+                console::log_1(&format!("Frames use zstd dictionary {}", id).into());
+            }
             
             // Hide overlay immediately on join
             {
@@ -120,6 +194,15 @@ fn handle_signal_message(state: Rc<RefCell<ClientState>>, msg: &str) {
                 match state.try_borrow_mut() {
                     Ok(mut client) => {
                         client.session_id = Some(session_id);
+                        client.caps = Capabilities::from_names(&capabilities);
+                        // Frame ids restart with every server session
+                        client.frames = FrameDecoder::new();
+                        // So do input sequence numbers
+                        client.input_seq.set(0);
+                        client.shown_frame = NO_FRAME;
+                        // A new session has no gamepad state, so send held controls again
+                        client.gamepads = Gamepads::default();
+                        send_viewport(&client);
                         if !client.game_started {
                             client.game_started = true;
                             if let Some(window) = web_sys::window() {
@@ -150,6 +233,9 @@ fn handle_signal_message(state: Rc<RefCell<ClientState>>, msg: &str) {
             
             setup_webrtc(state.clone());
         },
+        SignalMessage::REJECT { reason, .. } => {
+            show_error(&reason);
+        },
         SignalMessage::ANSWER { sdp } => {
             let state_rc = state.clone();
             let sdp_str = sdp.clone();
@@ -173,6 +259,7 @@ fn handle_signal_message(state: Rc<RefCell<ClientState>>, msg: &str) {
                 let _ = pc.add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&init));
             }
         }
+        SignalMessage::MESSAGE { payload } => receive_message(&payload),
         _ => {}
     }
 }
@@ -243,9 +330,12 @@ fn setup_webrtc(state: Rc<RefCell<ClientState>>) {
 }
 
 fn process_frame(state: Rc<RefCell<ClientState>>, data: &[u8]) {
-    // Attempt to decompress Zstd frame
+    let mut client = state.borrow_mut();
+
+    // Attempt to decompress Zstd frame. The decoder picks the dictionary named in
+    // the frame header, if any.
     let cursor = std::io::Cursor::new(data);
-    let mut decoder = match StreamingDecoder::new(cursor) {
+    let mut decoder = match StreamingDecoder::new_with_decoder(cursor, &mut client.zstd) {
         Ok(d) => d,
         Err(e) => {
             console::warn_1(&format!("Zstd Init Error: {:?}", e).into());
@@ -258,11 +348,9 @@ fn process_frame(state: Rc<RefCell<ClientState>>, data: &[u8]) {
         console::warn_1(&format!("Zstd Decompress Error: {:?}", e).into());
         return;
     }
-
-    let mut client = state.borrow_mut();
     
     client.frame_count = client.frame_count.wrapping_add(1);
-    if client.frame_count == 1 || client.frame_count % 120 == 0 {
+    if client.frame_count == 1 || client.frame_count.is_multiple_of(120) {
         console::log_1(&format!("Rendered Frame #{} ({} bytes)", client.frame_count, decompressed.len()).into());
     }
     
@@ -278,10 +366,42 @@ fn process_frame(state: Rc<RefCell<ClientState>>, data: &[u8]) {
         }
     }
 
+    let client = &mut *client;
     let audio = client.audio.clone();
-    if let Err(e) = client.renderer.render_frame(&decompressed, &audio) {
+    if !client.caps.delta_frames {
+        if let Err(e) = client.renderer.render_frame(&decompressed, &audio) {
+            console::warn_1(&format!("Render Error: {}", e).into());
+        }
+        return;
+    }
+
+    let (id, frame) = match client.frames.decode(&decompressed) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            // Not acknowledged, so the server falls back to a full frame soon
+            console::warn_1(&format!("Frame Error: {}", e).into());
+            return;
+        }
+    };
+
+    if let Err(e) = client.renderer.render_frame(frame, &audio) {
         console::warn_1(&format!("Render Error: {}", e).into());
     }
+    client.shown_frame = id;
+    send_bytes(client, &delta::encode_ack(id));
+}
+
+// Leaves the loading overlay up with `msg`, for connections that can't go on.
+fn show_error(msg: &str) {
+    console::error_1(&msg.into());
+    if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+        if let Some(text) = document.get_element_by_id("loading-text") {
+            text.set_text_content(Some(msg));
+        }
+        if let Some(overlay) = document.get_element_by_id("loading-overlay") {
+            let _ = overlay.class_list().remove_1("hidden");
+        }
+    }
 }
 
 fn setup_input(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
@@ -293,8 +413,8 @@ fn setup_input(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
         // Resume audio on first interaction
         state_down.borrow().audio.resume();
         
-        if !e.repeat() {
-            send_input(state_down.clone(), e.key_code(), true);
+        if !e.repeat() && !is_typing(&e) {
+            send_key(&state_down.borrow(), &e, true);
         }
     });
     document.add_event_listener_with_callback("keydown", onkeydown.as_ref().unchecked_ref())?;
@@ -302,7 +422,9 @@ fn setup_input(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
 
     let state_up = state.clone();
     let onkeyup = Closure::<dyn FnMut(_)>::new(move |e: web_sys::KeyboardEvent| {
-        send_input(state_up.clone(), e.key_code(), false);
+        if !is_typing(&e) {
+            send_key(&state_up.borrow(), &e, false);
+        }
     });
     document.add_event_listener_with_callback("keyup", onkeyup.as_ref().unchecked_ref())?;
     onkeyup.forget();
@@ -315,24 +437,300 @@ fn setup_input(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
     document.add_event_listener_with_callback("click", onclick.as_ref().unchecked_ref())?;
     onclick.forget();
 
+    let state_resize = state.clone();
+    let onresize = Closure::<dyn FnMut(_)>::new(move |_e: web_sys::Event| {
+        let mut client = state_resize.borrow_mut();
+        client.renderer.layout();
+        send_viewport(&client);
+    });
+    window.add_event_listener_with_callback("resize", onresize.as_ref().unchecked_ref())?;
+    onresize.forget();
+
+    setup_pointer(state.clone())?;
+    poll_gamepads(state);
+    Ok(())
+}
+
+// Sends gamepad changes every animation frame; the Gamepad API has no change events
+fn poll_gamepads(state: Rc<RefCell<ClientState>>) {
+    let callback = Rc::new(RefCell::new(None::<Closure<dyn FnMut()>>));
+    let next = callback.clone();
+    let request_frame = |callback: &Closure<dyn FnMut()>| {
+        let _ = web_sys::window().unwrap().request_animation_frame(callback.as_ref().unchecked_ref());
+    };
+    *callback.borrow_mut() = Some(Closure::new(move || {
+        {
+            let mut client = state.borrow_mut();
+            for input in client.gamepads.poll() {
+                send_stamped(&client, &input.encode());
+            }
+        }
+        request_frame(next.borrow().as_ref().unwrap());
+    }));
+    request_frame(callback.borrow().as_ref().unwrap());
+}
+
+// Pixels of wheel deltaY per notch, as most browsers report a mouse wheel
+const WHEEL_NOTCH: f64 = 100.0;
+
+fn setup_pointer(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
+    let canvas = state.borrow().renderer.canvas().clone();
+    // Touches are pointer events, not scrolling
+    let _ = canvas.style().set_property("touch-action", "none");
+
+    let state_down = state.clone();
+    let canvas_down = canvas.clone();
+    let onpointerdown = Closure::<dyn FnMut(_)>::new(move |e: web_sys::PointerEvent| {
+        // So the up arrives even off the canvas
+        let _ = canvas_down.set_pointer_capture(e.pointer_id());
+        flush_pointer_move(&state_down);
+        send_pointer(&state_down, PointerKind::Down, e.button() as i8 + 1, &e);
+    });
+    canvas.add_event_listener_with_callback("pointerdown", onpointerdown.as_ref().unchecked_ref())?;
+    onpointerdown.forget();
+
+    let state_up = state.clone();
+    let onpointerup = Closure::<dyn FnMut(_)>::new(move |e: web_sys::PointerEvent| {
+        flush_pointer_move(&state_up);
+        send_pointer(&state_up, PointerKind::Up, e.button() as i8 + 1, &e);
+    });
+    canvas.add_event_listener_with_callback("pointerup", onpointerup.as_ref().unchecked_ref())?;
+    onpointerup.forget();
+
+    let state_move = state.clone();
+    let onpointermove = Closure::<dyn FnMut(_)>::new(move |e: web_sys::PointerEvent| {
+        let mut client = state_move.borrow_mut();
+        if client.pending_move.is_none() {
+            let state_flush = state_move.clone();
+            let flush = Closure::once_into_js(move || flush_pointer_move(&state_flush));
+            let _ = web_sys::window().unwrap().request_animation_frame(flush.unchecked_ref());
+        }
+        client.pending_move = Some(client.renderer.to_virtual(e.client_x() as f64, e.client_y() as f64));
+    });
+    canvas.add_event_listener_with_callback("pointermove", onpointermove.as_ref().unchecked_ref())?;
+    onpointermove.forget();
+
+    let state_wheel = state.clone();
+    let onwheel = Closure::<dyn FnMut(_)>::new(move |e: web_sys::WheelEvent| {
+        e.prevent_default();
+        let unit = match e.delta_mode() {
+            web_sys::WheelEvent::DOM_DELTA_LINE => WHEEL_NOTCH / 3.0,
+            web_sys::WheelEvent::DOM_DELTA_PAGE => WHEEL_NOTCH,
+            _ => 1.0,
+        };
+        let notches = {
+            let mut client = state_wheel.borrow_mut();
+            client.wheel_delta += e.delta_y() * unit;
+            let notches = (client.wheel_delta / WHEEL_NOTCH).trunc().clamp(-127.0, 127.0);
+            client.wheel_delta -= notches * WHEEL_NOTCH;
+            notches as i8
+        };
+        if notches != 0 {
+            flush_pointer_move(&state_wheel);
+            send_pointer(&state_wheel, PointerKind::Wheel, notches, &e);
+        }
+    });
+    let options = web_sys::AddEventListenerOptions::new();
+    options.set_passive(false);
+    canvas.add_event_listener_with_callback_and_add_event_listener_options(
+        "wheel",
+        onwheel.as_ref().unchecked_ref(),
+        &options,
+    )?;
+    onwheel.forget();
+
+    let oncontextmenu = Closure::<dyn FnMut(_)>::new(move |e: web_sys::MouseEvent| {
+        e.prevent_default();
+    });
+    canvas.add_event_listener_with_callback("contextmenu", oncontextmenu.as_ref().unchecked_ref())?;
+    oncontextmenu.forget();
+
+    Ok(())
+}
+
+fn flush_pointer_move(state: &Rc<RefCell<ClientState>>) {
+    let mut client = state.borrow_mut();
+    if let Some((x, y)) = client.pending_move.take() {
+        let input = Input::Pointer { kind: PointerKind::Move, button: 0, x, y };
+        send_stamped(&client, &input.encode());
+    }
+}
+
+fn send_pointer(state: &Rc<RefCell<ClientState>>, kind: PointerKind, button: i8, e: &web_sys::MouseEvent) {
+    let client = state.borrow();
+    let (x, y) = client.renderer.to_virtual(e.client_x() as f64, e.client_y() as f64);
+    send_stamped(&client, &Input::Pointer { kind, button, x, y }.encode());
+}
+
+// Tells the server how much room the canvas has, for `api.screen_size`
+fn send_viewport(client: &ClientState) {
+    let (width, height) = client.renderer.viewport_size();
+    if let Some(ws) = &client.ws {
+        let msg = json!({ "type": "VIEWPORT", "width": width, "height": height });
+        let _ = ws.send_with_str(&msg.to_string());
+    }
+}
+
+// Hooks for the page hosting the game, as `window.cleoselene`, keeping any it set up
+// before the client started. The page sets `onMessage(payload)` to receive `api.send`.
+fn setup_hooks(state: Rc<RefCell<ClientState>>) -> Result<(), JsValue> {
+    let state_send = state.clone();
+    let send = Closure::<dyn FnMut(JsValue) -> bool>::new(move |payload: JsValue| {
+        send_message(&state_send.borrow(), payload)
+    });
+    // How many inputs the server has yet to acknowledge, e.g. for showing input lag
+    let input_seq = Closure::<dyn FnMut() -> JsValue>::new(move || {
+        let client = state.borrow();
+        let counts = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(&counts, &"sent".into(), &client.input_seq.get().into());
+        let acked = client.frames.input_ack().unwrap_or(0);
+        let _ = js_sys::Reflect::set(&counts, &"acked".into(), &acked.into());
+        counts.into()
+    });
+    let window = web_sys::window().unwrap();
+    let hooks = js_sys::Reflect::get(&window, &"cleoselene".into())
+        .ok()
+        .filter(|hooks| hooks.is_object())
+        .unwrap_or_else(|| js_sys::Object::new().into());
+    js_sys::Reflect::set(&hooks, &"sendMessage".into(), send.as_ref())?;
+    js_sys::Reflect::set(&hooks, &"inputSeq".into(), input_seq.as_ref())?;
+    send.forget();
+    input_seq.forget();
+    js_sys::Reflect::set(&window, &"cleoselene".into(), &hooks)?;
     Ok(())
 }
 
-fn send_input(state: Rc<RefCell<ClientState>>, code: u32, is_down: bool) {
+// Frames per second to ask the server for: `fps` in the page's URL, or
+// `window.cleoselene.frameRate` set by the page, e.g. a lower one on phones
+fn frame_rate(window: &web_sys::Window) -> Option<u32> {
+    let from_url = window
+        .location()
+        .search()
+        .ok()
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+        .and_then(|params| params.get("fps"))
+        .and_then(|fps| fps.parse::<f64>().ok());
+    let from_page = || {
+        js_sys::Reflect::get(window, &"cleoselene".into())
+            .and_then(|hooks| js_sys::Reflect::get(&hooks, &"frameRate".into()))
+            .ok()
+            .and_then(|fps| fps.as_f64())
+    };
+    from_url
+        .or_else(from_page)
+        .filter(|fps| *fps >= 1.0)
+        .map(|fps| fps.round() as u32)
+}
+
+// Room to join, `room` in the page's URL, e.g. from a link a friend shared
+fn room(window: &web_sys::Window) -> Option<String> {
+    window
+        .location()
+        .search()
+        .ok()
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+        .and_then(|params| params.get("room"))
+        .filter(|room| !room.is_empty())
+}
+
+// Passes a message from the game's `api.send` to the page's `onMessage` hook.
+// Messages that arrive before the page sets one are dropped.
+fn receive_message(payload: &serde_json::Value) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let on_message = js_sys::Reflect::get(&window, &"cleoselene".into())
+        .and_then(|hooks| js_sys::Reflect::get(&hooks, &"onMessage".into()));
+    let Ok(on_message) = on_message.and_then(|f| f.dyn_into::<js_sys::Function>()) else {
+        return;
+    };
+    let Ok(payload) = js_sys::JSON::parse(&payload.to_string()) else {
+        return;
+    };
+    if let Err(e) = on_message.call1(&JsValue::NULL, &payload) {
+        console::error_1(&format!("onMessage Error: {:?}", e).into());
+    }
+}
+
+// Sends any JSON value to the game's on_message, over the WebSocket, which unlike
+// the data channel doesn't drop messages. Returns whether it was sent.
+fn send_message(client: &ClientState, payload: JsValue) -> bool {
+    let Some(ws) = client.ws.as_ref().filter(|ws| ws.ready_state() == WebSocket::OPEN) else {
+        return false;
+    };
+    let payload = js_sys::JSON::stringify(&payload)
+        .ok()
+        .and_then(|json| json.as_string())
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+        .unwrap_or_default();
+    let text = json!({ "type": "MESSAGE", "payload": payload }).to_string();
+    if text.len() > MAX_MESSAGE_LEN {
+        console::warn_1(&"Message too long, not sent".into());
+        return false;
+    }
+    ws.send_with_str(&text).is_ok()
+}
+
+fn send_input(client: &ClientState, code: u32, is_down: bool) {
     let mut buf = [0u8; 2];
     buf[0] = code as u8;
     buf[1] = if is_down { 1 } else { 0 };
 
-    let client = state.borrow();
+    send_stamped(client, &buf);
+}
+
+// Keys typed into the page's own text fields, e.g. for on_message, aren't game input
+fn is_typing(e: &web_sys::KeyboardEvent) -> bool {
+    let Some(target) = e.target().and_then(|t| t.dyn_into::<web_sys::HtmlElement>().ok()) else {
+        return false;
+    };
+    target.is_content_editable() || matches!(target.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+}
+
+// Sends the physical key's name with the modifiers held, or its legacy code to
+// servers without named keys
+fn send_key(client: &ClientState, e: &web_sys::KeyboardEvent, is_down: bool) {
+    let name = e.code();
+    if !client.caps.named_keys
+        || name.is_empty()
+        || name.len() > MAX_KEY_NAME
+        || !name.bytes().all(|b| b.is_ascii_alphanumeric())
+    {
+        send_input(client, e.key_code(), is_down);
+        return;
+    }
+    let mods = Modifiers {
+        shift: e.shift_key(),
+        ctrl: e.ctrl_key(),
+        alt: e.alt_key(),
+        meta: e.meta_key(),
+    };
+    send_stamped(client, &Input::NamedKey { name, down: is_down, mods }.encode());
+}
+
+// Sends an input packet, stamped with the next sequence number and the frame on
+// screen if the server takes stamps
+fn send_stamped(client: &ClientState, packet: &[u8]) {
+    if !client.caps.input_seq {
+        send_bytes(client, packet);
+        return;
+    }
+    let seq = client.input_seq.get().wrapping_add(1);
+    client.input_seq.set(seq);
+    send_bytes(client, &Stamp { seq, frame: client.shown_frame }.wrap(packet));
+}
+
+// Prefers the data channel, falling back to the WebSocket
+fn send_bytes(client: &ClientState, buf: &[u8]) {
     if let Some(dc) = &client.dc {
         if dc.ready_state() == web_sys::RtcDataChannelState::Open {
-            let _ = dc.send_with_u8_array(&buf);
+            let _ = dc.send_with_u8_array(buf);
             return;
         }
     }
     if let Some(ws) = &client.ws {
         if ws.ready_state() == WebSocket::OPEN {
-            let _ = ws.send_with_u8_array(&buf);
+            let _ = ws.send_with_u8_array(buf);
         }
     }
 }
@@ -358,6 +756,30 @@ fn load_predictor(state: Rc<RefCell<ClientState>>) {
     });
 }
 
+// Fetches the game's trained zstd dictionary, which most games don't have
+async fn load_dictionary(state: &Rc<RefCell<ClientState>>) {
+    let url = format!("{}/assets/zstd.dict", base_path());
+    let Ok(bytes) = fetch_bytes(&url).await else { return };
+    match Dictionary::decode_dict(&bytes) {
+        Ok(dict) => {
+            let mut client = state.borrow_mut();
+            client.dictionary_id = Some(dict.id);
+            let _ = client.zstd.add_dict(dict);
+        },
+        Err(e) => console::warn_1(&format!("Ignoring zstd dictionary: {:?}", e).into()),
+    }
+}
+
+// `basePath` injected by the server into index.html
+fn base_path() -> String {
+    let window = web_sys::window().unwrap();
+    js_sys::Reflect::get(&window, &"CLEOSELENE_CONFIG".into())
+        .and_then(|config| js_sys::Reflect::get(&config, &"basePath".into()))
+        .ok()
+        .and_then(|path| path.as_string())
+        .unwrap_or_default()
+}
+
 async fn fetch_bytes(url: &str) -> Result<Vec<u8>, JsValue> {
     let window = web_sys::window().unwrap();
     let resp = JsFuture::from(window.fetch_with_str(url)).await?;
@@ -1,26 +1,31 @@
 use crate::audio::AudioManager;
-use byteorder::{ByteOrder, LittleEndian};
+use engine::protocol::{
+    Decoder, DrawCommand, LineCap, LineJoin, ScaleMode, TextAlign, TextBaseline, DEFAULT_FONT,
+    DEFAULT_FONT_SIZE, DEFAULT_HEIGHT, DEFAULT_WIDTH, MAX_LIST_DEPTH,
+};
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::rc::Rc;
 use wasm_bindgen::JsCast;
 use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
 
-// OpCodes
-const OP_CLEAR: u8 = 0x01;
-const OP_SET_COLOR: u8 = 0x02;
-const OP_FILL_RECT: u8 = 0x03;
-const OP_DRAW_LINE: u8 = 0x04;
-const OP_DRAW_TEXT: u8 = 0x05;
-const OP_LOAD_SOUND: u8 = 0x06;
-const OP_PLAY_SOUND: u8 = 0x07;
-const OP_STOP_SOUND: u8 = 0x08;
-const OP_SET_VOLUME: u8 = 0x09;
-const OP_LOAD_IMAGE: u8 = 0x0A;
-const OP_DRAW_IMAGE: u8 = 0x0B;
-const OP_FILL_POLY: u8 = 0x0C;
+fn css_font(name: &str, size: f32) -> String {
+    format!("{}px {}", size, name)
+}
 
 pub struct Renderer {
+    canvas: HtmlCanvasElement,
     ctx: CanvasRenderingContext2d,
+    // Game resolution and scaling, from `SetResolution`
+    resolution: (u16, u16, ScaleMode),
+    // Visible virtual area, and device pixels per virtual pixel
     width: f64,
     height: f64,
+    scale: (f64, f64),
+    // Number of unmatched `Push`es in the current frame
+    depth: u32,
+    // Display lists, kept across frames until redefined
+    lists: HashMap<String, Rc<[u8]>>,
 }
 
 impl Renderer {
@@ -32,270 +37,342 @@ impl Renderer {
             .dyn_into::<CanvasRenderingContext2d>()
             .map_err(|_| "Context cast failed")?;
 
-        let width = canvas.width() as f64;
-        let height = canvas.height() as f64;
+        let mut renderer = Self {
+            canvas,
+            ctx,
+            resolution: (DEFAULT_WIDTH, DEFAULT_HEIGHT, ScaleMode::default()),
+            width: DEFAULT_WIDTH as f64,
+            height: DEFAULT_HEIGHT as f64,
+            scale: (1.0, 1.0),
+            depth: 0,
+            lists: HashMap::new(),
+        };
+        renderer.layout();
+        Ok(renderer)
+    }
+
+    /// Size of the area the canvas can take, in CSS pixels.
+    pub fn viewport_size(&self) -> (f64, f64) {
+        if let Some(parent) = self.canvas.parent_element() {
+            if parent.client_width() > 0 && parent.client_height() > 0 {
+                return (parent.client_width() as f64, parent.client_height() as f64);
+            }
+        }
+        let window = web_sys::window().unwrap();
+        let size = |v: Result<wasm_bindgen::JsValue, _>| v.ok().and_then(|v| v.as_f64()).unwrap_or(0.0);
+        (size(window.inner_width()), size(window.inner_height()))
+    }
+
+    pub fn canvas(&self) -> &HtmlCanvasElement {
+        &self.canvas
+    }
 
-        Ok(Self { ctx, width, height })
+    /// Maps a position in CSS pixels, as on pointer events, to virtual coordinates. The
+    /// canvas starts at the virtual origin in every scaling mode.
+    pub fn to_virtual(&self, client_x: f64, client_y: f64) -> (f32, f32) {
+        let rect = self.canvas.get_bounding_client_rect();
+        let dpr = web_sys::window().unwrap().device_pixel_ratio();
+        (
+            ((client_x - rect.left()) * dpr / self.scale.0) as f32,
+            ((client_y - rect.top()) * dpr / self.scale.1) as f32,
+        )
+    }
+
+    /// Sizes the canvas for the current resolution, scaling mode and viewport. Must be
+    /// called again when the viewport changes.
+    pub fn layout(&mut self) {
+        let dpr = web_sys::window().unwrap().device_pixel_ratio();
+        let (vw, vh) = self.viewport_size();
+        let (rw, rh, mode) = self.resolution;
+        let (rw, rh) = (rw as f64, rh as f64);
+        let fit = (vw / rw).min(vh / rh);
+
+        // Canvas size in CSS pixels and CSS pixels per virtual pixel
+        let (css_w, css_h, sx, sy) = match mode {
+            ScaleMode::Letterbox => (rw * fit, rh * fit, fit, fit),
+            ScaleMode::Stretch => (vw, vh, vw / rw, vh / rh),
+            ScaleMode::Integer => {
+                let s = (fit * dpr).floor().max(1.0) / dpr;
+                (rw * s, rh * s, s, s)
+            }
+            ScaleMode::Expand => (vw, vh, fit, fit),
+        };
+        let (width, height) = mode.visible_size(rw as f32, rh as f32, (vw as f32, vh as f32));
+
+        self.canvas.set_width((css_w * dpr).round() as u32);
+        self.canvas.set_height((css_h * dpr).round() as u32);
+        let style = self.canvas.style();
+        let _ = style.set_property("width", &format!("{}px", css_w));
+        let _ = style.set_property("height", &format!("{}px", css_h));
+        // Resizing resets the context, smoothing included
+        self.ctx.set_image_smoothing_enabled(mode != ScaleMode::Integer);
+
+        self.width = width as f64;
+        self.height = height as f64;
+        self.scale = (sx * dpr, sy * dpr);
+        let _ = self.ctx.set_transform(self.scale.0, 0.0, 0.0, self.scale.1, 0.0, 0.0);
     }
 
     pub fn render_frame(&mut self, data: &[u8], audio: &AudioManager) -> Result<(), String> {
-        let mut offset = 0;
-        while offset < data.len() {
-            let op = data[offset];
-            offset += 1;
+        let _ = self.ctx.set_transform(self.scale.0, 0.0, 0.0, self.scale.1, 0.0, 0.0);
+        // Text style is per frame, like the transform stack
+        self.ctx.set_font(&css_font(DEFAULT_FONT, DEFAULT_FONT_SIZE));
+        self.ctx.set_text_align(TextAlign::default().as_str());
+        self.ctx.set_text_baseline(TextBaseline::default().as_str());
+        let result = self.render_commands(data, audio, 0);
 
-            match op {
-                OP_CLEAR => {
-                    if offset + 3 > data.len() {
-                        break;
-                    }
-                    let r = data[offset];
-                    offset += 1;
-                    let g = data[offset];
-                    offset += 1;
-                    let b = data[offset];
-                    offset += 1;
+        // Don't let an unbalanced frame leak its transform into the next one
+        self.restore_to(0);
+        result
+    }
+
+    // Plays a command stream; `level` is the number of display lists it is nested in.
+    fn render_commands(
+        &mut self,
+        data: &[u8],
+        audio: &AudioManager,
+        level: usize,
+    ) -> Result<(), String> {
+        let mut result = Ok(());
+        // A list can't pop state pushed outside of it
+        let floor = self.depth;
+        for cmd in Decoder::new(data) {
+            // A malformed command leaves the rest of the frame unaligned, so stop there.
+            let cmd = match cmd {
+                Ok(cmd) => cmd,
+                Err(e) => {
+                    result = Err(e.to_string());
+                    break;
+                }
+            };
+            match cmd {
+                DrawCommand::Clear { r, g, b } => {
                     let color = format!("rgb({},{},{})", r, g, b);
                     self.ctx.set_fill_style(&color.into());
                     self.ctx.fill_rect(0.0, 0.0, self.width, self.height);
                 }
-                OP_SET_COLOR => {
-                    if offset + 4 > data.len() {
-                        break;
-                    }
-                    let r = data[offset];
-                    offset += 1;
-                    let g = data[offset];
-                    offset += 1;
-                    let b = data[offset];
-                    offset += 1;
-                    let a = data[offset];
-                    offset += 1;
+                DrawCommand::SetColor { r, g, b, a } => {
                     let color = format!("rgba({},{},{},{})", r, g, b, a as f32 / 255.0);
                     self.ctx.set_fill_style(&color.clone().into());
                     self.ctx.set_stroke_style(&color.into());
                 }
-                OP_FILL_RECT => {
-                    if offset + 16 > data.len() {
-                        break;
-                    }
-                    let x = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let y = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let w = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let h = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    self.ctx.fill_rect(x, y, w, h);
+                DrawCommand::FillRect { x, y, w, h } => {
+                    self.ctx.fill_rect(x as f64, y as f64, w as f64, h as f64);
                 }
-                OP_DRAW_LINE => {
-                    if offset + 20 > data.len() {
-                        break;
-                    }
-                    let x1 = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let y1 = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let x2 = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let y2 = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let w = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-
-                    self.ctx.set_line_width(w);
+                DrawCommand::DrawLine {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    width,
+                } => {
+                    self.ctx.set_line_width(width as f64);
                     self.ctx.begin_path();
-                    self.ctx.move_to(x1, y1);
-                    self.ctx.line_to(x2, y2);
+                    self.ctx.move_to(x1 as f64, y1 as f64);
+                    self.ctx.line_to(x2 as f64, y2 as f64);
                     self.ctx.stroke();
                     self.ctx.set_line_width(1.0); // Reset
                 }
-                OP_DRAW_TEXT => {
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let x = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let y = LittleEndian::read_f32(&data[offset..]) as f64;
-                    offset += 4;
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let text_bytes = &data[offset..offset + len];
-                    offset += len;
-
-                    if let Ok(text) = std::str::from_utf8(text_bytes) {
-                        self.ctx.set_font("14px monospace");
-                        self.ctx.set_text_baseline("middle");
-                        let _ = self.ctx.fill_text(text, x, y);
-                    }
+                DrawCommand::DrawText { x, y, text } => {
+                    let _ = self.ctx.fill_text(&text, x as f64, y as f64);
                 }
-                OP_LOAD_SOUND => {
-                    // Name
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let name = String::from_utf8_lossy(&data[offset..offset + len]).to_string();
-                    offset += len;
-
-                    // URL
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let url = String::from_utf8_lossy(&data[offset..offset + len]).to_string();
-                    offset += len;
-
+                DrawCommand::LoadSound { name, url } => {
                     audio.preload_sound(name, url);
                 }
-                OP_PLAY_SOUND => {
-                    // Name
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let name = String::from_utf8_lossy(&data[offset..offset + len]).to_string();
-                    offset += len;
-
-                    if offset + 5 > data.len() {
-                        break;
-                    }
-                    let loop_val = data[offset] != 0;
-                    offset += 1;
-                    let vol = LittleEndian::read_f32(&data[offset..]);
-                    offset += 4;
-
-                    audio.play_sound(&name, loop_val, vol);
+                DrawCommand::PlaySound {
+                    name,
+                    looped,
+                    volume,
+                } => {
+                    audio.play_sound(&name, looped, volume);
                 }
-                OP_STOP_SOUND => {
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let name = String::from_utf8_lossy(&data[offset..offset + len]).to_string();
-                    offset += len;
-
+                DrawCommand::StopSound { name } => {
                     audio.stop_sound(&name);
                 }
-                OP_SET_VOLUME => {
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    let name = String::from_utf8_lossy(&data[offset..offset + len]).to_string();
-                    offset += len;
-
-                    if offset + 4 > data.len() {
-                        break;
-                    }
-                    let vol = LittleEndian::read_f32(&data[offset..]);
-                    offset += 4;
-
-                    audio.set_volume(&name, vol);
+                DrawCommand::SetVolume { name, volume } => {
+                    audio.set_volume(&name, volume);
                 }
-                OP_LOAD_IMAGE => {
-                    // Name
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    // let name = ...;
-                    offset += len;
-
-                    // URL
-                    if offset + 2 > data.len() {
-                        break;
-                    }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
-                    }
-                    // let url = ...;
-                    offset += len;
+                DrawCommand::SetFont { name, size } => {
+                    self.ctx.set_font(&css_font(&name, size));
                 }
-                OP_DRAW_IMAGE => {
-                    // Name
-                    if offset + 2 > data.len() {
-                        break;
+                DrawCommand::SetTextAlign { align, baseline } => {
+                    self.ctx.set_text_align(align.as_str());
+                    self.ctx.set_text_baseline(baseline.as_str());
+                }
+                DrawCommand::LoadImage { .. }
+                | DrawCommand::DrawImage { .. }
+                | DrawCommand::LoadFont { .. } => {
+                    // Images are not supported by the WASM renderer yet.
+                }
+                DrawCommand::FillPoly { points } => {
+                    if let Some((&(x0, y0), rest)) = points.split_first() {
+                        self.ctx.begin_path();
+                        self.ctx.move_to(x0 as f64, y0 as f64);
+                        for &(x, y) in rest {
+                            self.ctx.line_to(x as f64, y as f64);
+                        }
+                        self.ctx.close_path();
+                        self.ctx.fill();
                     }
-                    let len = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-                    if offset + len > data.len() {
-                        break;
+                }
+                DrawCommand::Push => {
+                    self.ctx.save();
+                    self.depth += 1;
+                }
+                DrawCommand::Pop => {
+                    if self.depth > floor {
+                        self.ctx.restore();
+                        self.depth -= 1;
                     }
-                    // let name = ...;
-                    offset += len;
-
-                    // 11 floats: x, y, w, h, sx, sy, sw, sh, r, ox, oy
-                    if offset + 44 > data.len() {
-                        break;
+                }
+                DrawCommand::Translate { x, y } => {
+                    let _ = self.ctx.translate(x as f64, y as f64);
+                }
+                DrawCommand::Rotate { angle } => {
+                    let _ = self.ctx.rotate(angle as f64);
+                }
+                DrawCommand::Scale { x, y } => {
+                    let _ = self.ctx.scale(x as f64, y as f64);
+                }
+                DrawCommand::FillCircle { x, y, r } => {
+                    self.ctx.begin_path();
+                    let _ = self.ctx.arc(x as f64, y as f64, r.abs() as f64, 0.0, TAU);
+                    self.ctx.fill();
+                }
+                DrawCommand::StrokeCircle { x, y, r, width } => {
+                    self.ctx.begin_path();
+                    let _ = self.ctx.arc(x as f64, y as f64, r.abs() as f64, 0.0, TAU);
+                    self.stroke_path(width);
+                }
+                DrawCommand::StrokeRect { x, y, w, h, width } => {
+                    self.ctx.begin_path();
+                    self.ctx.rect(x as f64, y as f64, w as f64, h as f64);
+                    self.stroke_path(width);
+                }
+                DrawCommand::Arc {
+                    x,
+                    y,
+                    r,
+                    start,
+                    end,
+                    width,
+                } => {
+                    self.ctx.begin_path();
+                    if width > 0.0 {
+                        let _ = self.ctx.arc(x as f64, y as f64, r.abs() as f64, start as f64, end as f64);
+                        self.stroke_path(width);
+                    } else {
+                        self.ctx.move_to(x as f64, y as f64);
+                        let _ = self.ctx.arc(x as f64, y as f64, r.abs() as f64, start as f64, end as f64);
+                        self.ctx.close_path();
+                        self.ctx.fill();
                     }
-                    offset += 44;
                 }
-                OP_FILL_POLY => {
-                    if offset + 2 > data.len() {
-                        break;
+                DrawCommand::Ellipse {
+                    x,
+                    y,
+                    rx,
+                    ry,
+                    rotation,
+                    width,
+                } => {
+                    self.ctx.begin_path();
+                    let _ = self.ctx.ellipse(
+                        x as f64,
+                        y as f64,
+                        rx.abs() as f64,
+                        ry.abs() as f64,
+                        rotation as f64,
+                        0.0,
+                        TAU,
+                    );
+                    if width > 0.0 {
+                        self.stroke_path(width);
+                    } else {
+                        self.ctx.fill();
                     }
-                    let count = LittleEndian::read_u16(&data[offset..]) as usize;
-                    offset += 2;
-
-                    if count > 0 {
+                }
+                DrawCommand::StrokePoly {
+                    width,
+                    join,
+                    cap,
+                    closed,
+                    points,
+                } => {
+                    if let Some((&(x0, y0), rest)) = points.split_first() {
                         self.ctx.begin_path();
-                        for i in 0..count {
-                            if offset + 8 > data.len() {
-                                break;
-                            }
-                            let x = LittleEndian::read_f32(&data[offset..]) as f64;
-                            offset += 4;
-                            let y = LittleEndian::read_f32(&data[offset..]) as f64;
-                            offset += 4;
-
-                            if i == 0 {
-                                self.ctx.move_to(x, y);
-                            } else {
-                                self.ctx.line_to(x, y);
-                            }
+                        self.ctx.move_to(x0 as f64, y0 as f64);
+                        for &(x, y) in rest {
+                            self.ctx.line_to(x as f64, y as f64);
                         }
-                        self.ctx.close_path();
-                        self.ctx.fill();
+                        if closed {
+                            self.ctx.close_path();
+                        }
+                        self.ctx.set_line_join(join.as_str());
+                        self.ctx.set_line_cap(cap.as_str());
+                        self.stroke_path(width);
+                        self.ctx.set_line_join(LineJoin::default().as_str());
+                        self.ctx.set_line_cap(LineCap::default().as_str());
                     }
                 }
-                _ => {
-                    // Unknown op, break to avoid desync
-                    break;
+                DrawCommand::DefineList { name, commands } => {
+                    self.lists.insert(name, commands.into());
+                }
+                DrawCommand::SetResolution {
+                    width,
+                    height,
+                    mode,
+                } => {
+                    if (width, height, mode) != self.resolution {
+                        self.resolution = (width, height, mode);
+                        self.layout();
+                    }
+                }
+                DrawCommand::CallList {
+                    name,
+                    x,
+                    y,
+                    rotation,
+                    sx,
+                    sy,
+                } => {
+                    // Unknown lists draw nothing; so does nesting past the limit
+                    let Some(list) = self.lists.get(&name).cloned() else {
+                        continue;
+                    };
+                    if level >= MAX_LIST_DEPTH {
+                        continue;
+                    }
+                    self.ctx.save();
+                    let _ = self.ctx.translate(x as f64, y as f64);
+                    let _ = self.ctx.rotate(rotation as f64);
+                    let _ = self.ctx.scale(sx as f64, sy as f64);
+                    let depth = self.depth;
+                    let nested = self.render_commands(&list, audio, level + 1);
+                    self.restore_to(depth);
+                    self.ctx.restore();
+                    if nested.is_err() {
+                        result = nested;
+                        break;
+                    }
                 }
             }
         }
-        Ok(())
+        result
+    }
+
+    // Pops unmatched `Push`es until `depth` are left.
+    fn restore_to(&mut self, depth: u32) {
+        while self.depth > depth {
+            self.ctx.restore();
+            self.depth -= 1;
+        }
+    }
+
+    // Strokes the current path with `width`, then restores the default width.
+    fn stroke_path(&self, width: f32) {
+        self.ctx.set_line_width(width as f64);
+        self.ctx.stroke();
+        self.ctx.set_line_width(1.0);
     }
 }
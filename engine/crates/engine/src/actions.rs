@@ -0,0 +1,257 @@
+//! Named actions, bound to keys, gamepad controls and pointer buttons in the game's
+//! controls.json:
+//!
+//! ```json
+//! {
+//!     "actions": {
+//!         "thrust": { "keys": ["ArrowUp", "KeyW"], "gamepad": ["a", "left_y-"] },
+//!         "fire": { "keys": ["KeyZ", 32], "gamepad": ["right_trigger"], "pointer": [1] }
+//!     },
+//!     "touch": [[{ "label": "🚀", "action": "thrust" }, { "label": "⚡️", "action": "fire" }]]
+//! }
+//! ```
+//!
+//! Keys are names or legacy codes, as in `input`. Gamepad bindings are control names,
+//! with `+` or `-` after an axis for one direction of it. Touch buttons press their
+//! action's first key.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use crate::input;
+
+pub const FILE_NAME: &str = "controls.json";
+/// How far an axis or analog button must move for its bindings to be down.
+pub const GAMEPAD_THRESHOLD: f32 = 0.5;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ControlsFile {
+    #[serde(default)]
+    actions: BTreeMap<String, ActionDef>,
+    #[serde(default)]
+    touch: Vec<Vec<TouchButton>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ActionDef {
+    #[serde(default)]
+    keys: Vec<KeyDef>,
+    #[serde(default)]
+    gamepad: Vec<String>,
+    #[serde(default)]
+    pointer: Vec<i8>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyDef {
+    Code(u8),
+    Name(String),
+}
+
+/// A button of the on-screen touch controls.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TouchButton {
+    pub label: String,
+    pub action: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Binding {
+    Key(String),
+    // `direction` is 1 or -1 for one side of an axis, 0 for a button
+    Gamepad { control: String, direction: f32 },
+    Pointer(i8),
+}
+
+/// The actions a game defines and what they're bound to.
+#[derive(Debug, Default)]
+pub struct Controls {
+    bindings: Vec<(Binding, String)>,
+    // First key bound to each action, which its touch buttons press
+    first_keys: HashMap<String, String>,
+    pub touch: Vec<Vec<TouchButton>>,
+}
+
+impl Controls {
+    /// The controls.json in `dir`, or no actions if there is none.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(dir.join(FILE_NAME)) {
+            Ok(text) => Self::parse(&text).map_err(|e| anyhow::anyhow!("{}: {}", FILE_NAME, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(anyhow::anyhow!("{}: {}", FILE_NAME, e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let file: ControlsFile = serde_json::from_str(text)?;
+        let mut controls = Controls::default();
+        for (action, def) in file.actions {
+            for key in def.keys {
+                // Codes are matched by name, which they have when sent as codes too
+                let name = match key {
+                    KeyDef::Code(code) => input::key_name(code).ok_or_else(|| {
+                        anyhow::anyhow!("{}: key code {} has no name", action, code)
+                    })?,
+                    KeyDef::Name(name)
+                        if !name.is_empty()
+                            && name.len() <= input::MAX_KEY_NAME
+                            && name.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+                    {
+                        name
+                    }
+                    KeyDef::Name(name) => anyhow::bail!("{}: bad key name '{}'", action, name),
+                };
+                controls
+                    .first_keys
+                    .entry(action.clone())
+                    .or_insert_with(|| name.clone());
+                controls.bindings.push((Binding::Key(name), action.clone()));
+            }
+            for control in def.gamepad {
+                let (control, direction) = match control.as_bytes().last() {
+                    Some(b'+') => (&control[..control.len() - 1], 1.0),
+                    Some(b'-') => (&control[..control.len() - 1], -1.0),
+                    _ => (&control[..], 0.0),
+                };
+                let binding = Binding::Gamepad {
+                    control: control.to_string(),
+                    direction,
+                };
+                controls.bindings.push((binding, action.clone()));
+            }
+            for button in def.pointer {
+                controls
+                    .bindings
+                    .push((Binding::Pointer(button), action.clone()));
+            }
+        }
+        for button in file.touch.iter().flatten() {
+            if !controls.first_keys.contains_key(&button.action) {
+                anyhow::bail!(
+                    "touch button '{}': action '{}' has no keys to press",
+                    button.label,
+                    button.action
+                );
+            }
+        }
+        controls.touch = file.touch;
+        Ok(controls)
+    }
+
+    /// The key a touch button for `action` presses.
+    pub fn touch_key(&self, action: &str) -> Option<&str> {
+        self.first_keys.get(action).map(String::as_str)
+    }
+}
+
+/// Which bindings of each action every session holds down.
+#[derive(Default)]
+pub struct Actions {
+    controls: Controls,
+    // Session -> action -> the inputs holding it down, e.g. "key:ArrowUp"
+    held: HashMap<String, HashMap<String, HashSet<String>>>,
+}
+
+impl Actions {
+    pub fn new(controls: Controls) -> Self {
+        Self {
+            controls,
+            held: HashMap::new(),
+        }
+    }
+
+    /// A key going down or up. Returns the actions that were pressed or released.
+    pub fn key(&mut self, session_id: &str, name: &str, down: bool) -> Vec<(String, bool)> {
+        let source = format!("key:{}", name);
+        self.apply(session_id, &source, |binding| match binding {
+            Binding::Key(key) if key == name => Some(down),
+            _ => None,
+        })
+    }
+
+    /// A gamepad control's new value.
+    pub fn gamepad(
+        &mut self,
+        session_id: &str,
+        pad: u8,
+        control: &str,
+        value: f32,
+    ) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        // Each direction of an axis is held separately
+        for direction in [-1.0, 0.0, 1.0] {
+            let source = format!("pad{}:{}:{}", pad, control, direction);
+            let value = if direction == 0.0 {
+                value
+            } else {
+                value * direction
+            };
+            changes.extend(self.apply(session_id, &source, |binding| match binding {
+                Binding::Gamepad {
+                    control: c,
+                    direction: d,
+                } if c == control && *d == direction => Some(value >= GAMEPAD_THRESHOLD),
+                _ => None,
+            }));
+        }
+        changes
+    }
+
+    /// A pointer button going down or up.
+    pub fn pointer(&mut self, session_id: &str, button: i8, down: bool) -> Vec<(String, bool)> {
+        let source = format!("pointer:{}", button);
+        self.apply(session_id, &source, |binding| match binding {
+            Binding::Pointer(b) if *b == button => Some(down),
+            _ => None,
+        })
+    }
+
+    pub fn is_down(&self, session_id: &str, action: &str) -> bool {
+        self.held
+            .get(session_id)
+            .and_then(|actions| actions.get(action))
+            .is_some_and(|sources| !sources.is_empty())
+    }
+
+    pub fn remove_session(&mut self, session_id: &str) {
+        self.held.remove(session_id);
+    }
+
+    // Holds or releases `source` for each action whose binding `matches` says so, and
+    // returns the actions that changed
+    fn apply(
+        &mut self,
+        session_id: &str,
+        source: &str,
+        matches: impl Fn(&Binding) -> Option<bool>,
+    ) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        for (binding, action) in &self.controls.bindings {
+            let Some(down) = matches(binding) else {
+                continue;
+            };
+            let sources = self
+                .held
+                .entry(session_id.to_string())
+                .or_default()
+                .entry(action.clone())
+                .or_default();
+            let was_down = !sources.is_empty();
+            if down {
+                sources.insert(source.to_string());
+            } else {
+                sources.remove(source);
+            }
+            let is_down = !sources.is_empty();
+            if was_down != is_down {
+                changes.push((action.clone(), down));
+            }
+        }
+        changes
+    }
+}
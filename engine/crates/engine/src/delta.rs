@@ -0,0 +1,305 @@
+//! Per-session delta frame encoding.
+//!
+//! Each frame sent to a client is wrapped in a small envelope (before zstd). Once the
+//! client has acknowledged a frame, later frames are sent as a list of copy/insert ops
+//! against it, or as a bare "same as frame N" marker when nothing changed.
+//!
+//! ```text
+//! FULL:  u8 FRAME_FULL,  u32 id, frame bytes
+//! DELTA: u8 FRAME_DELTA, u32 id, u32 base_id, ops...
+//!          op: u8 OP_COPY, u32 offset, u32 len   (bytes from the base frame)
+//!            | u8 OP_INSERT, u32 len, bytes
+//! SAME:  u8 FRAME_SAME,  u32 id, u32 base_id
+//! ACK:   u8 ACK_TAG, u32 id                        (client -> server)
+//! ```
+//!
+//! For clients that stamp their inputs, `FRAME_INPUT_ACK` is set on the kind once the
+//! server has handled one, and a u32 follows the id: the highest input sequence number
+//! handled before the frame was drawn.
+//!
+//! The data channel is unordered and lossy, so the server only deltas against frames
+//! at most `MAX_BASE_AGE` ids old, and the client keeps that many frames around.
+
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use thiserror::Error;
+
+pub const FRAME_FULL: u8 = 0x00;
+pub const FRAME_DELTA: u8 = 0x01;
+pub const FRAME_SAME: u8 = 0x02;
+/// Flag on the frame kind: the id is followed by an input acknowledgement.
+pub const FRAME_INPUT_ACK: u8 = 0x80;
+
+pub const OP_COPY: u8 = 0x01;
+pub const OP_INSERT: u8 = 0x02;
+
+pub const ACK_TAG: u8 = 0xAC;
+pub const ACK_LEN: usize = 5;
+
+/// Oldest base frame, in ids behind the frame being sent, that a delta may refer to.
+pub const MAX_BASE_AGE: u32 = 32;
+
+/// Matches shorter than this are sent as literals; a copy op costs 9 bytes.
+const BLOCK: usize = 16;
+
+pub fn encode_ack(id: u32) -> [u8; ACK_LEN] {
+    let b = id.to_le_bytes();
+    [ACK_TAG, b[0], b[1], b[2], b[3]]
+}
+
+pub fn decode_ack(data: &[u8]) -> Option<u32> {
+    match data {
+        [ACK_TAG, a, b, c, d] => Some(u32::from_le_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+/// Server side: wraps each `draw()` output for one session.
+#[derive(Default)]
+pub struct FrameEncoder {
+    next_id: u32,
+    /// Frames sent but not yet acknowledged, oldest first.
+    sent: VecDeque<(u32, Bytes)>,
+    /// Newest acknowledged frame.
+    base: Option<(u32, Bytes)>,
+    /// Highest input sequence number handled, sent with every frame once known.
+    input_ack: Option<u32>,
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn encode(&mut self, frame: Bytes) -> Vec<u8> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let base = self
+            .base
+            .as_ref()
+            .filter(|(base_id, _)| id.wrapping_sub(*base_id) <= MAX_BASE_AGE);
+
+        let mut out = Vec::with_capacity(frame.len() + 13);
+        let header = |out: &mut Vec<u8>, kind: u8| match self.input_ack {
+            Some(seq) => {
+                out.push(kind | FRAME_INPUT_ACK);
+                out.extend_from_slice(&id.to_le_bytes());
+                out.extend_from_slice(&seq.to_le_bytes());
+            }
+            None => {
+                out.push(kind);
+                out.extend_from_slice(&id.to_le_bytes());
+            }
+        };
+        match base {
+            Some((base_id, base)) if *base == frame => {
+                header(&mut out, FRAME_SAME);
+                out.extend_from_slice(&base_id.to_le_bytes());
+            }
+            Some((base_id, base)) => {
+                let ops = diff(base, &frame);
+                if ops.len() + 4 < frame.len() {
+                    header(&mut out, FRAME_DELTA);
+                    out.extend_from_slice(&base_id.to_le_bytes());
+                    out.extend_from_slice(&ops);
+                } else {
+                    header(&mut out, FRAME_FULL);
+                    out.extend_from_slice(&frame);
+                }
+            }
+            None => {
+                header(&mut out, FRAME_FULL);
+                out.extend_from_slice(&frame);
+            }
+        }
+
+        self.sent.push_back((id, frame));
+        while self.sent.len() > MAX_BASE_AGE as usize {
+            self.sent.pop_front();
+        }
+        out
+    }
+
+    /// Records that the client has reconstructed frame `id`. Unknown or stale ids
+    /// are ignored.
+    pub fn ack(&mut self, id: u32) {
+        let Some(pos) = self.sent.iter().position(|(sent_id, _)| *sent_id == id) else {
+            return;
+        };
+        // Everything sent before it is older than the new base.
+        self.base = self.sent.drain(..=pos).last();
+    }
+
+    /// Acknowledges input `seq` in this and every later frame. Lower numbers, from
+    /// inputs that arrived out of order, are ignored.
+    pub fn ack_input(&mut self, seq: u32) {
+        self.input_ack = Some(self.input_ack.map_or(seq, |acked| acked.max(seq)));
+    }
+}
+
+/// Copy/insert ops that turn `base` into `target`.
+fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    // Index the base at block boundaries; the scan below tries every target offset,
+    // so shifted content is still found.
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        index.entry(&base[start..start + BLOCK]).or_insert(start);
+    }
+
+    let mut ops = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + BLOCK <= target.len() {
+        let Some(&found) = index.get(&target[i..i + BLOCK]) else {
+            i += 1;
+            continue;
+        };
+
+        // Grow the match in both directions.
+        let mut src = found;
+        let mut dst = i;
+        while dst > literal_start && src > 0 && base[src - 1] == target[dst - 1] {
+            src -= 1;
+            dst -= 1;
+        }
+        let mut end = i + BLOCK;
+        let mut src_end = found + BLOCK;
+        while end < target.len() && src_end < base.len() && base[src_end] == target[end] {
+            end += 1;
+            src_end += 1;
+        }
+
+        put_insert(&mut ops, &target[literal_start..dst]);
+        ops.push(OP_COPY);
+        ops.extend_from_slice(&(src as u32).to_le_bytes());
+        ops.extend_from_slice(&((end - dst) as u32).to_le_bytes());
+        i = end;
+        literal_start = end;
+    }
+    put_insert(&mut ops, &target[literal_start..]);
+    ops
+}
+
+fn put_insert(ops: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    ops.push(OP_INSERT);
+    ops.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    ops.extend_from_slice(bytes);
+}
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum DeltaError {
+    #[error("empty frame")]
+    Empty,
+    #[error("unknown frame kind 0x{0:02x}")]
+    UnknownKind(u8),
+    #[error("truncated frame")]
+    Truncated,
+    #[error("base frame {0} is no longer available")]
+    MissingBase(u32),
+    #[error("copy op out of range of base frame {0}")]
+    BadCopy(u32),
+    #[error("unknown delta op 0x{0:02x}")]
+    UnknownOp(u8),
+}
+
+/// Client side: rebuilds full frames from what `FrameEncoder` sent.
+#[derive(Default)]
+pub struct FrameDecoder {
+    /// Recently reconstructed frames, newest last.
+    recent: VecDeque<(u32, Vec<u8>)>,
+    /// Highest input sequence number the server has acknowledged.
+    input_ack: Option<u32>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the frame id, to be acknowledged, and the full command stream.
+    pub fn decode(&mut self, data: &[u8]) -> Result<(u32, &[u8]), DeltaError> {
+        let (&kind, rest) = data.split_first().ok_or(DeltaError::Empty)?;
+        let (id, rest) = split_u32(rest)?;
+        let (kind, rest) = if kind & FRAME_INPUT_ACK != 0 {
+            let (seq, rest) = split_u32(rest)?;
+            self.input_ack = Some(self.input_ack.map_or(seq, |acked| acked.max(seq)));
+            (kind & !FRAME_INPUT_ACK, rest)
+        } else {
+            (kind, rest)
+        };
+
+        let frame = match kind {
+            FRAME_FULL => rest.to_vec(),
+            FRAME_SAME => {
+                let (base_id, _) = split_u32(rest)?;
+                self.base(base_id)?.to_vec()
+            }
+            FRAME_DELTA => {
+                let (base_id, ops) = split_u32(rest)?;
+                apply(self.base(base_id)?, base_id, ops)?
+            }
+            other => return Err(DeltaError::UnknownKind(other)),
+        };
+
+        // Frames can arrive out of order; keep everything the server may still use.
+        let newest = self.recent.iter().fold(id, |newest, (n, _)| newest.max(*n));
+        self.recent
+            .retain(|(kept, _)| newest.wrapping_sub(*kept) <= MAX_BASE_AGE && *kept != id);
+        self.recent.push_back((id, frame));
+        Ok((id, &self.recent.back().unwrap().1))
+    }
+
+    /// Highest input sequence number the server had handled in the frames so far.
+    pub fn input_ack(&self) -> Option<u32> {
+        self.input_ack
+    }
+
+    fn base(&self, id: u32) -> Result<&[u8], DeltaError> {
+        self.recent
+            .iter()
+            .find(|(kept, _)| *kept == id)
+            .map(|(_, frame)| frame.as_slice())
+            .ok_or(DeltaError::MissingBase(id))
+    }
+}
+
+fn split_u32(data: &[u8]) -> Result<(u32, &[u8]), DeltaError> {
+    if data.len() < 4 {
+        return Err(DeltaError::Truncated);
+    }
+    let (head, rest) = data.split_at(4);
+    Ok((
+        u32::from_le_bytes([head[0], head[1], head[2], head[3]]),
+        rest,
+    ))
+}
+
+fn apply(base: &[u8], base_id: u32, mut ops: &[u8]) -> Result<Vec<u8>, DeltaError> {
+    let mut out = Vec::with_capacity(base.len());
+    while let Some((&op, rest)) = ops.split_first() {
+        match op {
+            OP_COPY => {
+                let (offset, rest) = split_u32(rest)?;
+                let (len, rest) = split_u32(rest)?;
+                let range = (offset as usize)..(offset as usize).saturating_add(len as usize);
+                out.extend_from_slice(base.get(range).ok_or(DeltaError::BadCopy(base_id))?);
+                ops = rest;
+            }
+            OP_INSERT => {
+                let (len, rest) = split_u32(rest)?;
+                if rest.len() < len as usize {
+                    return Err(DeltaError::Truncated);
+                }
+                let (bytes, rest) = rest.split_at(len as usize);
+                out.extend_from_slice(bytes);
+                ops = rest;
+            }
+            other => return Err(DeltaError::UnknownOp(other)),
+        }
+    }
+    Ok(out)
+}
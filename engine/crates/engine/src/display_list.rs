@@ -0,0 +1,135 @@
+use bytes::{Bytes, BytesMut};
+use std::collections::HashMap;
+
+use crate::protocol::{Decoder, DrawCommand, MAX_LIST_DEPTH};
+
+struct List {
+    version: u64,
+    commands: Bytes,
+}
+
+/// Display lists recorded by the script, and which version of each list every
+/// session has been sent.
+#[derive(Default)]
+pub struct DisplayLists {
+    lists: HashMap<String, List>,
+    next_version: u64,
+    sent: HashMap<String, HashMap<String, u64>>,
+}
+
+impl DisplayLists {
+    /// Stores a list. Re-recording identical commands keeps the current version, so
+    /// scripts can rebuild their lists every frame without causing re-sends.
+    pub fn define(&mut self, name: &str, commands: Bytes) {
+        if let Some(list) = self.lists.get(name) {
+            if list.commands == commands {
+                return;
+            }
+        }
+        self.next_version += 1;
+        self.lists.insert(
+            name.to_string(),
+            List {
+                version: self.next_version,
+                commands,
+            },
+        );
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.lists.contains_key(name)
+    }
+
+    /// `DefineList` commands for every list `session_id` lacks or has an old copy of,
+    /// which are then considered sent.
+    pub fn take_updates(&mut self, session_id: &str) -> Bytes {
+        let sent = self.sent.entry(session_id.to_string()).or_default();
+        let mut out = BytesMut::new();
+        for (name, list) in &self.lists {
+            if sent.get(name) != Some(&list.version) {
+                DrawCommand::DefineList {
+                    name: name.clone(),
+                    commands: list.commands.to_vec(),
+                }
+                .encode(&mut out);
+                sent.insert(name.clone(), list.version);
+            }
+        }
+        out.freeze()
+    }
+
+    /// Forgets what `session_id` was sent, so every list goes out again.
+    pub fn reset_session(&mut self, session_id: &str) {
+        self.sent.remove(session_id);
+    }
+
+    /// Replaces each `CallList` in `frame` with the list's commands, wrapped in the
+    /// push/transform/pop a renderer would apply, for clients that don't keep lists.
+    pub fn inline(&self, frame: &[u8]) -> Bytes {
+        let mut out = BytesMut::with_capacity(frame.len());
+        self.inline_into(frame, 0, &mut out);
+        out.freeze()
+    }
+
+    fn inline_into(&self, commands: &[u8], level: usize, out: &mut BytesMut) {
+        // Pushes made by this stream, so a list can't pop its caller's state
+        let mut depth = 0;
+        for cmd in Decoder::new(commands) {
+            let Ok(cmd) = cmd else { break };
+            match cmd {
+                DrawCommand::CallList {
+                    name,
+                    x,
+                    y,
+                    rotation,
+                    sx,
+                    sy,
+                } => {
+                    let Some(list) = self.lists.get(&name) else {
+                        continue;
+                    };
+                    if level >= MAX_LIST_DEPTH {
+                        continue;
+                    }
+                    DrawCommand::Push.encode(out);
+                    DrawCommand::Translate { x, y }.encode(out);
+                    DrawCommand::Rotate { angle: rotation }.encode(out);
+                    DrawCommand::Scale { x: sx, y: sy }.encode(out);
+                    self.inline_into(&list.commands, level + 1, out);
+                    DrawCommand::Pop.encode(out);
+                }
+                DrawCommand::DefineList { .. } => {}
+                DrawCommand::Push => {
+                    depth += 1;
+                    cmd.encode(out);
+                }
+                DrawCommand::Pop => {
+                    if depth > 0 {
+                        depth -= 1;
+                        cmd.encode(out);
+                    }
+                }
+                cmd => cmd.encode(out),
+            }
+        }
+        // Renderers unwind a frame's own pushes at its end
+        if level > 0 {
+            for _ in 0..depth {
+                DrawCommand::Pop.encode(out);
+            }
+        }
+    }
+
+    /// `DefineList` commands for all lists, for renderers without per-session state.
+    pub fn all_definitions(&self) -> Bytes {
+        let mut out = BytesMut::new();
+        for (name, list) in &self.lists {
+            DrawCommand::DefineList {
+                name: name.clone(),
+                commands: list.commands.to_vec(),
+            }
+            .encode(&mut out);
+        }
+        out.freeze()
+    }
+}
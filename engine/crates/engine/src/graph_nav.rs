@@ -1,6 +1,8 @@
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
 
+use serde::{Deserialize, Serialize};
+
 // --- Estruturas para A* ---
 
 #[derive(Copy, Clone, PartialEq)]
@@ -28,12 +30,15 @@ impl PartialOrd for State {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 struct Node {
     x: f32,
     y: f32,
     edges: Vec<u64>, // IDs dos vizinhos
 }
 
+// Serialized as is for carrying it across a hot reload
+#[derive(Serialize, Deserialize)]
 pub struct Graph {
     nodes: HashMap<u64, Node>,
 }
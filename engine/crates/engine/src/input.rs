@@ -0,0 +1,401 @@
+//! Input packets, sent by the client as binary messages over the data channel (or the
+//! WebSocket before it opens).
+//!
+//! ```text
+//! KEY:     u8 key code, u8 down                                  (2 bytes)
+//! NAMED:   u8 KEY_TAG, u8 flags, key name                         (3 to 34 bytes)
+//! POINTER: u8 POINTER_TAG, u8 kind, i8 button, f32 x, f32 y      (11 bytes)
+//! GAMEPAD: u8 GAMEPAD_TAG, u8 pad, u8 control, f32 value          (7 bytes)
+//! STAMPED: u8 STAMP_TAG, u32 seq, u32 frame, one of the above
+//! ```
+//!
+//! Two-byte packets are legacy key codes, and longer ones are told apart by their first
+//! byte; frame acknowledgements (`delta::ACK_TAG`, 5 bytes) share the same channels.
+//!
+//! Named keys carry the browser's `KeyboardEvent.code` (`"ArrowLeft"`, `"KeyZ"`), which
+//! names the physical key whatever the keyboard layout, and the modifiers held with it.
+//! Clients send them once the server has agreed to `protocol::CAP_NAMED_KEYS`. Flags
+//! are `KEY_DOWN` and the `KEY_SHIFT` to `KEY_META` modifier bits.
+//!
+//! Pointer positions are in the game's virtual coordinates: the client undoes device
+//! pixel ratio and scaling, so `(0, 0)` is the top left of what the game draws. Buttons
+//! are 1 (primary), 2 (middle) and 3 (secondary), and 0 for moves. For wheel events the
+//! button is the number of notches scrolled, positive for scrolling down.
+//!
+//! Clients that agreed to `protocol::CAP_INPUT_SEQ` stamp each input with a sequence
+//! number, counting from 1 for the session, and the `delta` id of the newest frame
+//! they had shown, or `NO_FRAME`.
+//!
+//! Gamepad packets carry one control's new value: pads are numbered from 1, and
+//! controls below `GAMEPAD_BUTTON` are axes (-1 to 1) while the rest are buttons (0 to
+//! 1, fractional for analog triggers), both indexed as in the browser's standard
+//! gamepad mapping.
+
+pub const POINTER_TAG: u8 = 0xB0;
+pub const KEY_LEN: usize = 2;
+pub const KEY_TAG: u8 = 0xB2;
+/// Longest key name a packet may carry.
+pub const MAX_KEY_NAME: usize = 32;
+/// Named key flags.
+pub const KEY_DOWN: u8 = 0x01;
+pub const KEY_SHIFT: u8 = 0x02;
+pub const KEY_CTRL: u8 = 0x04;
+pub const KEY_ALT: u8 = 0x08;
+pub const KEY_META: u8 = 0x10;
+pub const POINTER_LEN: usize = 11;
+pub const GAMEPAD_TAG: u8 = 0xB1;
+pub const GAMEPAD_LEN: usize = 7;
+pub const STAMP_TAG: u8 = 0xB3;
+pub const STAMP_LEN: usize = 9;
+/// A stamp's frame before the client has shown one.
+pub const NO_FRAME: u32 = u32::MAX;
+/// Gamepad control of button 0; lower controls are axes.
+pub const GAMEPAD_BUTTON: u8 = 0x40;
+
+/// Names of the standard mapping's axes, as Lua sees them.
+pub const GAMEPAD_AXES: [&str; 4] = ["left_x", "left_y", "right_x", "right_y"];
+/// Names of the standard mapping's buttons.
+pub const GAMEPAD_BUTTONS: [&str; 17] = [
+    "a",
+    "b",
+    "x",
+    "y",
+    "left_bumper",
+    "right_bumper",
+    "left_trigger",
+    "right_trigger",
+    "back",
+    "start",
+    "left_stick",
+    "right_stick",
+    "dpad_up",
+    "dpad_down",
+    "dpad_left",
+    "dpad_right",
+    "home",
+];
+
+/// Lua's name for a gamepad control. Controls outside the standard mapping are
+/// `axis_<n>` and `button_<n>`.
+pub fn gamepad_control_name(control: u8) -> String {
+    if control < GAMEPAD_BUTTON {
+        match GAMEPAD_AXES.get(control as usize) {
+            Some(name) => name.to_string(),
+            None => format!("axis_{}", control),
+        }
+    } else {
+        let button = control - GAMEPAD_BUTTON;
+        match GAMEPAD_BUTTONS.get(button as usize) {
+            Some(name) => name.to_string(),
+            None => format!("button_{}", button),
+        }
+    }
+}
+
+// Legacy `KeyboardEvent.keyCode`s of the named keys that aren't letters, digits,
+// numpad digits or function keys, as US layouts report them. Where keys share a code
+// the first is the one old codes turn into.
+const KEY_CODES: [(&str, u8); 47] = [
+    ("Backspace", 8),
+    ("Tab", 9),
+    ("Enter", 13),
+    ("NumpadEnter", 13),
+    ("ShiftLeft", 16),
+    ("ShiftRight", 16),
+    ("ControlLeft", 17),
+    ("ControlRight", 17),
+    ("AltLeft", 18),
+    ("AltRight", 18),
+    ("Pause", 19),
+    ("CapsLock", 20),
+    ("Escape", 27),
+    ("Space", 32),
+    ("PageUp", 33),
+    ("PageDown", 34),
+    ("End", 35),
+    ("Home", 36),
+    ("ArrowLeft", 37),
+    ("ArrowUp", 38),
+    ("ArrowRight", 39),
+    ("ArrowDown", 40),
+    ("Insert", 45),
+    ("Delete", 46),
+    ("MetaLeft", 91),
+    ("MetaRight", 92),
+    ("ContextMenu", 93),
+    ("NumpadMultiply", 106),
+    ("NumpadAdd", 107),
+    ("NumpadSubtract", 109),
+    ("NumpadDecimal", 110),
+    ("NumpadDivide", 111),
+    ("NumLock", 144),
+    ("ScrollLock", 145),
+    ("Semicolon", 186),
+    ("Equal", 187),
+    ("Comma", 188),
+    ("Minus", 189),
+    ("Period", 190),
+    ("Slash", 191),
+    ("Backquote", 192),
+    ("BracketLeft", 219),
+    ("Backslash", 220),
+    ("BracketRight", 221),
+    ("Quote", 222),
+    ("IntlBackslash", 226),
+    ("IntlRo", 193),
+];
+
+/// The legacy key code scripts written for numeric keys expect for a key name, if the
+/// key has one.
+pub fn legacy_key_code(name: &str) -> Option<u8> {
+    let single = |rest: &str| match rest.as_bytes() {
+        [c] => Some(*c),
+        _ => None,
+    };
+    if let Some(c) = name.strip_prefix("Key").and_then(single) {
+        return c.is_ascii_uppercase().then_some(c);
+    }
+    if let Some(c) = name.strip_prefix("Digit").and_then(single) {
+        return c.is_ascii_digit().then_some(c);
+    }
+    if let Some(c) = name.strip_prefix("Numpad").and_then(single) {
+        return c.is_ascii_digit().then(|| c - b'0' + 96);
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        return (1..=12).contains(&n).then(|| n - 1 + 112);
+    }
+    KEY_CODES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|&(_, code)| code)
+}
+
+/// The key name for a legacy key code, as games reading names see keys from older
+/// clients and touch buttons.
+pub fn key_name(code: u8) -> Option<String> {
+    match code {
+        b'A'..=b'Z' => Some(format!("Key{}", code as char)),
+        b'0'..=b'9' => Some(format!("Digit{}", code as char)),
+        96..=105 => Some(format!("Numpad{}", code - 96)),
+        112..=123 => Some(format!("F{}", code - 112 + 1)),
+        _ => KEY_CODES
+            .iter()
+            .find(|&&(_, c)| c == code)
+            .map(|(key, _)| key.to_string()),
+    }
+}
+
+/// Modifier keys held when a named key changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// The modifier bits of a named key's flags.
+    pub fn as_u8(self) -> u8 {
+        let bit = |held: bool, flag: u8| if held { flag } else { 0 };
+        bit(self.shift, KEY_SHIFT)
+            | bit(self.ctrl, KEY_CTRL)
+            | bit(self.alt, KEY_ALT)
+            | bit(self.meta, KEY_META)
+    }
+
+    pub fn from_u8(flags: u8) -> Self {
+        Self {
+            shift: flags & KEY_SHIFT != 0,
+            ctrl: flags & KEY_CTRL != 0,
+            alt: flags & KEY_ALT != 0,
+            meta: flags & KEY_META != 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerKind {
+    Move,
+    Down,
+    Up,
+    Wheel,
+}
+
+impl PointerKind {
+    pub const fn as_u8(self) -> u8 {
+        match self {
+            PointerKind::Move => 0,
+            PointerKind::Down => 1,
+            PointerKind::Up => 2,
+            PointerKind::Wheel => 3,
+        }
+    }
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(PointerKind::Move),
+            1 => Some(PointerKind::Down),
+            2 => Some(PointerKind::Up),
+            3 => Some(PointerKind::Wheel),
+            _ => None,
+        }
+    }
+
+    /// The `state` argument of Lua's `on_pointer`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PointerKind::Move => "move",
+            PointerKind::Down => "down",
+            PointerKind::Up => "up",
+            PointerKind::Wheel => "wheel",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Input {
+    Key {
+        code: u8,
+        down: bool,
+    },
+    NamedKey {
+        name: String,
+        down: bool,
+        mods: Modifiers,
+    },
+    Pointer {
+        kind: PointerKind,
+        button: i8,
+        x: f32,
+        y: f32,
+    },
+    Gamepad {
+        pad: u8,
+        control: u8,
+        value: f32,
+    },
+}
+
+impl Input {
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Input::Key { code, down } => vec![code, down as u8],
+            Input::NamedKey {
+                ref name,
+                down,
+                mods,
+            } => {
+                let mut buf = Vec::with_capacity(2 + name.len());
+                buf.push(KEY_TAG);
+                buf.push(mods.as_u8() | if down { KEY_DOWN } else { 0 });
+                buf.extend_from_slice(name.as_bytes());
+                buf
+            }
+            Input::Pointer { kind, button, x, y } => {
+                let mut buf = Vec::with_capacity(POINTER_LEN);
+                buf.push(POINTER_TAG);
+                buf.push(kind.as_u8());
+                buf.push(button as u8);
+                buf.extend_from_slice(&x.to_le_bytes());
+                buf.extend_from_slice(&y.to_le_bytes());
+                buf
+            }
+            Input::Gamepad {
+                pad,
+                control,
+                value,
+            } => {
+                let mut buf = Vec::with_capacity(GAMEPAD_LEN);
+                buf.push(GAMEPAD_TAG);
+                buf.push(pad);
+                buf.push(control);
+                buf.extend_from_slice(&value.to_le_bytes());
+                buf
+            }
+        }
+    }
+
+    /// `None` for anything that isn't a valid input packet, acknowledgements included.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() == KEY_LEN {
+            return Some(Input::Key {
+                code: data[0],
+                down: data[1] != 0,
+            });
+        }
+        match (data.first()?, data.len()) {
+            (&KEY_TAG, 3..) => {
+                // Key names are plain ASCII, like "ArrowLeft" and "Digit1"
+                let name = &data[2..];
+                if name.len() > MAX_KEY_NAME || !name.iter().all(u8::is_ascii_alphanumeric) {
+                    return None;
+                }
+                Some(Input::NamedKey {
+                    name: String::from_utf8(name.to_vec()).ok()?,
+                    down: data[1] & KEY_DOWN != 0,
+                    mods: Modifiers::from_u8(data[1]),
+                })
+            }
+            (&POINTER_TAG, POINTER_LEN) => {
+                let x = f32::from_le_bytes(data[3..7].try_into().unwrap());
+                let y = f32::from_le_bytes(data[7..11].try_into().unwrap());
+                if !x.is_finite() || !y.is_finite() {
+                    return None;
+                }
+                Some(Input::Pointer {
+                    kind: PointerKind::from_u8(data[1])?,
+                    button: data[2] as i8,
+                    x,
+                    y,
+                })
+            }
+            (&GAMEPAD_TAG, GAMEPAD_LEN) => {
+                let value = f32::from_le_bytes(data[3..7].try_into().unwrap());
+                if !value.is_finite() || data[1] == 0 {
+                    return None;
+                }
+                Some(Input::Gamepad {
+                    pad: data[1],
+                    control: data[2],
+                    value: value.clamp(-1.0, 1.0),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// When an input happened, relative to the client's other inputs and the frames it
+/// has shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    pub seq: u32,
+    pub frame: u32,
+}
+
+impl Stamp {
+    /// `packet`, an encoded input, with the stamp in front.
+    pub fn wrap(&self, packet: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(STAMP_LEN + packet.len());
+        buf.push(STAMP_TAG);
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.extend_from_slice(&self.frame.to_le_bytes());
+        buf.extend_from_slice(packet);
+        buf
+    }
+
+    /// The stamp of a stamped packet, if it is one, and the input packet inside.
+    pub fn split(data: &[u8]) -> (Option<Stamp>, &[u8]) {
+        match data {
+            [STAMP_TAG, ..] if data.len() > STAMP_LEN => {
+                let stamp = Stamp {
+                    seq: u32::from_le_bytes(data[1..5].try_into().unwrap()),
+                    frame: u32::from_le_bytes(data[5..9].try_into().unwrap()),
+                };
+                (Some(stamp), &data[STAMP_LEN..])
+            }
+            _ => (None, data),
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 #[cfg(feature = "lua")]
 use mlua::{AnyUserData, Function, Lua, LuaOptions, LuaSerdeExt, StdLib, UserData};
 use serde_json::Value;
+#[cfg(feature = "lua")]
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 mod spatial_db;
@@ -10,21 +12,37 @@ mod physics;
 use physics::PhysicsWorld;
 mod graph_nav;
 use graph_nav::Graph;
+#[cfg(feature = "lua")]
+mod display_list;
+#[cfg(feature = "lua")]
+use display_list::DisplayLists;
 pub mod transformer;
-
-// OpCodes
-const OP_CLEAR: u8 = 0x01;
-const OP_SET_COLOR: u8 = 0x02;
-const OP_FILL_RECT: u8 = 0x03;
-const OP_DRAW_LINE: u8 = 0x04;
-const OP_DRAW_TEXT: u8 = 0x05;
-const OP_LOAD_SOUND: u8 = 0x06;
-const OP_PLAY_SOUND: u8 = 0x07;
-const OP_STOP_SOUND: u8 = 0x08;
-const OP_SET_VOLUME: u8 = 0x09;
-const OP_LOAD_IMAGE: u8 = 0x0A;
-const OP_DRAW_IMAGE: u8 = 0x0B;
-const OP_FILL_POLY: u8 = 0x0C;
+pub mod protocol;
+pub mod delta;
+pub mod input;
+#[cfg(feature = "lua")]
+pub mod actions;
+#[cfg(feature = "lua")]
+use actions::{Actions, Controls};
+#[cfg(feature = "lua")]
+use input::{Input, Modifiers, PointerKind};
+use protocol::{DrawCommand, Encoding};
+#[cfg(feature = "lua")]
+use protocol::{LineCap, LineJoin, ScaleMode, TextAlign, TextBaseline};
+#[cfg(feature = "lua")]
+pub mod text;
+#[cfg(feature = "lua")]
+use text::{TextLayout, TextState};
+#[cfg(feature = "lua")]
+pub mod rooms;
+#[cfg(feature = "lua")]
+use rooms::Room;
+#[cfg(feature = "lua")]
+mod saved_state;
+#[cfg(feature = "lua")]
+mod screen;
+#[cfg(feature = "lua")]
+use screen::Screen;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum GameMode {
@@ -210,13 +228,38 @@ impl UserData for GraphWrapper {
 #[derive(Clone)]
 pub struct CommandBuffer {
     data: Arc<Mutex<BytesMut>>,
+    // Display list being recorded; while set, pushes go here instead of `data`
+    recording: Arc<Mutex<Option<(String, BytesMut)>>>,
+    encoding: Encoding,
 }
 
 impl CommandBuffer {
     pub fn new() -> Self {
+        Self::with_encoding(Encoding::Float)
+    }
+
+    pub fn with_encoding(encoding: Encoding) -> Self {
         Self {
             data: Arc::new(Mutex::new(BytesMut::with_capacity(1024))),
+            recording: Arc::new(Mutex::new(None)),
+            encoding,
+        }
+    }
+
+    /// Starts recording into list `name`. Fails with the name of the list already
+    /// being recorded, since lists don't nest.
+    pub fn begin_recording(&self, name: &str) -> Result<(), String> {
+        let mut recording = self.recording.lock().unwrap();
+        if let Some((current, _)) = recording.as_ref() {
+            return Err(current.clone());
         }
+        *recording = Some((name.to_string(), BytesMut::new()));
+        Ok(())
+    }
+
+    pub fn end_recording(&self) -> Option<(String, Bytes)> {
+        let (name, data) = self.recording.lock().unwrap().take()?;
+        Some((name, data.freeze()))
     }
 
     pub fn clear(&self) {
@@ -229,164 +272,87 @@ impl CommandBuffer {
         Bytes::copy_from_slice(&data)
     }
 
-    // --- Primitive Writers ---
-
-    fn cmd_clear_screen(&self, r: u8, g: u8, b: u8) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_CLEAR);
-        data.put_u8(r);
-        data.put_u8(g);
-        data.put_u8(b);
-    }
-
-    fn cmd_set_color(&self, r: u8, g: u8, b: u8, a: u8) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_SET_COLOR);
-        data.put_u8(r);
-        data.put_u8(g);
-        data.put_u8(b);
-        data.put_u8(a);
-    }
-
-    fn cmd_fill_rect(&self, x: f32, y: f32, w: f32, h: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_FILL_RECT);
-        data.put_f32_le(x);
-        data.put_f32_le(y);
-        data.put_f32_le(w);
-        data.put_f32_le(h);
-    }
-
-    fn cmd_draw_line(&self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_DRAW_LINE);
-        data.put_f32_le(x1);
-        data.put_f32_le(y1);
-        data.put_f32_le(x2);
-        data.put_f32_le(y2);
-        data.put_f32_le(width);
-    }
-
-    fn cmd_draw_text(&self, text: &str, x: f32, y: f32) {
+    pub fn push(&self, cmd: &DrawCommand) {
+        if let Some((_, list)) = self.recording.lock().unwrap().as_mut() {
+            cmd.encode_with(list, self.encoding);
+            return;
+        }
         let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_DRAW_TEXT);
-        data.put_f32_le(x);
-        data.put_f32_le(y);
-        let bytes = text.as_bytes();
-        data.put_u16_le(bytes.len() as u16);
-        data.put_slice(bytes);
+        cmd.encode_with(&mut *data, self.encoding);
     }
 
-    fn cmd_load_sound(&self, name: &str, url: &str) {
+    pub fn append(&self, other: &CommandBuffer) {
         let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_LOAD_SOUND);
-
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-
-        let url_bytes = url.as_bytes();
-        data.put_u16_le(url_bytes.len() as u16);
-        data.put_slice(url_bytes);
+        let other_data = other.data.lock().unwrap();
+        data.extend_from_slice(&other_data);
     }
 
-    fn cmd_play_sound(&self, name: &str, loop_sound: bool, volume: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_PLAY_SOUND);
-
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-
-        data.put_u8(if loop_sound { 1 } else { 0 });
-        data.put_f32_le(volume);
-    }
-
-    fn cmd_stop_sound(&self, name: &str) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_STOP_SOUND);
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
+    /// Appends already encoded commands.
+    pub fn extend(&self, commands: &[u8]) {
+        self.data.lock().unwrap().extend_from_slice(commands);
     }
+}
 
-    fn cmd_set_volume(&self, name: &str, volume: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_SET_VOLUME);
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-        data.put_f32_le(volume);
+// api.stroke_poly(points, [width], [join], [cap], [closed])
+#[cfg(feature = "lua")]
+type StrokePolyArgs = (
+    Vec<f32>,
+    Option<f32>,
+    Option<String>,
+    Option<String>,
+    Option<bool>,
+);
+
+// api.call_list(name, [x], [y], [rotation], [sx], [sy])
+#[cfg(feature = "lua")]
+type CallListArgs = (
+    String,
+    Option<f32>,
+    Option<f32>,
+    Option<f32>,
+    Option<f32>,
+    Option<f32>,
+);
+
+// Pairs up a flat [x1, y1, x2, y2, ...] list. Odd-length lists are rejected.
+#[cfg(feature = "lua")]
+fn to_points(flat: &[f32]) -> Option<Vec<(f32, f32)>> {
+    if !flat.len().is_multiple_of(2) {
+        return None;
     }
+    Some(flat.chunks_exact(2).map(|c| (c[0], c[1])).collect())
+}
 
-    fn cmd_load_image(&self, name: &str, url: &str) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_LOAD_IMAGE);
-
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-
-        let url_bytes = url.as_bytes();
-        data.put_u16_le(url_bytes.len() as u16);
-        data.put_slice(url_bytes);
+// The game directory's metadata.json, or Null if it has none
+#[cfg(feature = "lua")]
+fn read_metadata(dir: &std::path::Path) -> anyhow::Result<Value> {
+    match std::fs::read_to_string(dir.join("metadata.json")) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("metadata.json: {}", e)),
+        Err(_) => Ok(Value::Null),
     }
+}
 
-    fn cmd_draw_image(
-        &self,
-        name: &str,
-        x: f32,
-        y: f32,
-        w: Option<f32>,
-        h: Option<f32>,
-        sx: Option<f32>,
-        sy: Option<f32>,
-        sw: Option<f32>,
-        sh: Option<f32>,
-        r: Option<f32>,
-        ox: Option<f32>,
-        oy: Option<f32>,
-    ) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_DRAW_IMAGE);
-
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-
-        // Dest Rect
-        data.put_f32_le(x);
-        data.put_f32_le(y);
-        data.put_f32_le(w.unwrap_or(-1.0)); // -1.0 means use original size
-        data.put_f32_le(h.unwrap_or(-1.0));
-
-        // Source Rect (Sprite Sheet support)
-        data.put_f32_le(sx.unwrap_or(0.0));
-        data.put_f32_le(sy.unwrap_or(0.0));
-        data.put_f32_le(sw.unwrap_or(-1.0)); // -1.0 means full width
-        data.put_f32_le(sh.unwrap_or(-1.0));
-
-        // Rotation & Origin (Optional)
-        data.put_f32_le(r.unwrap_or(0.0));
-        data.put_f32_le(ox.unwrap_or(0.0)); // Origin X relative to Dest X (0.0 = top-left, 0.5 = center of w)
-        data.put_f32_le(oy.unwrap_or(0.0));
-    }
+// The host behind the room functions, which games run without a server don't have
+#[cfg(feature = "lua")]
+fn room_host(host: &Option<Arc<dyn rooms::RoomHost>>) -> mlua::Result<&dyn rooms::RoomHost> {
+    host.as_deref()
+        .ok_or_else(|| mlua::Error::RuntimeError("rooms need a server to host them".into()))
+}
 
-    fn cmd_fill_poly(&self, points: &[(f32, f32)]) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_FILL_POLY);
-        data.put_u16_le(points.len() as u16);
-        for (x, y) in points {
-            data.put_f32_le(*x);
-            data.put_f32_le(*y);
-        }
-    }
+// Each session's gamepad controls that aren't at rest, by pad and name, for
+// `api.get_axis`
+#[cfg(feature = "lua")]
+type Gamepads = HashMap<String, HashMap<(u8, String), f32>>;
 
-    pub fn append(&self, other: &CommandBuffer) {
-        let mut data = self.data.lock().unwrap();
-        let other_data = other.data.lock().unwrap();
-        data.extend_from_slice(&other_data);
-    }
+/// When an input happened, passed to the input callbacks as their last arguments.
+#[cfg(feature = "lua")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputTiming {
+    /// The input's sequence number, from clients that stamp their inputs.
+    pub seq: Option<u32>,
+    /// `api.tick()` of the newest frame the client had shown.
+    pub frame: Option<u64>,
 }
 
 #[cfg(feature = "lua")]
@@ -395,6 +361,22 @@ pub struct GameState {
     command_buffer: CommandBuffer,
     event_buffer: CommandBuffer,
     current_mode: Arc<Mutex<GameMode>>,
+    text: Arc<Mutex<TextLayout>>,
+    lists: Arc<Mutex<DisplayLists>>,
+    screen: Arc<Mutex<Screen>>,
+    gamepads: Arc<Mutex<Gamepads>>,
+    actions: Arc<Mutex<Actions>>,
+    // Updates run so far, for `api.tick`
+    tick: Arc<Mutex<u64>>,
+    // Messages from `api.send`, by session, until the server takes them
+    outbox: Arc<Mutex<Vec<(String, Value)>>>,
+    encoding: Encoding,
+    // metadata.json's `"keys": "names"`: `on_input` gets key names instead of codes
+    key_names: bool,
+    // What `draw_shared()` drew this tick and the text style it left, until the next update
+    shared: Mutex<Option<(Bytes, TextState)>>,
+    // How far frames are between the last update and the next, for `draw`
+    alpha: Mutex<f32>,
 }
 
 #[cfg(feature = "lua")]
@@ -402,6 +384,16 @@ impl GameState {
     pub fn new(
         script_content: &str,
         script_path: Option<&std::path::Path>,
+    ) -> anyhow::Result<Self> {
+        Self::with_room(script_content, script_path, Room::default())
+    }
+
+    /// Loads a game into `room`, whose params `init` gets and whose host backs the
+    /// room functions.
+    pub fn with_room(
+        script_content: &str,
+        script_path: Option<&std::path::Path>,
+        room: Room,
     ) -> anyhow::Result<Self> {
         // SANDBOX SECURITY:
         // 1. Only load safe standard libraries. NO IO, NO OS, NO DEBUG.
@@ -435,9 +427,40 @@ impl GameState {
             package.set("path", path_str)?;
         }
 
-        let command_buffer = CommandBuffer::new();
+        // metadata.json sets the starting resolution (the script may change it), the
+        // command encoding and how `on_input` sees keys
+        let metadata = match script_path.and_then(|p| p.parent()) {
+            Some(dir) => read_metadata(dir)?,
+            None => Value::Null,
+        };
+        let encoding = match metadata.get("encoding").and_then(Value::as_str) {
+            Some(name) => Encoding::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("metadata.json: unknown encoding '{}'", name))?,
+            None => Encoding::Float,
+        };
+        let key_names = match metadata.get("keys").and_then(Value::as_str) {
+            Some("names") => true,
+            Some("codes") | None => false,
+            Some(name) => anyhow::bail!("metadata.json: unknown keys '{}'", name),
+        };
+
+        let command_buffer = CommandBuffer::with_encoding(encoding);
         let event_buffer = CommandBuffer::new();
         let current_mode = Arc::new(Mutex::new(GameMode::Update));
+        let lists = Arc::new(Mutex::new(DisplayLists::default()));
+        let text = Arc::new(Mutex::new(TextLayout::new(
+            script_path.and_then(|p| p.parent()).map(|p| p.to_path_buf()),
+        )));
+        let screen = Arc::new(Mutex::new(Screen::default()));
+        screen.lock().unwrap().apply_metadata(&metadata)?;
+        let gamepads = Arc::new(Mutex::new(Gamepads::new()));
+        let controls = match script_path.and_then(|p| p.parent()) {
+            Some(dir) => Controls::load(dir)?,
+            None => Controls::default(),
+        };
+        let actions = Arc::new(Mutex::new(Actions::new(controls)));
+        let tick = Arc::new(Mutex::new(0));
+        let outbox = Arc::new(Mutex::new(Vec::new()));
 
         // Expose API to Lua
         {
@@ -448,7 +471,7 @@ impl GameState {
             api.set(
                 "clear_screen",
                 lua.create_function(move |_, (r, g, b): (u8, u8, u8)| {
-                    buf_clone.cmd_clear_screen(r, g, b);
+                    buf_clone.push(&DrawCommand::Clear { r, g, b });
                     Ok(())
                 })?,
             )?;
@@ -457,7 +480,12 @@ impl GameState {
             api.set(
                 "set_color",
                 lua.create_function(move |_, (r, g, b, a): (u8, u8, u8, Option<u8>)| {
-                    buf_clone.cmd_set_color(r, g, b, a.unwrap_or(255));
+                    buf_clone.push(&DrawCommand::SetColor {
+                        r,
+                        g,
+                        b,
+                        a: a.unwrap_or(255),
+                    });
                     Ok(())
                 })?,
             )?;
@@ -466,7 +494,7 @@ impl GameState {
             api.set(
                 "fill_rect",
                 lua.create_function(move |_, (x, y, w, h): (f32, f32, f32, f32)| {
-                    buf_clone.cmd_fill_rect(x, y, w, h);
+                    buf_clone.push(&DrawCommand::FillRect { x, y, w, h });
                     Ok(())
                 })?,
             )?;
@@ -476,17 +504,218 @@ impl GameState {
                 "draw_line",
                 lua.create_function(
                     move |_, (x1, y1, x2, y2, w): (f32, f32, f32, f32, Option<f32>)| {
-                        buf_clone.cmd_draw_line(x1, y1, x2, y2, w.unwrap_or(1.0));
+                        buf_clone.push(&DrawCommand::DrawLine {
+                            x1,
+                            y1,
+                            x2,
+                            y2,
+                            width: w.unwrap_or(1.0),
+                        });
                         Ok(())
                     },
                 )?,
             )?;
 
             let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            // api.draw_text(text, x, y, [max_width])
             api.set(
                 "draw_text",
-                lua.create_function(move |_, (text, x, y): (String, f32, f32)| {
-                    buf_clone.cmd_draw_text(&text, x, y);
+                lua.create_function(
+                    move |_, (text, x, y, max_width): (String, f32, f32, Option<f32>)| {
+                        if max_width.is_none() && !text.contains('\n') {
+                            buf_clone.push(&DrawCommand::DrawText { x, y, text });
+                            return Ok(());
+                        }
+                        let layout = text_ref.lock().unwrap();
+                        let lines = layout.wrap(&text, max_width);
+                        let offsets = layout.line_offsets(lines.len());
+                        for (text, dy) in lines.into_iter().zip(offsets) {
+                            buf_clone.push(&DrawCommand::DrawText { x, y: y + dy, text });
+                        }
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let screen_ref = screen.clone();
+            api.set(
+                "set_resolution",
+                lua.create_function(
+                    move |_, (width, height, mode): (u16, u16, Option<String>)| {
+                        let mut screen = screen_ref.lock().unwrap();
+                        let mode = match mode {
+                            Some(name) => ScaleMode::from_name(&name).ok_or_else(|| {
+                                mlua::Error::RuntimeError(format!(
+                                    "unknown scaling mode '{}'",
+                                    name
+                                ))
+                            })?,
+                            None => screen.mode(),
+                        };
+                        screen
+                            .set(width, height, mode)
+                            .map_err(mlua::Error::RuntimeError)
+                    },
+                )?,
+            )?;
+
+            let screen_ref = screen.clone();
+            api.set(
+                "screen_size",
+                lua.create_function(move |_, session_id: String| {
+                    Ok(screen_ref.lock().unwrap().size_for(&session_id))
+                })?,
+            )?;
+
+            let gamepads_ref = gamepads.clone();
+            api.set(
+                "get_axis",
+                lua.create_function(
+                    move |_, (session_id, axis, pad): (String, String, Option<u8>)| {
+                        let gamepads = gamepads_ref.lock().unwrap();
+                        Ok(gamepads
+                            .get(&session_id)
+                            .and_then(|controls| controls.get(&(pad.unwrap_or(1), axis)))
+                            .copied()
+                            .unwrap_or(0.0))
+                    },
+                )?,
+            )?;
+
+            let tick_ref = tick.clone();
+            api.set(
+                "tick",
+                lua.create_function(move |_, ()| Ok(*tick_ref.lock().unwrap()))?,
+            )?;
+
+            let outbox_ref = outbox.clone();
+            api.set(
+                "send",
+                lua.create_function(
+                    move |lua, (session_id, payload): (String, mlua::Value)| {
+                        let payload: Value = lua.from_value(payload)?;
+                        let len = payload.to_string().len();
+                        if len > protocol::MAX_MESSAGE_LEN {
+                            return Err(mlua::Error::RuntimeError(format!(
+                                "message is {} bytes of JSON, over the limit of {}",
+                                len,
+                                protocol::MAX_MESSAGE_LEN
+                            )));
+                        }
+                        outbox_ref.lock().unwrap().push((session_id, payload));
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let room_id = room.id.clone();
+            api.set(
+                "room_id",
+                lua.create_function(move |_, ()| Ok(room_id.clone()))?,
+            )?;
+
+            let host = room.host.clone();
+            api.set(
+                "create_room",
+                lua.create_function(
+                    move |lua, (script, params): (String, Option<mlua::Value>)| {
+                        let params: Value = match params {
+                            Some(params) => lua.from_value(params)?,
+                            None => Value::Null,
+                        };
+                        room_host(&host)?
+                            .create_room(&script, params)
+                            .map_err(|e| mlua::Error::RuntimeError(e.to_string()))
+                    },
+                )?,
+            )?;
+
+            let host = room.host.clone();
+            api.set(
+                "move_session",
+                lua.create_function(move |_, (session_id, room_id): (String, String)| {
+                    room_host(&host)?
+                        .move_session(&session_id, &room_id)
+                        .map_err(|e| mlua::Error::RuntimeError(e.to_string()))
+                })?,
+            )?;
+
+            let host = room.host.clone();
+            api.set(
+                "list_rooms",
+                lua.create_function(move |lua, ()| {
+                    let rooms = room_host(&host)?.list_rooms();
+                    let options = mlua::SerializeOptions::new()
+                        .serialize_none_to_null(false)
+                        .serialize_unit_to_null(false);
+                    lua.to_value_with(&rooms, options)
+                })?,
+            )?;
+
+            let actions_ref = actions.clone();
+            api.set(
+                "is_action_down",
+                lua.create_function(move |_, (session_id, action): (String, String)| {
+                    Ok(actions_ref.lock().unwrap().is_down(&session_id, &action))
+                })?,
+            )?;
+
+            let text_ref = text.clone();
+            api.set(
+                "measure_text",
+                lua.create_function(move |_, (text, max_width): (String, Option<f32>)| {
+                    Ok(text_ref.lock().unwrap().measure(&text, max_width))
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "load_font",
+                lua.create_function(move |_, (name, url): (String, String)| {
+                    text_ref
+                        .lock()
+                        .unwrap()
+                        .load_font(&name, &url)
+                        .map_err(|e| mlua::Error::RuntimeError(e.to_string()))?;
+                    buf_clone.push(&DrawCommand::LoadFont { name, url });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "set_font",
+                lua.create_function(move |_, (name, size): (String, Option<f32>)| {
+                    let size = size.unwrap_or(protocol::DEFAULT_FONT_SIZE);
+                    let mut layout = text_ref.lock().unwrap();
+                    layout.style.font = name.clone();
+                    layout.style.size = size;
+                    buf_clone.push(&DrawCommand::SetFont { name, size });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "set_text_align",
+                lua.create_function(move |_, (align, baseline): (String, Option<String>)| {
+                    let mut layout = text_ref.lock().unwrap();
+                    let align = TextAlign::from_name(&align).ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("unknown text align '{}'", align))
+                    })?;
+                    let baseline = match baseline {
+                        Some(name) => TextBaseline::from_name(&name).ok_or_else(|| {
+                            mlua::Error::RuntimeError(format!("unknown text baseline '{}'", name))
+                        })?,
+                        None => layout.style.baseline,
+                    };
+                    layout.style.align = align;
+                    layout.style.baseline = baseline;
+                    buf_clone.push(&DrawCommand::SetTextAlign { align, baseline });
                     Ok(())
                 })?,
             )?;
@@ -495,7 +724,7 @@ impl GameState {
             api.set(
                 "load_sound",
                 lua.create_function(move |_, (name, url): (String, String)| {
-                    buf_clone.cmd_load_sound(&name, &url);
+                    buf_clone.push(&DrawCommand::LoadSound { name, url });
                     Ok(())
                 })?,
             )?;
@@ -510,12 +739,15 @@ impl GameState {
                 lua.create_function(
                     move |_, (name, loop_val, volume): (String, Option<bool>, Option<f32>)| {
                         let mode = *mode_ref.lock().unwrap();
-                        let vol = volume.unwrap_or(1.0);
-                        let lp = loop_val.unwrap_or(false);
+                        let cmd = DrawCommand::PlaySound {
+                            name,
+                            looped: loop_val.unwrap_or(false),
+                            volume: volume.unwrap_or(1.0),
+                        };
 
                         match mode {
-                            GameMode::Update => event_buf.cmd_play_sound(&name, lp, vol),
-                            GameMode::Draw => cmd_buf.cmd_play_sound(&name, lp, vol),
+                            GameMode::Update => event_buf.push(&cmd),
+                            GameMode::Draw => cmd_buf.push(&cmd),
                         }
                         Ok(())
                     },
@@ -529,9 +761,10 @@ impl GameState {
                 "stop_sound",
                 lua.create_function(move |_, name: String| {
                     let mode = *mode_ref.lock().unwrap();
+                    let cmd = DrawCommand::StopSound { name };
                     match mode {
-                        GameMode::Update => event_buf.cmd_stop_sound(&name),
-                        GameMode::Draw => cmd_buf.cmd_stop_sound(&name),
+                        GameMode::Update => event_buf.push(&cmd),
+                        GameMode::Draw => cmd_buf.push(&cmd),
                     }
                     Ok(())
                 })?,
@@ -544,9 +777,10 @@ impl GameState {
                 "set_volume",
                 lua.create_function(move |_, (name, vol): (String, f32)| {
                     let mode = *mode_ref.lock().unwrap();
+                    let cmd = DrawCommand::SetVolume { name, volume: vol };
                     match mode {
-                        GameMode::Update => event_buf.cmd_set_volume(&name, vol),
-                        GameMode::Draw => cmd_buf.cmd_set_volume(&name, vol),
+                        GameMode::Update => event_buf.push(&cmd),
+                        GameMode::Draw => cmd_buf.push(&cmd),
                     }
                     Ok(())
                 })?,
@@ -556,7 +790,7 @@ impl GameState {
             api.set(
                 "load_image",
                 lua.create_function(move |_, (name, url): (String, String)| {
-                    buf_clone.cmd_load_image(&name, &url);
+                    buf_clone.push(&DrawCommand::LoadImage { name, url });
                     Ok(())
                 })?,
             )?;
@@ -581,7 +815,20 @@ impl GameState {
                         Option<f32>,
                         Option<f32>,
                     )| {
-                        buf_clone.cmd_draw_image(&name, x, y, w, h, sx, sy, sw, sh, r, ox, oy);
+                        buf_clone.push(&DrawCommand::DrawImage {
+                            name,
+                            x,
+                            y,
+                            w: w.unwrap_or(-1.0), // -1.0 means use original size
+                            h: h.unwrap_or(-1.0),
+                            sx: sx.unwrap_or(0.0),
+                            sy: sy.unwrap_or(0.0),
+                            sw: sw.unwrap_or(-1.0), // -1.0 means full width
+                            sh: sh.unwrap_or(-1.0),
+                            rotation: r.unwrap_or(0.0),
+                            ox: ox.unwrap_or(0.0), // Origin relative to Dest (in pixels)
+                            oy: oy.unwrap_or(0.0),
+                        });
                         Ok(())
                     },
                 )?,
@@ -591,13 +838,233 @@ impl GameState {
             api.set(
                 "fill_poly",
                 lua.create_function(move |_, points: Vec<f32>| {
-                    if points.len() % 2 == 0 {
-                        let mut pts = Vec::with_capacity(points.len() / 2);
-                        for i in (0..points.len()).step_by(2) {
-                            pts.push((points[i], points[i + 1]));
+                    if let Some(points) = to_points(&points) {
+                        buf_clone.push(&DrawCommand::FillPoly { points });
+                    }
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "stroke_poly",
+                lua.create_function(
+                    move |_, (points, width, join, cap, closed): StrokePolyArgs| {
+                        let join = match join {
+                            Some(name) => LineJoin::from_name(&name).ok_or_else(|| {
+                                mlua::Error::RuntimeError(format!("unknown line join '{}'", name))
+                            })?,
+                            None => LineJoin::default(),
+                        };
+                        let cap = match cap {
+                            Some(name) => LineCap::from_name(&name).ok_or_else(|| {
+                                mlua::Error::RuntimeError(format!("unknown line cap '{}'", name))
+                            })?,
+                            None => LineCap::default(),
+                        };
+                        if let Some(points) = to_points(&points) {
+                            buf_clone.push(&DrawCommand::StrokePoly {
+                                width: width.unwrap_or(1.0),
+                                join,
+                                cap,
+                                closed: closed.unwrap_or(true),
+                                points,
+                            });
                         }
-                        buf_clone.cmd_fill_poly(&pts);
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "fill_circle",
+                lua.create_function(move |_, (x, y, r): (f32, f32, f32)| {
+                    buf_clone.push(&DrawCommand::FillCircle { x, y, r });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "stroke_circle",
+                lua.create_function(move |_, (x, y, r, w): (f32, f32, f32, Option<f32>)| {
+                    buf_clone.push(&DrawCommand::StrokeCircle {
+                        x,
+                        y,
+                        r,
+                        width: w.unwrap_or(1.0),
+                    });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "stroke_rect",
+                lua.create_function(
+                    move |_, (x, y, w, h, width): (f32, f32, f32, f32, Option<f32>)| {
+                        buf_clone.push(&DrawCommand::StrokeRect {
+                            x,
+                            y,
+                            w,
+                            h,
+                            width: width.unwrap_or(1.0),
+                        });
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            // api.arc(x, y, r, start, end, [width]) -- filled pie slice when width is omitted
+            api.set(
+                "arc",
+                lua.create_function(
+                    move |_, (x, y, r, start, end, width): (f32, f32, f32, f32, f32, Option<f32>)| {
+                        buf_clone.push(&DrawCommand::Arc {
+                            x,
+                            y,
+                            r,
+                            start,
+                            end,
+                            width: width.unwrap_or(0.0),
+                        });
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            // api.ellipse(x, y, rx, ry, [rotation], [width]) -- filled when width is omitted
+            api.set(
+                "ellipse",
+                lua.create_function(
+                    move |_,
+                          (x, y, rx, ry, rotation, width): (
+                        f32,
+                        f32,
+                        f32,
+                        f32,
+                        Option<f32>,
+                        Option<f32>,
+                    )| {
+                        buf_clone.push(&DrawCommand::Ellipse {
+                            x,
+                            y,
+                            rx,
+                            ry,
+                            rotation: rotation.unwrap_or(0.0),
+                            width: width.unwrap_or(0.0),
+                        });
+                        Ok(())
+                    },
+                )?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "push",
+                lua.create_function(move |_, ()| {
+                    text_ref.lock().unwrap().push();
+                    buf_clone.push(&DrawCommand::Push);
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "pop",
+                lua.create_function(move |_, ()| {
+                    text_ref.lock().unwrap().pop();
+                    buf_clone.push(&DrawCommand::Pop);
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "translate",
+                lua.create_function(move |_, (x, y): (f32, f32)| {
+                    buf_clone.push(&DrawCommand::Translate { x, y });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "rotate",
+                lua.create_function(move |_, angle: f32| {
+                    buf_clone.push(&DrawCommand::Rotate { angle });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            api.set(
+                "scale",
+                lua.create_function(move |_, (x, y): (f32, Option<f32>)| {
+                    // A single argument scales uniformly
+                    buf_clone.push(&DrawCommand::Scale { x, y: y.unwrap_or(x) });
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            api.set(
+                "begin_list",
+                lua.create_function(move |_, name: String| {
+                    buf_clone.begin_recording(&name).map_err(|current| {
+                        mlua::Error::RuntimeError(format!(
+                            "begin_list('{}') while recording '{}'",
+                            name, current
+                        ))
+                    })?;
+                    // Replays are wrapped in save/restore, so recording can't change
+                    // the text style of the frame either
+                    text_ref.lock().unwrap().push();
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let text_ref = text.clone();
+            let lists_ref = lists.clone();
+            api.set(
+                "end_list",
+                lua.create_function(move |_, ()| {
+                    let (name, commands) = buf_clone.end_recording().ok_or_else(|| {
+                        mlua::Error::RuntimeError("end_list() without begin_list()".to_string())
+                    })?;
+                    text_ref.lock().unwrap().pop();
+                    lists_ref.lock().unwrap().define(&name, commands);
+                    Ok(())
+                })?,
+            )?;
+
+            let buf_clone = command_buffer.clone();
+            let lists_ref = lists.clone();
+            api.set(
+                "call_list",
+                lua.create_function(move |_, (name, x, y, rotation, sx, sy): CallListArgs| {
+                    if !lists_ref.lock().unwrap().contains(&name) {
+                        return Err(mlua::Error::RuntimeError(format!(
+                            "unknown display list '{}'",
+                            name
+                        )));
                     }
+                    let sx = sx.unwrap_or(1.0);
+                    buf_clone.push(&DrawCommand::CallList {
+                        name,
+                        x: x.unwrap_or(0.0),
+                        y: y.unwrap_or(0.0),
+                        rotation: rotation.unwrap_or(0.0),
+                        sx,
+                        sy: sy.unwrap_or(sx),
+                    });
                     Ok(())
                 })?,
             )?;
@@ -632,9 +1099,13 @@ impl GameState {
             // Load the game script
             lua.load(script_content).exec()?;
 
-            // Call init if exists
+            // Call init if exists, with the room's params
             if let Ok(init) = globals.get::<_, Function>("init") {
-                init.call::<_, ()>(())?;
+                let options = mlua::SerializeOptions::new()
+                    .serialize_none_to_null(false)
+                    .serialize_unit_to_null(false);
+                let params = lua.to_value_with(&room.params, options)?;
+                init.call::<_, ()>(params)?;
             }
         }
 
@@ -643,15 +1114,58 @@ impl GameState {
             command_buffer,
             event_buffer,
             current_mode,
+            text,
+            lists,
+            screen,
+            gamepads,
+            actions,
+            tick,
+            outbox,
+            encoding,
+            key_names,
+            shared: Mutex::new(None),
+            alpha: Mutex::new(0.0),
         })
     }
 
+    /// Updates run so far. Frames drawn after the `n`th update show tick `n`.
+    pub fn tick(&self) -> u64 {
+        *self.tick.lock().unwrap()
+    }
+
+    /// Encoding of the commands `draw()` returns and display lists hold.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
     pub fn begin_frame(&self) {
         self.event_buffer.clear();
     }
 
+    /// Sounds started or stopped by the updates since the last call. `draw` includes
+    /// them until they're taken, so callers drawing sessions at their own pace take
+    /// them and deliver them to each session once.
+    pub fn take_events(&self) -> Bytes {
+        let events = self.event_buffer.get_bytes();
+        self.event_buffer.clear();
+        events
+    }
+
+    /// Sets how far the next frames are from the last update toward the next one,
+    /// from 0 to 1, for games that interpolate in `draw`.
+    pub fn set_alpha(&self, alpha: f32) {
+        let mut current = self.alpha.lock().unwrap();
+        if *current != alpha {
+            *current = alpha;
+            // draw_shared() may interpolate too
+            *self.shared.lock().unwrap() = None;
+        }
+    }
+
     pub fn update(&self, dt: f32) -> anyhow::Result<()> {
         *self.current_mode.lock().unwrap() = GameMode::Update;
+        *self.tick.lock().unwrap() += 1;
+        *self.shared.lock().unwrap() = None;
         let globals = self.lua.globals();
         if let Ok(update) = globals.get::<_, Function>("update") {
             update.call::<_, ()>(dt)?;
@@ -662,36 +1176,317 @@ impl GameState {
     // Now accepts session_id so Lua knows WHO to draw for
     pub fn draw(&self, session_id: &str) -> anyhow::Result<Bytes> {
         *self.current_mode.lock().unwrap() = GameMode::Draw;
+        let shared = self.draw_shared()?;
 
         // Clear previous buffer
         self.command_buffer.clear();
+        // Each frame starts from the default text style, as the client does
+        self.text.lock().unwrap().reset();
+        // A list left open by a failed draw must not swallow this frame
+        self.command_buffer.end_recording();
 
         // Include events from update (sounds)
         self.command_buffer.append(&self.event_buffer);
 
+        // Then the world, which `draw()` continues on top of
+        if let Some((commands, text)) = shared {
+            self.command_buffer.extend(&commands);
+            self.text.lock().unwrap().set_state(text);
+        }
+
         let globals = self.lua.globals();
         if let Ok(draw) = globals.get::<_, Function>("draw") {
-            draw.call::<_, ()>(session_id)?;
+            let alpha = *self.alpha.lock().unwrap();
+            draw.call::<_, ()>((session_id, alpha))?;
+        }
+        if let Some((name, _)) = self.command_buffer.end_recording() {
+            anyhow::bail!("begin_list('{}') without end_list()", name);
         }
 
         Ok(self.command_buffer.get_bytes())
     }
 
+    /// Output of the game's `draw_shared()`, if it has one. It is only run for the
+    /// first session drawn after each update or alpha change; the rest get the same
+    /// commands.
+    fn draw_shared(&self) -> anyhow::Result<Option<(Bytes, TextState)>> {
+        if let Some(shared) = self.shared.lock().unwrap().as_ref() {
+            return Ok(Some(shared.clone()));
+        }
+        let Ok(draw_shared) = self.lua.globals().get::<_, Function>("draw_shared") else {
+            return Ok(None);
+        };
+
+        self.command_buffer.clear();
+        self.text.lock().unwrap().reset();
+        self.command_buffer.end_recording();
+        draw_shared.call::<_, ()>(*self.alpha.lock().unwrap())?;
+        if let Some((name, _)) = self.command_buffer.end_recording() {
+            anyhow::bail!("begin_list('{}') without end_list()", name);
+        }
+
+        let shared = (self.command_buffer.get_bytes(), self.text.lock().unwrap().state());
+        *self.shared.lock().unwrap() = Some(shared.clone());
+        Ok(Some(shared))
+    }
+
+    /// `DefineList` commands for the display lists `session_id` hasn't received yet
+    /// (or has an outdated copy of). They must reach the client before the frame that
+    /// uses them, and are not sent again.
+    pub fn take_list_updates(&self, session_id: &str) -> Bytes {
+        self.lists.lock().unwrap().take_updates(session_id)
+    }
+
+    /// Marks every display list as unsent for `session_id`, e.g. after its update was lost.
+    pub fn resend_lists(&self, session_id: &str) {
+        self.lists.lock().unwrap().reset_session(session_id);
+    }
+
+    /// `DefineList` commands for all display lists.
+    pub fn list_definitions(&self) -> Bytes {
+        self.lists.lock().unwrap().all_definitions()
+    }
+
+    /// A `SetResolution` command if `session_id` hasn't received the current resolution.
+    /// Like list updates, it must reach the client reliably.
+    pub fn take_screen_update(&self, session_id: &str) -> Bytes {
+        self.screen.lock().unwrap().take_update(session_id)
+    }
+
+    /// Marks the resolution as unsent for `session_id`, e.g. after its update was lost.
+    pub fn resend_screen(&self, session_id: &str) {
+        self.screen.lock().unwrap().reset_session(session_id);
+    }
+
+    /// Records the size of `session_id`'s screen, in CSS pixels.
+    pub fn set_viewport(&self, session_id: &str, width: f32, height: f32) {
+        self.screen
+            .lock()
+            .unwrap()
+            .set_viewport(session_id, width, height);
+    }
+
+    /// Virtual size visible to `session_id`, as returned by `api.screen_size`.
+    pub fn screen_size(&self, session_id: &str) -> (f32, f32) {
+        self.screen.lock().unwrap().size_for(session_id)
+    }
+
+    /// The current `SetResolution` command.
+    pub fn screen_command(&self) -> DrawCommand {
+        self.screen.lock().unwrap().command()
+    }
+
+    /// `frame` with every `CallList` expanded, for clients without display list support.
+    pub fn inline_lists(&self, frame: &[u8]) -> Bytes {
+        self.lists.lock().unwrap().inline(frame)
+    }
+
+    /// A legacy key code, from older clients and touch buttons.
     pub fn handle_input(
         &self,
         session_id: &str,
         input_code: u8,
         active: bool,
+    ) -> anyhow::Result<()> {
+        let input = Input::Key {
+            code: input_code,
+            down: active,
+        };
+        self.handle(session_id, input, InputTiming::default())
+    }
+
+    /// A named key.
+    pub fn handle_key(
+        &self,
+        session_id: &str,
+        name: &str,
+        active: bool,
+        mods: Modifiers,
+    ) -> anyhow::Result<()> {
+        let input = Input::NamedKey {
+            name: name.to_string(),
+            down: active,
+            mods,
+        };
+        self.handle(session_id, input, InputTiming::default())
+    }
+
+    /// A pointer event, with `x` and `y` in virtual coordinates.
+    pub fn handle_pointer(
+        &self,
+        session_id: &str,
+        x: f32,
+        y: f32,
+        button: i8,
+        kind: PointerKind,
+    ) -> anyhow::Result<()> {
+        let input = Input::Pointer { kind, button, x, y };
+        self.handle(session_id, input, InputTiming::default())
+    }
+
+    /// A gamepad control's new value.
+    pub fn handle_gamepad(
+        &self,
+        session_id: &str,
+        pad: u8,
+        control: u8,
+        value: f32,
+    ) -> anyhow::Result<()> {
+        let input = Input::Gamepad {
+            pad,
+            control,
+            value,
+        };
+        self.handle(session_id, input, InputTiming::default())
+    }
+
+    /// Passes an input to the Lua callback for its kind, then to `on_action` for the
+    /// actions it pressed or released. Every callback gets `timing` as its last two
+    /// arguments, `seq` and `frame`.
+    ///
+    /// Keys go to `on_input`: games reading key codes get a named key's legacy code,
+    /// games reading names get a code's name, and keys without the other are dropped.
+    /// Gamepad values are also recorded for `api.get_axis`.
+    pub fn handle(
+        &self,
+        session_id: &str,
+        input: Input,
+        timing: InputTiming,
+    ) -> anyhow::Result<()> {
+        let globals = self.lua.globals();
+        let changes = match input {
+            Input::Key { code, down } => {
+                let name = input::key_name(code);
+                if !self.key_names {
+                    self.call_on_input(session_id, code, down, Modifiers::default(), timing)?;
+                } else if let Some(name) = &name {
+                    let mods = Modifiers::default();
+                    self.call_on_input(session_id, name.as_str(), down, mods, timing)?;
+                }
+                match name {
+                    Some(name) => self.actions.lock().unwrap().key(session_id, &name, down),
+                    None => Vec::new(),
+                }
+            }
+            Input::NamedKey { name, down, mods } => {
+                if self.key_names {
+                    self.call_on_input(session_id, name.as_str(), down, mods, timing)?;
+                } else if let Some(code) = input::legacy_key_code(&name) {
+                    self.call_on_input(session_id, code, down, mods, timing)?;
+                }
+                self.actions.lock().unwrap().key(session_id, &name, down)
+            }
+            Input::Pointer { kind, button, x, y } => {
+                if let Ok(on_pointer) = globals.get::<_, Function>("on_pointer") {
+                    on_pointer.call::<_, ()>((
+                        session_id,
+                        x,
+                        y,
+                        button,
+                        kind.as_str(),
+                        timing.seq,
+                        timing.frame,
+                    ))?;
+                }
+                match kind {
+                    PointerKind::Down | PointerKind::Up => {
+                        let down = kind == PointerKind::Down;
+                        self.actions
+                            .lock()
+                            .unwrap()
+                            .pointer(session_id, button, down)
+                    }
+                    PointerKind::Move | PointerKind::Wheel => Vec::new(),
+                }
+            }
+            Input::Gamepad {
+                pad,
+                control,
+                value,
+            } => {
+                let axis = input::gamepad_control_name(control);
+                {
+                    let mut gamepads = self.gamepads.lock().unwrap();
+                    let controls = gamepads.entry(session_id.to_string()).or_default();
+                    if value == 0.0 {
+                        controls.remove(&(pad, axis.clone()));
+                    } else {
+                        controls.insert((pad, axis.clone()), value);
+                    }
+                }
+                if let Ok(on_axis) = globals.get::<_, Function>("on_axis") {
+                    on_axis.call::<_, ()>((
+                        session_id,
+                        axis.as_str(),
+                        value,
+                        pad,
+                        timing.seq,
+                        timing.frame,
+                    ))?;
+                }
+                self.actions
+                    .lock()
+                    .unwrap()
+                    .gamepad(session_id, pad, &axis, value)
+            }
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+        if let Ok(on_action) = globals.get::<_, Function>("on_action") {
+            for (action, pressed) in changes {
+                on_action.call::<_, ()>((session_id, action, pressed, timing.seq, timing.frame))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Calls Lua's `on_input(session_id, key, is_down, mods, seq, frame)`
+    fn call_on_input(
+        &self,
+        session_id: &str,
+        key: impl for<'lua> mlua::IntoLua<'lua>,
+        active: bool,
+        mods: Modifiers,
+        timing: InputTiming,
     ) -> anyhow::Result<()> {
         let globals = self.lua.globals();
         if let Ok(on_input) = globals.get::<_, Function>("on_input") {
-            on_input.call::<_, ()>((session_id, input_code, active))?;
+            let table = self.lua.create_table()?;
+            table.set("shift", mods.shift)?;
+            table.set("ctrl", mods.ctrl)?;
+            table.set("alt", mods.alt)?;
+            table.set("meta", mods.meta)?;
+            on_input.call::<_, ()>((session_id, key, active, table, timing.seq, timing.frame))?;
         }
         Ok(())
     }
 
+    /// Calls Lua's `on_message(session_id, payload)` with a message the client sent.
+    /// Objects and arrays become tables, and JSON `null` becomes `nil`.
+    pub fn handle_message(&self, session_id: &str, payload: &Value) -> anyhow::Result<()> {
+        let globals = self.lua.globals();
+        if let Ok(on_message) = globals.get::<_, Function>("on_message") {
+            let options = mlua::SerializeOptions::new()
+                .serialize_none_to_null(false)
+                .serialize_unit_to_null(false);
+            let payload = self.lua.to_value_with(payload, options)?;
+            on_message.call::<_, ()>((session_id, payload))?;
+        }
+        Ok(())
+    }
+
+    /// Messages `api.send` queued since the last call, with the session each is for.
+    pub fn take_messages(&self) -> Vec<(String, Value)> {
+        std::mem::take(&mut *self.outbox.lock().unwrap())
+    }
+
     pub fn on_connect(&self, session_id: &str) -> anyhow::Result<Bytes> {
         self.command_buffer.clear();
+        self.text.lock().unwrap().reset();
+        // A reconnecting session starts with an empty renderer
+        self.resend_lists(session_id);
+        self.resend_screen(session_id);
         let globals = self.lua.globals();
         if let Ok(cb) = globals.get::<_, Function>("on_connect") {
             cb.call::<_, ()>(session_id)?;
@@ -700,6 +1495,10 @@ impl GameState {
     }
 
     pub fn on_disconnect(&self, session_id: &str) -> anyhow::Result<()> {
+        self.resend_lists(session_id);
+        self.screen.lock().unwrap().remove_session(session_id);
+        self.gamepads.lock().unwrap().remove(session_id);
+        self.actions.lock().unwrap().remove_session(session_id);
         let globals = self.lua.globals();
         if let Ok(cb) = globals.get::<_, Function>("on_disconnect") {
             cb.call::<_, ()>(session_id)?;
@@ -709,45 +1508,30 @@ impl GameState {
 
     // --- State Persistence for Hot Reload ---
 
-    pub fn snapshot_state(&self) -> anyhow::Result<String> {
+    /// Calls Lua's `on_save()` before a hot reload and returns what it saved, along
+    /// with the tick, or None if the script has no `on_save`.
+    pub fn save_state(&self) -> anyhow::Result<Option<Value>> {
         let globals = self.lua.globals();
-
-        // Get as generic Lua Value first
-        let players_lua: mlua::Value = globals.get("players")?;
-        let asteroids_lua: mlua::Value = globals.get("asteroids")?;
-        let bullets_lua: mlua::Value = globals.get("bullets")?;
-
-        // Convert to Serde Value
-        let players: Value = self.lua.from_value(players_lua)?;
-        let asteroids: Value = self.lua.from_value(asteroids_lua)?;
-        let bullets: Value = self.lua.from_value(bullets_lua)?;
-
-        let state = serde_json::json!({
-            "players": players,
-            "asteroids": asteroids,
-            "bullets": bullets
-        });
-
-        Ok(state.to_string())
+        let Ok(on_save) = globals.get::<_, Function>("on_save") else {
+            return Ok(None);
+        };
+        let mut saved = saved_state::save(on_save.call::<_, mlua::Value>(())?)?;
+        saved["tick"] = self.tick().into();
+        Ok(Some(saved))
     }
 
-    pub fn restore_state(&self, json_state: &str) -> anyhow::Result<()> {
+    /// Continues from state `save_state` saved in the previous version of the script:
+    /// the tick goes on from where it was, and Lua's `on_restore(state)` gets what
+    /// `on_save()` returned.
+    pub fn restore_state(&self, saved: &Value) -> anyhow::Result<()> {
+        if let Some(tick) = saved["tick"].as_u64() {
+            *self.tick.lock().unwrap() = tick;
+        }
+        *self.shared.lock().unwrap() = None;
         let globals = self.lua.globals();
-        let state: Value = serde_json::from_str(json_state)?;
-
-        if let Some(obj) = state.as_object() {
-            if let Some(p) = obj.get("players") {
-                let lua_val = self.lua.to_value(p)?;
-                globals.set("players", lua_val)?;
-            }
-            if let Some(a) = obj.get("asteroids") {
-                let lua_val = self.lua.to_value(a)?;
-                globals.set("asteroids", lua_val)?;
-            }
-            if let Some(b) = obj.get("bullets") {
-                let lua_val = self.lua.to_value(b)?;
-                globals.set("bullets", lua_val)?;
-            }
+        if let Ok(on_restore) = globals.get::<_, Function>("on_restore") {
+            let state = saved_state::restore(&self.lua, saved)?;
+            on_restore.call::<_, ()>(state)?;
         }
         Ok(())
     }
@@ -2,7 +2,9 @@ use crate::spatial_db::{EntityKind, SpatialDb};
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
-#[derive(Clone, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RigidBody {
     pub vx: f32,
     pub vy: f32,
@@ -29,6 +31,16 @@ impl RigidBody {
     }
 }
 
+/// A PhysicsWorld's bodies and settings, without the SpatialDb it moves things in,
+/// for carrying it across a hot reload.
+#[derive(Serialize, Deserialize)]
+pub struct PhysicsWorldSnapshot {
+    bodies: HashMap<u64, RigidBody>,
+    gravity_x: f32,
+    gravity_y: f32,
+    collisions: Vec<(u64, u64)>,
+}
+
 pub struct PhysicsWorld {
     db: Arc<Mutex<SpatialDb>>,
     bodies: HashMap<u64, RigidBody>,
@@ -48,6 +60,29 @@ impl PhysicsWorld {
         }
     }
 
+    pub fn snapshot(&self) -> PhysicsWorldSnapshot {
+        PhysicsWorldSnapshot {
+            bodies: self.bodies.clone(),
+            gravity_x: self.gravity_x,
+            gravity_y: self.gravity_y,
+            collisions: self.collisions.iter().copied().collect(),
+        }
+    }
+
+    pub fn from_snapshot(db: Arc<Mutex<SpatialDb>>, snapshot: PhysicsWorldSnapshot) -> Self {
+        Self {
+            db,
+            bodies: snapshot.bodies,
+            gravity_x: snapshot.gravity_x,
+            gravity_y: snapshot.gravity_y,
+            collisions: snapshot.collisions.into_iter().collect(),
+        }
+    }
+
+    pub fn db(&self) -> &Arc<Mutex<SpatialDb>> {
+        &self.db
+    }
+
     pub fn get_collision_events(&mut self) -> Vec<(u64, u64)> {
         self.collisions.drain().collect()
     }
@@ -0,0 +1,1414 @@
+//! Draw command wire format.
+//!
+//! This is the single definition of the command stream produced by `CommandBuffer`
+//! and consumed by the server's debug renderer and the WASM client. A new opcode only
+//! needs a constant, a `DrawCommand` variant and its encode/decode arms here, plus an
+//! entry in `js_module` if `client/main.js` uses the constant.
+//!
+//! Every command is `u8 opcode, varint payload length, payload`, with the length as
+//! unsigned LEB128. Decoders skip opcodes they don't know and ignore payload bytes past
+//! the fields they read, so new commands (or new trailing fields) don't break older
+//! clients. Changes that older clients can't skip bump `PROTOCOL_VERSION`.
+//!
+//! Commands with coordinates may instead be sent in the compact `Encoding`, marked by
+//! `OP_COMPACT` in the opcode, to clients with the `compact_coords` capability.
+
+use bytes::BufMut;
+use thiserror::Error;
+
+/// Version of the command stream and frame envelope, exchanged in the WebSocket handshake.
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Oldest client version the server still talks to. Version 1 had no length prefixes.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+
+/// Longest `MESSAGE` a client may send for Lua's `on_message`, in bytes of JSON
+/// including the envelope.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Frames are wrapped in the `delta` envelope and acknowledged by the client.
+pub const CAP_DELTA_FRAMES: &str = "delta_frames";
+/// The client keeps `DefineList` lists and replays `CallList`.
+pub const CAP_DISPLAY_LISTS: &str = "display_lists";
+/// The client decodes commands in the compact `Encoding`.
+pub const CAP_COMPACT_COORDS: &str = "compact_coords";
+/// The client sends keys as `KeyboardEvent.code` names (`input::KEY_TAG` packets).
+pub const CAP_NAMED_KEYS: &str = "named_keys";
+/// The client stamps inputs (`input::STAMP_TAG`), and frames acknowledge them
+/// (`delta::FRAME_INPUT_ACK`).
+pub const CAP_INPUT_SEQ: &str = "input_seq";
+
+/// Optional protocol features. The client lists the ones it supports when connecting,
+/// and the server answers with the ones it will use for that session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub delta_frames: bool,
+    pub display_lists: bool,
+    pub compact_coords: bool,
+    pub named_keys: bool,
+    pub input_seq: bool,
+}
+
+impl Capabilities {
+    /// Everything this build implements.
+    pub const ALL: Capabilities = Capabilities {
+        delta_frames: true,
+        display_lists: true,
+        compact_coords: true,
+        named_keys: true,
+        input_seq: true,
+    };
+
+    /// Unknown names are ignored, so newer peers can list features we don't have.
+    pub fn from_names<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut caps = Self::default();
+        for name in names {
+            match name.as_ref().trim() {
+                CAP_DELTA_FRAMES => caps.delta_frames = true,
+                CAP_DISPLAY_LISTS => caps.display_lists = true,
+                CAP_COMPACT_COORDS => caps.compact_coords = true,
+                CAP_NAMED_KEYS => caps.named_keys = true,
+                CAP_INPUT_SEQ => caps.input_seq = true,
+                _ => {}
+            }
+        }
+        caps
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.delta_frames {
+            names.push(CAP_DELTA_FRAMES);
+        }
+        if self.display_lists {
+            names.push(CAP_DISPLAY_LISTS);
+        }
+        if self.compact_coords {
+            names.push(CAP_COMPACT_COORDS);
+        }
+        if self.named_keys {
+            names.push(CAP_NAMED_KEYS);
+        }
+        if self.input_seq {
+            names.push(CAP_INPUT_SEQ);
+        }
+        names
+    }
+}
+
+// OpCodes
+pub const OP_CLEAR: u8 = 0x01;
+pub const OP_SET_COLOR: u8 = 0x02;
+pub const OP_FILL_RECT: u8 = 0x03;
+pub const OP_DRAW_LINE: u8 = 0x04;
+pub const OP_DRAW_TEXT: u8 = 0x05;
+pub const OP_LOAD_SOUND: u8 = 0x06;
+pub const OP_PLAY_SOUND: u8 = 0x07;
+pub const OP_STOP_SOUND: u8 = 0x08;
+pub const OP_SET_VOLUME: u8 = 0x09;
+pub const OP_LOAD_IMAGE: u8 = 0x0A;
+pub const OP_DRAW_IMAGE: u8 = 0x0B;
+pub const OP_FILL_POLY: u8 = 0x0C;
+pub const OP_PUSH: u8 = 0x0D;
+pub const OP_POP: u8 = 0x0E;
+pub const OP_TRANSLATE: u8 = 0x0F;
+pub const OP_ROTATE: u8 = 0x10;
+pub const OP_SCALE: u8 = 0x11;
+pub const OP_FILL_CIRCLE: u8 = 0x12;
+pub const OP_STROKE_CIRCLE: u8 = 0x13;
+pub const OP_STROKE_RECT: u8 = 0x14;
+pub const OP_ARC: u8 = 0x15;
+pub const OP_ELLIPSE: u8 = 0x16;
+pub const OP_STROKE_POLY: u8 = 0x17;
+pub const OP_LOAD_FONT: u8 = 0x18;
+pub const OP_SET_FONT: u8 = 0x19;
+pub const OP_SET_TEXT_ALIGN: u8 = 0x1A;
+pub const OP_DEFINE_LIST: u8 = 0x1B;
+pub const OP_CALL_LIST: u8 = 0x1C;
+pub const OP_SET_RESOLUTION: u8 = 0x1D;
+/// Set in the opcode of a command whose payload uses `Encoding::Compact`.
+pub const OP_COMPACT: u8 = 0x80;
+
+/// Compact coordinates are fixed point with this many steps per pixel.
+pub const COORD_SCALE: f32 = 16.0;
+
+// Flags byte of a compact `DrawImage`, one per group of fields that differ from the
+// defaults `api.draw_image` fills in
+const IMAGE_SIZE: u8 = 0x01;
+const IMAGE_SOURCE: u8 = 0x02;
+const IMAGE_ROTATION: u8 = 0x04;
+const IMAGE_ORIGIN: u8 = 0x08;
+
+/// How deep `CallList`s may nest before renderers stop following them, which also
+/// stops a list that calls itself.
+pub const MAX_LIST_DEPTH: usize = 8;
+
+/// Virtual canvas size for games that don't declare their own.
+pub const DEFAULT_WIDTH: u16 = 800;
+pub const DEFAULT_HEIGHT: u16 = 600;
+
+/// Font in effect at the start of every frame, until a `SetFont`.
+pub const DEFAULT_FONT: &str = "monospace";
+pub const DEFAULT_FONT_SIZE: f32 = 14.0;
+
+/// How two stroked segments meet. Values match the wire byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LineJoin {
+    #[default]
+    Miter = 0,
+    Round = 1,
+    Bevel = 2,
+}
+
+impl LineJoin {
+    /// Unknown values fall back to the default rather than failing the frame.
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => LineJoin::Round,
+            2 => LineJoin::Bevel,
+            _ => LineJoin::Miter,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "miter" => Some(LineJoin::Miter),
+            "round" => Some(LineJoin::Round),
+            "bevel" => Some(LineJoin::Bevel),
+            _ => None,
+        }
+    }
+
+    /// Name as used by the canvas `lineJoin` property.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineJoin::Miter => "miter",
+            LineJoin::Round => "round",
+            LineJoin::Bevel => "bevel",
+        }
+    }
+}
+
+/// How the ends of an open stroke are drawn. Values match the wire byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LineCap {
+    #[default]
+    Butt = 0,
+    Round = 1,
+    Square = 2,
+}
+
+impl LineCap {
+    /// Unknown values fall back to the default rather than failing the frame.
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => LineCap::Round,
+            2 => LineCap::Square,
+            _ => LineCap::Butt,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "butt" => Some(LineCap::Butt),
+            "round" => Some(LineCap::Round),
+            "square" => Some(LineCap::Square),
+            _ => None,
+        }
+    }
+
+    /// Name as used by the canvas `lineCap` property.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineCap::Butt => "butt",
+            LineCap::Round => "round",
+            LineCap::Square => "square",
+        }
+    }
+}
+
+/// Horizontal text anchor relative to the `DrawText` x coordinate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left = 0,
+    Center = 1,
+    Right = 2,
+}
+
+impl TextAlign {
+    /// Unknown values fall back to the default rather than failing the frame.
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => TextAlign::Center,
+            2 => TextAlign::Right,
+            _ => TextAlign::Left,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(TextAlign::Left),
+            "center" => Some(TextAlign::Center),
+            "right" => Some(TextAlign::Right),
+            _ => None,
+        }
+    }
+
+    /// Name as used by the canvas `textAlign` property.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TextAlign::Left => "left",
+            TextAlign::Center => "center",
+            TextAlign::Right => "right",
+        }
+    }
+}
+
+/// Vertical text anchor relative to the `DrawText` y coordinate. `Middle` is the
+/// default because that is what `draw_text` has always used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TextBaseline {
+    #[default]
+    Middle = 0,
+    Top = 1,
+    Bottom = 2,
+    Alphabetic = 3,
+}
+
+impl TextBaseline {
+    /// Unknown values fall back to the default rather than failing the frame.
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => TextBaseline::Top,
+            2 => TextBaseline::Bottom,
+            3 => TextBaseline::Alphabetic,
+            _ => TextBaseline::Middle,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "middle" => Some(TextBaseline::Middle),
+            "top" => Some(TextBaseline::Top),
+            "bottom" => Some(TextBaseline::Bottom),
+            "alphabetic" => Some(TextBaseline::Alphabetic),
+            _ => None,
+        }
+    }
+
+    /// Name as used by the canvas `textBaseline` property.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TextBaseline::Middle => "middle",
+            TextBaseline::Top => "top",
+            TextBaseline::Bottom => "bottom",
+            TextBaseline::Alphabetic => "alphabetic",
+        }
+    }
+}
+
+/// How coordinates (positions, sizes, radii and line widths) are written. Angles and
+/// scale factors are always f32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Little-endian f32.
+    #[default]
+    Float,
+    /// Zigzag varints in 1/`COORD_SCALE` pixels, with point lists delta coded and
+    /// default `DrawImage` fields left out. Costs precision below 1/16 of a pixel.
+    Compact,
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "float" => Some(Encoding::Float),
+            "compact" => Some(Encoding::Compact),
+            _ => None,
+        }
+    }
+}
+
+/// How the virtual canvas is fitted to a viewer's screen. Values match the wire byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// Uniform scale, with bars on the sides that don't fit.
+    #[default]
+    Letterbox = 0,
+    /// Fills the screen, ignoring the aspect ratio.
+    Stretch = 1,
+    /// Largest whole number of device pixels per virtual pixel, for pixel art.
+    Integer = 2,
+    /// Uniform scale, with the canvas grown along one axis to fill the screen.
+    Expand = 3,
+}
+
+impl ScaleMode {
+    /// Unknown values fall back to the default rather than failing the frame.
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => ScaleMode::Stretch,
+            2 => ScaleMode::Integer,
+            3 => ScaleMode::Expand,
+            _ => ScaleMode::Letterbox,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "letterbox" => Some(ScaleMode::Letterbox),
+            "stretch" => Some(ScaleMode::Stretch),
+            "integer" => Some(ScaleMode::Integer),
+            "expand" => Some(ScaleMode::Expand),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScaleMode::Letterbox => "letterbox",
+            ScaleMode::Stretch => "stretch",
+            ScaleMode::Integer => "integer",
+            ScaleMode::Expand => "expand",
+        }
+    }
+
+    /// Virtual size visible on a `viewport` of any unit. Only `Expand` shows more than
+    /// `width` x `height`; the extra space is to the right or below.
+    pub fn visible_size(&self, width: f32, height: f32, viewport: (f32, f32)) -> (f32, f32) {
+        let (vw, vh) = viewport;
+        if *self != ScaleMode::Expand || vw <= 0.0 || vh <= 0.0 {
+            return (width, height);
+        }
+        if vw * height > vh * width {
+            (height * vw / vh, height)
+        } else {
+            (width, width * vh / vw)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Clear {
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+    SetColor {
+        r: u8,
+        g: u8,
+        b: u8,
+        a: u8,
+    },
+    FillRect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    },
+    DrawLine {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+        width: f32,
+    },
+    DrawText {
+        x: f32,
+        y: f32,
+        text: String,
+    },
+    LoadSound {
+        name: String,
+        url: String,
+    },
+    PlaySound {
+        name: String,
+        looped: bool,
+        volume: f32,
+    },
+    StopSound {
+        name: String,
+    },
+    SetVolume {
+        name: String,
+        volume: f32,
+    },
+    LoadImage {
+        name: String,
+        url: String,
+    },
+    /// `w`/`h` and `sw`/`sh` of -1.0 mean "use the image's own size".
+    DrawImage {
+        name: String,
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        sx: f32,
+        sy: f32,
+        sw: f32,
+        sh: f32,
+        rotation: f32,
+        ox: f32,
+        oy: f32,
+    },
+    FillPoly {
+        points: Vec<(f32, f32)>,
+    },
+    /// Saves the current transform and color, like `CanvasRenderingContext2d::save`.
+    Push,
+    /// Restores the state saved by the matching `Push`. Unbalanced pops are ignored.
+    Pop,
+    Translate {
+        x: f32,
+        y: f32,
+    },
+    /// Rotation in radians, clockwise in screen space.
+    Rotate {
+        angle: f32,
+    },
+    Scale {
+        x: f32,
+        y: f32,
+    },
+    FillCircle {
+        x: f32,
+        y: f32,
+        r: f32,
+    },
+    StrokeCircle {
+        x: f32,
+        y: f32,
+        r: f32,
+        width: f32,
+    },
+    StrokeRect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        width: f32,
+    },
+    /// Angles in radians. A `width` of 0 fills the pie slice instead of stroking the arc.
+    Arc {
+        x: f32,
+        y: f32,
+        r: f32,
+        start: f32,
+        end: f32,
+        width: f32,
+    },
+    /// A `width` of 0 fills the ellipse instead of stroking its outline.
+    Ellipse {
+        x: f32,
+        y: f32,
+        rx: f32,
+        ry: f32,
+        rotation: f32,
+        width: f32,
+    },
+    StrokePoly {
+        width: f32,
+        join: LineJoin,
+        cap: LineCap,
+        closed: bool,
+        points: Vec<(f32, f32)>,
+    },
+    /// Registers a font file under `name` so `SetFont` can refer to it.
+    LoadFont {
+        name: String,
+        url: String,
+    },
+    /// `name` is either a loaded font or a CSS generic family such as `monospace`.
+    /// `size` is the em size in pixels.
+    SetFont {
+        name: String,
+        size: f32,
+    },
+    SetTextAlign {
+        align: TextAlign,
+        baseline: TextBaseline,
+    },
+    /// Stores an encoded command stream on the client under `name`, replacing any
+    /// previous list with that name.
+    DefineList {
+        name: String,
+        commands: Vec<u8>,
+    },
+    /// Replays a stored list as if it was wrapped in `Push`, `Translate`, `Rotate`,
+    /// `Scale` ... `Pop`. Unknown names draw nothing.
+    CallList {
+        name: String,
+        x: f32,
+        y: f32,
+        rotation: f32,
+        sx: f32,
+        sy: f32,
+    },
+    /// The game's virtual canvas size and how clients fit it to their screen.
+    SetResolution {
+        width: u16,
+        height: u16,
+        mode: ScaleMode,
+    },
+}
+
+impl DrawCommand {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            DrawCommand::Clear { .. } => OP_CLEAR,
+            DrawCommand::SetColor { .. } => OP_SET_COLOR,
+            DrawCommand::FillRect { .. } => OP_FILL_RECT,
+            DrawCommand::DrawLine { .. } => OP_DRAW_LINE,
+            DrawCommand::DrawText { .. } => OP_DRAW_TEXT,
+            DrawCommand::LoadSound { .. } => OP_LOAD_SOUND,
+            DrawCommand::PlaySound { .. } => OP_PLAY_SOUND,
+            DrawCommand::StopSound { .. } => OP_STOP_SOUND,
+            DrawCommand::SetVolume { .. } => OP_SET_VOLUME,
+            DrawCommand::LoadImage { .. } => OP_LOAD_IMAGE,
+            DrawCommand::DrawImage { .. } => OP_DRAW_IMAGE,
+            DrawCommand::FillPoly { .. } => OP_FILL_POLY,
+            DrawCommand::Push => OP_PUSH,
+            DrawCommand::Pop => OP_POP,
+            DrawCommand::Translate { .. } => OP_TRANSLATE,
+            DrawCommand::Rotate { .. } => OP_ROTATE,
+            DrawCommand::Scale { .. } => OP_SCALE,
+            DrawCommand::FillCircle { .. } => OP_FILL_CIRCLE,
+            DrawCommand::StrokeCircle { .. } => OP_STROKE_CIRCLE,
+            DrawCommand::StrokeRect { .. } => OP_STROKE_RECT,
+            DrawCommand::Arc { .. } => OP_ARC,
+            DrawCommand::Ellipse { .. } => OP_ELLIPSE,
+            DrawCommand::StrokePoly { .. } => OP_STROKE_POLY,
+            DrawCommand::LoadFont { .. } => OP_LOAD_FONT,
+            DrawCommand::SetFont { .. } => OP_SET_FONT,
+            DrawCommand::SetTextAlign { .. } => OP_SET_TEXT_ALIGN,
+            DrawCommand::DefineList { .. } => OP_DEFINE_LIST,
+            DrawCommand::CallList { .. } => OP_CALL_LIST,
+            DrawCommand::SetResolution { .. } => OP_SET_RESOLUTION,
+        }
+    }
+
+    /// Whether the compact encoding changes this command's payload.
+    fn has_coords(&self) -> bool {
+        !matches!(
+            self,
+            DrawCommand::Clear { .. }
+                | DrawCommand::SetColor { .. }
+                | DrawCommand::LoadSound { .. }
+                | DrawCommand::PlaySound { .. }
+                | DrawCommand::StopSound { .. }
+                | DrawCommand::SetVolume { .. }
+                | DrawCommand::LoadImage { .. }
+                | DrawCommand::Push
+                | DrawCommand::Pop
+                | DrawCommand::Rotate { .. }
+                | DrawCommand::Scale { .. }
+                | DrawCommand::LoadFont { .. }
+                | DrawCommand::SetFont { .. }
+                | DrawCommand::SetTextAlign { .. }
+                | DrawCommand::DefineList { .. }
+                | DrawCommand::SetResolution { .. }
+        )
+    }
+
+    /// Appends the wire representation of this command to `out`.
+    pub fn encode<B: BufMut>(&self, out: &mut B) {
+        self.encode_with(out, Encoding::Float);
+    }
+
+    /// Like `encode`, with coordinates written in `encoding`. Commands without
+    /// coordinates come out the same either way.
+    pub fn encode_with<B: BufMut>(&self, out: &mut B, encoding: Encoding) {
+        let compact = encoding == Encoding::Compact && self.has_coords();
+        let mut payload = Vec::new();
+        self.encode_payload(&mut payload, compact);
+        out.put_u8(if compact {
+            self.opcode() | OP_COMPACT
+        } else {
+            self.opcode()
+        });
+        put_varint(out, payload.len() as u32);
+        out.put_slice(&payload);
+    }
+
+    fn encode_payload<B: BufMut>(&self, out: &mut B, compact: bool) {
+        match self {
+            DrawCommand::Clear { r, g, b } => {
+                out.put_u8(*r);
+                out.put_u8(*g);
+                out.put_u8(*b);
+            }
+            DrawCommand::SetColor { r, g, b, a } => {
+                out.put_u8(*r);
+                out.put_u8(*g);
+                out.put_u8(*b);
+                out.put_u8(*a);
+            }
+            DrawCommand::FillRect { x, y, w, h } => {
+                put_coords(out, compact, &[*x, *y, *w, *h]);
+            }
+            DrawCommand::DrawLine {
+                x1,
+                y1,
+                x2,
+                y2,
+                width,
+            } => {
+                put_coords(out, compact, &[*x1, *y1, *x2, *y2, *width]);
+            }
+            DrawCommand::DrawText { x, y, text } => {
+                put_coords(out, compact, &[*x, *y]);
+                put_str(out, text);
+            }
+            DrawCommand::LoadSound { name, url }
+            | DrawCommand::LoadImage { name, url }
+            | DrawCommand::LoadFont { name, url } => {
+                put_str(out, name);
+                put_str(out, url);
+            }
+            DrawCommand::PlaySound {
+                name,
+                looped,
+                volume,
+            } => {
+                put_str(out, name);
+                out.put_u8(u8::from(*looped));
+                out.put_f32_le(*volume);
+            }
+            DrawCommand::StopSound { name } => {
+                put_str(out, name);
+            }
+            DrawCommand::SetVolume { name, volume } => {
+                put_str(out, name);
+                out.put_f32_le(*volume);
+            }
+            DrawCommand::DrawImage {
+                name,
+                x,
+                y,
+                w,
+                h,
+                sx,
+                sy,
+                sw,
+                sh,
+                rotation,
+                ox,
+                oy,
+            } => {
+                put_str(out, name);
+                if !compact {
+                    put_f32s(out, &[*x, *y, *w, *h, *sx, *sy, *sw, *sh, *rotation, *ox, *oy]);
+                    return;
+                }
+                let size = (*w, *h) != (-1.0, -1.0);
+                let source = (*sx, *sy, *sw, *sh) != (0.0, 0.0, -1.0, -1.0);
+                let rotated = *rotation != 0.0;
+                let origin = (*ox, *oy) != (0.0, 0.0);
+                let flag = |set: bool, flag: u8| if set { flag } else { 0 };
+                out.put_u8(
+                    flag(size, IMAGE_SIZE)
+                        | flag(source, IMAGE_SOURCE)
+                        | flag(rotated, IMAGE_ROTATION)
+                        | flag(origin, IMAGE_ORIGIN),
+                );
+                put_coords(out, true, &[*x, *y]);
+                if size {
+                    put_coords(out, true, &[*w, *h]);
+                }
+                if source {
+                    put_coords(out, true, &[*sx, *sy, *sw, *sh]);
+                }
+                if rotated {
+                    out.put_f32_le(*rotation);
+                }
+                if origin {
+                    put_coords(out, true, &[*ox, *oy]);
+                }
+            }
+            DrawCommand::FillPoly { points } => {
+                put_points(out, compact, points);
+            }
+            DrawCommand::Push | DrawCommand::Pop => {}
+            DrawCommand::Translate { x, y } => {
+                put_coords(out, compact, &[*x, *y]);
+            }
+            DrawCommand::Scale { x, y } => {
+                put_f32s(out, &[*x, *y]);
+            }
+            DrawCommand::Rotate { angle } => {
+                out.put_f32_le(*angle);
+            }
+            DrawCommand::FillCircle { x, y, r } => {
+                put_coords(out, compact, &[*x, *y, *r]);
+            }
+            DrawCommand::StrokeCircle { x, y, r, width } => {
+                put_coords(out, compact, &[*x, *y, *r, *width]);
+            }
+            DrawCommand::StrokeRect { x, y, w, h, width } => {
+                put_coords(out, compact, &[*x, *y, *w, *h, *width]);
+            }
+            DrawCommand::Arc {
+                x,
+                y,
+                r,
+                start,
+                end,
+                width,
+            } => {
+                put_coords(out, compact, &[*x, *y, *r]);
+                put_f32s(out, &[*start, *end]);
+                put_coords(out, compact, &[*width]);
+            }
+            DrawCommand::Ellipse {
+                x,
+                y,
+                rx,
+                ry,
+                rotation,
+                width,
+            } => {
+                put_coords(out, compact, &[*x, *y, *rx, *ry]);
+                out.put_f32_le(*rotation);
+                put_coords(out, compact, &[*width]);
+            }
+            DrawCommand::StrokePoly {
+                width,
+                join,
+                cap,
+                closed,
+                points,
+            } => {
+                put_coords(out, compact, &[*width]);
+                out.put_u8(*join as u8);
+                out.put_u8(*cap as u8);
+                out.put_u8(u8::from(*closed));
+                put_points(out, compact, points);
+            }
+            DrawCommand::SetFont { name, size } => {
+                put_str(out, name);
+                out.put_f32_le(*size);
+            }
+            DrawCommand::SetTextAlign { align, baseline } => {
+                out.put_u8(*align as u8);
+                out.put_u8(*baseline as u8);
+            }
+            DrawCommand::DefineList { name, commands } => {
+                put_str(out, name);
+                // Lists can be far bigger than a u16 string
+                out.put_u32_le(commands.len() as u32);
+                out.put_slice(commands);
+            }
+            DrawCommand::CallList {
+                name,
+                x,
+                y,
+                rotation,
+                sx,
+                sy,
+            } => {
+                put_str(out, name);
+                put_coords(out, compact, &[*x, *y]);
+                put_f32s(out, &[*rotation, *sx, *sy]);
+            }
+            DrawCommand::SetResolution {
+                width,
+                height,
+                mode,
+            } => {
+                out.put_u16_le(*width);
+                out.put_u16_le(*height);
+                out.put_u8(*mode as u8);
+            }
+        }
+    }
+}
+
+fn put_f32s<B: BufMut>(out: &mut B, values: &[f32]) {
+    for v in values {
+        out.put_f32_le(*v);
+    }
+}
+
+fn put_coords<B: BufMut>(out: &mut B, compact: bool, values: &[f32]) {
+    for v in values {
+        if compact {
+            put_zigzag(out, quantize(*v));
+        } else {
+            out.put_f32_le(*v);
+        }
+    }
+}
+
+// Saturates out of range values, and NaN becomes 0
+fn quantize(v: f32) -> i32 {
+    (v * COORD_SCALE).round() as i32
+}
+
+/// Point lists are prefixed with a u16 count (a varint when compact); extra points are
+/// dropped. Compact points are each relative to the previous one.
+fn put_points<B: BufMut>(out: &mut B, compact: bool, points: &[(f32, f32)]) {
+    let count = points.len().min(u16::MAX as usize);
+    if !compact {
+        out.put_u16_le(count as u16);
+        for (x, y) in &points[..count] {
+            out.put_f32_le(*x);
+            out.put_f32_le(*y);
+        }
+        return;
+    }
+    put_varint(out, count as u32);
+    let mut last = (0i32, 0i32);
+    for (x, y) in &points[..count] {
+        let point = (quantize(*x), quantize(*y));
+        put_zigzag(out, point.0.wrapping_sub(last.0));
+        put_zigzag(out, point.1.wrapping_sub(last.1));
+        last = point;
+    }
+}
+
+fn put_zigzag<B: BufMut>(out: &mut B, v: i32) {
+    put_varint(out, ((v << 1) ^ (v >> 31)) as u32);
+}
+
+fn put_varint<B: BufMut>(out: &mut B, mut v: u32) {
+    while v >= 0x80 {
+        out.put_u8(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.put_u8(v as u8);
+}
+
+/// Strings are length-prefixed with a u16; longer strings are cut at a char boundary.
+fn put_str<B: BufMut>(out: &mut B, s: &str) {
+    let mut len = s.len().min(u16::MAX as usize);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    out.put_u16_le(len as u16);
+    out.put_slice(&s.as_bytes()[..len]);
+}
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum DecodeError {
+    #[error("bad payload length for command 0x{op:02x} at offset {offset}")]
+    BadLength { op: u8, offset: usize },
+    #[error("truncated command 0x{op:02x} at offset {offset}")]
+    Truncated { op: u8, offset: usize },
+}
+
+/// Streaming, bounds-checked reader over an encoded command stream.
+///
+/// Yields one `DrawCommand` at a time and silently skips unknown opcodes. The first
+/// malformed command yields an error and ends the iteration, since the rest of the
+/// stream can no longer be trusted.
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            offset: 0,
+            failed: false,
+        }
+    }
+
+    /// Byte offset of the next command.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Decodes the command at the current offset, or `None` if its opcode is unknown.
+    fn decode_next(&mut self) -> Result<Option<DrawCommand>, DecodeError> {
+        let start = self.offset;
+        let byte = self.data[start];
+        let op = byte & !OP_COMPACT;
+        let mut r = Reader {
+            data: self.data,
+            pos: start + 1,
+            op: byte,
+            start,
+            compact: byte & OP_COMPACT != 0,
+        };
+        let len = r.varint()? as usize;
+        r.need(len)?;
+        let end = r.pos + len;
+        // Fields past the payload are out of bounds even if the stream goes on
+        r.data = &self.data[..end];
+        self.offset = end;
+
+        let cmd = match op {
+            OP_CLEAR => DrawCommand::Clear {
+                r: r.u8()?,
+                g: r.u8()?,
+                b: r.u8()?,
+            },
+            OP_SET_COLOR => DrawCommand::SetColor {
+                r: r.u8()?,
+                g: r.u8()?,
+                b: r.u8()?,
+                a: r.u8()?,
+            },
+            OP_FILL_RECT => DrawCommand::FillRect {
+                x: r.coord()?,
+                y: r.coord()?,
+                w: r.coord()?,
+                h: r.coord()?,
+            },
+            OP_DRAW_LINE => DrawCommand::DrawLine {
+                x1: r.coord()?,
+                y1: r.coord()?,
+                x2: r.coord()?,
+                y2: r.coord()?,
+                width: r.coord()?,
+            },
+            OP_DRAW_TEXT => DrawCommand::DrawText {
+                x: r.coord()?,
+                y: r.coord()?,
+                text: r.string()?,
+            },
+            OP_LOAD_SOUND => DrawCommand::LoadSound {
+                name: r.string()?,
+                url: r.string()?,
+            },
+            OP_PLAY_SOUND => DrawCommand::PlaySound {
+                name: r.string()?,
+                looped: r.u8()? != 0,
+                volume: r.f32()?,
+            },
+            OP_STOP_SOUND => DrawCommand::StopSound { name: r.string()? },
+            OP_SET_VOLUME => DrawCommand::SetVolume {
+                name: r.string()?,
+                volume: r.f32()?,
+            },
+            OP_LOAD_IMAGE => DrawCommand::LoadImage {
+                name: r.string()?,
+                url: r.string()?,
+            },
+            OP_DRAW_IMAGE if r.compact => r.compact_image()?,
+            OP_DRAW_IMAGE => DrawCommand::DrawImage {
+                name: r.string()?,
+                x: r.f32()?,
+                y: r.f32()?,
+                w: r.f32()?,
+                h: r.f32()?,
+                sx: r.f32()?,
+                sy: r.f32()?,
+                sw: r.f32()?,
+                sh: r.f32()?,
+                rotation: r.f32()?,
+                ox: r.f32()?,
+                oy: r.f32()?,
+            },
+            OP_FILL_POLY => DrawCommand::FillPoly {
+                points: r.points()?,
+            },
+            OP_PUSH => DrawCommand::Push,
+            OP_POP => DrawCommand::Pop,
+            OP_TRANSLATE => DrawCommand::Translate {
+                x: r.coord()?,
+                y: r.coord()?,
+            },
+            OP_ROTATE => DrawCommand::Rotate { angle: r.f32()? },
+            OP_SCALE => DrawCommand::Scale {
+                x: r.f32()?,
+                y: r.f32()?,
+            },
+            OP_FILL_CIRCLE => DrawCommand::FillCircle {
+                x: r.coord()?,
+                y: r.coord()?,
+                r: r.coord()?,
+            },
+            OP_STROKE_CIRCLE => DrawCommand::StrokeCircle {
+                x: r.coord()?,
+                y: r.coord()?,
+                r: r.coord()?,
+                width: r.coord()?,
+            },
+            OP_STROKE_RECT => DrawCommand::StrokeRect {
+                x: r.coord()?,
+                y: r.coord()?,
+                w: r.coord()?,
+                h: r.coord()?,
+                width: r.coord()?,
+            },
+            OP_ARC => DrawCommand::Arc {
+                x: r.coord()?,
+                y: r.coord()?,
+                r: r.coord()?,
+                start: r.f32()?,
+                end: r.f32()?,
+                width: r.coord()?,
+            },
+            OP_ELLIPSE => DrawCommand::Ellipse {
+                x: r.coord()?,
+                y: r.coord()?,
+                rx: r.coord()?,
+                ry: r.coord()?,
+                rotation: r.f32()?,
+                width: r.coord()?,
+            },
+            OP_STROKE_POLY => DrawCommand::StrokePoly {
+                width: r.coord()?,
+                join: LineJoin::from_u8(r.u8()?),
+                cap: LineCap::from_u8(r.u8()?),
+                closed: r.u8()? != 0,
+                points: r.points()?,
+            },
+            OP_LOAD_FONT => DrawCommand::LoadFont {
+                name: r.string()?,
+                url: r.string()?,
+            },
+            OP_SET_FONT => DrawCommand::SetFont {
+                name: r.string()?,
+                size: r.f32()?,
+            },
+            OP_SET_TEXT_ALIGN => DrawCommand::SetTextAlign {
+                align: TextAlign::from_u8(r.u8()?),
+                baseline: TextBaseline::from_u8(r.u8()?),
+            },
+            OP_DEFINE_LIST => DrawCommand::DefineList {
+                name: r.string()?,
+                commands: r.blob()?,
+            },
+            OP_CALL_LIST => DrawCommand::CallList {
+                name: r.string()?,
+                x: r.coord()?,
+                y: r.coord()?,
+                rotation: r.f32()?,
+                sx: r.f32()?,
+                sy: r.f32()?,
+            },
+            OP_SET_RESOLUTION => DrawCommand::SetResolution {
+                width: r.u16()?,
+                height: r.u16()?,
+                mode: ScaleMode::from_u8(r.u8()?),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(cmd))
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = Result<DrawCommand, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed && self.offset < self.data.len() {
+            match self.decode_next() {
+                Ok(Some(cmd)) => return Some(Ok(cmd)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Re-encodes `stream`, including the lists it defines, with `encoding`. Used for
+/// clients that can't decode what the game was recorded in. Unknown commands are
+/// dropped and so is everything from the first malformed one.
+pub fn transcode(stream: &[u8], encoding: Encoding) -> Vec<u8> {
+    let mut out = Vec::with_capacity(stream.len());
+    for cmd in Decoder::new(stream).map_while(Result::ok) {
+        match cmd {
+            DrawCommand::DefineList { name, commands } => DrawCommand::DefineList {
+                name,
+                commands: transcode(&commands, encoding),
+            }
+            .encode(&mut out),
+            cmd => cmd.encode_with(&mut out, encoding),
+        }
+    }
+    out
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    op: u8,
+    start: usize,
+    // Payload is in `Encoding::Compact`
+    compact: bool,
+}
+
+impl Reader<'_> {
+    fn need(&self, n: usize) -> Result<(), DecodeError> {
+        if self.data.len() - self.pos < n {
+            return Err(DecodeError::Truncated {
+                op: self.op,
+                offset: self.start,
+            });
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8], DecodeError> {
+        self.need(n)?;
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Unsigned LEB128, at most 5 bytes.
+    fn varint(&mut self) -> Result<u32, DecodeError> {
+        let mut v: u32 = 0;
+        for i in 0..5 {
+            let b = self.u8()?;
+            v |= ((b & 0x7F) as u32) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(DecodeError::BadLength {
+            op: self.op,
+            offset: self.start,
+        })
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, DecodeError> {
+        let b = self.take(4)?;
+        Ok(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn zigzag(&mut self) -> Result<i32, DecodeError> {
+        let v = self.varint()?;
+        Ok((v >> 1) as i32 ^ -((v & 1) as i32))
+    }
+
+    /// A position, size, radius or line width, in either encoding.
+    fn coord(&mut self) -> Result<f32, DecodeError> {
+        if self.compact {
+            Ok(self.zigzag()? as f32 / COORD_SCALE)
+        } else {
+            self.f32()
+        }
+    }
+
+    fn points(&mut self) -> Result<Vec<(f32, f32)>, DecodeError> {
+        let count = if self.compact {
+            self.varint()? as usize
+        } else {
+            self.u16()? as usize
+        };
+        // The encoder caps counts at u16::MAX; a bigger one would overflow `need` on wasm32.
+        if count > u16::MAX as usize {
+            return Err(DecodeError::BadLength {
+                op: self.op,
+                offset: self.start,
+            });
+        }
+        // Check the whole payload up front so a bogus count can't drive a huge allocation.
+        self.need(count * if self.compact { 2 } else { 8 })?;
+        let mut points = Vec::with_capacity(count);
+        let mut last = (0i32, 0i32);
+        for _ in 0..count {
+            if self.compact {
+                last.0 = last.0.wrapping_add(self.zigzag()?);
+                last.1 = last.1.wrapping_add(self.zigzag()?);
+                points.push((last.0 as f32 / COORD_SCALE, last.1 as f32 / COORD_SCALE));
+            } else {
+                points.push((self.f32()?, self.f32()?));
+            }
+        }
+        Ok(points)
+    }
+
+    /// `DrawImage` with a flags byte saying which fields differ from their defaults.
+    fn compact_image(&mut self) -> Result<DrawCommand, DecodeError> {
+        let name = self.string()?;
+        let flags = self.u8()?;
+        let (x, y) = (self.coord()?, self.coord()?);
+        let (w, h) = if flags & IMAGE_SIZE != 0 {
+            (self.coord()?, self.coord()?)
+        } else {
+            (-1.0, -1.0)
+        };
+        let (sx, sy, sw, sh) = if flags & IMAGE_SOURCE != 0 {
+            (self.coord()?, self.coord()?, self.coord()?, self.coord()?)
+        } else {
+            (0.0, 0.0, -1.0, -1.0)
+        };
+        let rotation = if flags & IMAGE_ROTATION != 0 {
+            self.f32()?
+        } else {
+            0.0
+        };
+        let (ox, oy) = if flags & IMAGE_ORIGIN != 0 {
+            (self.coord()?, self.coord()?)
+        } else {
+            (0.0, 0.0)
+        };
+        Ok(DrawCommand::DrawImage {
+            name,
+            x,
+            y,
+            w,
+            h,
+            sx,
+            sy,
+            sw,
+            sh,
+            rotation,
+            ox,
+            oy,
+        })
+    }
+
+    fn blob(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+/// The constants `client/main.js` uses, as an ES module. The server serves it as
+/// `/protocol.js`, so the JS client can't drift from this file.
+pub fn js_module() -> String {
+    use crate::delta::{
+        ACK_TAG, FRAME_DELTA, FRAME_FULL, FRAME_INPUT_ACK, FRAME_SAME, MAX_BASE_AGE,
+        OP_COPY as DELTA_COPY, OP_INSERT as DELTA_INSERT,
+    };
+    use crate::input::{
+        PointerKind, GAMEPAD_BUTTON, GAMEPAD_LEN, GAMEPAD_TAG, KEY_ALT, KEY_CTRL, KEY_DOWN,
+        KEY_META, KEY_SHIFT, KEY_TAG, MAX_KEY_NAME, NO_FRAME, POINTER_LEN, POINTER_TAG, STAMP_LEN,
+        STAMP_TAG,
+    };
+    const POINTER_MOVE: u8 = PointerKind::Move.as_u8();
+    const POINTER_DOWN: u8 = PointerKind::Down.as_u8();
+    const POINTER_UP: u8 = PointerKind::Up.as_u8();
+    const POINTER_WHEEL: u8 = PointerKind::Wheel.as_u8();
+
+    let mut js = String::from("// Generated from engine/crates/engine/src/protocol.rs\n");
+    macro_rules! export {
+        ($($name:ident),* $(,)?) => {
+            $(js.push_str(&format!("export const {} = {:?};\n", stringify!($name), $name));)*
+        };
+    }
+    // Canvas property names, indexed by the wire byte
+    macro_rules! export_names {
+        ($($name:ident: $($value:expr),*;)*) => {
+            $(js.push_str(&format!(
+                "export const {} = {:?};\n",
+                stringify!($name),
+                [$($value.as_str()),*]
+            ));)*
+        };
+    }
+    export!(
+        OP_CLEAR,
+        OP_SET_COLOR,
+        OP_FILL_RECT,
+        OP_DRAW_LINE,
+        OP_DRAW_TEXT,
+        OP_LOAD_SOUND,
+        OP_PLAY_SOUND,
+        OP_STOP_SOUND,
+        OP_SET_VOLUME,
+        OP_LOAD_IMAGE,
+        OP_DRAW_IMAGE,
+        OP_FILL_POLY,
+        OP_PUSH,
+        OP_POP,
+        OP_TRANSLATE,
+        OP_ROTATE,
+        OP_SCALE,
+        OP_FILL_CIRCLE,
+        OP_STROKE_CIRCLE,
+        OP_STROKE_RECT,
+        OP_ARC,
+        OP_ELLIPSE,
+        OP_STROKE_POLY,
+        OP_LOAD_FONT,
+        OP_SET_FONT,
+        OP_SET_TEXT_ALIGN,
+        DEFAULT_FONT,
+        DEFAULT_FONT_SIZE,
+        FRAME_FULL,
+        FRAME_DELTA,
+        FRAME_SAME,
+        DELTA_COPY,
+        DELTA_INSERT,
+        ACK_TAG,
+        MAX_BASE_AGE,
+        OP_DEFINE_LIST,
+        OP_CALL_LIST,
+        MAX_LIST_DEPTH,
+        PROTOCOL_VERSION,
+        MIN_PROTOCOL_VERSION,
+        CAP_DELTA_FRAMES,
+        CAP_DISPLAY_LISTS,
+        OP_SET_RESOLUTION,
+        OP_COMPACT,
+        COORD_SCALE,
+        IMAGE_SIZE,
+        IMAGE_SOURCE,
+        IMAGE_ROTATION,
+        IMAGE_ORIGIN,
+        CAP_COMPACT_COORDS,
+        POINTER_TAG,
+        POINTER_LEN,
+        POINTER_MOVE,
+        POINTER_DOWN,
+        POINTER_UP,
+        POINTER_WHEEL,
+        GAMEPAD_TAG,
+        GAMEPAD_LEN,
+        GAMEPAD_BUTTON,
+        CAP_NAMED_KEYS,
+        KEY_TAG,
+        MAX_KEY_NAME,
+        KEY_DOWN,
+        KEY_SHIFT,
+        KEY_CTRL,
+        KEY_ALT,
+        KEY_META,
+        MAX_MESSAGE_LEN,
+        CAP_INPUT_SEQ,
+        FRAME_INPUT_ACK,
+        STAMP_TAG,
+        STAMP_LEN,
+        NO_FRAME,
+    );
+    export_names!(
+        LINE_JOINS: LineJoin::Miter, LineJoin::Round, LineJoin::Bevel;
+        LINE_CAPS: LineCap::Butt, LineCap::Round, LineCap::Square;
+        TEXT_ALIGNS: TextAlign::Left, TextAlign::Center, TextAlign::Right;
+        TEXT_BASELINES: TextBaseline::Middle, TextBaseline::Top, TextBaseline::Bottom,
+            TextBaseline::Alphabetic;
+        SCALE_MODES: ScaleMode::Letterbox, ScaleMode::Stretch, ScaleMode::Integer,
+            ScaleMode::Expand;
+    );
+    js
+}
@@ -0,0 +1,53 @@
+//! Rooms are games running side by side on one server, each with its own Lua state and
+//! players. The server hosts them; a game reaches them through `api.create_room`,
+//! `api.move_session`, `api.list_rooms` and `api.room_id`, and a room's script gets
+//! the params it was created with in `init(params)`.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// The room the server starts with, running the script it was given.
+pub const MAIN_ROOM: &str = "main";
+
+/// A room as `api.list_rooms` describes it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoomInfo {
+    pub id: String,
+    /// Script path, relative to the game's directory.
+    pub script: String,
+    pub players: usize,
+    pub params: Value,
+}
+
+/// What hosts rooms, for the room functions of the games it runs.
+pub trait RoomHost: Send + Sync {
+    /// Starts `script`, relative to the game's directory, in a new room whose
+    /// `init(params)` gets `params`. Returns the new room's id.
+    fn create_room(&self, script: &str, params: Value) -> anyhow::Result<String>;
+
+    /// Sends `session_id`, a player of this room, to `room_id` after this tick.
+    fn move_session(&self, session_id: &str, room_id: &str) -> anyhow::Result<()>;
+
+    fn list_rooms(&self) -> Vec<RoomInfo>;
+}
+
+/// The room a game runs in.
+#[derive(Clone)]
+pub struct Room {
+    pub id: String,
+    pub params: Value,
+    /// None for games run without a server, e.g. in tests; their room functions
+    /// raise errors.
+    pub host: Option<Arc<dyn RoomHost>>,
+}
+
+impl Default for Room {
+    fn default() -> Self {
+        Self {
+            id: MAIN_ROOM.to_string(),
+            params: Value::Null,
+            host: None,
+        }
+    }
+}
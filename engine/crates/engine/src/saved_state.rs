@@ -0,0 +1,230 @@
+//! Game state carried across a hot reload. What the old script's `on_save()` returns
+//! is saved as JSON and rebuilt for the new script's `on_restore(state)`. Spatial DBs,
+//! physics worlds and graphs in it are saved with their contents and recreated, and
+//! ones referenced from several places stay one object.
+//!
+//! Saved state is `{"state": ..., "userdata": [...]}`. In `state`, sequences are
+//! arrays, tables with only string keys are objects and other tables are
+//! `{"$pairs": [[key, value], ...]}`, so integer keys stay integers and keys starting
+//! with `$` aren't mistaken for these markers; engine objects are
+//! `{"$userdata": index}` into `userdata`, where a physics world comes after its
+//! spatial DB.
+
+use crate::graph_nav::Graph;
+use crate::physics::PhysicsWorld;
+use crate::spatial_db::SpatialDb;
+use crate::{GraphWrapper, PhysicsWrapper, SpatialDbWrapper};
+use anyhow::{anyhow, bail};
+use mlua::{AnyUserData, Lua, Table, Value as LuaValue};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const USERDATA_KEY: &str = "$userdata";
+const PAIRS_KEY: &str = "$pairs";
+
+// Tables nested deeper than this are taken to be a cycle
+const MAX_DEPTH: usize = 64;
+
+/// Saves `value`, as returned by `on_save()`.
+pub fn save(value: LuaValue) -> anyhow::Result<Value> {
+    let mut saver = Saver::default();
+    let state = saver.value(value, "state", 0)?;
+    Ok(json!({ "state": state, "userdata": saver.userdata }))
+}
+
+/// Rebuilds saved state in `lua`, for `on_restore(state)`.
+pub fn restore<'lua>(lua: &'lua Lua, saved: &Value) -> anyhow::Result<LuaValue<'lua>> {
+    let mut objects = Vec::new();
+    // Spatial DBs among `objects`, for the physics worlds that come after them
+    let mut dbs = HashMap::new();
+    for entry in saved["userdata"].as_array().into_iter().flatten() {
+        let object = if let Some(db) = entry.get("spatial_db") {
+            let db = SpatialDb::from_snapshot(serde_json::from_value(db.clone())?);
+            let db = Arc::new(Mutex::new(db));
+            dbs.insert(objects.len(), db.clone());
+            lua.create_userdata(SpatialDbWrapper(db))?
+        } else if let Some(world) = entry.get("physics_world") {
+            let db = world["db"]
+                .as_u64()
+                .and_then(|index| dbs.get(&(index as usize)))
+                .ok_or_else(|| anyhow!("saved physics world without its spatial db"))?;
+            let snapshot = serde_json::from_value(world["world"].clone())?;
+            let world = PhysicsWorld::from_snapshot(db.clone(), snapshot);
+            lua.create_userdata(PhysicsWrapper(Arc::new(Mutex::new(world))))?
+        } else if let Some(graph) = entry.get("graph") {
+            let graph: Graph = serde_json::from_value(graph.clone())?;
+            lua.create_userdata(GraphWrapper(Arc::new(Mutex::new(graph))))?
+        } else {
+            bail!("unknown saved object {}", entry);
+        };
+        objects.push(object);
+    }
+    restore_value(lua, &saved["state"], &objects)
+}
+
+#[derive(Default)]
+struct Saver {
+    userdata: Vec<Value>,
+    // Index in `userdata` of each engine object saved so far, by address
+    saved: HashMap<usize, usize>,
+}
+
+impl Saver {
+    // `path` names the value in errors, e.g. `state.players[2]`
+    fn value(&mut self, value: LuaValue, path: &str, depth: usize) -> anyhow::Result<Value> {
+        Ok(match value {
+            LuaValue::Nil => Value::Null,
+            LuaValue::Boolean(b) => Value::Bool(b),
+            LuaValue::Integer(i) => Value::from(i),
+            LuaValue::Number(n) => serde_json::Number::from_f64(n)
+                .map(Value::Number)
+                .ok_or_else(|| anyhow!("{}: can't save {}", path, n))?,
+            LuaValue::String(s) => Value::String(
+                s.to_str()
+                    .map_err(|_| anyhow!("{}: can't save a string that isn't UTF-8", path))?
+                    .to_string(),
+            ),
+            LuaValue::Table(table) => self.table(table, path, depth)?,
+            LuaValue::UserData(object) => json!({ USERDATA_KEY: self.object(&object, path)? }),
+            other => bail!("{}: can't save a {}", path, other.type_name()),
+        })
+    }
+
+    fn table(&mut self, table: Table, path: &str, depth: usize) -> anyhow::Result<Value> {
+        if depth == MAX_DEPTH {
+            bail!("{}: tables nested too deeply, is there a cycle?", path);
+        }
+        let pairs = table
+            .pairs::<LuaValue, LuaValue>()
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let len = pairs.len();
+
+        // Keys 1 to n, each once
+        if len > 0
+            && pairs.iter().all(
+                |(key, _)| matches!(key, LuaValue::Integer(i) if *i >= 1 && *i as usize <= len),
+            )
+        {
+            let mut items = vec![Value::Null; len];
+            for (key, value) in pairs {
+                if let LuaValue::Integer(i) = key {
+                    let path = format!("{}[{}]", path, i);
+                    items[i as usize - 1] = self.value(value, &path, depth + 1)?;
+                }
+            }
+            return Ok(Value::Array(items));
+        }
+
+        // Keys starting with `$` would read back as markers, so those tables are pairs
+        if pairs.iter().all(|(key, _)| match key {
+            LuaValue::String(key) => !key.as_bytes().starts_with(b"$"),
+            _ => false,
+        }) {
+            let mut map = Map::new();
+            for (key, value) in pairs {
+                let Value::String(key) = self.value(key, path, depth + 1)? else {
+                    unreachable!("string keys save as strings");
+                };
+                let value = self.value(value, &format!("{}.{}", path, key), depth + 1)?;
+                map.insert(key, value);
+            }
+            return Ok(Value::Object(map));
+        }
+
+        let mut items = Vec::with_capacity(len);
+        for (key, value) in pairs {
+            let key = self.value(key, path, depth + 1)?;
+            let value = self.value(value, &format!("{}[{}]", path, key), depth + 1)?;
+            items.push(json!([key, value]));
+        }
+        Ok(json!({ PAIRS_KEY: items }))
+    }
+
+    // Index of an engine object in `userdata`, saving it the first time
+    fn object(&mut self, object: &AnyUserData, path: &str) -> anyhow::Result<usize> {
+        if let Ok(db) = object.borrow::<SpatialDbWrapper>() {
+            return Ok(self.spatial_db(&db.0));
+        }
+        if let Ok(world) = object.borrow::<PhysicsWrapper>() {
+            return Ok(self.add(&world.0, |saver| {
+                let world = world.0.lock().unwrap();
+                let db = saver.spatial_db(world.db());
+                json!({ "physics_world": { "db": db, "world": world.snapshot() } })
+            }));
+        }
+        if let Ok(graph) = object.borrow::<GraphWrapper>() {
+            return Ok(self.add(&graph.0, |_| json!({ "graph": &*graph.0.lock().unwrap() })));
+        }
+        bail!("{}: can't save this userdata", path)
+    }
+
+    fn spatial_db(&mut self, db: &Arc<Mutex<SpatialDb>>) -> usize {
+        self.add(
+            db,
+            |_| json!({ "spatial_db": db.lock().unwrap().snapshot() }),
+        )
+    }
+
+    fn add<T>(&mut self, object: &Arc<Mutex<T>>, save: impl FnOnce(&mut Self) -> Value) -> usize {
+        let address = Arc::as_ptr(object) as usize;
+        if let Some(&index) = self.saved.get(&address) {
+            return index;
+        }
+        let saved = save(self);
+        let index = self.userdata.len();
+        self.userdata.push(saved);
+        self.saved.insert(address, index);
+        index
+    }
+}
+
+fn restore_value<'lua>(
+    lua: &'lua Lua,
+    value: &Value,
+    objects: &[AnyUserData<'lua>],
+) -> anyhow::Result<LuaValue<'lua>> {
+    Ok(match value {
+        Value::Null => LuaValue::Nil,
+        Value::Bool(b) => LuaValue::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => LuaValue::Integer(i),
+            None => LuaValue::Number(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => LuaValue::String(lua.create_string(s)?),
+        Value::Array(items) => {
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for (i, item) in items.iter().enumerate() {
+                table.raw_set(i + 1, restore_value(lua, item, objects)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Value::Object(map) => {
+            if let (1, Some(index)) = (map.len(), map.get(USERDATA_KEY)) {
+                let object = index
+                    .as_u64()
+                    .and_then(|index| objects.get(index as usize))
+                    .ok_or_else(|| anyhow!("saved state refers to a missing object {}", index))?;
+                return Ok(LuaValue::UserData(object.clone()));
+            }
+            let table = lua.create_table()?;
+            if let (1, Some(Value::Array(pairs))) = (map.len(), map.get(PAIRS_KEY)) {
+                for pair in pairs {
+                    let [key, value] = pair.as_array().map(Vec::as_slice).unwrap_or_default()
+                    else {
+                        bail!("saved table pair isn't [key, value]: {}", pair);
+                    };
+                    table.raw_set(
+                        restore_value(lua, key, objects)?,
+                        restore_value(lua, value, objects)?,
+                    )?;
+                }
+            } else {
+                for (key, value) in map {
+                    table.raw_set(key.as_str(), restore_value(lua, value, objects)?)?;
+                }
+            }
+            LuaValue::Table(table)
+        }
+    })
+}
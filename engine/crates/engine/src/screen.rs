@@ -0,0 +1,114 @@
+use bytes::{Bytes, BytesMut};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::protocol::{DrawCommand, ScaleMode, DEFAULT_HEIGHT, DEFAULT_WIDTH};
+
+// `"resolution"` entry of a game's metadata.json
+#[derive(Deserialize)]
+struct Resolution {
+    width: u16,
+    height: u16,
+    scaling: Option<String>,
+}
+
+/// The game's virtual resolution, which version of it every session has been sent,
+/// and the size of each viewer's screen.
+pub struct Screen {
+    width: u16,
+    height: u16,
+    mode: ScaleMode,
+    version: u64,
+    sent: HashMap<String, u64>,
+    viewports: HashMap<String, (f32, f32)>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            mode: ScaleMode::default(),
+            version: 0,
+            sent: HashMap::new(),
+            viewports: HashMap::new(),
+        }
+    }
+}
+
+impl Screen {
+    /// Applies the `resolution` entry of the game's metadata.json, if there is one.
+    pub fn apply_metadata(&mut self, meta: &serde_json::Value) -> anyhow::Result<()> {
+        let Some(resolution) = meta.get("resolution") else {
+            return Ok(());
+        };
+        let resolution = Resolution::deserialize(resolution)
+            .map_err(|e| anyhow::anyhow!("metadata.json: bad resolution: {}", e))?;
+        let mode = match resolution.scaling.as_deref() {
+            Some(name) => ScaleMode::from_name(name)
+                .ok_or_else(|| anyhow::anyhow!("metadata.json: unknown scaling mode '{}'", name))?,
+            None => self.mode,
+        };
+        self.set(resolution.width, resolution.height, mode)
+            .map_err(|e| anyhow::anyhow!("metadata.json: {}", e))
+    }
+
+    pub fn set(&mut self, width: u16, height: u16, mode: ScaleMode) -> Result<(), String> {
+        if width == 0 || height == 0 {
+            return Err(format!("invalid resolution {}x{}", width, height));
+        }
+        if (width, height, mode) != (self.width, self.height, self.mode) {
+            self.width = width;
+            self.height = height;
+            self.mode = mode;
+            self.version += 1;
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self) -> ScaleMode {
+        self.mode
+    }
+
+    /// Records the size of `session_id`'s screen, in CSS pixels.
+    pub fn set_viewport(&mut self, session_id: &str, width: f32, height: f32) {
+        self.viewports
+            .insert(session_id.to_string(), (width, height));
+    }
+
+    /// Virtual size visible to `session_id`, which only differs from the resolution
+    /// in `Expand` mode.
+    pub fn size_for(&self, session_id: &str) -> (f32, f32) {
+        let viewport = self.viewports.get(session_id).copied().unwrap_or_default();
+        self.mode
+            .visible_size(self.width as f32, self.height as f32, viewport)
+    }
+
+    pub fn command(&self) -> DrawCommand {
+        DrawCommand::SetResolution {
+            width: self.width,
+            height: self.height,
+            mode: self.mode,
+        }
+    }
+
+    /// A `SetResolution` command if `session_id` hasn't been sent the current one.
+    pub fn take_update(&mut self, session_id: &str) -> Bytes {
+        let mut out = BytesMut::new();
+        if self.sent.get(session_id) != Some(&self.version) {
+            self.command().encode(&mut out);
+            self.sent.insert(session_id.to_string(), self.version);
+        }
+        out.freeze()
+    }
+
+    /// Forgets what `session_id` was sent, so the resolution goes out again.
+    pub fn reset_session(&mut self, session_id: &str) {
+        self.sent.remove(session_id);
+    }
+
+    pub fn remove_session(&mut self, session_id: &str) {
+        self.sent.remove(session_id);
+        self.viewports.remove(session_id);
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 
+use serde::{Deserialize, Serialize};
+
 // --- Tipos Geométricos ---
 
 #[derive(Clone, Debug)]
@@ -10,7 +12,7 @@ pub enum Shape {
     Segment { x2: f32, y2: f32 }, // Relativo ao x1,y1 do objeto para simplificar movimento? Não, melhor absoluto para paredes estáticas e relativo para móveis. Vamos simplificar: Posição central + dados
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Entity {
     id: u64,
     x: f32,
@@ -21,7 +23,7 @@ struct Entity {
     tag_hash: u64, // Hash da string "wall", "enemy", etc.
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum EntityKind {
     Circle { radius: f32 },
     Segment { x2: f32, y2: f32 }, // x,y no Entity é o start. x2,y2 aqui é o end.
@@ -29,6 +31,15 @@ pub enum EntityKind {
 
 // --- Spatial DB ---
 
+/// A SpatialDb's entities, from which the grid is rebuilt, for carrying it across a
+/// hot reload.
+#[derive(Serialize, Deserialize)]
+pub struct SpatialDbSnapshot {
+    next_id: u64,
+    cell_size: f32,
+    entities: Vec<Entity>,
+}
+
 pub struct SpatialDb {
     next_id: u64,
     cell_size: f32,
@@ -46,6 +57,27 @@ impl SpatialDb {
         }
     }
 
+    pub fn snapshot(&self) -> SpatialDbSnapshot {
+        let mut entities: Vec<Entity> = self.entities.values().cloned().collect();
+        entities.sort_by_key(|e| e.id);
+        SpatialDbSnapshot {
+            next_id: self.next_id,
+            cell_size: self.cell_size,
+            entities,
+        }
+    }
+
+    pub fn from_snapshot(snapshot: SpatialDbSnapshot) -> Self {
+        let mut db = Self::new(snapshot.cell_size);
+        db.next_id = snapshot.next_id;
+        for e in snapshot.entities {
+            let id = e.id;
+            db.entities.insert(id, e);
+            db.add_to_grid(id);
+        }
+        db
+    }
+
     fn calculate_hash(tag: &str) -> u64 {
         let mut s = DefaultHasher::new();
         tag.hash(&mut s);
@@ -0,0 +1,197 @@
+//! Server-side text metrics.
+//!
+//! `api.measure_text` and the word wrapping done by `api.draw_text` both go through
+//! `TextLayout`, so Lua always sees the same numbers the layout was built from.
+//! Fonts registered with `api.load_font` are measured with their own glyph advances;
+//! anything else (e.g. the default `monospace`) with those of the bundled
+//! DejaVu Sans Mono.
+
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::protocol::{TextAlign, TextBaseline, DEFAULT_FONT, DEFAULT_FONT_SIZE};
+/// Distance between wrapped lines, as a multiple of the font size.
+pub const LINE_HEIGHT: f32 = 1.2;
+/// Font used to measure fonts we have no file for. The server's rasterizer draws them
+/// with it too.
+pub const BUNDLED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+fn bundled_font() -> &'static FontArc {
+    static FONT: OnceLock<FontArc> = OnceLock::new();
+    FONT.get_or_init(|| FontArc::try_from_slice(BUNDLED_FONT).expect("Bundled font is valid"))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextStyle {
+    pub font: String,
+    pub size: f32,
+    pub align: TextAlign,
+    pub baseline: TextBaseline,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: DEFAULT_FONT.to_string(),
+            size: DEFAULT_FONT_SIZE,
+            align: TextAlign::default(),
+            baseline: TextBaseline::default(),
+        }
+    }
+}
+
+/// The file behind an `/assets/...` URL, or `None` for other URLs and paths that would
+/// leave `assets_dir`.
+pub fn asset_path(assets_dir: &Path, url: &str) -> Option<PathBuf> {
+    let rel = Path::new(url.strip_prefix("/assets/")?);
+    if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    Some(assets_dir.join(rel))
+}
+
+/// Style and `api.push` stack of a `TextLayout`.
+pub type TextState = (TextStyle, Vec<TextStyle>);
+
+#[derive(Default)]
+pub struct TextLayout {
+    fonts: HashMap<String, FontArc>,
+    /// Directory served as `/assets`, used to resolve font URLs.
+    assets_dir: Option<PathBuf>,
+    pub style: TextStyle,
+    /// Styles saved by `api.push`, since canvas `save()` covers the font as well.
+    saved: Vec<TextStyle>,
+}
+
+impl TextLayout {
+    pub fn new(assets_dir: Option<PathBuf>) -> Self {
+        Self {
+            assets_dir,
+            ..Default::default()
+        }
+    }
+
+    /// Back to the default style with an empty stack, as at the start of a frame.
+    pub fn reset(&mut self) {
+        self.style = TextStyle::default();
+        self.saved.clear();
+    }
+
+    /// The current style and stack, so a later frame can continue from this point.
+    pub fn state(&self) -> TextState {
+        (self.style.clone(), self.saved.clone())
+    }
+
+    pub fn set_state(&mut self, (style, saved): TextState) {
+        self.style = style;
+        self.saved = saved;
+    }
+
+    pub fn push(&mut self) {
+        self.saved.push(self.style.clone());
+    }
+
+    pub fn pop(&mut self) {
+        if let Some(style) = self.saved.pop() {
+            self.style = style;
+        }
+    }
+
+    /// Loads the font behind `url` for measuring. Only `/assets/...` URLs can be
+    /// resolved; others return `Ok(false)` and keep using the bundled font.
+    pub fn load_font(&mut self, name: &str, url: &str) -> anyhow::Result<bool> {
+        let Some(path) = self.resolve_asset(url) else {
+            return Ok(false);
+        };
+        let data = std::fs::read(&path)
+            .map_err(|e| anyhow::anyhow!("failed to read font {:?}: {}", path, e))?;
+        let font = FontArc::try_from_vec(data)
+            .map_err(|_| anyhow::anyhow!("invalid font file {:?}", path))?;
+        self.fonts.insert(name.to_string(), font);
+        Ok(true)
+    }
+
+    fn resolve_asset(&self, url: &str) -> Option<PathBuf> {
+        asset_path(self.assets_dir.as_ref()?, url)
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.style.size * LINE_HEIGHT
+    }
+
+    /// Width of a single line in the current style.
+    pub fn line_width(&self, text: &str) -> f32 {
+        let size = self.style.size;
+        let font = self
+            .fonts
+            .get(&self.style.font)
+            .unwrap_or_else(|| bundled_font());
+
+        // `size` is an em size, like CSS; ab_glyph scales by ascent - descent.
+        let units_per_em = font.units_per_em().unwrap_or(1000.0);
+        let scale = PxScale::from(size * font.height_unscaled() / units_per_em);
+        let scaled = font.as_scaled(scale);
+
+        let mut width = 0.0;
+        let mut prev = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(prev) = prev {
+                width += scaled.kern(prev, id);
+            }
+            width += scaled.h_advance(id);
+            prev = Some(id);
+        }
+        width
+    }
+
+    /// Splits `text` into lines: on `\n`, and greedily on whitespace when `max_width`
+    /// is given. A single word wider than `max_width` gets a line of its own.
+    pub fn wrap(&self, text: &str, max_width: Option<f32>) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let Some(max_width) = max_width else {
+                lines.push(paragraph.to_string());
+                continue;
+            };
+
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                if line.is_empty() {
+                    line.push_str(word);
+                    continue;
+                }
+                let candidate = format!("{} {}", line, word);
+                if self.line_width(&candidate) <= max_width {
+                    line = candidate;
+                } else {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Width of the widest line and total height of the block.
+    pub fn measure(&self, text: &str, max_width: Option<f32>) -> (f32, f32) {
+        let lines = self.wrap(text, max_width);
+        let width = lines.iter().map(|l| self.line_width(l)).fold(0.0, f32::max);
+        (width, lines.len() as f32 * self.line_height())
+    }
+
+    /// Positions the lines of a block anchored at `y` so that the current baseline
+    /// applies to the block as a whole (e.g. `middle` centers all lines on `y`).
+    pub fn line_offsets(&self, count: usize) -> impl Iterator<Item = f32> {
+        let lh = self.line_height();
+        let span = count.saturating_sub(1) as f32 * lh;
+        let first = match self.style.baseline {
+            TextBaseline::Top | TextBaseline::Alphabetic => 0.0,
+            TextBaseline::Middle => -span / 2.0,
+            TextBaseline::Bottom => -span,
+        };
+        (0..count).map(move |i| first + i as f32 * lh)
+    }
+}
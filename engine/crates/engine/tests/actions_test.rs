@@ -0,0 +1,127 @@
+use engine::actions::{Actions, Controls, TouchButton};
+use engine::input::{Modifiers, PointerKind, GAMEPAD_BUTTON};
+use engine::GameState;
+
+const CONTROLS: &str = r#"{
+    "actions": {
+        "left": { "keys": ["ArrowLeft", "KeyA"], "gamepad": ["left_x-", "dpad_left"] },
+        "right": { "keys": [39], "gamepad": ["left_x+"] },
+        "fire": { "keys": ["Space"], "gamepad": ["right_trigger"], "pointer": [1] }
+    },
+    "touch": [[{ "label": "<", "action": "left" }, { "label": "*", "action": "fire" }]]
+}"#;
+
+fn pressed(action: &str) -> Vec<(String, bool)> {
+    vec![(action.to_string(), true)]
+}
+
+fn released(action: &str) -> Vec<(String, bool)> {
+    vec![(action.to_string(), false)]
+}
+
+#[test]
+fn test_actions_stay_down_while_any_binding_is() {
+    let mut actions = Actions::new(Controls::parse(CONTROLS).unwrap());
+    assert_eq!(actions.key("p1", "ArrowLeft", true), pressed("left"));
+    assert_eq!(actions.key("p1", "KeyA", true), vec![]);
+    assert_eq!(actions.key("p1", "ArrowLeft", false), vec![]);
+    assert!(actions.is_down("p1", "left"));
+    assert!(!actions.is_down("p2", "left"));
+    assert_eq!(actions.key("p1", "KeyA", false), released("left"));
+    assert!(!actions.is_down("p1", "left"));
+
+    // Unbound keys and releases of keys that weren't down change nothing
+    assert_eq!(actions.key("p1", "KeyQ", true), vec![]);
+    assert_eq!(actions.key("p1", "Space", false), vec![]);
+
+    assert_eq!(actions.pointer("p1", 1, true), pressed("fire"));
+    assert_eq!(actions.pointer("p1", 3, true), vec![]);
+    actions.remove_session("p1");
+    assert!(!actions.is_down("p1", "fire"));
+}
+
+#[test]
+fn test_gamepad_bindings_need_the_threshold_in_their_direction() {
+    let mut actions = Actions::new(Controls::parse(CONTROLS).unwrap());
+    assert_eq!(actions.gamepad("p1", 1, "left_x", -0.3), vec![]);
+    assert_eq!(actions.gamepad("p1", 1, "left_x", -0.8), pressed("left"));
+    // Swinging the stick across releases one side and presses the other
+    assert_eq!(
+        actions.gamepad("p1", 1, "left_x", 0.9),
+        vec![("left".to_string(), false), ("right".to_string(), true)]
+    );
+    assert_eq!(actions.gamepad("p1", 1, "left_x", 0.0), released("right"));
+
+    assert_eq!(
+        actions.gamepad("p1", 2, "right_trigger", 0.6),
+        pressed("fire")
+    );
+    assert_eq!(actions.gamepad("p1", 1, "right_trigger", 1.0), vec![]);
+    assert_eq!(actions.gamepad("p1", 2, "right_trigger", 0.0), vec![]);
+    assert_eq!(
+        actions.gamepad("p1", 1, "right_trigger", 0.0),
+        released("fire")
+    );
+}
+
+#[test]
+fn test_controls_are_validated() {
+    let controls = Controls::parse(CONTROLS).unwrap();
+    assert_eq!(controls.touch_key("left"), Some("ArrowLeft"));
+    assert_eq!(controls.touch_key("right"), Some("ArrowRight"));
+    assert_eq!(
+        controls.touch[0][1],
+        TouchButton {
+            label: "*".to_string(),
+            action: "fire".to_string()
+        }
+    );
+
+    for bad in [
+        r#"{ "actions": { "jump": { "keys": [0] } } }"#,
+        r#"{ "actions": { "jump": { "keys": ["Arrow Up"] } } }"#,
+        r#"{ "actions": { "jump": { "gamepad": ["a"] } }, "touch": [[{ "label": "^", "action": "jump" }]] }"#,
+        r#"{ "actions": { "jump": { "buttons": ["a"] } } }"#,
+    ] {
+        assert!(Controls::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_on_action_and_is_action_down() {
+    let script = r#"
+        events = {}
+        function on_action(session_id, action, pressed)
+            table.insert(events, string.format("%s %s %s", session_id, action, pressed))
+        end
+        function held(session_id)
+            return string.format("%s %s", api.is_action_down(session_id, "left"),
+                api.is_action_down(session_id, "fire"))
+        end
+    "#;
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("controls.json"), CONTROLS).unwrap();
+    let game = GameState::new(script, Some(&dir.path().join("main.lua")))
+        .expect("Failed to init game");
+
+    // Every kind of input drives actions, legacy codes included
+    game.handle_input("p1", 37, true).unwrap();
+    game.handle_key("p1", "KeyA", true, Modifiers::default())
+        .unwrap();
+    game.handle_pointer("p1", 5.0, 5.0, 1, PointerKind::Down)
+        .unwrap();
+    assert_eq!(game.eval("return held('p1')"), r#"String("true true")"#);
+    game.handle_input("p1", 37, false).unwrap();
+    game.handle_key("p1", "KeyA", false, Modifiers::default())
+        .unwrap();
+    game.handle_gamepad("p2", 1, GAMEPAD_BUTTON + 14, 1.0)
+        .unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("p1 left true, p1 fire true, p1 left false, p2 left true")"#
+    );
+    assert_eq!(game.eval("return held('p1')"), r#"String("false true")"#);
+
+    game.on_disconnect("p2").unwrap();
+    assert_eq!(game.eval("return held('p2')"), r#"String("false false")"#);
+}
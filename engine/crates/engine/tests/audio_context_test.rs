@@ -1,10 +1,8 @@
 use engine::GameState;
 use bytes::Buf;
+use engine::protocol::OP_PLAY_SOUND;
 use std::io::Cursor;
 
-// Replicate OpCodes from lib.rs for testing
-const OP_PLAY_SOUND: u8 = 0x07;
-
 #[test]
 fn test_audio_context_separation() {
     let script = r#"
@@ -19,7 +17,7 @@ fn test_audio_context_separation() {
         end
     "#;
 
-    let game = GameState::new(script).expect("Failed to init game");
+    let game = GameState::new(script, None).expect("Failed to init game");
 
     // 1. Run Update
     // This should write "global_boom" to event_buffer
@@ -36,6 +34,7 @@ fn test_audio_context_separation() {
     
     // --- Verify First Sound (Global) ---
     assert_eq!(cursor.get_u8(), OP_PLAY_SOUND, "Expected OP_PLAY_SOUND (Global)");
+    cursor.advance(1); // Payload length
     
     let len1 = cursor.get_u16_le() as usize;
     let pos1 = cursor.position() as usize;
@@ -51,6 +50,7 @@ fn test_audio_context_separation() {
 
     // --- Verify Second Sound (Local) ---
     assert_eq!(cursor.get_u8(), OP_PLAY_SOUND, "Expected OP_PLAY_SOUND (Local)");
+    cursor.advance(1); // Payload length
     
     let len2 = cursor.get_u16_le() as usize;
     let pos2 = cursor.position() as usize;
@@ -81,7 +81,7 @@ fn test_draw_clears_local_buffer() {
         end
     "#;
 
-    let game = GameState::new(script).expect("Failed to init");
+    let game = GameState::new(script, None).expect("Failed to init");
 
     // Frame 1
     game.update(0.16).unwrap();
@@ -104,6 +104,7 @@ fn test_draw_clears_local_buffer() {
     while cursor.has_remaining() {
         if cursor.get_u8() == OP_PLAY_SOUND {
             count += 1;
+            cursor.advance(1); // Payload length
             let len = cursor.get_u16_le() as usize;
             cursor.advance(len); // Name
             cursor.advance(1); // Loop
@@ -0,0 +1,201 @@
+use bytes::{Bytes, BytesMut};
+use engine::delta::{
+    decode_ack, encode_ack, DeltaError, FrameDecoder, FrameEncoder, FRAME_DELTA, FRAME_FULL,
+    FRAME_INPUT_ACK, FRAME_SAME, MAX_BASE_AGE, OP_COPY, OP_INSERT,
+};
+use engine::protocol::DrawCommand;
+
+// A mostly static scene with one moving circle, like a typical HUD over a still level.
+fn scene(t: u32) -> Bytes {
+    let mut buf = BytesMut::new();
+    DrawCommand::Clear { r: 0, g: 0, b: 0 }.encode(&mut buf);
+    for i in 0..100 {
+        DrawCommand::FillRect {
+            x: (i % 10) as f32 * 80.0,
+            y: (i / 10) as f32 * 60.0,
+            w: 70.0,
+            h: 50.0,
+        }
+        .encode(&mut buf);
+    }
+    DrawCommand::FillCircle {
+        x: t as f32,
+        y: 300.0,
+        r: 8.0,
+    }
+    .encode(&mut buf);
+    DrawCommand::DrawText {
+        x: 10.0,
+        y: 10.0,
+        text: format!("Score: {}", t),
+    }
+    .encode(&mut buf);
+    buf.freeze()
+}
+
+#[test]
+fn test_round_trip_with_acks() {
+    let mut enc = FrameEncoder::new();
+    let mut dec = FrameDecoder::new();
+
+    let mut kinds = Vec::new();
+    for t in 0..20 {
+        // Repeat some frames so the SAME marker is exercised
+        let frame = scene(t / 2);
+        let wire = enc.encode(frame.clone());
+        kinds.push(wire[0]);
+
+        let (id, decoded) = dec.decode(&wire).expect("Frame should decode");
+        assert_eq!(decoded, &frame[..]);
+        enc.ack(id);
+
+        if wire[0] == FRAME_DELTA {
+            assert!(wire.len() < frame.len() / 4, "Delta should be small");
+        }
+    }
+    assert_eq!(kinds[0], FRAME_FULL);
+    assert!(kinds.contains(&FRAME_DELTA));
+    assert!(kinds.contains(&FRAME_SAME));
+}
+
+#[test]
+fn test_no_delta_without_ack() {
+    let mut enc = FrameEncoder::new();
+    for t in 0..5 {
+        assert_eq!(enc.encode(scene(t))[0], FRAME_FULL);
+    }
+}
+
+#[test]
+fn test_lost_and_reordered_frames() {
+    let mut enc = FrameEncoder::new();
+    let mut dec = FrameDecoder::new();
+
+    let first = enc.encode(scene(0));
+    let (id, _) = dec.decode(&first).unwrap();
+    enc.ack(id);
+
+    // Two frames in flight, delivered in reverse order; a third one is lost.
+    let a = enc.encode(scene(1));
+    let b = enc.encode(scene(2));
+    let _lost = enc.encode(scene(3));
+    assert_eq!(dec.decode(&b).unwrap().1, &scene(2)[..]);
+    assert_eq!(dec.decode(&a).unwrap().1, &scene(1)[..]);
+
+    // A late ack for an older frame doesn't move the base backwards
+    enc.ack(2);
+    enc.ack(1);
+    let next = enc.encode(scene(4));
+    assert_eq!(dec.decode(&next).unwrap().1, &scene(4)[..]);
+}
+
+#[test]
+fn test_stale_base_falls_back_to_full_frame() {
+    let mut enc = FrameEncoder::new();
+    let first = enc.encode(scene(0));
+    assert_eq!(first[0], FRAME_FULL);
+    enc.ack(0);
+
+    // Acks stop arriving; once the base is too old the encoder sends full frames again.
+    let mut kinds = Vec::new();
+    for t in 1..=MAX_BASE_AGE + 1 {
+        kinds.push(enc.encode(scene(t))[0]);
+    }
+    assert_eq!(kinds[0], FRAME_DELTA);
+    assert_eq!(*kinds.last().unwrap(), FRAME_FULL);
+}
+
+#[test]
+fn test_missing_base_is_reported() {
+    let mut enc = FrameEncoder::new();
+    enc.encode(scene(0));
+    enc.ack(0);
+    let delta = enc.encode(scene(1));
+
+    // A fresh decoder (e.g. after a reconnect) never saw frame 0
+    let mut dec = FrameDecoder::new();
+    assert_eq!(dec.decode(&delta), Err(DeltaError::MissingBase(0)));
+}
+
+#[test]
+fn test_malformed_frames_do_not_panic() {
+    let mut dec = FrameDecoder::new();
+    dec.decode(&[FRAME_FULL, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+
+    assert_eq!(dec.decode(&[]), Err(DeltaError::Empty));
+    assert_eq!(dec.decode(&[FRAME_DELTA, 1, 0]), Err(DeltaError::Truncated));
+    assert_eq!(
+        dec.decode(&[0x7F, 1, 0, 0, 0]),
+        Err(DeltaError::UnknownKind(0x7F))
+    );
+
+    // Delta for frame 1 against frame 0 (the 3-byte frame above)
+    let delta = |ops: &[u8]| [&[FRAME_DELTA, 1, 0, 0, 0, 0, 0, 0, 0], ops].concat();
+
+    // Copy of 5 bytes from offset 2
+    let bad_copy = delta(&[OP_COPY, 2, 0, 0, 0, 5, 0, 0, 0]);
+    assert_eq!(dec.decode(&bad_copy), Err(DeltaError::BadCopy(0)));
+
+    // Insert longer than the remaining data
+    let bad_insert = delta(&[OP_INSERT, 0xFF, 0xFF, 0, 0, 1]);
+    assert_eq!(dec.decode(&bad_insert), Err(DeltaError::Truncated));
+
+    assert_eq!(
+        dec.decode(&delta(&[0x7F])),
+        Err(DeltaError::UnknownOp(0x7F))
+    );
+}
+
+#[test]
+fn test_ack_round_trip() {
+    let ack = encode_ack(0xDEAD_BEEF);
+    assert_eq!(decode_ack(&ack), Some(0xDEAD_BEEF));
+    // Input packets are two bytes and must never look like an ack
+    assert_eq!(decode_ack(&[0xAC, 1]), None);
+}
+
+#[test]
+fn test_input_acks_ride_on_every_frame_kind() {
+    let mut enc = FrameEncoder::new();
+    let mut dec = FrameDecoder::new();
+
+    // Nothing to acknowledge until an input was handled
+    let wire = enc.encode(scene(0));
+    assert_eq!(wire[0], FRAME_FULL);
+    let (id, _) = dec.decode(&wire).unwrap();
+    enc.ack(id);
+    assert_eq!(dec.input_ack(), None);
+
+    enc.ack_input(5);
+    // Inputs that arrived late don't take the acknowledgement back
+    enc.ack_input(3);
+    let mut kinds = Vec::new();
+    for t in [0, 1, 1] {
+        let frame = scene(t);
+        let wire = enc.encode(frame.clone());
+        kinds.push(wire[0] & !FRAME_INPUT_ACK);
+        assert_ne!(wire[0] & FRAME_INPUT_ACK, 0);
+        assert_eq!(wire[5..9], 5u32.to_le_bytes());
+
+        let (id, decoded) = dec.decode(&wire).expect("Frame should decode");
+        assert_eq!(decoded, &frame[..]);
+        assert_eq!(dec.input_ack(), Some(5));
+        enc.ack(id);
+    }
+    assert_eq!(kinds, [FRAME_SAME, FRAME_DELTA, FRAME_SAME]);
+
+    // A frame that arrives late doesn't either
+    enc.ack_input(9);
+    let newer = enc.encode(scene(2));
+    let mut older = newer.clone();
+    older[5..9].copy_from_slice(&7u32.to_le_bytes());
+    dec.decode(&newer).unwrap();
+    dec.decode(&older).unwrap();
+    assert_eq!(dec.input_ack(), Some(9));
+
+    // The acknowledgement must be there if the flag says so
+    assert_eq!(
+        dec.decode(&[FRAME_FULL | FRAME_INPUT_ACK, 1, 0, 0, 0, 2]),
+        Err(DeltaError::Truncated)
+    );
+}
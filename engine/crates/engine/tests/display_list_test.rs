@@ -0,0 +1,173 @@
+use engine::protocol::{Decoder, DrawCommand};
+use engine::GameState;
+
+fn decode(bytes: &[u8]) -> Vec<DrawCommand> {
+    Decoder::new(bytes)
+        .collect::<Result<_, _>>()
+        .expect("Commands should decode")
+}
+
+// Lists rebuilt every frame, with the tree's colour driven by input
+const SCRIPT: &str = r#"
+    shade = 100
+    function on_input(session_id, code, active)
+        shade = code
+    end
+    function draw(session_id)
+        api.begin_list("tree")
+        api.set_color(0, shade, 0)
+        api.fill_rect(0, 0, 10, 20)
+        api.end_list()
+        api.call_list("tree", 50, 60)
+        api.call_list("tree", 80, 60, 0, 2)
+    end
+"#;
+
+fn defined_lists(game: &GameState, session_id: &str) -> Vec<String> {
+    decode(&game.take_list_updates(session_id))
+        .into_iter()
+        .map(|cmd| match cmd {
+            DrawCommand::DefineList { name, .. } => name,
+            other => panic!("Unexpected command: {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn test_recorded_commands_are_replaced_by_calls() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    let cmds = decode(&game.draw("sess_1").expect("Draw failed"));
+
+    let call = |x: f32, s: f32| DrawCommand::CallList {
+        name: "tree".to_string(),
+        x,
+        y: 60.0,
+        rotation: 0.0,
+        sx: s,
+        sy: s,
+    };
+    assert_eq!(cmds, vec![call(50.0, 1.0), call(80.0, 2.0)]);
+
+    match &decode(&game.take_list_updates("sess_1"))[..] {
+        [DrawCommand::DefineList { name, commands }] => {
+            assert_eq!(name, "tree");
+            assert_eq!(decode(commands).len(), 2);
+        }
+        other => panic!("Unexpected updates: {:?}", other),
+    }
+}
+
+#[test]
+fn test_lists_are_sent_once_per_session_until_changed() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+
+    game.draw("sess_1").unwrap();
+    assert_eq!(defined_lists(&game, "sess_1"), vec!["tree"]);
+    assert_eq!(defined_lists(&game, "sess_2"), vec!["tree"]);
+
+    // Identical re-recording is not sent again
+    game.draw("sess_1").unwrap();
+    assert!(defined_lists(&game, "sess_1").is_empty());
+
+    // A changed list goes out to every session
+    game.handle_input("sess_2", 200, true).unwrap();
+    game.draw("sess_1").unwrap();
+    assert_eq!(defined_lists(&game, "sess_1"), vec!["tree"]);
+    assert_eq!(defined_lists(&game, "sess_2"), vec!["tree"]);
+
+    // A reconnect starts from an empty client
+    game.on_connect("sess_1").unwrap();
+    assert_eq!(defined_lists(&game, "sess_1"), vec!["tree"]);
+    assert!(defined_lists(&game, "sess_2").is_empty());
+}
+
+#[test]
+fn test_inline_lists_for_clients_without_list_support() {
+    let script = r#"
+        function draw(session_id)
+            api.begin_list("leaky")
+            api.push()
+            api.pop()
+            api.pop()
+            api.push()
+            api.end_list()
+            api.call_list("leaky", 1, 2, 0.5, 3)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let frame = game.draw("sess_1").expect("Draw failed");
+
+    // The list's stray pop is dropped and its unmatched push is closed
+    assert_eq!(
+        decode(&game.inline_lists(&frame)),
+        vec![
+            DrawCommand::Push,
+            DrawCommand::Translate { x: 1.0, y: 2.0 },
+            DrawCommand::Rotate { angle: 0.5 },
+            DrawCommand::Scale { x: 3.0, y: 3.0 },
+            DrawCommand::Push,
+            DrawCommand::Pop,
+            DrawCommand::Push,
+            DrawCommand::Pop,
+            DrawCommand::Pop,
+        ]
+    );
+}
+
+#[test]
+fn test_list_misuse_is_an_error() {
+    let cases = [
+        (
+            r#"api.begin_list("a") api.begin_list("b")"#,
+            "begin_list('b') while recording 'a'",
+        ),
+        (r#"api.end_list()"#, "end_list() without begin_list()"),
+        (
+            r#"api.call_list("missing")"#,
+            "unknown display list 'missing'",
+        ),
+        (
+            r#"api.begin_list("a")"#,
+            "begin_list('a') without end_list()",
+        ),
+    ];
+    for (body, expected) in cases {
+        let script = format!("function draw(session_id) {} end", body);
+        let game = GameState::new(&script, None).expect("Failed to init game");
+        let err = game.draw("sess_1").unwrap_err();
+        assert!(
+            format!("{:#}", err).contains(expected),
+            "{:?} should contain {:?}",
+            err,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_unfinished_list_does_not_leak_into_next_frame() {
+    let script = r#"
+        frame = 0
+        function draw(session_id)
+            frame = frame + 1
+            if frame == 1 then
+                api.begin_list("a")
+                error("boom")
+            end
+            api.fill_rect(1, 2, 3, 4)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    assert!(game.draw("sess_1").is_err());
+
+    let cmds = decode(&game.draw("sess_1").expect("Draw failed"));
+    assert_eq!(
+        cmds,
+        vec![DrawCommand::FillRect {
+            x: 1.0,
+            y: 2.0,
+            w: 3.0,
+            h: 4.0
+        }]
+    );
+}
@@ -0,0 +1,171 @@
+use engine::GameState;
+
+// Reloads `old` as `new`, as the server does on a file change
+fn reload(old: &GameState, new: &str) -> GameState {
+    let saved = old.save_state().expect("on_save failed");
+    let game = GameState::new(new, None).expect("Failed to init game");
+    if let Some(saved) = saved {
+        game.restore_state(&saved).expect("on_restore failed");
+    }
+    game
+}
+
+const RESTORE: &str = r#"
+    function on_restore(state)
+        players, scores, entities, db, phys, graph = state.players, state.scores,
+            state.entities, state.db, state.phys, state.graph
+    end
+"#;
+
+#[test]
+fn test_on_save_state_reaches_on_restore() {
+    let old = GameState::new(
+        r#"
+        players = { alice = { x = 1.5, y = 2, name = "Alice", alive = true, items = {} } }
+        scores = { 10, 20, 30 }
+        entities = { [7] = "ship", [42] = "rock" }
+        function on_save()
+            return { players = players, scores = scores, entities = entities }
+        end
+        "#,
+        None,
+    )
+    .expect("Failed to init game");
+    let game = reload(&old, RESTORE);
+
+    let checks = [
+        "players.alice.x == 1.5 and math.type(players.alice.y) == 'integer'",
+        "players.alice.name == 'Alice' and players.alice.alive == true",
+        "next(players.alice.items) == nil",
+        "#scores == 3 and scores[3] == 30",
+        // Sparse integer keys stay integers
+        "entities[7] == 'ship' and entities[42] == 'rock' and entities['7'] == nil",
+    ];
+    for check in checks {
+        assert_eq!(
+            game.eval(&format!("return {}", check)),
+            "Boolean(true)",
+            "{}",
+            check
+        );
+    }
+}
+
+#[test]
+fn test_engine_objects_are_recreated_with_their_contents() {
+    let old = GameState::new(
+        r#"
+        db = api.new_spatial_db(50)
+        phys = api.new_physics_world(db)
+        graph = api.new_graph()
+        ship = db:add_circle(100, 100, 10, "ship")
+        wall = db:add_segment(0, 0, 500, 0, "wall")
+        phys:add_body(ship, { mass = 1 })
+        phys:set_velocity(ship, 30, 0)
+        graph:add_node(1, 0, 0)
+        graph:add_node(2, 10, 0)
+        graph:add_edge(1, 2)
+        function on_save()
+            return { db = db, phys = phys, graph = graph, ship = ship }
+        end
+        "#,
+        None,
+    )
+    .expect("Failed to init game");
+    let game = reload(&old, RESTORE);
+
+    let checks = [
+        "select(1, db:get_position(1)) == 100",
+        "#db:query_range(100, 100, 20, 'ship') == 1",
+        "#db:query_rect(-10, -10, 510, 10, 'wall') == 1",
+        "select(1, phys:get_velocity(1)) == 30",
+        "#graph:find_path(1, 2) == 2",
+        // New entities don't reuse ids
+        "db:add_circle(0, 0, 1, 'rock') == 3",
+    ];
+    for check in checks {
+        assert_eq!(
+            game.eval(&format!("return {}", check)),
+            "Boolean(true)",
+            "{}",
+            check
+        );
+    }
+
+    // The physics world still moves things in the restored spatial db
+    game.eval("phys:step(1)");
+    assert_eq!(
+        game.eval("return select(1, db:get_position(1)) > 100"),
+        "Boolean(true)"
+    );
+}
+
+#[test]
+fn test_the_tick_carries_over() {
+    let old =
+        GameState::new("function on_save() return {} end", None).expect("Failed to init game");
+    for _ in 0..5 {
+        old.update(0.1).expect("Update failed");
+    }
+    let game = reload(&old, "");
+    assert_eq!(game.tick(), 5);
+}
+
+#[test]
+fn test_scripts_without_on_save_start_over() {
+    let old = GameState::new("players = { 1 }", None).expect("Failed to init game");
+    assert_eq!(old.save_state().unwrap(), None);
+}
+
+#[test]
+fn test_unsaveable_state_is_an_error() {
+    let old = GameState::new(
+        r#"
+        function on_save() return { players = { bob = { think = function() end } } } end
+        "#,
+        None,
+    )
+    .expect("Failed to init game");
+    let error = old.save_state().unwrap_err().to_string();
+    assert!(error.contains("state.players.bob.think"), "{}", error);
+
+    let old = GameState::new(
+        r#"
+        local loop = {}
+        loop.self = loop
+        function on_save() return loop end
+        "#,
+        None,
+    )
+    .expect("Failed to init game");
+    let error = old.save_state().unwrap_err().to_string();
+    assert!(error.contains("cycle"), "{}", error);
+}
+
+#[test]
+fn test_keys_like_the_save_formats_markers_stay_plain_keys() {
+    let old = GameState::new(
+        r#"
+        function on_save()
+            return { players = { ["$userdata"] = 1 }, scores = { ["$pairs"] = { 2 } } }
+        end
+        "#,
+        None,
+    )
+    .expect("Failed to init game");
+    let game = reload(&old, RESTORE);
+
+    let checks = [
+        "type(players) == 'table' and players['$userdata'] == 1",
+        "next(players, '$userdata') == nil",
+        "scores['$pairs'][1] == 2 and next(scores, '$pairs') == nil",
+    ];
+    for check in checks {
+        assert_eq!(
+            game.eval(&format!("return {}", check)),
+            "Boolean(true)",
+            "{}",
+            check
+        );
+    }
+}
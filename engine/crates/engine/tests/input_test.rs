@@ -0,0 +1,438 @@
+use engine::delta;
+use engine::input::{
+    gamepad_control_name, key_name, legacy_key_code, Input, Modifiers, PointerKind, Stamp,
+    GAMEPAD_BUTTON, KEY_TAG, MAX_KEY_NAME, NO_FRAME, POINTER_LEN, STAMP_TAG,
+};
+use engine::{GameState, InputTiming};
+use serde_json::json;
+
+#[test]
+fn test_input_packets_round_trip() {
+    let inputs = [
+        Input::Key {
+            code: 37,
+            down: true,
+        },
+        Input::Key {
+            code: 0xB0,
+            down: false,
+        },
+        Input::NamedKey {
+            name: "ArrowLeft".to_string(),
+            down: true,
+            mods: Modifiers::default(),
+        },
+        Input::NamedKey {
+            name: "KeyZ".to_string(),
+            down: false,
+            mods: Modifiers {
+                shift: true,
+                ctrl: false,
+                alt: true,
+                meta: true,
+            },
+        },
+        Input::Pointer {
+            kind: PointerKind::Move,
+            button: 0,
+            x: 12.5,
+            y: -3.0,
+        },
+        Input::Pointer {
+            kind: PointerKind::Down,
+            button: 3,
+            x: 799.0,
+            y: 599.0,
+        },
+        Input::Pointer {
+            kind: PointerKind::Wheel,
+            button: -2,
+            x: 0.0,
+            y: 0.0,
+        },
+        Input::Gamepad {
+            pad: 2,
+            control: 1,
+            value: -0.5,
+        },
+        Input::Gamepad {
+            pad: 1,
+            control: GAMEPAD_BUTTON + 7,
+            value: 0.25,
+        },
+    ];
+    for input in inputs {
+        assert_eq!(Input::decode(&input.encode()), Some(input.clone()));
+    }
+    let pointer = Input::Pointer {
+        kind: PointerKind::Up,
+        button: 1,
+        x: 1.0,
+        y: 2.0,
+    };
+    assert_eq!(pointer.encode().len(), POINTER_LEN);
+}
+
+#[test]
+fn test_decode_rejects_other_packets() {
+    assert_eq!(Input::decode(&delta::encode_ack(7)), None);
+    assert_eq!(Input::decode(&[]), None);
+    assert_eq!(Input::decode(&[1, 2, 3]), None);
+
+    let mut pointer = Input::Pointer {
+        kind: PointerKind::Move,
+        button: 0,
+        x: 1.0,
+        y: 2.0,
+    }
+    .encode();
+    // Unknown kind
+    pointer[1] = 9;
+    assert_eq!(Input::decode(&pointer), None);
+    // Positions must be usable
+    pointer[1] = 0;
+    pointer[3..7].copy_from_slice(&f32::NAN.to_le_bytes());
+    assert_eq!(Input::decode(&pointer), None);
+    // Wrong tag
+    pointer[0] = 0xB1;
+    assert_eq!(Input::decode(&pointer), None);
+}
+
+#[test]
+fn test_named_key_packets_are_validated() {
+    let named = |name: &str| [&[KEY_TAG, 1][..], name.as_bytes()].concat();
+    assert!(Input::decode(&named("Digit1")).is_some());
+    // Without a name the packet is a legacy key
+    assert_eq!(
+        Input::decode(&named("")),
+        Some(Input::Key {
+            code: KEY_TAG,
+            down: true
+        })
+    );
+    // Names are plain ASCII of bounded length
+    assert_eq!(Input::decode(&named("Arrow Left")), None);
+    assert_eq!(Input::decode(&named("Ä")), None);
+    assert!(Input::decode(&named(&"K".repeat(MAX_KEY_NAME))).is_some());
+    assert_eq!(Input::decode(&named(&"K".repeat(MAX_KEY_NAME + 1))), None);
+}
+
+#[test]
+fn test_key_names_map_to_legacy_codes() {
+    for (name, code) in [
+        ("ArrowLeft", 37),
+        ("ArrowDown", 40),
+        ("Space", 32),
+        ("KeyA", 65),
+        ("KeyZ", 90),
+        ("Digit0", 48),
+        ("Numpad7", 103),
+        ("F1", 112),
+        ("F12", 123),
+        ("Quote", 222),
+    ] {
+        assert_eq!(legacy_key_code(name), Some(code), "{}", name);
+        assert_eq!(key_name(code).as_deref(), Some(name));
+    }
+    // Keys that share a code map back to the first of them
+    assert_eq!(legacy_key_code("ShiftRight"), Some(16));
+    assert_eq!(key_name(16).as_deref(), Some("ShiftLeft"));
+    for name in ["Keya", "KeyAB", "F13", "F0", "MediaPlayPause", ""] {
+        assert_eq!(legacy_key_code(name), None, "{}", name);
+    }
+    assert_eq!(key_name(0), None);
+}
+
+#[test]
+fn test_on_input_gets_codes_or_names() {
+    let script = r#"
+        events = {}
+        function on_input(session_id, key, is_down, mods)
+            local held = ""
+            for _, m in ipairs({"shift", "ctrl", "alt", "meta"}) do
+                if mods[m] then held = held .. "+" .. m end
+            end
+            table.insert(events, string.format("%s %s %s%s", session_id, key, is_down, held))
+        end
+    "#;
+    let shift = Modifiers {
+        shift: true,
+        ..Default::default()
+    };
+
+    // Scripts written for numeric codes keep getting them from named keys
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.handle_input("sess_1", 37, true).unwrap();
+    game.handle_key("sess_1", "ArrowLeft", false, shift)
+        .unwrap();
+    game.handle_key("sess_1", "MediaPlayPause", true, shift)
+        .unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("sess_1 37 true, sess_1 37 false+shift")"#
+    );
+
+    // metadata.json's "keys": "names" turns legacy codes into names
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("metadata.json"), r#"{ "keys": "names" }"#).unwrap();
+    let game = GameState::new(script, Some(&dir.path().join("main.lua")))
+        .expect("Failed to init game");
+    game.handle_input("sess_1", 90, true).unwrap();
+    game.handle_input("sess_1", 0, true).unwrap();
+    game.handle_key("sess_1", "IntlYen", false, shift).unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("sess_1 KeyZ true, sess_1 IntlYen false+shift")"#
+    );
+}
+
+#[test]
+fn test_on_message_receives_json_as_lua_values() {
+    let script = r#"
+        events = {}
+        function on_message(session_id, payload)
+            if type(payload) == "table" then
+                payload = string.format("%s:%s:%d", payload.kind, payload.text, #payload.tags)
+            end
+            table.insert(events, session_id .. " " .. tostring(payload))
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_message("sess_1", &json!("ALICE")).unwrap();
+    game.handle_message(
+        "sess_1",
+        &json!({"kind": "chat", "text": "hi", "tags": [1, 2]}),
+    )
+    .unwrap();
+    game.handle_message("sess_2", &json!(null)).unwrap();
+    game.handle_message("sess_2", &json!(1.5)).unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("sess_1 ALICE, sess_1 chat:hi:2, sess_2 nil, sess_2 1.5")"#
+    );
+
+    // Games without the callback ignore messages
+    let game = GameState::new("", None).unwrap();
+    game.handle_message("sess_1", &json!("hello")).unwrap();
+}
+
+#[test]
+fn test_api_send_queues_json_for_the_session() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    assert!(game.take_messages().is_empty());
+
+    game.eval(r#"api.send("sess_1", { kind = "score", scores = { 3, 1 } })"#);
+    game.eval(r#"api.send("sess_2", "share")"#);
+    game.eval(r#"api.send("sess_2", nil)"#);
+    assert_eq!(
+        game.take_messages(),
+        [
+            (
+                "sess_1".to_string(),
+                json!({"kind": "score", "scores": [3, 1]})
+            ),
+            ("sess_2".to_string(), json!("share")),
+            ("sess_2".to_string(), json!(null)),
+        ]
+    );
+    assert!(game.take_messages().is_empty());
+
+    // Messages have the same size limit both ways
+    let result = game.eval(r#"return pcall(api.send, "sess_1", string.rep("x", 5000))"#);
+    assert!(result.starts_with("Boolean(false)"), "{}", result);
+    assert!(game.take_messages().is_empty());
+}
+
+#[test]
+fn test_gamepad_packets_are_validated() {
+    let gamepad = |pad, value| {
+        Input::Gamepad {
+            pad,
+            control: 0,
+            value,
+        }
+        .encode()
+    };
+    // Pads are numbered from 1
+    assert_eq!(Input::decode(&gamepad(0, 1.0)), None);
+    assert_eq!(Input::decode(&gamepad(1, f32::INFINITY)), None);
+    assert_eq!(
+        Input::decode(&gamepad(1, 3.0)),
+        Some(Input::Gamepad {
+            pad: 1,
+            control: 0,
+            value: 1.0
+        })
+    );
+}
+
+#[test]
+fn test_gamepad_control_names_follow_the_standard_mapping() {
+    assert_eq!(gamepad_control_name(0), "left_x");
+    assert_eq!(gamepad_control_name(3), "right_y");
+    assert_eq!(gamepad_control_name(4), "axis_4");
+    assert_eq!(gamepad_control_name(GAMEPAD_BUTTON), "a");
+    assert_eq!(gamepad_control_name(GAMEPAD_BUTTON + 7), "right_trigger");
+    assert_eq!(gamepad_control_name(GAMEPAD_BUTTON + 15), "dpad_right");
+    assert_eq!(gamepad_control_name(GAMEPAD_BUTTON + 17), "button_17");
+}
+
+#[test]
+fn test_on_pointer_receives_virtual_coordinates() {
+    let script = r#"
+        events = {}
+        function on_pointer(session_id, x, y, button, state)
+            table.insert(events, string.format("%s %g %g %d %s", session_id, x, y, button, state))
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_pointer("sess_1", 10.5, 20.0, 0, PointerKind::Move)
+        .unwrap();
+    game.handle_pointer("sess_1", 10.5, 20.0, 1, PointerKind::Down)
+        .unwrap();
+    game.handle_pointer("sess_2", 0.0, 0.0, -3, PointerKind::Wheel)
+        .unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("sess_1 10.5 20 0 move, sess_1 10.5 20 1 down, sess_2 0 0 -3 wheel")"#
+    );
+
+    // Games without the callback ignore pointers
+    let game = GameState::new("", None).unwrap();
+    game.handle_pointer("sess_1", 1.0, 1.0, 1, PointerKind::Up)
+        .unwrap();
+}
+
+#[test]
+fn test_on_axis_and_get_axis_track_each_pad() {
+    let script = r#"
+        events = {}
+        function on_axis(session_id, axis, value, pad)
+            table.insert(events, string.format("%s %s %g %d", session_id, axis, value, pad))
+        end
+        function axes(session_id)
+            return string.format("%g %g %g %g",
+                api.get_axis(session_id, "left_x"),
+                api.get_axis(session_id, "left_x", 2),
+                api.get_axis(session_id, "right_trigger"),
+                api.get_axis(session_id, "no_such_axis"))
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_gamepad("sess_1", 1, 0, -0.75).unwrap();
+    game.handle_gamepad("sess_1", 2, 0, 0.5).unwrap();
+    game.handle_gamepad("sess_1", 1, GAMEPAD_BUTTON + 7, 1.0)
+        .unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("sess_1 left_x -0.75 1, sess_1 left_x 0.5 2, sess_1 right_trigger 1 1")"#
+    );
+    assert_eq!(
+        game.eval("return axes('sess_1')"),
+        r#"String("-0.75 0.5 1 0")"#
+    );
+    assert_eq!(game.eval("return axes('sess_2')"), r#"String("0 0 0 0")"#);
+
+    // Released controls read 0, and a disconnect releases everything
+    game.handle_gamepad("sess_1", 1, 0, 0.0).unwrap();
+    assert_eq!(game.eval("return axes('sess_1')"), r#"String("0 0.5 1 0")"#);
+    game.on_disconnect("sess_1").unwrap();
+    assert_eq!(game.eval("return axes('sess_1')"), r#"String("0 0 0 0")"#);
+}
+
+#[test]
+fn test_stamped_packets_split_into_stamp_and_input() {
+    let stamp = Stamp {
+        seq: 7,
+        frame: NO_FRAME,
+    };
+    for input in [
+        Input::Key {
+            code: 37,
+            down: true,
+        },
+        Input::Gamepad {
+            pad: 1,
+            control: 0,
+            value: 0.5,
+        },
+    ] {
+        let packet = input.encode();
+        let stamped = stamp.wrap(&packet);
+        assert_eq!(stamped[0], STAMP_TAG);
+        assert_eq!(Stamp::split(&stamped), (Some(stamp), &packet[..]));
+        assert_eq!(Input::decode(Stamp::split(&stamped).1), Some(input));
+    }
+
+    // Unstamped packets pass through, and a stamp needs an input behind it
+    let key = [37, 1];
+    assert_eq!(Stamp::split(&key), (None, &key[..]));
+    let empty = stamp.wrap(&[]);
+    assert_eq!(Stamp::split(&empty).0, None);
+    assert_eq!(Input::decode(Stamp::split(&empty).1), None);
+}
+
+#[test]
+fn test_callbacks_get_input_timing() {
+    let script = r#"
+        events = {}
+        local function log(name, seq, frame)
+            table.insert(events, string.format("%s %s %s", name, tostring(seq), tostring(frame)))
+        end
+        function on_input(session_id, key, is_down, mods, seq, frame) log("key", seq, frame) end
+        function on_pointer(session_id, x, y, button, state, seq, frame) log("pointer", seq, frame) end
+        function on_axis(session_id, axis, value, pad, seq, frame) log("axis", seq, frame) end
+        function on_action(session_id, action, pressed, seq, frame) log(action, seq, frame) end
+        function update(dt) end
+    "#;
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("controls.json"),
+        r#"{ "actions": { "fire": { "keys": ["Space"] } } }"#,
+    )
+    .unwrap();
+    let game = GameState::new(script, Some(&dir.path().join("main.lua")))
+        .expect("Failed to init game");
+
+    assert_eq!(game.tick(), 0);
+    game.update(0.016).unwrap();
+    game.update(0.016).unwrap();
+    assert_eq!(game.tick(), 2);
+    assert_eq!(game.eval("return api.tick()"), "Integer(2)");
+
+    let timing = InputTiming {
+        seq: Some(3),
+        frame: Some(1),
+    };
+    let space = Input::NamedKey {
+        name: "Space".to_string(),
+        down: true,
+        mods: Modifiers::default(),
+    };
+    game.handle("sess_1", space, timing).unwrap();
+    let pointer = Input::Pointer {
+        kind: PointerKind::Move,
+        button: 0,
+        x: 1.0,
+        y: 1.0,
+    };
+    game.handle("sess_1", pointer, InputTiming::default())
+        .unwrap();
+    let axis = Input::Gamepad {
+        pad: 1,
+        control: 0,
+        value: 1.0,
+    };
+    let timing = InputTiming {
+        seq: Some(4),
+        frame: None,
+    };
+    game.handle("sess_1", axis, timing).unwrap();
+    assert_eq!(
+        game.eval("return table.concat(events, ', ')"),
+        r#"String("key 3 1, fire 3 1, pointer nil nil, axis 4 nil")"#
+    );
+}
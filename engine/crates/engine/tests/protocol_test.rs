@@ -0,0 +1,570 @@
+use bytes::BytesMut;
+use engine::protocol::{
+    transcode, Capabilities, DecodeError, Decoder, DrawCommand, Encoding, LineCap, LineJoin,
+    ScaleMode, TextAlign, TextBaseline, OP_CLEAR, OP_COMPACT, OP_DRAW_IMAGE, OP_FILL_POLY,
+    OP_FILL_RECT, OP_STROKE_POLY,
+};
+
+fn sample_commands() -> Vec<DrawCommand> {
+    vec![
+        DrawCommand::Clear { r: 8, g: 8, b: 12 },
+        DrawCommand::SetColor {
+            r: 255,
+            g: 100,
+            b: 0,
+            a: 128,
+        },
+        DrawCommand::FillRect {
+            x: 10.0,
+            y: 20.5,
+            w: 100.0,
+            h: -4.0,
+        },
+        DrawCommand::DrawLine {
+            x1: 0.0,
+            y1: 0.0,
+            x2: 800.0,
+            y2: 600.0,
+            width: 2.5,
+        },
+        DrawCommand::DrawText {
+            x: 5.0,
+            y: 5.0,
+            text: "Olá, mundo".to_string(),
+        },
+        DrawCommand::LoadSound {
+            name: "laser".to_string(),
+            url: "/assets/laser-shot.wav".to_string(),
+        },
+        DrawCommand::PlaySound {
+            name: "laser".to_string(),
+            looped: true,
+            volume: 0.6,
+        },
+        DrawCommand::StopSound {
+            name: "laser".to_string(),
+        },
+        DrawCommand::SetVolume {
+            name: "laser".to_string(),
+            volume: 0.25,
+        },
+        DrawCommand::LoadImage {
+            name: "bg".to_string(),
+            url: "/assets/bg.png".to_string(),
+        },
+        DrawCommand::DrawImage {
+            name: "bg".to_string(),
+            x: 1.0,
+            y: 2.0,
+            w: -1.0,
+            h: -1.0,
+            sx: 0.0,
+            sy: 0.0,
+            sw: 32.0,
+            sh: 32.0,
+            rotation: 1.5,
+            ox: 16.0,
+            oy: 16.0,
+        },
+        DrawCommand::FillPoly {
+            points: vec![(0.0, 0.0), (10.0, 0.0), (5.0, 8.0)],
+        },
+        DrawCommand::FillPoly { points: vec![] },
+        DrawCommand::Push,
+        DrawCommand::Translate { x: -120.0, y: 64.0 },
+        DrawCommand::Rotate { angle: 0.785 },
+        DrawCommand::Scale { x: 2.0, y: 0.5 },
+        DrawCommand::Pop,
+        DrawCommand::FillCircle {
+            x: 400.0,
+            y: 300.0,
+            r: 12.0,
+        },
+        DrawCommand::StrokeCircle {
+            x: 400.0,
+            y: 300.0,
+            r: 20.0,
+            width: 2.0,
+        },
+        DrawCommand::StrokeRect {
+            x: 10.0,
+            y: 10.0,
+            w: 40.0,
+            h: 20.0,
+            width: 3.0,
+        },
+        DrawCommand::Arc {
+            x: 50.0,
+            y: 50.0,
+            r: 10.0,
+            start: 0.0,
+            end: 3.0,
+            width: 0.0,
+        },
+        DrawCommand::Ellipse {
+            x: 60.0,
+            y: 70.0,
+            rx: 30.0,
+            ry: 10.0,
+            rotation: 0.5,
+            width: 1.0,
+        },
+        DrawCommand::StrokePoly {
+            width: 4.0,
+            join: LineJoin::Round,
+            cap: LineCap::Square,
+            closed: false,
+            points: vec![(0.0, 0.0), (10.0, 10.0), (20.0, 0.0)],
+        },
+        DrawCommand::LoadFont {
+            name: "pixel".to_string(),
+            url: "/assets/pixel.ttf".to_string(),
+        },
+        DrawCommand::SetFont {
+            name: "pixel".to_string(),
+            size: 24.0,
+        },
+        DrawCommand::SetTextAlign {
+            align: TextAlign::Right,
+            baseline: TextBaseline::Alphabetic,
+        },
+        DrawCommand::DefineList {
+            name: "tree".to_string(),
+            commands: vec![0x01, 10, 20, 30],
+        },
+        DrawCommand::CallList {
+            name: "tree".to_string(),
+            x: 5.0,
+            y: 6.0,
+            rotation: 0.5,
+            sx: 2.0,
+            sy: -1.0,
+        },
+        DrawCommand::SetResolution {
+            width: 320,
+            height: 180,
+            mode: ScaleMode::Integer,
+        },
+    ]
+}
+
+fn encode_all(cmds: &[DrawCommand]) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for cmd in cmds {
+        cmd.encode(&mut buf);
+    }
+    buf
+}
+
+#[test]
+fn test_round_trip_all_opcodes() {
+    let cmds = sample_commands();
+    let buf = encode_all(&cmds);
+
+    let decoded: Vec<DrawCommand> = Decoder::new(&buf)
+        .collect::<Result<_, _>>()
+        .expect("Stream should decode");
+    assert_eq!(decoded, cmds);
+}
+
+#[test]
+fn test_compact_round_trip_all_opcodes() {
+    // Every coordinate in the samples is a multiple of 1/16, so nothing is lost
+    let cmds = sample_commands();
+    let mut buf = BytesMut::new();
+    for cmd in &cmds {
+        cmd.encode_with(&mut buf, Encoding::Compact);
+    }
+    assert!(buf.len() < encode_all(&cmds).len());
+
+    let decoded: Vec<DrawCommand> = Decoder::new(&buf)
+        .collect::<Result<_, _>>()
+        .expect("Stream should decode");
+    assert_eq!(decoded, cmds);
+}
+
+#[test]
+fn test_compact_encoding_quantizes_and_omits_defaults() {
+    let encode = |cmd: DrawCommand| {
+        let mut buf = Vec::new();
+        cmd.encode_with(&mut buf, Encoding::Compact);
+        buf
+    };
+
+    let rect = encode(DrawCommand::FillRect {
+        x: 1.03,
+        y: -300.0,
+        w: 0.0,
+        h: 1e12,
+    });
+    assert_eq!(rect[0], OP_FILL_RECT | OP_COMPACT);
+    assert_eq!(
+        Decoder::new(&rect).next(),
+        Some(Ok(DrawCommand::FillRect {
+            x: 1.0,
+            y: -300.0,
+            w: 0.0,
+            h: i32::MAX as f32 / 16.0,
+        }))
+    );
+
+    // A plain sprite is the name, a flags byte and two coordinates
+    let image = DrawCommand::DrawImage {
+        name: "ship".to_string(),
+        x: 400.0,
+        y: 300.0,
+        w: -1.0,
+        h: -1.0,
+        sx: 0.0,
+        sy: 0.0,
+        sw: -1.0,
+        sh: -1.0,
+        rotation: 0.0,
+        ox: 0.0,
+        oy: 0.0,
+    };
+    let encoded = encode(image.clone());
+    assert_eq!(encoded[0], OP_DRAW_IMAGE | OP_COMPACT);
+    assert_eq!(encoded.len(), 1 + 1 + (2 + 4) + 1 + 2 + 2);
+    assert_eq!(Decoder::new(&encoded).next(), Some(Ok(image)));
+
+    // Commands without coordinates are unchanged
+    assert_eq!(encode(DrawCommand::Clear { r: 1, g: 2, b: 3 })[0], OP_CLEAR);
+}
+
+#[test]
+fn test_transcode_expands_compact_streams_and_lists() {
+    let mut list = Vec::new();
+    DrawCommand::FillPoly {
+        points: vec![(0.0, 0.0), (-10.5, 3.0), (7.0, 7.0)],
+    }
+    .encode_with(&mut list, Encoding::Compact);
+    let cmds = [
+        DrawCommand::DefineList {
+            name: "tri".to_string(),
+            commands: list,
+        },
+        DrawCommand::Translate { x: 5.0, y: 6.0 },
+    ];
+    let mut compact = Vec::new();
+    for cmd in &cmds {
+        cmd.encode_with(&mut compact, Encoding::Compact);
+    }
+
+    let float = transcode(&compact, Encoding::Float);
+    assert_eq!(float[0], cmds[0].opcode());
+    match Decoder::new(&float).next() {
+        Some(Ok(DrawCommand::DefineList { commands, .. })) => {
+            assert_eq!(commands[0], OP_FILL_POLY);
+            assert_eq!(
+                Decoder::new(&commands).next(),
+                Some(Ok(DrawCommand::FillPoly {
+                    points: vec![(0.0, 0.0), (-10.5, 3.0), (7.0, 7.0)],
+                }))
+            );
+        }
+        other => panic!("Unexpected command: {:?}", other),
+    }
+    assert_eq!(transcode(&float, Encoding::Compact), compact);
+}
+
+#[test]
+fn test_long_string_is_truncated_on_char_boundary() {
+    let text = "é".repeat(40_000); // 80_000 bytes
+    let mut buf = BytesMut::new();
+    DrawCommand::DrawText {
+        x: 0.0,
+        y: 0.0,
+        text,
+    }
+    .encode(&mut buf);
+
+    let mut decoder = Decoder::new(&buf);
+    match decoder.next() {
+        Some(Ok(DrawCommand::DrawText { text, .. })) => {
+            assert!(text.len() <= u16::MAX as usize);
+            assert!(text.chars().all(|c| c == 'é'));
+        }
+        other => panic!("Unexpected decode result: {:?}", other),
+    }
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn test_truncated_stream_reports_error_and_stops() {
+    let buf = encode_all(&sample_commands());
+
+    // Byte offsets at which each command ends.
+    let mut ends = Vec::new();
+    let mut decoder = Decoder::new(&buf);
+    while let Some(res) = decoder.next() {
+        res.unwrap();
+        ends.push(decoder.offset());
+    }
+
+    for cut in 0..buf.len() {
+        let results: Vec<_> = Decoder::new(&buf[..cut]).collect();
+        let ok = results.iter().take_while(|r| r.is_ok()).count();
+        assert_eq!(ok, ends.iter().filter(|&&end| end <= cut).count());
+
+        let on_boundary = cut == 0 || ends.contains(&cut);
+        if on_boundary {
+            assert_eq!(results.len(), ok, "No error expected at cut {}", cut);
+        } else {
+            assert_eq!(
+                results.len(),
+                ok + 1,
+                "Exactly one error expected at cut {}",
+                cut
+            );
+            assert!(matches!(results[ok], Err(DecodeError::Truncated { .. })));
+        }
+    }
+}
+
+#[test]
+fn test_unknown_opcode_is_skipped() {
+    let data = [OP_CLEAR, 3, 0, 0, 0, 0xEE, 3, 1, 2, 3, OP_CLEAR, 3, 1, 2, 3];
+    let mut decoder = Decoder::new(&data);
+    assert_eq!(
+        decoder.next(),
+        Some(Ok(DrawCommand::Clear { r: 0, g: 0, b: 0 }))
+    );
+    assert_eq!(
+        decoder.next(),
+        Some(Ok(DrawCommand::Clear { r: 1, g: 2, b: 3 }))
+    );
+    assert_eq!(decoder.next(), None);
+}
+
+#[test]
+fn test_extra_payload_bytes_are_ignored() {
+    // A newer encoder may append fields to an existing command
+    let data = [OP_CLEAR, 5, 1, 2, 3, 0xAA, 0xBB, OP_CLEAR, 3, 4, 5, 6];
+    let decoded: Vec<DrawCommand> = Decoder::new(&data).collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        decoded,
+        vec![
+            DrawCommand::Clear { r: 1, g: 2, b: 3 },
+            DrawCommand::Clear { r: 4, g: 5, b: 6 }
+        ]
+    );
+}
+
+#[test]
+fn test_short_payload_and_bad_length_are_errors() {
+    // Fields can't be read from the next command
+    let data = [OP_CLEAR, 2, 1, 2, OP_CLEAR, 3, 4, 5, 6];
+    let results: Vec<_> = Decoder::new(&data).collect();
+    assert_eq!(
+        results,
+        vec![Err(DecodeError::Truncated {
+            op: OP_CLEAR,
+            offset: 0
+        })]
+    );
+
+    let data = [OP_CLEAR, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 1];
+    let results: Vec<_> = Decoder::new(&data).collect();
+    assert_eq!(
+        results,
+        vec![Err(DecodeError::BadLength {
+            op: OP_CLEAR,
+            offset: 0
+        })]
+    );
+}
+
+#[test]
+fn test_long_payload_length_round_trips() {
+    // 200 bytes of list payload need a two-byte length
+    let cmd = DrawCommand::DefineList {
+        name: "big".to_string(),
+        commands: vec![7; 200],
+    };
+    let mut buf = BytesMut::new();
+    cmd.encode(&mut buf);
+    assert_eq!(&buf[1..3], &[0xD1, 0x01]); // 2 + 3 + 4 + 200 = 209
+    assert_eq!(Decoder::new(&buf).next(), Some(Ok(cmd)));
+}
+
+#[test]
+fn test_capability_names() {
+    assert_eq!(
+        Capabilities::from_names(Capabilities::ALL.names()),
+        Capabilities::ALL
+    );
+    // Unknown names come from newer clients and are ignored
+    let caps = Capabilities::from_names("display_lists, hdr_colors".split(','));
+    assert_eq!(
+        caps,
+        Capabilities {
+            display_lists: true,
+            ..Default::default()
+        }
+    );
+    assert_eq!(caps.names(), vec!["display_lists"]);
+    assert_eq!(Capabilities::from_names([""]), Capabilities::default());
+}
+
+#[test]
+fn test_visible_size_only_grows_in_expand_mode() {
+    let wide = (1200.0, 450.0);
+    assert_eq!(
+        ScaleMode::Letterbox.visible_size(800.0, 600.0, wide),
+        (800.0, 600.0)
+    );
+    assert_eq!(
+        ScaleMode::Integer.visible_size(800.0, 600.0, wide),
+        (800.0, 600.0)
+    );
+    assert_eq!(
+        ScaleMode::Expand.visible_size(800.0, 600.0, wide),
+        (1600.0, 600.0)
+    );
+    assert_eq!(
+        ScaleMode::Expand.visible_size(800.0, 600.0, (600.0, 900.0)),
+        (800.0, 1200.0)
+    );
+    // No viewport reported yet
+    assert_eq!(
+        ScaleMode::Expand.visible_size(800.0, 600.0, (0.0, 0.0)),
+        (800.0, 600.0)
+    );
+    assert_eq!(ScaleMode::from_name("expand"), Some(ScaleMode::Expand));
+    assert_eq!(ScaleMode::from_u8(9), ScaleMode::Letterbox);
+}
+
+#[test]
+fn test_bogus_poly_count_does_not_allocate() {
+    let data = [OP_FILL_POLY, 4, 0xFF, 0xFF, 0, 0];
+    let results: Vec<_> = Decoder::new(&data).collect();
+    assert_eq!(
+        results,
+        vec![Err(DecodeError::Truncated {
+            op: OP_FILL_POLY,
+            offset: 0
+        })]
+    );
+}
+
+#[test]
+fn test_compact_poly_count_over_u16_is_an_error() {
+    // Count 0xFFFF_FFFF as a varint, which would wrap `count * 2` on 32-bit targets
+    let data = [
+        OP_FILL_POLY | OP_COMPACT,
+        7,
+        0xFF,
+        0xFF,
+        0xFF,
+        0xFF,
+        0x0F,
+        0,
+        0,
+    ];
+    let results: Vec<_> = Decoder::new(&data).collect();
+    assert_eq!(
+        results,
+        vec![Err(DecodeError::BadLength {
+            op: OP_FILL_POLY | OP_COMPACT,
+            offset: 0
+        })]
+    );
+}
+
+#[test]
+fn test_unknown_line_style_falls_back_to_default() {
+    let mut data = vec![OP_STROKE_POLY, 9];
+    data.extend_from_slice(&2.0f32.to_le_bytes());
+    data.extend_from_slice(&[0x7F, 0x7F, 1, 0, 0]);
+    let results: Vec<_> = Decoder::new(&data).collect();
+    assert_eq!(
+        results,
+        vec![Ok(DrawCommand::StrokePoly {
+            width: 2.0,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            closed: true,
+            points: vec![],
+        })]
+    );
+}
+
+// Deterministic xorshift so the fuzz corpus is reproducible without extra dependencies.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[test]
+fn test_fuzz_random_bytes_never_panic() {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..5_000 {
+        let len = (rng.next() % 256) as usize;
+        let data: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        for res in Decoder::new(&data) {
+            if res.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_mutated_streams_never_panic() {
+    let valid = encode_all(&sample_commands()).to_vec();
+    let mut rng = XorShift(42);
+    for _ in 0..5_000 {
+        let mut data = valid.clone();
+        for _ in 0..(rng.next() % 8) {
+            let i = (rng.next() as usize) % data.len();
+            data[i] = rng.next() as u8;
+        }
+        data.truncate((rng.next() as usize) % (data.len() + 1));
+
+        let mut last_offset = 0;
+        let mut decoder = Decoder::new(&data);
+        while let Some(res) = decoder.next() {
+            if res.is_err() {
+                break;
+            }
+            assert!(decoder.offset() > last_offset);
+            assert!(decoder.offset() <= data.len());
+            last_offset = decoder.offset();
+        }
+    }
+}
+
+#[test]
+fn test_main_js_imports_its_constants_from_the_generated_module() {
+    let js = engine::protocol::js_module();
+    assert!(js.contains("export const OP_FILL_POLY = 12;\n"), "{}", js);
+
+    let main_js = std::fs::read_to_string(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../client/main.js"),
+    )
+    .unwrap();
+    let start = main_js
+        .find("import {\n")
+        .expect("main.js imports the constants");
+    let end = start + main_js[start..].find("} from './protocol.js';").unwrap();
+    let names: Vec<&str> = main_js[start + "import {".len()..end]
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    assert!(names.contains(&"OP_CLEAR"));
+    for name in names {
+        assert!(
+            js.contains(&format!("export const {} = ", name)),
+            "protocol.js doesn't export {}",
+            name
+        );
+    }
+}
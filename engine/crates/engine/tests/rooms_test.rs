@@ -0,0 +1,127 @@
+use engine::rooms::{Room, RoomHost, RoomInfo, MAIN_ROOM};
+use engine::GameState;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+// Records what games asked for instead of running rooms
+#[derive(Default)]
+struct FakeHost {
+    created: Mutex<Vec<(String, Value)>>,
+    moved: Mutex<Vec<(String, String)>>,
+}
+
+impl RoomHost for FakeHost {
+    fn create_room(&self, script: &str, params: Value) -> anyhow::Result<String> {
+        if script.contains("..") {
+            anyhow::bail!(
+                "room script '{}' is not a path inside the game's directory",
+                script
+            );
+        }
+        self.created
+            .lock()
+            .unwrap()
+            .push((script.to_string(), params));
+        Ok("abc123".to_string())
+    }
+
+    fn move_session(&self, session_id: &str, room_id: &str) -> anyhow::Result<()> {
+        self.moved
+            .lock()
+            .unwrap()
+            .push((session_id.to_string(), room_id.to_string()));
+        Ok(())
+    }
+
+    fn list_rooms(&self) -> Vec<RoomInfo> {
+        vec![RoomInfo {
+            id: MAIN_ROOM.to_string(),
+            script: "main.lua".to_string(),
+            players: 2,
+            params: Value::Null,
+        }]
+    }
+}
+
+#[test]
+fn test_room_functions_go_to_the_host() {
+    let host = Arc::new(FakeHost::default());
+    let room = Room {
+        host: Some(host.clone()),
+        ..Room::default()
+    };
+    let game = GameState::with_room("", None, room).expect("Failed to init game");
+
+    let result =
+        game.eval(r#"return api.create_room("arena.lua", { players = { "a", "b" } }) == "abc123""#);
+    assert_eq!(result, "Boolean(true)");
+    game.eval(r#"api.create_room("arena.lua")"#);
+    assert_eq!(
+        *host.created.lock().unwrap(),
+        [
+            ("arena.lua".to_string(), json!({"players": ["a", "b"]})),
+            ("arena.lua".to_string(), json!(null)),
+        ]
+    );
+    // Host errors are Lua errors
+    let result = game.eval(r#"return pcall(api.create_room, "../secret.lua")"#);
+    assert!(result.starts_with("Boolean(false)"), "{}", result);
+
+    game.eval(r#"api.move_session("sess_1", "abc123")"#);
+    assert_eq!(
+        *host.moved.lock().unwrap(),
+        [("sess_1".to_string(), "abc123".to_string())]
+    );
+
+    let result = game.eval(
+        r#"local rooms = api.list_rooms()
+        return #rooms == 1 and rooms[1].id == "main" and rooms[1].script == "main.lua"
+            and rooms[1].players == 2 and rooms[1].params == nil"#,
+    );
+    assert_eq!(result, "Boolean(true)");
+}
+
+#[test]
+fn test_init_gets_the_room_params() {
+    let script = r#"
+        function init(params)
+            room = api.room_id()
+            players = params and #params.players or 0
+        end
+    "#;
+    let room = Room {
+        id: "abc123".to_string(),
+        params: json!({"players": ["a", "b"]}),
+        host: None,
+    };
+    let game = GameState::with_room(script, None, room).expect("Failed to init game");
+    assert_eq!(
+        game.eval(r#"return room == "abc123" and players == 2"#),
+        "Boolean(true)"
+    );
+
+    // Games run on their own are in the main room, without params
+    let game = GameState::new(script, None).expect("Failed to init game");
+    assert_eq!(
+        game.eval(r#"return room == "main" and players == 0"#),
+        "Boolean(true)"
+    );
+}
+
+#[test]
+fn test_room_functions_need_a_host() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    for call in [
+        r#"api.create_room("arena.lua")"#,
+        r#"api.move_session("sess_1", "main")"#,
+        "api.list_rooms()",
+    ] {
+        let result = game.eval(call);
+        assert!(
+            result.contains("rooms need a server to host them"),
+            "{}: {}",
+            call,
+            result
+        );
+    }
+}
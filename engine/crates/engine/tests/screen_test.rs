@@ -0,0 +1,159 @@
+use engine::protocol::{Decoder, DrawCommand, Encoding, ScaleMode, OP_COMPACT, OP_FILL_RECT};
+use engine::GameState;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+fn resolution_updates(game: &GameState, session_id: &str) -> Vec<DrawCommand> {
+    Decoder::new(&game.take_screen_update(session_id))
+        .collect::<Result<_, _>>()
+        .expect("Commands should decode")
+}
+
+fn set_resolution(width: u16, height: u16, mode: ScaleMode) -> DrawCommand {
+    DrawCommand::SetResolution {
+        width,
+        height,
+        mode,
+    }
+}
+
+// A game directory holding `metadata.json`, and the path of its script
+fn game_dir(metadata: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("metadata.json"), metadata).unwrap();
+    let script = dir.path().join("main.lua");
+    (dir, script)
+}
+
+#[test]
+fn test_resolution_is_sent_once_per_session_until_changed() {
+    let script = r#"
+        function on_input(session_id, code, active)
+            api.set_resolution(320, 180, "integer")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    assert_eq!(
+        resolution_updates(&game, "sess_1"),
+        vec![set_resolution(800, 600, ScaleMode::Letterbox)]
+    );
+    assert!(resolution_updates(&game, "sess_1").is_empty());
+
+    game.handle_input("sess_2", 1, true).unwrap();
+    assert_eq!(
+        resolution_updates(&game, "sess_1"),
+        vec![set_resolution(320, 180, ScaleMode::Integer)]
+    );
+
+    // Setting the same resolution again is not a change
+    game.handle_input("sess_2", 1, true).unwrap();
+    assert!(resolution_updates(&game, "sess_1").is_empty());
+
+    // A reconnect starts from an empty client
+    game.on_connect("sess_1").unwrap();
+    assert_eq!(
+        resolution_updates(&game, "sess_1"),
+        vec![set_resolution(320, 180, ScaleMode::Integer)]
+    );
+}
+
+#[test]
+fn test_screen_size_follows_viewport_in_expand_mode() {
+    let script = r#"
+        api.set_resolution(400, 300, "expand")
+        function draw(session_id)
+            local w, h = api.screen_size(session_id)
+            api.fill_rect(0, 0, w, h)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_viewport("sess_1", 1000.0, 500.0);
+
+    assert_eq!(game.screen_size("sess_1"), (600.0, 300.0));
+    // Sessions that haven't reported a viewport see the base resolution
+    assert_eq!(game.screen_size("sess_2"), (400.0, 300.0));
+
+    let cmds: Vec<_> = Decoder::new(&game.draw("sess_1").unwrap())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        cmds,
+        vec![DrawCommand::FillRect {
+            x: 0.0,
+            y: 0.0,
+            w: 600.0,
+            h: 300.0
+        }]
+    );
+}
+
+#[test]
+fn test_invalid_resolution_is_an_error() {
+    let cases = [
+        (
+            r#"api.set_resolution(320, 180, "zoom")"#,
+            "unknown scaling mode 'zoom'",
+        ),
+        (r#"api.set_resolution(0, 180)"#, "invalid resolution 0x180"),
+    ];
+    for (script, expected) in cases {
+        let err = GameState::new(script, None)
+            .err()
+            .expect("Init should fail");
+        assert!(
+            format!("{:#}", err).contains(expected),
+            "{:?} should contain {:?}",
+            err,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_resolution_from_metadata() {
+    let (_dir, script) = game_dir(
+        r#"{ "title": "Tiny", "resolution": { "width": 160, "height": 144, "scaling": "stretch" } }"#,
+    );
+    let game = GameState::new("", Some(&script)).expect("Failed to init game");
+    assert_eq!(
+        resolution_updates(&game, "sess_1"),
+        vec![set_resolution(160, 144, ScaleMode::Stretch)]
+    );
+
+    // The script can still change the size, keeping the declared mode
+    let game =
+        GameState::new("api.set_resolution(320, 288)", Some(&script)).expect("Failed to init game");
+    assert_eq!(
+        resolution_updates(&game, "sess_1"),
+        vec![set_resolution(320, 288, ScaleMode::Stretch)]
+    );
+
+    let (_bad, script) = game_dir(r#"{ "resolution": { "width": 160 } }"#);
+    let err = GameState::new("", Some(&script)).err().unwrap();
+    assert!(format!("{:#}", err).contains("metadata.json: bad resolution"));
+}
+
+#[test]
+fn test_compact_encoding_from_metadata() {
+    let script = "function draw(session_id) api.fill_rect(10, 20, 30.5, 40) end";
+    let (_dir, script_path) = game_dir(r#"{ "encoding": "compact" }"#);
+    let game = GameState::new(script, Some(&script_path)).expect("Failed to init game");
+    assert_eq!(game.encoding(), Encoding::Compact);
+
+    let frame = game.draw("sess_1").unwrap();
+    assert_eq!(frame[0], OP_FILL_RECT | OP_COMPACT);
+    assert_eq!(
+        Decoder::new(&frame).collect::<Result<Vec<_>, _>>().unwrap(),
+        vec![DrawCommand::FillRect {
+            x: 10.0,
+            y: 20.0,
+            w: 30.5,
+            h: 40.0
+        }]
+    );
+
+    let (_bad, script_path) = game_dir(r#"{ "encoding": "packed" }"#);
+    let err = GameState::new("", Some(&script_path)).err().unwrap();
+    assert!(format!("{:#}", err).contains("metadata.json: unknown encoding 'packed'"));
+}
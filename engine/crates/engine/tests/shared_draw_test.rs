@@ -0,0 +1,141 @@
+use engine::protocol::{Decoder, DrawCommand};
+use engine::GameState;
+
+fn decode(frame: &[u8]) -> Vec<DrawCommand> {
+    Decoder::new(frame)
+        .collect::<Result<_, _>>()
+        .expect("Commands should decode")
+}
+
+fn rect(x: f32) -> DrawCommand {
+    DrawCommand::FillRect {
+        x,
+        y: 0.0,
+        w: 1.0,
+        h: 1.0,
+    }
+}
+
+fn text(x: f32) -> DrawCommand {
+    DrawCommand::DrawText {
+        x,
+        y: 0.0,
+        text: "hp".to_string(),
+    }
+}
+
+#[test]
+fn test_shared_layer_is_drawn_once_per_update() {
+    let script = r#"
+        shared_draws = 0
+        function draw_shared()
+            shared_draws = shared_draws + 1
+            api.fill_rect(shared_draws, 0, 1, 1)
+        end
+        function draw(session_id)
+            api.draw_text("hp", session_id == "sess_1" and 1 or 2, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.update(0.1).unwrap();
+    assert_eq!(
+        decode(&game.draw("sess_1").unwrap()),
+        vec![rect(1.0), text(1.0)]
+    );
+    assert_eq!(
+        decode(&game.draw("sess_2").unwrap()),
+        vec![rect(1.0), text(2.0)]
+    );
+
+    game.update(0.1).unwrap();
+    assert_eq!(
+        decode(&game.draw("sess_2").unwrap()),
+        vec![rect(2.0), text(2.0)]
+    );
+    assert_eq!(
+        decode(&game.draw("sess_1").unwrap()),
+        vec![rect(2.0), text(1.0)]
+    );
+}
+
+#[test]
+fn test_session_draw_continues_from_shared_text_style() {
+    let script = r#"
+        function draw_shared()
+            api.set_font("monospace", 40)
+        end
+        function draw(session_id)
+            local w = api.measure_text("abc")
+            api.fill_rect(w, 0, 1, 1)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let widths: Vec<_> = ["sess_1", "sess_2"]
+        .iter()
+        .map(|id| match decode(&game.draw(id).unwrap()).pop() {
+            Some(DrawCommand::FillRect { x, .. }) => x,
+            other => panic!("Unexpected command: {:?}", other),
+        })
+        .collect();
+    // Three monospace advances of about 0.6 at size 40, both times
+    assert_eq!(widths[0], widths[1]);
+    assert!((widths[0] - 72.0).abs() < 1.0, "{:?}", widths);
+}
+
+#[test]
+fn test_open_list_in_shared_layer_is_an_error() {
+    let script = r#"
+        function draw_shared()
+            api.begin_list("world")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let err = game.draw("sess_1").expect_err("Draw should fail");
+    assert!(format!("{:#}", err).contains("begin_list('world') without end_list()"));
+}
+
+#[test]
+fn test_draws_get_the_interpolation_alpha() {
+    let script = r#"
+        shared_draws = 0
+        function update(dt) api.play_sound("tick") end
+        function draw_shared(alpha)
+            shared_draws = shared_draws + 1
+            api.fill_rect(alpha * 10, 0, 1, 1)
+        end
+        function draw(session_id, alpha)
+            api.draw_text("hp", alpha * 100, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let tick = DrawCommand::PlaySound {
+        name: "tick".to_string(),
+        looped: false,
+        volume: 1.0,
+    };
+
+    game.update(0.1).unwrap();
+    assert_eq!(
+        decode(&game.draw("sess_1").unwrap()),
+        vec![tick.clone(), rect(0.0), text(0.0)]
+    );
+
+    // A new alpha draws the shared layer again; the same one reuses it
+    game.set_alpha(0.5);
+    assert_eq!(
+        decode(&game.draw("sess_1").unwrap()),
+        vec![tick.clone(), rect(5.0), text(50.0)]
+    );
+    game.set_alpha(0.5);
+    game.draw("sess_2").unwrap();
+    assert_eq!(game.eval("return shared_draws"), "Integer(2)");
+
+    // Taken events are left to the caller
+    assert_eq!(decode(&game.take_events()), vec![tick]);
+    assert_eq!(
+        decode(&game.draw("sess_1").unwrap()),
+        vec![rect(5.0), text(50.0)]
+    );
+    assert!(game.take_events().is_empty());
+}
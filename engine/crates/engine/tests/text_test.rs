@@ -0,0 +1,130 @@
+use ab_glyph::{Font, FontRef};
+use engine::protocol::{Decoder, DrawCommand, TextAlign, TextBaseline};
+use engine::text::BUNDLED_FONT;
+use engine::GameState;
+
+fn draw_commands(game: &GameState) -> Vec<DrawCommand> {
+    let bytes = game.draw("sess_1").expect("Draw failed");
+    Decoder::new(&bytes)
+        .collect::<Result<_, _>>()
+        .expect("Frame should decode")
+}
+
+// Advance of every glyph of the bundled monospace font at `size`
+fn advance(size: f32) -> f32 {
+    let font = FontRef::try_from_slice(BUNDLED_FONT).unwrap();
+    font.h_advance_unscaled(font.glyph_id('a')) / font.units_per_em().unwrap() * size
+}
+
+fn assert_rect_size(cmd: &DrawCommand, width: f32, height: f32) {
+    match *cmd {
+        DrawCommand::FillRect { w, h, .. } => {
+            assert!((w - width).abs() < 1e-3, "width {} != {}", w, width);
+            assert!((h - height).abs() < 1e-3, "height {} != {}", h, height);
+        }
+        ref other => panic!("Unexpected command: {:?}", other),
+    }
+}
+
+#[test]
+fn test_measure_text_uses_current_font_size() {
+    // Without a font file, text is measured with the bundled font, and lines are
+    // 1.2 * size apart.
+    let script = r#"
+        function draw(session_id)
+            api.set_font("monospace", 10)
+            local w, h = api.measure_text("abcd")
+            api.fill_rect(0, 0, w, h)
+            w, h = api.measure_text("aaa bbb ccc", 45)
+            api.fill_rect(0, 0, w, h)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let cmds = draw_commands(&game);
+
+    assert!((advance(10.0) - 6.0).abs() < 0.1, "{}", advance(10.0));
+    assert_rect_size(&cmds[1], 4.0 * advance(10.0), 12.0);
+    // "aaa bbb" and "ccc"
+    assert_rect_size(&cmds[2], 7.0 * advance(10.0), 24.0);
+}
+
+#[test]
+fn test_draw_text_wraps_and_anchors_block() {
+    let script = r#"
+        function draw(session_id)
+            api.set_font("monospace", 10)
+            api.set_text_align("center", "top")
+            api.draw_text("aaa bbb ccc", 100, 50, 45)
+            api.set_text_align("center", "middle")
+            api.draw_text("one\ntwo", 100, 50)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let cmds = draw_commands(&game);
+
+    let text = |y: f32, text: &str| DrawCommand::DrawText {
+        x: 100.0,
+        y,
+        text: text.to_string(),
+    };
+    assert_eq!(
+        cmds,
+        vec![
+            DrawCommand::SetFont {
+                name: "monospace".to_string(),
+                size: 10.0
+            },
+            DrawCommand::SetTextAlign {
+                align: TextAlign::Center,
+                baseline: TextBaseline::Top
+            },
+            text(50.0, "aaa bbb"),
+            text(62.0, "ccc"),
+            DrawCommand::SetTextAlign {
+                align: TextAlign::Center,
+                baseline: TextBaseline::Middle
+            },
+            text(44.0, "one"),
+            text(56.0, "two"),
+        ]
+    );
+}
+
+#[test]
+fn test_text_style_follows_push_pop_and_resets_each_frame() {
+    let script = r#"
+        frame = 0
+        function draw(session_id)
+            frame = frame + 1
+            if frame == 1 then
+                api.set_font("monospace", 10)
+                api.push()
+                api.set_font("monospace", 20)
+                api.pop()
+            end
+            local w = api.measure_text("ab")
+            api.fill_rect(0, 0, w, 1)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let cmds = draw_commands(&game);
+    assert_rect_size(cmds.last().unwrap(), 2.0 * advance(10.0), 1.0);
+
+    // Second frame starts again from the 14px default
+    let cmds = draw_commands(&game);
+    assert_eq!(cmds.len(), 1);
+    assert_rect_size(&cmds[0], 2.0 * advance(14.0), 1.0);
+}
+
+#[test]
+fn test_unknown_text_align_is_an_error() {
+    let script = r#"
+        function draw(session_id)
+            api.set_text_align("justify")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let err = game.draw("sess_1").unwrap_err();
+    assert!(err.to_string().contains("unknown text align 'justify'"));
+}
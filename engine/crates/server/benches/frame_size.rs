@@ -0,0 +1,39 @@
+// Bytes per frame on astro-maze in each encoding, before and after compression.
+// Run with `cargo bench -p cleoselene --bench frame_size`.
+
+use cleoselene::dictionary;
+use engine::protocol::{self, Encoding};
+use engine::GameState;
+use std::path::PathBuf;
+
+const FRAMES: usize = 300;
+const SESSIONS: usize = 4;
+
+fn main() -> anyhow::Result<()> {
+    let script_path =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../games/astro-maze/main.lua");
+    let script = std::fs::read_to_string(&script_path)?;
+    let game = GameState::new(&script, Some(&script_path))?;
+    let frames = dictionary::sample_frames(&game, FRAMES, SESSIONS)?;
+
+    println!("astro-maze, {} frames x {} sessions", FRAMES, SESSIONS);
+    println!(
+        "{:<10} {:>12} {:>12}",
+        "encoding", "raw B/frame", "zstd B/frame"
+    );
+    for (name, encoding) in [("float", Encoding::Float), ("compact", Encoding::Compact)] {
+        let (mut raw, mut compressed) = (0, 0);
+        for frame in &frames {
+            let frame = protocol::transcode(frame, encoding);
+            raw += frame.len();
+            compressed += dictionary::compress(&frame, None)?.len();
+        }
+        println!(
+            "{:<10} {:>12.1} {:>12.1}",
+            name,
+            raw as f64 / frames.len() as f64,
+            compressed as f64 / frames.len() as f64
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// Zstd dictionaries trained on a game's own frames. Frames are small and very
+// repetitive across sessions (same opcodes, colours, strings), so a dictionary lets
+// each one compress as if the previous frames were still in the window.
+
+use engine::GameState;
+use std::io::Write;
+use std::path::Path;
+use zstd::dict::EncoderDictionary;
+
+// Where `train-dict` writes and the server looks, next to the game script. Served to
+// clients from `/assets`.
+pub const FILE_NAME: &str = "zstd.dict";
+
+// Same level as frames compressed without a dictionary
+const LEVEL: i32 = 0;
+
+// Keys pressed at random while sampling, so frames cover more than the idle state
+const SAMPLE_KEYS: [u8; 10] = [13, 32, 37, 38, 39, 40, 65, 68, 83, 87];
+
+pub struct Dictionary {
+    pub id: u32,
+    encoder: EncoderDictionary<'static>,
+}
+
+impl Dictionary {
+    pub fn new(bytes: &[u8]) -> anyhow::Result<Self> {
+        // Raw content dictionaries have no id, and clients can't tell them apart
+        let id = zstd::zstd_safe::get_dict_id_from_dict(bytes)
+            .ok_or_else(|| anyhow::anyhow!("not a trained zstd dictionary"))?;
+        Ok(Self {
+            id: id.get(),
+            encoder: EncoderDictionary::copy(bytes, LEVEL),
+        })
+    }
+
+    /// The game's dictionary, if `dir` has one.
+    pub fn load(dir: &Path) -> Option<Self> {
+        let path = dir.join(FILE_NAME);
+        let bytes = std::fs::read(&path).ok()?;
+        match Self::new(&bytes) {
+            Ok(dict) => Some(dict),
+            Err(e) => {
+                eprintln!("Ignoring {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+pub fn compress(data: &[u8], dict: Option<&Dictionary>) -> std::io::Result<Vec<u8>> {
+    let mut encoder = match dict {
+        Some(dict) => zstd::stream::write::Encoder::with_prepared_dictionary(Vec::new(), &dict.encoder)?,
+        None => zstd::stream::write::Encoder::new(Vec::new(), LEVEL)?,
+    };
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Plays the game headless with `sessions` simulated players pressing random keys,
+/// and returns what the server would have sent each of them over `frames` ticks.
+pub fn sample_frames(game: &GameState, frames: usize, sessions: usize) -> anyhow::Result<Vec<Vec<u8>>> {
+    let session_ids: Vec<String> = (0..sessions).map(|i| format!("sample_{}", i)).collect();
+    let mut samples = Vec::new();
+    // Players join a running game, as on the server
+    game.update(1.0 / 30.0)?;
+    for session_id in &session_ids {
+        let setup = game.on_connect(session_id)?;
+        if !setup.is_empty() {
+            samples.push(setup.to_vec());
+        }
+    }
+
+    // Deterministic xorshift, so the same game trains the same dictionary
+    let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+
+    for _ in 0..frames {
+        game.begin_frame();
+        for session_id in &session_ids {
+            if random() % 8 == 0 {
+                let key = SAMPLE_KEYS[(random() % SAMPLE_KEYS.len() as u64) as usize];
+                game.handle_input(session_id, key, random() % 2 == 0)?;
+            }
+        }
+        game.update(1.0 / 30.0)?;
+        for session_id in &session_ids {
+            let frame = game.draw(session_id)?;
+            let mut sample = game.take_screen_update(session_id).to_vec();
+            sample.extend_from_slice(&game.take_list_updates(session_id));
+            sample.extend_from_slice(&frame);
+            if !sample.is_empty() {
+                samples.push(sample);
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// Trains a dictionary of at most `max_size` bytes on `samples`.
+pub fn train(samples: &[Vec<u8>], max_size: usize) -> anyhow::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+        .map_err(|e| anyhow::anyhow!("dictionary training failed on {} samples: {}", samples.len(), e))
+}
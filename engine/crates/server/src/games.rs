@@ -0,0 +1,77 @@
+// Several games from one server, with `--games <DIR>`: every directory in DIR with a
+// main.lua is a game, served under its directory name (`/astro-maze/`) with its own
+// game loop, hot reload and `/assets`. The root lists them.
+
+use std::path::{Path, PathBuf};
+
+// The script each game directory starts with
+pub const MAIN_SCRIPT: &str = "main.lua";
+
+pub struct Game {
+    /// Directory name, which is also the game's path on the server.
+    pub name: String,
+    pub script_path: PathBuf,
+    /// metadata.json's `title`, or else the name.
+    pub title: String,
+}
+
+/// The games in `dir`, by name. Directories whose names can't be a path segment as
+/// they are, and hidden ones, are skipped.
+pub fn find(dir: &Path) -> std::io::Result<Vec<Game>> {
+    let mut games = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let script_path = path.join(MAIN_SCRIPT);
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !is_path_segment(name) || !script_path.is_file() {
+            continue;
+        }
+        let title = std::fs::read_to_string(path.join("metadata.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+            .and_then(|metadata| metadata.get("title")?.as_str().map(String::from))
+            .unwrap_or_else(|| name.to_string());
+        games.push(Game {
+            name: name.to_string(),
+            script_path,
+            title,
+        });
+    }
+    games.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(games)
+}
+
+fn is_path_segment(name: &str) -> bool {
+    !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The root page, linking to each game under `base_path`.
+pub fn index_html(games: &[Game], base_path: &str) -> String {
+    let base_path = base_path.trim_end_matches('/');
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset='utf-8'><title>Cleoselene</title></head>\n<body>\n<ul>\n",
+    );
+    for game in games {
+        html.push_str(&format!(
+            "<li><a href='{}/{}/'>{}</a></li>\n",
+            base_path,
+            game.name,
+            escape(&game.title)
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\'', "&#39;")
+        .replace('"', "&quot;")
+}
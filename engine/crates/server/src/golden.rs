@@ -0,0 +1,213 @@
+// Golden-image tests: a scenario plays the game headless, connecting sessions,
+// pressing keys and running ticks, and renders chosen sessions with the software
+// renderer to compare them against PNGs committed next to the scenario.
+
+use crate::raster::Rasterizer;
+use anyhow::Context;
+use engine::GameState;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// Where snapshots that don't match are written, next to the goldens, as `<name>.png`
+// and `<name>.diff.png`
+pub const FAILURES_DIR: &str = "failures";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    // Game script, relative to the scenario file
+    script: PathBuf,
+    // Seconds per tick
+    #[serde(default = "default_dt")]
+    dt: f32,
+    #[serde(default)]
+    tolerance: Tolerance,
+    steps: Vec<Step>,
+}
+
+fn default_dt() -> f32 {
+    1.0 / 30.0
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+struct Tolerance {
+    // Largest difference in any channel for a pixel to still match
+    channel: u8,
+    // Fraction of the pixels allowed not to match
+    pixels: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            pixels: 0.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Step {
+    Connect(String),
+    Disconnect(String),
+    Press { session: String, key: Key },
+    Release { session: String, key: Key },
+    // Runs this many ticks, drawing every session as the server does
+    Tick(u32),
+    // Renders what the session sees now and compares it with `<name>.png`
+    Snapshot { session: String, name: String },
+}
+
+// A legacy key code, or a key name as newer clients send
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Key {
+    Code(u8),
+    Name(String),
+}
+
+/// Plays the scenario at `path` and checks each snapshot against its golden, or
+/// with `update` writes the goldens instead. Returns the snapshots that didn't match.
+pub fn run(path: &Path, update: bool) -> anyhow::Result<Vec<String>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let scenario: Scenario = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("invalid scenario {:?}", path))?;
+    let script_path = dir.join(&scenario.script);
+    let script = std::fs::read_to_string(&script_path)
+        .with_context(|| format!("can't read {:?}", script_path))?;
+    let game = GameState::new(&script, Some(&script_path))?;
+    let assets_dir = script_path.parent().unwrap_or(Path::new("."));
+    let failures_dir = dir.join(FAILURES_DIR);
+
+    // Connected sessions and what on_connect sent them, in the order they joined
+    let mut sessions: Vec<(String, bytes::Bytes)> = Vec::new();
+    let mut failures = Vec::new();
+    game.begin_frame();
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let context = || format!("step {}", i + 1);
+        match step {
+            Step::Connect(session) => {
+                let setup = game.on_connect(session).with_context(context)?;
+                sessions.push((session.clone(), setup));
+            }
+            Step::Disconnect(session) => {
+                game.on_disconnect(session).with_context(context)?;
+                sessions.retain(|(id, _)| id != session);
+            }
+            Step::Press { session, key } | Step::Release { session, key } => {
+                let down = matches!(step, Step::Press { .. });
+                match key {
+                    Key::Code(code) => game.handle_input(session, *code, down),
+                    Key::Name(name) => game.handle_key(session, name, down, Default::default()),
+                }
+                .with_context(context)?;
+            }
+            Step::Tick(ticks) => {
+                for _ in 0..*ticks {
+                    game.update(scenario.dt).with_context(context)?;
+                    for (session, _) in &sessions {
+                        game.draw(session).with_context(context)?;
+                    }
+                    game.begin_frame();
+                }
+            }
+            Step::Snapshot { session, name } => {
+                let Some((_, setup)) = sessions.iter().find(|(id, _)| id == session) else {
+                    anyhow::bail!("step {}: {} is not connected", i + 1, session);
+                };
+                // Everything the session has been sent that the frame builds on
+                let frame = game.draw(session).with_context(context)?;
+                let mut screen = Vec::new();
+                game.screen_command().encode(&mut screen);
+                let bytes = [setup, &screen[..], &game.list_definitions(), &frame].concat();
+                let mut raster = Rasterizer::new(assets_dir);
+                raster
+                    .render(&bytes)
+                    .with_context(|| format!("rendering {}", name))?;
+
+                let golden_path = dir.join(format!("{}.png", name));
+                if update {
+                    raster.image().save(&golden_path)?;
+                    println!("  {}: written", name);
+                    continue;
+                }
+                let _ = std::fs::remove_file(failures_dir.join(format!("{}.png", name)));
+                let _ = std::fs::remove_file(failures_dir.join(format!("{}.diff.png", name)));
+                let result = match image::open(&golden_path) {
+                    Ok(golden) => compare(raster.image(), &golden.to_rgba8(), scenario.tolerance),
+                    Err(e) => Err(Mismatch {
+                        reason: format!("can't read {:?}: {}", golden_path, e),
+                        diff: None,
+                    }),
+                };
+                match result {
+                    Ok(()) => println!("  {}: ok", name),
+                    Err(mismatch) => {
+                        println!("  {}: FAILED, {}", name, mismatch.reason);
+                        std::fs::create_dir_all(&failures_dir)?;
+                        raster
+                            .image()
+                            .save(failures_dir.join(format!("{}.png", name)))?;
+                        if let Some(diff) = mismatch.diff {
+                            diff.save(failures_dir.join(format!("{}.diff.png", name)))?;
+                        }
+                        failures.push(name.clone());
+                    }
+                }
+            }
+        }
+    }
+    Ok(failures)
+}
+
+struct Mismatch {
+    reason: String,
+    // The golden dimmed to grey, with the pixels that differ in red
+    diff: Option<RgbaImage>,
+}
+
+fn compare(actual: &RgbaImage, golden: &RgbaImage, tolerance: Tolerance) -> Result<(), Mismatch> {
+    if actual.dimensions() != golden.dimensions() {
+        return Err(Mismatch {
+            reason: format!(
+                "size is {:?}, the golden's {:?}",
+                actual.dimensions(),
+                golden.dimensions()
+            ),
+            diff: None,
+        });
+    }
+    let mut diff = RgbaImage::new(golden.width(), golden.height());
+    let mut differing = 0;
+    let mut max_delta = 0;
+    for ((a, g), d) in actual.pixels().zip(golden.pixels()).zip(diff.pixels_mut()) {
+        let delta =
+            a.0.iter()
+                .zip(g.0)
+                .map(|(a, g)| a.abs_diff(g))
+                .max()
+                .unwrap_or(0);
+        max_delta = max_delta.max(delta);
+        *d = if delta > tolerance.channel {
+            differing += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let grey = ((g[0] as u32 * 3 + g[1] as u32 * 6 + g[2] as u32) / 30) as u8;
+            Rgba([grey, grey, grey, 255])
+        };
+    }
+    let allowed = (tolerance.pixels as f64 * (golden.width() * golden.height()) as f64) as usize;
+    if differing > allowed {
+        return Err(Mismatch {
+            reason: format!(
+                "{} pixels differ, by up to {} (tolerance {} pixels, {} per channel)",
+                differing, max_delta, allowed, tolerance.channel
+            ),
+            diff: Some(diff),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+//! Protocol negotiation at the start of a WebSocket connection.
+
+use engine::protocol::{Capabilities, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+/// The capabilities to use with a client that connected with the `protocol` and `caps`
+/// query parameters, or the reason it is turned away. Old clients can't parse the
+/// stream at all; missing capabilities are only downgraded per session.
+pub fn negotiate(protocol: Option<u16>, caps: Option<&str>) -> Result<Capabilities, String> {
+    // Clients from before the handshake carried a version speak protocol 1
+    let version = protocol.unwrap_or(1);
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(format!(
+            "Client protocol version {} is not supported (server speaks {}). Reload the page.",
+            version, PROTOCOL_VERSION
+        ));
+    }
+    Ok(Capabilities::from_names(caps.unwrap_or("").split(',')))
+}
@@ -0,0 +1,13 @@
+//! The parts of the server that don't need the network or a game loop, so tests and
+//! benches can use them directly.
+
+pub mod dictionary;
+pub mod games;
+pub mod golden;
+pub mod handshake;
+pub mod messages;
+pub mod outbox;
+pub mod pacing;
+pub mod raster;
+pub mod recording;
+pub mod rooms;
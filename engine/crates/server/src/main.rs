@@ -1,11 +1,27 @@
 use axum::{
     extract::{Query, State, ws::{Message, WebSocket, WebSocketUpgrade}, Json},
-    response::IntoResponse,
+    response::{Html, IntoResponse},
     routing::{get, post},
     Router,
 };
-use engine::GameState;
+use cleoselene::dictionary::{self, Dictionary};
+use cleoselene::games;
+use cleoselene::golden;
+use cleoselene::handshake;
+use cleoselene::messages::{MessageLimits, MAX_SOCKET_MESSAGE_LEN};
+use cleoselene::outbox::{OutFrame, Outbox};
+use cleoselene::pacing::{FixedStep, FramePacer};
+use cleoselene::raster::Rasterizer;
+use cleoselene::recording::{self, Recorder};
+use cleoselene::rooms::{Rooms, ROOM_IDLE_TIMEOUT};
+use engine::{GameState, InputTiming};
+use engine::actions::Controls;
+use engine::delta::{self, FrameEncoder};
+use engine::input::{Input, Stamp};
+use engine::protocol::{self, Capabilities, Encoding, PROTOCOL_VERSION};
+use engine::rooms::{Room, RoomHost, RoomInfo, MAIN_ROOM};
 use futures::{sink::SinkExt, stream::StreamExt};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -17,14 +33,10 @@ use uuid::Uuid;
 use notify::{Watcher, RecursiveMode, Event};
 use std::sync::mpsc::channel;
 use std::path::{Path, PathBuf};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rust_embed::RustEmbed;
 use axum::http::{header, StatusCode, Uri};
 use sysinfo::{System, RefreshKind, CpuRefreshKind, MemoryRefreshKind};
-use image::{ImageBuffer, RgbImage, Rgba, Rgb};
-use imageproc::rect::Rect;
-use std::io::Cursor;
-use bytes::Buf;
 
 // WebRTC Imports
 use webrtc::api::interceptor_registry::register_default_interceptors;
@@ -53,7 +65,7 @@ DEBUGGING WITH LLMs (MCP):
   1. evaluate: Execute Lua code on the server.
      Payload: { \"action\": \"evaluate\", \"code\": \"return players[1].x\" }
   
-  2. render: Render the current frame for a session to PNG.
+  2. render: Render the current frame for a session to PNG, as the player sees it.
      Payload: { \"action\": \"render\", \"session_id\": \"...\" }
   
   3. inspect: Get server resource usage (RAM/CPU).
@@ -66,13 +78,28 @@ DEBUGGING WITH LLMs (MCP):
   \"Connect to the game server at localhost:3425/mcp and inspect the global 'players' table.\"
 ";
 
+// Game updates per second, and the limits of it and of clients' frame rates
+const DEFAULT_TICK_RATE: u32 = 30;
+const MAX_TICK_RATE: u32 = 240;
+const MAX_FRAME_RATE: u32 = 120;
+
 #[derive(Parser)]
 #[command(name = "Cleoselene", about = "A Multiplayer-First Server-Rendered Game Engine with Lua Scripting")]
 #[command(version = env!("BUILD_TIMESTAMP"))]
 #[command(after_help = format!("{}\n{}", LUA_API_DOCS, HELP_TUTORIAL))]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to the Lua game script
-    script_path: PathBuf,
+    #[arg(required_unless_present = "games")]
+    script_path: Option<PathBuf>,
+
+    /// Serve every game in this directory, each directory with a main.lua, under
+    /// /<directory name>/ instead of a single script
+    #[arg(long, value_name = "DIR", conflicts_with = "script_path")]
+    games: Option<PathBuf>,
 
     /// Port to start the server on
     #[arg(long, default_value_t = 3425)]
@@ -82,6 +109,15 @@ struct Cli {
     #[arg(long, default_value = "/")]
     base_path: String,
 
+    /// Game updates per second. Every update() gets the same dt, 1 / tick rate.
+    #[arg(long, default_value_t = DEFAULT_TICK_RATE, value_parser = clap::value_parser!(u32).range(1..=MAX_TICK_RATE as i64))]
+    tick_rate: u32,
+
+    /// Frames per second sent to clients that don't ask for a rate (default: the
+    /// tick rate). Clients ask with `fps` in the page's URL.
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=MAX_FRAME_RATE as i64))]
+    frame_rate: Option<u32>,
+
     /// Export the embedded client assets to a directory (for static hosting)
     #[arg(long)]
     export_client: Option<PathBuf>,
@@ -90,16 +126,79 @@ struct Cli {
     #[arg(long)]
     debug_mcp: bool,
 
+    /// Record the frames sent to each session into this directory, one file per
+    /// session. Turn them into images with `render-recording`.
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+
     /// Run the game script in test mode (headless). 
     /// Initializes the engine, runs init() and one update() cycle, then exits.
     #[arg(long)]
     test: bool,
 }
 
-struct ClientConnection {
-    session_id: String,
-    tx_render: mpsc::Sender<bytes::Bytes>,
-    rx_input: mpsc::Receiver<(u8, bool)>,
+#[derive(Subcommand)]
+enum Command {
+    /// Train a zstd dictionary on the game's frames, for smaller network frames.
+    /// Written to zstd.dict next to the script, which the server loads on start.
+    TrainDict {
+        /// Path to the Lua game script
+        script_path: PathBuf,
+
+        /// Game ticks to sample
+        #[arg(long, default_value_t = 1000)]
+        frames: usize,
+
+        /// Simulated players, each pressing random keys
+        #[arg(long, default_value_t = 4)]
+        sessions: usize,
+
+        /// Maximum dictionary size in bytes
+        #[arg(long, default_value_t = 16 * 1024)]
+        max_size: usize,
+
+        /// Where to write the dictionary instead of next to the script
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Render a session recorded with --record to PNG frames or an animated GIF.
+    RenderRecording {
+        /// The session's .rec file
+        recording: PathBuf,
+
+        /// An output path ending in .gif writes an animated GIF; anything else is a
+        /// directory to write numbered PNG frames into
+        #[arg(long)]
+        output: PathBuf,
+
+        /// Game directory, which the recorded images and fonts are loaded from
+        #[arg(long, default_value = ".")]
+        assets: PathBuf,
+
+        /// Images per second of recording
+        #[arg(long, default_value_t = 30.0)]
+        fps: f32,
+    },
+    /// Play a golden-image test scenario and compare the snapshots it renders with
+    /// the PNGs next to it. Exits with 1 if any differ, writing them to failures/.
+    TestGolden {
+        /// The scenario's JSON file
+        scenario: PathBuf,
+
+        /// Write the rendered snapshots as the new goldens instead of comparing
+        #[arg(long)]
+        update: bool,
+    },
+}
+
+// Events from a client's connection for the game loop
+enum ClientInput {
+    // Keys and pointer events, stamped by clients with the input_seq capability
+    Input(Input, Option<Stamp>),
+    // Screen size in CSS pixels
+    Viewport { width: f32, height: f32 },
+    // For Lua's on_message, within the session's limits
+    Message(serde_json::Value),
 }
 
 enum DebugCommand {
@@ -109,19 +208,33 @@ enum DebugCommand {
 
 // Global state used by Axum to push new clients to the game loop
 struct AppState {
-    // Queue of new clients waiting to join the game loop
-    new_clients: Arc<Mutex<Vec<ClientConnection>>>,
+    // Each room's game loop takes its new clients from here
+    rooms: Arc<Rooms<ActiveClient>>,
     base_path: String,
     assets_dir: PathBuf,
     instance_id: String,
     tx_debug: Option<mpsc::Sender<DebugCommand>>,
     sys: Arc<Mutex<System>>,
+    // Trained on this game's frames, used for clients that have the same one
+    dictionary: Option<Arc<Dictionary>>,
+    // Frame rate of clients that don't ask for one
+    frame_rate: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 enum SignalMessage {
-    WELCOME { session_id: String, server_instance_id: String },
+    WELCOME {
+        session_id: String,
+        server_instance_id: String,
+        protocol_version: u16,
+        capabilities: Vec<String>,
+        // Zstd dictionary frames are compressed with, if any
+        dictionary_id: Option<u32>,
+    },
+    REJECT { reason: String, protocol_version: u16 },
+    VIEWPORT { width: f32, height: f32 },
+    MESSAGE { payload: serde_json::Value },
     OFFER { sdp: String },
     ANSWER { sdp: String },
     CANDIDATE { candidate: String, sdp_mid: Option<String>, sdp_mline_index: Option<u16> },
@@ -134,27 +247,89 @@ async fn main() {
 
     let args = Cli::parse();
 
+    match args.command {
+        Some(Command::TrainDict { script_path, frames, sessions, max_size, output }) => {
+            let output = output.unwrap_or_else(|| {
+                script_path.parent().unwrap_or(Path::new(".")).join(dictionary::FILE_NAME)
+            });
+            match train_dict(&script_path, frames, sessions, max_size, &output) {
+                Ok(()) => std::process::exit(0),
+                Err(e) => {
+                    eprintln!("Training failed: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::RenderRecording { recording, output, assets, fps }) => {
+            match render_recording(&recording, &output, &assets, fps) {
+                Ok(()) => std::process::exit(0),
+                Err(e) => {
+                    eprintln!("Rendering failed: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::TestGolden { scenario, update }) => {
+            println!("Running golden-image scenario {:?}", scenario);
+            match golden::run(&scenario, update) {
+                Ok(failures) if failures.is_empty() => std::process::exit(0),
+                Ok(failures) => {
+                    eprintln!(
+                        "Test Failed: {} snapshot(s) differ from their goldens: {}. See {:?} for what was rendered.",
+                        failures.len(),
+                        failures.join(", "),
+                        scenario.parent().unwrap_or(Path::new(".")).join(golden::FAILURES_DIR)
+                    );
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Test Failed: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {}
+    }
+    // The games to serve: the one script, or every game in --games
+    let games = args.games.as_ref().map(|dir| match games::find(dir) {
+        Ok(games) if !games.is_empty() => games,
+        Ok(_) => {
+            eprintln!("No games in {:?}: none of its directories has a {}", dir, games::MAIN_SCRIPT);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to read games directory {:?}: {}", dir, e);
+            std::process::exit(1);
+        }
+    });
+    let script_paths: Vec<PathBuf> = match &games {
+        Some(games) => games.iter().map(|game| game.script_path.clone()).collect(),
+        None => vec![args.script_path.clone().expect("script path is required without a subcommand")],
+    };
+
     // Test Mode
     if args.test {
-        println!("Running in TEST mode: {:?}", args.script_path);
-        let script_path_str = args.script_path.to_string_lossy().to_string();
-        
-        match load_game(&script_path_str) {
-            Some(game) => {
-                println!("Script loaded successfully.");
-                // Try running one update step
-                if let Err(e) = game.update(0.1) {
-                    eprintln!("Test Failed: Runtime error during update: {}", e);
+        for script_path in &script_paths {
+            println!("Running in TEST mode: {:?}", script_path);
+            let script_path_str = script_path.to_string_lossy().to_string();
+
+            match load_game(&script_path_str) {
+                Some(game) => {
+                    println!("Script loaded successfully.");
+                    // Try running one update step
+                    if let Err(e) = game.update(0.1) {
+                        eprintln!("Test Failed: Runtime error during update: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                None => {
+                    eprintln!("Test Failed: Could not load script.");
                     std::process::exit(1);
                 }
-                println!("Test Passed: init() and update() executed without errors.");
-                std::process::exit(0);
-            }
-            None => {
-                eprintln!("Test Failed: Could not load script.");
-                std::process::exit(1);
             }
         }
+        println!("Test Passed: init() and update() executed without errors.");
+        std::process::exit(0);
     }
 
     // Export Client Mode
@@ -178,36 +353,95 @@ async fn main() {
                 }
             }
         }
+        if let Err(e) = std::fs::write(target_dir.join("protocol.js"), protocol::js_module()) {
+            eprintln!("Failed to write protocol.js: {}", e);
+        } else {
+            println!("  Generated: protocol.js");
+        }
         println!("Export complete.");
         std::process::exit(0);
     }
     
     println!("Starting Cleoselene Server...");
-    println!("Script: {:?}", args.script_path);
     println!("Port: {}", args.port);
-    println!("Base Path: {}", args.base_path);
+    println!("Tick Rate: {} Hz", args.tick_rate);
+
+    let app = match &games {
+        Some(games) => {
+            // Each game under its own path, and a list of them at the root
+            let mut app = Router::new();
+            for game in games {
+                let base_path = format!("{}/{}", args.base_path.trim_end_matches('/'), game.name);
+                let record = args.record.as_ref().map(|dir| dir.join(&game.name));
+                app = app.nest_service(&format!("/{}", game.name), start_game(&game.script_path, base_path, record, &args));
+            }
+            let index = games::index_html(games, &args.base_path);
+            app.route("/", get(move || async move { Html(index) }))
+                .fallback(static_handler)
+        }
+        None => start_game(&script_paths[0], args.base_path.clone(), args.record.clone(), &args),
+    };
+    let app = app.layer(TraceLayer::new_for_http());
+
+    let addr = format!("0.0.0.0:{}", args.port);
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    println!("Listening on http://localhost:{}", args.port);
+    axum::serve(listener, app).await.unwrap();
+}
+
+// Starts a game's main room and returns the routes it's served on: the page, its
+// WebSocket, /assets and /mcp, relative to `base_path`
+fn start_game(script_path: &Path, base_path: String, record: Option<PathBuf>, args: &Cli) -> Router {
+    println!("Script: {:?}", script_path);
+    println!("Base Path: {}", base_path);
 
-    let new_clients_queue = Arc::new(Mutex::new(Vec::new()));
-    
     // Debug Channel
     let (tx_debug, rx_debug) = if args.debug_mcp {
         let (tx, rx) = mpsc::channel(10);
-        println!("Debug MCP endpoint enabled at /mcp");
+        println!("Debug MCP endpoint enabled at {}/mcp", base_path.trim_end_matches('/'));
         (Some(tx), Some(rx))
     } else {
         (None, None)
     };
 
-    // Start the Global Game Loop
-    let queue_clone = new_clients_queue.clone();
-    let script_path = args.script_path.clone();
-    
-    thread::spawn(move || {
-        game_loop(queue_clone, script_path, rx_debug);
+    let recorder = record.map(|dir| match Recorder::new(&dir) {
+        Ok(recorder) => {
+            println!("Recording sessions to {:?}", dir);
+            Arc::new(Mutex::new(recorder))
+        }
+        Err(e) => {
+            eprintln!("Failed to create recording directory {:?}: {}", dir, e);
+            std::process::exit(1);
+        }
     });
 
     // Determine assets dir (parent of script)
-    let assets_dir = args.script_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let assets_dir = script_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+
+    // Start the Main Room's Game Loop
+    let main_script = script_path.file_name().unwrap_or_default().to_string_lossy();
+    let rooms = Arc::new(Rooms::new(&assets_dir, &main_script));
+    let link = Arc::new(RoomLink {
+        id: MAIN_ROOM.to_string(),
+        params: serde_json::Value::Null,
+        rooms: rooms.clone(),
+        tick_rate: args.tick_rate,
+        recorder,
+    });
+    let game = load_room(script_path, link.room()).unwrap_or_else(|e| {
+        eprintln!("Failed to load initial game script: {}", e);
+        std::process::exit(1);
+    });
+    let game_script = script_path.to_path_buf();
+
+    thread::spawn(move || {
+        game_loop(link, game, game_script, rx_debug);
+    });
+
+    let dictionary = Dictionary::load(&assets_dir).map(Arc::new);
+    if let Some(dict) = &dictionary {
+        println!("Zstd dictionary: {} (id {})", dictionary::FILE_NAME, dict.id);
+    }
     
     // Generate unique ID for this server process run
     let instance_id = Uuid::new_v4().to_string();
@@ -216,28 +450,25 @@ async fn main() {
     let sys = System::new_with_specifics(RefreshKind::nothing().with_cpu(CpuRefreshKind::everything()).with_memory(MemoryRefreshKind::everything()));
 
     let app_state = Arc::new(AppState {
-        new_clients: new_clients_queue,
-        base_path: args.base_path.clone(),
+        rooms,
+        base_path,
         assets_dir: assets_dir.clone(),
         instance_id,
         tx_debug,
         sys: Arc::new(Mutex::new(sys)),
+        dictionary,
+        frame_rate: args.frame_rate.unwrap_or(args.tick_rate.min(MAX_FRAME_RATE)),
     });
 
-    let app = Router::new()
+    Router::new()
         .route("/ws", get(ws_handler))
         .route("/mcp", post(mcp_handler))
         .route("/", get(serve_index))
         .route("/index.html", get(serve_index))
+        .route("/protocol.js", get(serve_protocol))
         .nest_service("/assets", ServeDir::new(assets_dir))
         .fallback(static_handler)
-        .layer(TraceLayer::new_for_http())
-        .with_state(app_state);
-
-    let addr = format!("0.0.0.0:{}", args.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("Listening on http://localhost:{}", args.port);
-    axum::serve(listener, app).await.unwrap();
+        .with_state(app_state)
 }
 
 #[derive(Deserialize)]
@@ -309,7 +540,8 @@ async fn mcp_handler(State(state): State<Arc<AppState>>, Json(payload): Json<Mcp
                 if tx.send(DebugCommand::Render(session_id, reply_tx)).await.is_ok() {
                     if let Ok(Some(bytes)) = reply_rx.await {
                          // Convert commands to PNG
-                         match render_to_png(bytes, &state.assets_dir) {
+                         let mut raster = Rasterizer::new(&state.assets_dir);
+                         match raster.render(&bytes).and_then(|_| raster.to_png()) {
                              Ok(png_bytes) => {
                                  // Save to file
                                  let mcp_dir = state.assets_dir.join(".cleoselene-mcp");
@@ -414,6 +646,85 @@ fn get_sdk_docs() -> Vec<SdkFunction> {
             ],
             returns: vec![],
         },
+        SdkFunction {
+            name: "api.stroke_rect".to_string(),
+            description: "Draws a rectangle outline using the current color.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "X coordinate".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Y coordinate".into(), optional: false },
+                SdkParam { name: "w".into(), type_name: "f32".into(), description: "Width".into(), optional: false },
+                SdkParam { name: "h".into(), type_name: "f32".into(), description: "Height".into(), optional: false },
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Line width (default 1.0)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.fill_circle".to_string(),
+            description: "Draws a filled circle using the current color.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "Center X".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Center Y".into(), optional: false },
+                SdkParam { name: "r".into(), type_name: "f32".into(), description: "Radius".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.stroke_circle".to_string(),
+            description: "Draws a circle outline using the current color.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "Center X".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Center Y".into(), optional: false },
+                SdkParam { name: "r".into(), type_name: "f32".into(), description: "Radius".into(), optional: false },
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Line width (default 1.0)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.arc".to_string(),
+            description: "Draws an arc. Without a width it fills the pie slice.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "Center X".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Center Y".into(), optional: false },
+                SdkParam { name: "r".into(), type_name: "f32".into(), description: "Radius".into(), optional: false },
+                SdkParam { name: "start".into(), type_name: "f32".into(), description: "Start angle (radians)".into(), optional: false },
+                SdkParam { name: "end".into(), type_name: "f32".into(), description: "End angle (radians)".into(), optional: false },
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Line width. Omit to fill.".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.ellipse".to_string(),
+            description: "Draws an ellipse. Without a width it is filled.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "Center X".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Center Y".into(), optional: false },
+                SdkParam { name: "rx".into(), type_name: "f32".into(), description: "Radius X".into(), optional: false },
+                SdkParam { name: "ry".into(), type_name: "f32".into(), description: "Radius Y".into(), optional: false },
+                SdkParam { name: "rotation".into(), type_name: "f32".into(), description: "Rotation (radians)".into(), optional: true },
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Line width. Omit to fill.".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.fill_poly".to_string(),
+            description: "Fills a polygon using the current color.".to_string(),
+            params: vec![
+                SdkParam { name: "points".into(), type_name: "Vec<f32>".into(), description: "Flat list {x1, y1, x2, y2, ...}".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.stroke_poly".to_string(),
+            description: "Strokes a polyline or polygon outline.".to_string(),
+            params: vec![
+                SdkParam { name: "points".into(), type_name: "Vec<f32>".into(), description: "Flat list {x1, y1, x2, y2, ...}".into(), optional: false },
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Line width (default 1.0)".into(), optional: true },
+                SdkParam { name: "join".into(), type_name: "string".into(), description: "\"miter\" (default), \"round\" or \"bevel\"".into(), optional: true },
+                SdkParam { name: "cap".into(), type_name: "string".into(), description: "\"butt\" (default), \"round\" or \"square\"".into(), optional: true },
+                SdkParam { name: "closed".into(), type_name: "boolean".into(), description: "Connect the last point to the first (default true)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
         SdkFunction {
             name: "api.draw_text".to_string(),
             description: "Draws text at the specified coordinates.".to_string(),
@@ -421,6 +732,94 @@ fn get_sdk_docs() -> Vec<SdkFunction> {
                 SdkParam { name: "text".into(), type_name: "string".into(), description: "The text to draw".into(), optional: false },
                 SdkParam { name: "x".into(), type_name: "f32".into(), description: "X coordinate".into(), optional: false },
                 SdkParam { name: "y".into(), type_name: "f32".into(), description: "Y coordinate".into(), optional: false },
+                SdkParam { name: "max_width".into(), type_name: "f32".into(), description: "Word-wrap lines to this width".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.set_resolution".to_string(),
+            description: "Sets the virtual canvas size and how clients scale it to their screen. Overrides metadata.json.".to_string(),
+            params: vec![
+                SdkParam { name: "width".into(), type_name: "u16".into(), description: "Virtual width in pixels".into(), optional: false },
+                SdkParam { name: "height".into(), type_name: "u16".into(), description: "Virtual height in pixels".into(), optional: false },
+                SdkParam { name: "mode".into(), type_name: "string".into(), description: "\"letterbox\", \"stretch\", \"integer\" or \"expand\" (default: unchanged)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.screen_size".to_string(),
+            description: "Returns the virtual area visible to a session. Larger than the resolution in \"expand\" mode.".to_string(),
+            params: vec![
+                SdkParam { name: "session_id".into(), type_name: "string".into(), description: "The viewer's session".into(), optional: false },
+            ],
+            returns: vec![
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Visible width".into(), optional: false },
+                SdkParam { name: "height".into(), type_name: "f32".into(), description: "Visible height".into(), optional: false },
+            ],
+        },
+        SdkFunction {
+            name: "api.measure_text".to_string(),
+            description: "Measures text in the current font, laid out as draw_text would.".to_string(),
+            params: vec![
+                SdkParam { name: "text".into(), type_name: "string".into(), description: "The text to measure".into(), optional: false },
+                SdkParam { name: "max_width".into(), type_name: "f32".into(), description: "Word-wrap lines to this width".into(), optional: true },
+            ],
+            returns: vec![
+                SdkParam { name: "width".into(), type_name: "f32".into(), description: "Width of the widest line".into(), optional: false },
+                SdkParam { name: "height".into(), type_name: "f32".into(), description: "Height of all lines".into(), optional: false },
+            ],
+        },
+        SdkFunction {
+            name: "api.load_font".to_string(),
+            description: "Loads a font file for use with set_font.".to_string(),
+            params: vec![
+                SdkParam { name: "name".into(), type_name: "string".into(), description: "Name to register the font under".into(), optional: false },
+                SdkParam { name: "url".into(), type_name: "string".into(), description: "Font URL, e.g. /assets/font.ttf".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.set_font".to_string(),
+            description: "Sets the font used by draw_text and measure_text.".to_string(),
+            params: vec![
+                SdkParam { name: "name".into(), type_name: "string".into(), description: "Loaded font or CSS generic family".into(), optional: false },
+                SdkParam { name: "size".into(), type_name: "f32".into(), description: "Size in pixels (default 14)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.set_text_align".to_string(),
+            description: "Sets how draw_text is anchored to its coordinates.".to_string(),
+            params: vec![
+                SdkParam { name: "align".into(), type_name: "string".into(), description: "\"left\", \"center\" or \"right\"".into(), optional: false },
+                SdkParam { name: "baseline".into(), type_name: "string".into(), description: "\"middle\", \"top\", \"bottom\" or \"alphabetic\"".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.begin_list".to_string(),
+            description: "Starts recording drawing commands into a display list kept by the client.".to_string(),
+            params: vec![
+                SdkParam { name: "name".into(), type_name: "string".into(), description: "Name of the list".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.end_list".to_string(),
+            description: "Stops recording and stores the display list. Unchanged lists are not re-sent.".to_string(),
+            params: vec![],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.call_list".to_string(),
+            description: "Draws a recorded display list with its own transform.".to_string(),
+            params: vec![
+                SdkParam { name: "name".into(), type_name: "string".into(), description: "Name of the list".into(), optional: false },
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "X offset (default 0)".into(), optional: true },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Y offset (default 0)".into(), optional: true },
+                SdkParam { name: "rotation".into(), type_name: "f32".into(), description: "Rotation in radians (default 0)".into(), optional: true },
+                SdkParam { name: "sx".into(), type_name: "f32".into(), description: "Horizontal scale (default 1)".into(), optional: true },
+                SdkParam { name: "sy".into(), type_name: "f32".into(), description: "Vertical scale (default sx)".into(), optional: true },
             ],
             returns: vec![],
         },
@@ -488,6 +887,120 @@ fn get_sdk_docs() -> Vec<SdkFunction> {
             ],
             returns: vec![],
         },
+        SdkFunction {
+            name: "api.push".to_string(),
+            description: "Saves the current transform and color on the transform stack.".to_string(),
+            params: vec![],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.pop".to_string(),
+            description: "Restores the transform and color saved by the matching api.push().".to_string(),
+            params: vec![],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.translate".to_string(),
+            description: "Moves the origin of subsequent drawing.".to_string(),
+            params: vec![
+                SdkParam { name: "x".into(), type_name: "f32".into(), description: "Offset X".into(), optional: false },
+                SdkParam { name: "y".into(), type_name: "f32".into(), description: "Offset Y".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.rotate".to_string(),
+            description: "Rotates subsequent drawing around the current origin.".to_string(),
+            params: vec![
+                SdkParam { name: "rad".into(), type_name: "f32".into(), description: "Angle in radians (clockwise)".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.scale".to_string(),
+            description: "Scales subsequent drawing.".to_string(),
+            params: vec![
+                SdkParam { name: "sx".into(), type_name: "f32".into(), description: "Scale X".into(), optional: false },
+                SdkParam { name: "sy".into(), type_name: "f32".into(), description: "Scale Y (defaults to sx)".into(), optional: true },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.get_axis".to_string(),
+            description: "Returns the latest value of a gamepad stick, trigger or button (0 at rest).".to_string(),
+            params: vec![
+                SdkParam { name: "session_id".into(), type_name: "string".into(), description: "The player's session".into(), optional: false },
+                SdkParam { name: "axis".into(), type_name: "string".into(), description: "Control name, e.g. \"left_x\" or \"a\"".into(), optional: false },
+                SdkParam { name: "pad".into(), type_name: "u8".into(), description: "Gamepad number, from 1 (default 1)".into(), optional: true },
+            ],
+            returns: vec![
+                SdkParam { name: "value".into(), type_name: "f32".into(), description: "-1 to 1 for sticks, 0 to 1 for triggers, 0 or 1 for buttons".into(), optional: false }
+            ],
+        },
+        SdkFunction {
+            name: "api.is_action_down".to_string(),
+            description: "Says whether a controls.json action is down, i.e. any of its bindings is held.".to_string(),
+            params: vec![
+                SdkParam { name: "session_id".into(), type_name: "string".into(), description: "The player's session".into(), optional: false },
+                SdkParam { name: "action".into(), type_name: "string".into(), description: "Action name from controls.json".into(), optional: false },
+            ],
+            returns: vec![
+                SdkParam { name: "down".into(), type_name: "boolean".into(), description: "Whether the action is down now".into(), optional: false }
+            ],
+        },
+        SdkFunction {
+            name: "api.tick".to_string(),
+            description: "Counts the updates run so far; the frame drawn after an update shows that tick.".to_string(),
+            params: vec![],
+            returns: vec![
+                SdkParam { name: "tick".into(), type_name: "u64".into(), description: "The current tick".into(), optional: false }
+            ],
+        },
+        SdkFunction {
+            name: "api.send".to_string(),
+            description: "Sends a value as JSON to a player's page, which gets it in window.cleoselene.onMessage(payload) at the end of the tick.".to_string(),
+            params: vec![
+                SdkParam { name: "session_id".into(), type_name: "string".into(), description: "The player's session".into(), optional: false },
+                SdkParam { name: "value".into(), type_name: "any".into(), description: "Table, string, number, boolean or nil, up to 4096 bytes of JSON".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.room_id".to_string(),
+            description: "Returns this room's id (\"main\" for the room players join first).".to_string(),
+            params: vec![],
+            returns: vec![
+                SdkParam { name: "room_id".into(), type_name: "string".into(), description: "This room's id".into(), optional: false }
+            ],
+        },
+        SdkFunction {
+            name: "api.create_room".to_string(),
+            description: "Starts a script in a new room with its own Lua state and game loop. Its init(params) runs before this returns.".to_string(),
+            params: vec![
+                SdkParam { name: "script".into(), type_name: "string".into(), description: "Path inside the game's directory, e.g. \"arena.lua\"".into(), optional: false },
+                SdkParam { name: "params".into(), type_name: "any".into(), description: "Passed to the script's init(params)".into(), optional: true },
+            ],
+            returns: vec![
+                SdkParam { name: "room_id".into(), type_name: "string".into(), description: "The new room's id".into(), optional: false }
+            ],
+        },
+        SdkFunction {
+            name: "api.move_session".to_string(),
+            description: "Sends one of this room's players to another room after this tick, calling on_disconnect here and on_connect there.".to_string(),
+            params: vec![
+                SdkParam { name: "session_id".into(), type_name: "string".into(), description: "The player's session".into(), optional: false },
+                SdkParam { name: "room_id".into(), type_name: "string".into(), description: "The room to move them to".into(), optional: false },
+            ],
+            returns: vec![],
+        },
+        SdkFunction {
+            name: "api.list_rooms".to_string(),
+            description: "Lists the open rooms, \"main\" first.".to_string(),
+            params: vec![],
+            returns: vec![
+                SdkParam { name: "rooms".into(), type_name: "Vec<Table>".into(), description: "Tables with id, script, players and params".into(), optional: false }
+            ],
+        },
         SdkFunction {
             name: "api.new_spatial_db".to_string(),
             description: "Creates a new Spatial Database for optimized 2D spatial queries.".to_string(),
@@ -564,130 +1077,6 @@ fn get_sdk_docs() -> Vec<SdkFunction> {
     ]
 }
 
-// Simple Software Renderer for Debugging
-fn render_to_png(mut commands: bytes::Bytes, _assets_dir: &Path) -> anyhow::Result<Vec<u8>> {
-    const WIDTH: u32 = 800;
-    const HEIGHT: u32 = 600;
-    
-    let mut img: RgbImage = ImageBuffer::new(WIDTH, HEIGHT);
-    
-    // Default background black
-    imageproc::drawing::draw_filled_rect_mut(&mut img, Rect::at(0, 0).of_size(WIDTH, HEIGHT), Rgb([0, 0, 0]));
-
-    let mut current_color = Rgba([255, 255, 255, 255]);
-    
-    // OpCodes (Must match engine/src/lib.rs)
-    const OP_CLEAR: u8 = 0x01;
-    const OP_SET_COLOR: u8 = 0x02;
-    const OP_FILL_RECT: u8 = 0x03;
-    const OP_DRAW_LINE: u8 = 0x04;
-    const OP_DRAW_TEXT: u8 = 0x05;
-    const OP_DRAW_IMAGE: u8 = 0x0B;
-
-    while commands.has_remaining() {
-        let op = commands.get_u8();
-        match op {
-            OP_CLEAR => {
-                let r = commands.get_u8();
-                let g = commands.get_u8();
-                let b = commands.get_u8();
-                imageproc::drawing::draw_filled_rect_mut(&mut img, Rect::at(0, 0).of_size(WIDTH, HEIGHT), Rgb([r, g, b]));
-            },
-            OP_SET_COLOR => {
-                let r = commands.get_u8();
-                let g = commands.get_u8();
-                let b = commands.get_u8();
-                let a = commands.get_u8();
-                current_color = Rgba([r, g, b, a]);
-            },
-            OP_FILL_RECT => {
-                let x = commands.get_f32_le() as i32;
-                let y = commands.get_f32_le() as i32;
-                let w = commands.get_f32_le() as u32;
-                let h = commands.get_f32_le() as u32;
-                let rgb = Rgb([current_color[0], current_color[1], current_color[2]]);
-                imageproc::drawing::draw_filled_rect_mut(&mut img, Rect::at(x, y).of_size(w, h), rgb);
-            },
-            OP_DRAW_LINE => {
-                let x1 = commands.get_f32_le();
-                let y1 = commands.get_f32_le();
-                let x2 = commands.get_f32_le();
-                let y2 = commands.get_f32_le();
-                let _width = commands.get_f32_le(); // Width ignored in simple renderer
-                let rgb = Rgb([current_color[0], current_color[1], current_color[2]]);
-                imageproc::drawing::draw_line_segment_mut(&mut img, (x1, y1), (x2, y2), rgb);
-            },
-            OP_DRAW_TEXT => {
-                let x = commands.get_f32_le();
-                let y = commands.get_f32_le();
-                let len = commands.get_u16_le() as usize;
-                let _bytes = commands.copy_to_bytes(len);
-                // Placeholder: Draw a small rect for text
-                let rgb = Rgb([current_color[0], current_color[1], current_color[2]]);
-                 imageproc::drawing::draw_filled_rect_mut(&mut img, Rect::at(x as i32, y as i32).of_size(len as u32 * 8, 10), rgb);
-            },
-            OP_DRAW_IMAGE => {
-                let len = commands.get_u16_le() as usize;
-                let _name_bytes = commands.copy_to_bytes(len);
-                // Skip args
-                let x = commands.get_f32_le() as i32;
-                let y = commands.get_f32_le() as i32;
-                let w = commands.get_f32_le(); // w
-                let h = commands.get_f32_le(); // h
-                let _ = commands.get_f32_le(); // sx
-                let _ = commands.get_f32_le(); // sy
-                let _ = commands.get_f32_le(); // sw
-                let _ = commands.get_f32_le(); // sh
-                let _ = commands.get_f32_le(); // r
-                let _ = commands.get_f32_le(); // ox
-                let _ = commands.get_f32_le(); // oy
-
-                // Placeholder: Draw a blue rect for images
-                let rgb = Rgb([0, 0, 255]);
-                let width = if w > 0.0 { w as u32 } else { 32 };
-                let height = if h > 0.0 { h as u32 } else { 32 };
-                imageproc::drawing::draw_filled_rect_mut(&mut img, Rect::at(x, y).of_size(width, height), rgb);
-            },
-            0x06 | 0x07 | 0x08 | 0x09 => {
-                // Sound ops - skip
-                // Variable length args handling is tricky here without strict parsing logic
-                // OP_LOAD_SOUND: len(u16) + bytes + len(u16) + bytes
-                if op == 0x06 {
-                    let l1 = commands.get_u16_le() as usize; commands.advance(l1);
-                    let l2 = commands.get_u16_le() as usize; commands.advance(l2);
-                }
-                // OP_PLAY_SOUND: len(u16) + bytes + u8 + f32
-                if op == 0x07 {
-                    let l1 = commands.get_u16_le() as usize; commands.advance(l1);
-                    commands.advance(1 + 4);
-                }
-                // OP_STOP_SOUND: len(u16) + bytes
-                if op == 0x08 {
-                    let l1 = commands.get_u16_le() as usize; commands.advance(l1);
-                }
-                // OP_SET_VOLUME: len(u16) + bytes + f32
-                if op == 0x09 {
-                    let l1 = commands.get_u16_le() as usize; commands.advance(l1);
-                    commands.advance(4);
-                }
-            },
-            0x0A => {
-                // OP_LOAD_IMAGE: len(u16) + bytes + len(u16) + bytes
-                 let l1 = commands.get_u16_le() as usize; commands.advance(l1);
-                 let l2 = commands.get_u16_le() as usize; commands.advance(l2);
-            },
-            _ => {
-                // Unknown op, stop to avoid misalignment
-                break;
-            }
-        }
-    }
-
-    let mut cursor = Cursor::new(Vec::new());
-    img.write_to(&mut cursor, image::ImageFormat::Png)?;
-    Ok(cursor.into_inner())
-}
-
 // Serve index.html with config injection from Embedded Assets
 async fn serve_index(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     match ClientAssets::get("index.html") {
@@ -716,35 +1105,67 @@ async fn serve_index(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }
 }
 
+// Protocol constants for main.js, generated from the engine
+async fn serve_protocol() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript")],
+        protocol::js_module()
+    )
+}
+
 #[derive(Deserialize)]
 struct KeyDef {
     label: String,
-    key: u32,
+    // A legacy key code or a key name
+    key: serde_json::Value,
 }
 
+// Touch buttons from controls.json's layout, or else keys.json's
 fn generate_controls_html(assets_dir: &Path) -> String {
-    let keys_path = assets_dir.join("keys.json");
-    if let Ok(file) = std::fs::File::open(keys_path) {
-        let layout: Vec<Vec<KeyDef>> = serde_json::from_reader(file).unwrap_or_default();
-        if layout.is_empty() { return String::new(); }
-
-        let mut html = String::from("<div id='mobile-controls' class='touch-controls' style='display: none;'>");
-        for row in layout {
-            let cols = row.len();
-            html.push_str(&format!("<div class='control-row' style='display: grid; grid-template-columns: repeat({}, 1fr); gap: 10px;'>", cols));
-            for btn in row {
-                html.push_str(&format!(
-                    "<div class='touch-btn' data-key='{}'>{}</div>",
-                    btn.key, btn.label
-                ));
-            }
-            html.push_str("</div>");
+    let layout: Vec<Vec<(String, String)>> = match Controls::load(assets_dir) {
+        Ok(controls) if !controls.touch.is_empty() => controls
+            .touch
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|btn| (btn.label.clone(), controls.touch_key(&btn.action).unwrap_or_default().to_string()))
+                    .collect()
+            })
+            .collect(),
+        _ => {
+            let Ok(file) = std::fs::File::open(assets_dir.join("keys.json")) else {
+                return String::new();
+            };
+            let layout: Vec<Vec<KeyDef>> = serde_json::from_reader(file).unwrap_or_default();
+            layout
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|btn| match btn.key {
+                            serde_json::Value::String(name) => (btn.label, name),
+                            key => (btn.label, key.to_string()),
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    };
+    if layout.is_empty() { return String::new(); }
+
+    let mut html = String::from("<div id='mobile-controls' class='touch-controls' style='display: none;'>");
+    for row in layout {
+        let cols = row.len();
+        html.push_str(&format!("<div class='control-row' style='display: grid; grid-template-columns: repeat({}, 1fr); gap: 10px;'>", cols));
+        for (label, key) in row {
+            html.push_str(&format!(
+                "<div class='touch-btn' data-key='{}'>{}</div>",
+                key, label
+            ));
         }
         html.push_str("</div>");
-        html
-    } else {
-        String::new()
     }
+    html.push_str("</div>");
+    html
 }
 
 // Serve other static files from Embedded Assets
@@ -767,16 +1188,149 @@ async fn static_handler(uri: Uri) -> impl IntoResponse {
 
 struct ActiveClient {
     session_id: String,
-    tx_render: mpsc::Sender<bytes::Bytes>,
-    rx_input: mpsc::Receiver<(u8, bool)>,
+    caps: Capabilities,
+    // What on_connect sent, e.g. images to load, for rendering the session's screen
+    setup: bytes::Bytes,
+    tx_render: mpsc::Sender<OutFrame>,
+    rx_input: mpsc::Receiver<ClientInput>,
+    tx_text: mpsc::Sender<Message>,
+    pacer: FramePacer,
+    // Setup and sounds it hasn't been sent yet
+    outbox: Outbox,
+    // Highest input sequence number handled, acknowledged in the next frame
+    input_ack: Option<u32>,
+    // Delta id the next frame sent will get, and the tick of the frames sent lately,
+    // to tell stamped inputs which tick the player was looking at
+    next_frame_id: u32,
+    frame_ticks: VecDeque<(u32, u64)>,
 }
 
-fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path: PathBuf, mut rx_debug: Option<mpsc::Receiver<DebugCommand>>) {
-    println!("Global Game Loop Started");
-    
-    // Convert PathBuf to String for loading
-    let script_path_str = script_path.to_string_lossy().to_string();
-    
+// Frames remembered per client for stamped inputs, about 4 seconds' worth
+const FRAME_TICKS: usize = 128;
+
+impl ActiveClient {
+    fn new(
+        session_id: String,
+        caps: Capabilities,
+        tx_render: mpsc::Sender<OutFrame>,
+        rx_input: mpsc::Receiver<ClientInput>,
+        tx_text: mpsc::Sender<Message>,
+        frame_rate: u32,
+    ) -> Self {
+        Self {
+            session_id,
+            caps,
+            setup: bytes::Bytes::new(),
+            tx_render,
+            rx_input,
+            tx_text,
+            pacer: FramePacer::new(frame_rate, Instant::now()),
+            outbox: Outbox::default(),
+            input_ack: None,
+            next_frame_id: 0,
+            frame_ticks: VecDeque::new(),
+        }
+    }
+
+    // Forgets what the room it leaves sent. The connection, and with it the frame
+    // ids and input acks, carries on into the next room.
+    fn leave(&mut self) {
+        self.setup = bytes::Bytes::new();
+        self.outbox = Outbox::default();
+        self.frame_ticks.clear();
+    }
+
+    // Records a frame showing `tick` going out, which the coordinator gives the next id
+    fn sent_frame(&mut self, tick: u64) {
+        if !self.caps.delta_frames {
+            return;
+        }
+        self.frame_ticks.push_back((self.next_frame_id, tick));
+        if self.frame_ticks.len() > FRAME_TICKS {
+            self.frame_ticks.pop_front();
+        }
+        self.next_frame_id = self.next_frame_id.wrapping_add(1);
+    }
+
+    // Sends on_connect's setup if it's still pending and the queue has room
+    fn send_setup(&mut self, tick: u64, recorder: &Option<Arc<Mutex<Recorder>>>) {
+        if let Some(bytes) = self.outbox.send_setup(&self.tx_render, self.input_ack) {
+            self.sent_frame(tick);
+            if let Some(recorder) = recorder {
+                recorder.lock().unwrap().record(&self.session_id, &bytes);
+            }
+        }
+    }
+
+    fn timing(&mut self, stamp: Option<Stamp>) -> InputTiming {
+        let Some(stamp) = stamp else {
+            return InputTiming::default();
+        };
+        self.input_ack = Some(self.input_ack.map_or(stamp.seq, |acked| acked.max(stamp.seq)));
+        let frame = self
+            .frame_ticks
+            .iter()
+            .find(|(id, _)| *id == stamp.frame)
+            .map(|(_, tick)| *tick);
+        InputTiming { seq: Some(stamp.seq), frame }
+    }
+}
+
+// A room's game loop's way to the other rooms, behind its Lua's room functions
+struct RoomLink {
+    id: String,
+    params: serde_json::Value,
+    rooms: Arc<Rooms<ActiveClient>>,
+    tick_rate: u32,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+}
+
+impl RoomLink {
+    // The room as the room's games see it
+    fn room(self: &Arc<Self>) -> Room {
+        Room {
+            id: self.id.clone(),
+            params: self.params.clone(),
+            host: Some(self.clone()),
+        }
+    }
+}
+
+impl RoomHost for RoomLink {
+    fn create_room(&self, script: &str, params: serde_json::Value) -> anyhow::Result<String> {
+        let path = self.rooms.script_path(script)?;
+        let id = self.rooms.open(script, params.clone())?;
+        let link = Arc::new(RoomLink {
+            id: id.clone(),
+            params,
+            rooms: self.rooms.clone(),
+            tick_rate: self.tick_rate,
+            recorder: self.recorder.clone(),
+        });
+        let game = match load_room(&path, link.room()) {
+            Ok(game) => game,
+            Err(e) => {
+                self.rooms.close(&id);
+                return Err(e);
+            }
+        };
+        println!("Room {} opened: {}", id, script);
+        thread::spawn(move || game_loop(link, game, path, None));
+        Ok(id)
+    }
+
+    fn move_session(&self, session_id: &str, room_id: &str) -> anyhow::Result<()> {
+        self.rooms.move_session(&self.id, session_id, room_id)
+    }
+
+    fn list_rooms(&self) -> Vec<RoomInfo> {
+        self.rooms.list()
+    }
+}
+
+fn game_loop(link: Arc<RoomLink>, mut game: GameState, script_path: PathBuf, mut rx_debug: Option<mpsc::Receiver<DebugCommand>>) {
+    println!("Game Loop Started: room {}", link.id);
+
     // File Watcher
     let (tx_notify, rx_notify) = channel();
     let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
@@ -796,14 +1350,12 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
          let _ = watcher.watch(Path::new("."), RecursiveMode::Recursive);
     }
 
-    // Init Game
-    let mut game = load_game(&script_path_str).expect("Failed to load initial game script");
-    
     // Active Clients List
     let mut clients: Vec<ActiveClient> = Vec::new();
+    // Since when the room has had no players
+    let mut empty_since: Option<Instant> = None;
 
-    let target_fps = 30;
-    let frame_duration = Duration::from_micros(1_000_000 / target_fps);
+    let mut clock = FixedStep::new(link.tick_rate);
     let mut last_time = Instant::now();
 
     loop {
@@ -813,49 +1365,54 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
             thread::sleep(Duration::from_millis(50)); // Debounce
             println!("Hot Reload Triggered!");
             
-            // Load new game without state preservation
-            if let Some(new_game) = load_game(&script_path_str) {
+            // Load new game, carrying over what the script saves with on_save
+            let saved = game.save_state().unwrap_or_else(|e| {
+                eprintln!("Lua on_save Error: {}", e);
+                None
+            });
+            if let Ok(new_game) = load_room(&script_path, link.room()).map_err(|e| eprintln!("{}", e)) {
                 game = new_game;
                 println!("Reload & Swap Successful!");
                 
                 // Re-register existing clients in the new Lua instance
-                for client in &clients {
+                for client in &mut clients {
                     if let Ok(bytes) = game.on_connect(&client.session_id) {
-                        let _ = client.tx_render.try_send(bytes);
+                        client.setup = bytes.clone();
+                        client.outbox.push_setup(bytes);
+                        client.send_setup(game.tick(), &link.recorder);
+                    }
+                }
+
+                // Last, so the saved players replace the ones on_connect just made
+                if let Some(saved) = saved {
+                    if let Err(e) = game.restore_state(&saved) {
+                        eprintln!("Lua on_restore Error: {}", e);
                     }
                 }
             }
         }
 
         let now = Instant::now();
-        let dt = now.duration_since(last_time).as_secs_f32();
+        let steps = clock.advance(now.duration_since(last_time));
         last_time = now;
 
-        // Reset frame state (events)
-        game.begin_frame();
+        // 2. Accept New Clients, and players from other rooms
+        for mut client in link.rooms.take_joining(&link.id) {
+            println!("Player {} joined room {}", client.session_id, link.id);
 
-        // 2. Accept New Clients
-        {
-            let mut queue = new_clients_queue.lock().unwrap();
-            while let Some(conn) = queue.pop() {
-                println!("New player joined game: {}", conn.session_id);
-                
-                // Init player and get initialization commands (e.g. load_sound)
-                match game.on_connect(&conn.session_id) {
-                    Ok(bytes) => {
-                        let _ = conn.tx_render.try_send(bytes);
-                    },
-                    Err(e) => {
-                        eprintln!("Lua on_connect Error (Session {}): {}", conn.session_id, e);
-                    }
+            // Init player and get initialization commands (e.g. load_sound)
+            match game.on_connect(&client.session_id) {
+                Ok(bytes) => {
+                    client.setup = bytes.clone();
+                    client.outbox.push_setup(bytes);
+                    client.send_setup(game.tick(), &link.recorder);
+                },
+                Err(e) => {
+                    eprintln!("Lua on_connect Error (Session {}): {}", client.session_id, e);
                 }
-                
-                clients.push(ActiveClient {
-                    session_id: conn.session_id,
-                    tx_render: conn.tx_render,
-                    rx_input: conn.rx_input,
-                });
-            }
+            };
+
+            clients.push(client);
         }
 
         // Handle Debug
@@ -870,7 +1427,18 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
                         // We must re-run draw for this specific session
                         // Note: This might have side effects if draw() mutates state (it shouldn't, but Lua...)
                         // Ideally we'd cache the last frame, but we don't store it.
-                        let result = game.draw(&session_id).ok();
+                        // The session's setup, resolution and display lists are normally sent
+                        // once, so include them all
+                        let connect = clients
+                            .iter()
+                            .find(|c| c.session_id == session_id)
+                            .map(|c| c.setup.clone())
+                            .unwrap_or_default();
+                        let result = game.draw(&session_id).ok().map(|frame| {
+                            let mut setup = bytes::BytesMut::new();
+                            game.screen_command().encode(&mut setup);
+                            [connect, setup.freeze(), game.list_definitions(), frame].concat().into()
+                        });
                         let _ = tx.send(result);
                     }
                 }
@@ -882,11 +1450,20 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
             // Read all pending inputs
             loop {
                 match client.rx_input.try_recv() {
-                    Ok((code, active)) => {
-                        if let Err(e) = game.handle_input(&client.session_id, code, active) {
+                    Ok(ClientInput::Input(input, stamp)) => {
+                        let timing = client.timing(stamp);
+                        if let Err(e) = game.handle(&client.session_id, input, timing) {
                             eprintln!("Input error {}: {}", client.session_id, e);
                         }
                     },
+                    Ok(ClientInput::Viewport { width, height }) => {
+                        game.set_viewport(&client.session_id, width, height);
+                    },
+                    Ok(ClientInput::Message(payload)) => {
+                        if let Err(e) = game.handle_message(&client.session_id, &payload) {
+                            eprintln!("Lua on_message Error (Session {}): {}", client.session_id, e);
+                        }
+                    },
                     Err(mpsc::error::TryRecvError::Empty) => break, // No more inputs
                     Err(mpsc::error::TryRecvError::Disconnected) => {
                         println!("Player disconnected: {}", client.session_id);
@@ -898,20 +1475,73 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
             true
         });
 
-        // 4. Update World
-        if let Err(e) = game.update(dt) {
-            eprintln!("Update error: {}", e);
+        // 4. Update World, in fixed steps
+        for _ in 0..steps {
+            if let Err(e) = game.update(clock.dt()) {
+                eprintln!("Update error: {}", e);
+            }
+        }
+        // Sounds go out with each client's next frame, whenever that is
+        let events = game.take_events();
+        if !events.is_empty() {
+            for client in &mut clients {
+                client.outbox.push_events(&events);
+            }
         }
+        game.set_alpha(clock.alpha());
 
-        // 5. Render for Each Client
-        clients.retain(|client| {
+        // 5. Render for Each Client whose frame is due, once its setup got through
+        clients.retain_mut(|client| {
+            client.send_setup(game.tick(), &link.recorder);
+            if client.outbox.has_setup() || !client.pacer.due(now) {
+                return true;
+            }
             match game.draw(&client.session_id) {
                 Ok(bytes) => {
+                    let events = client.outbox.take_events();
+                    let bytes = if events.is_empty() {
+                        bytes
+                    } else {
+                        [&events[..], &bytes[..]].concat().into()
+                    };
+                    // Resolution changes and new or changed display lists go first, in the same frame
+                    let mut setup = game.take_screen_update(&client.session_id).to_vec();
+                    let mut bytes = if client.caps.display_lists {
+                        setup.extend_from_slice(&game.take_list_updates(&client.session_id));
+                        bytes
+                    } else {
+                        game.inline_lists(&bytes)
+                    };
+                    if game.encoding() != Encoding::Float && !client.caps.compact_coords {
+                        setup = protocol::transcode(&setup, Encoding::Float);
+                        bytes = protocol::transcode(&bytes, Encoding::Float).into();
+                    }
+                    let input_ack = client.input_ack;
+                    let frame = if setup.is_empty() {
+                        OutFrame { bytes, reliable: false, input_ack }
+                    } else {
+                        setup.extend_from_slice(&bytes);
+                        OutFrame { bytes: setup.into(), reliable: true, input_ack }
+                    };
                     // Try to send. If receiver dropped (client closed connection), this fails.
                     // If channel full, we drop the frame (lag), but don't disconnect.
-                    match client.tx_render.try_send(bytes) {
-                        Ok(_) => true,
-                        Err(mpsc::error::TrySendError::Full(_)) => true, // Lag
+                    match client.outbox.send_frame(&client.tx_render, frame, events) {
+                        Ok(sent) => {
+                            client.sent_frame(game.tick());
+                            if let Some(recorder) = &link.recorder {
+                                recorder.lock().unwrap().record(&client.session_id, &sent);
+                            }
+                            true
+                        },
+                        Err(mpsc::error::TrySendError::Full(frame)) => {
+                            // Lag. Dropped setup has to go out with a later frame, like the
+                            // sounds the outbox kept.
+                            if frame.reliable {
+                                game.resend_screen(&client.session_id);
+                                game.resend_lists(&client.session_id);
+                            }
+                            true
+                        }
                         Err(mpsc::error::TrySendError::Closed(_)) => {
                              println!("Render channel closed for {}", client.session_id);
                              let _ = game.on_disconnect(&client.session_id);
@@ -926,28 +1556,147 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
             }
         });
 
-        // Sleep
-        let elapsed = now.elapsed();
-        if elapsed < frame_duration {
-            thread::sleep(frame_duration - elapsed);
+        // 6. Deliver api.send Messages, over the WebSocket so they aren't lost
+        for (session_id, payload) in game.take_messages() {
+            let Some(client) = clients.iter().find(|c| c.session_id == session_id) else {
+                continue; // Not connected (any more)
+            };
+            let text = serde_json::to_string(&SignalMessage::MESSAGE { payload }).unwrap();
+            if let Err(mpsc::error::TrySendError::Full(_)) = client.tx_text.try_send(Message::Text(text)) {
+                eprintln!("Message to {} dropped: client not keeping up", session_id);
+            }
+        }
+
+        // 7. Send Players to the Rooms api.move_session Asked For, as if they had
+        // left this one
+        for (session_id, room_id) in link.rooms.take_leaving(&link.id) {
+            if room_id == link.id {
+                continue;
+            }
+            let Some(i) = clients.iter().position(|c| c.session_id == session_id) else {
+                continue; // Not here (any more)
+            };
+            let mut client = clients.remove(i);
+            let _ = game.on_disconnect(&session_id);
+            client.leave();
+            println!("Player {} moving from room {} to {}", session_id, link.id, room_id);
+            // If the room closed in the meantime, the player comes back here
+            if let Err(client) = link.rooms.join(&room_id, client) {
+                let _ = link.rooms.join(&link.id, client);
+            }
+        }
+
+        // Rooms other than the main one close once nobody has played in them for a while
+        link.rooms.set_players(&link.id, clients.len());
+        if clients.is_empty() && link.id != MAIN_ROOM {
+            let idle = *empty_since.get_or_insert(now);
+            if now.duration_since(idle) >= ROOM_IDLE_TIMEOUT && link.rooms.close_if_empty(&link.id) {
+                println!("Room {} closed", link.id);
+                return;
+            }
+        } else {
+            empty_since = None;
         }
+
+        // Sleep until the next update or frame is due
+        let next = clients
+            .iter()
+            .map(|client| client.pacer.next())
+            .fold(now + clock.until_next(), Instant::min);
+        thread::sleep(next.saturating_duration_since(Instant::now()));
     }
 }
 
 fn load_game(path: &str) -> Option<GameState> {
-    match std::fs::read_to_string(path) {
-        Ok(script) => match GameState::new(&script, Some(std::path::Path::new(path))) {
-            Ok(g) => Some(g),
-            Err(e) => {
-                eprintln!("Lua Init Error: {}", e);
-                None
+    load_room(Path::new(path), Room::default())
+        .map_err(|e| eprintln!("{}", e))
+        .ok()
+}
+
+fn load_room(path: &Path, room: Room) -> anyhow::Result<GameState> {
+    let script = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("File Read Error: {}", e))?;
+    GameState::with_room(&script, Some(path), room)
+        .map_err(|e| anyhow::anyhow!("Lua Init Error: {}", e))
+}
+
+// `train-dict`: samples the game's frames and writes a dictionary trained on them
+fn train_dict(script_path: &Path, frames: usize, sessions: usize, max_size: usize, output: &Path) -> anyhow::Result<()> {
+    let script_path_str = script_path.to_string_lossy().to_string();
+    let game = load_game(&script_path_str).ok_or_else(|| anyhow::anyhow!("could not load {:?}", script_path))?;
+
+    println!("Sampling {} frames for {} sessions...", frames, sessions);
+    let samples = dictionary::sample_frames(&game, frames, sessions)?;
+    let dict_bytes = dictionary::train(&samples, max_size)?;
+    let dict = Dictionary::new(&dict_bytes)?;
+    std::fs::write(output, &dict_bytes)?;
+
+    // Compression of the samples themselves, as a rough guide
+    let compressed_size = |dict: Option<&Dictionary>| -> std::io::Result<usize> {
+        samples.iter().try_fold(0, |total, sample| Ok(total + dictionary::compress(sample, dict)?.len()))
+    };
+    let raw: usize = samples.iter().map(Vec::len).sum();
+    println!("Wrote {:?} ({} bytes, id {})", output, dict_bytes.len(), dict.id);
+    println!(
+        "{} samples, {} bytes: {} compressed, {} with the dictionary",
+        samples.len(),
+        raw,
+        compressed_size(None)?,
+        compressed_size(Some(&dict))?
+    );
+    Ok(())
+}
+
+// `render-recording`: replays a recording through the software renderer, taking an
+// image every 1/fps seconds of recording time
+fn render_recording(recording: &Path, output: &Path, assets_dir: &Path, fps: f32) -> anyhow::Result<()> {
+    if fps.is_nan() || fps <= 0.0 {
+        anyhow::bail!("--fps must be positive");
+    }
+    let frames = recording::read(recording)?;
+    let Some(&(first, _)) = frames.first() else {
+        anyhow::bail!("{:?} has no frames", recording);
+    };
+    let gif = output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+    let mut encoder = if gif {
+        let mut encoder = image::codecs::gif::GifEncoder::new(std::fs::File::create(output)?);
+        encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+        Some(encoder)
+    } else {
+        std::fs::create_dir_all(output)?;
+        None
+    };
+
+    let mut raster = Rasterizer::new(assets_dir);
+    let mut images = 0;
+    let mut write_image = |raster: &Rasterizer| -> anyhow::Result<()> {
+        match &mut encoder {
+            Some(encoder) => {
+                let delay = image::Delay::from_numer_denom_ms(1000, fps.round().max(1.0) as u32);
+                encoder.encode_frame(image::Frame::from_parts(raster.image().clone(), 0, 0, delay))?;
             }
-        },
-        Err(e) => {
-            eprintln!("File Read Error: {}", e);
-            None
+            None => raster.image().save(output.join(format!("frame_{:05}.png", images)))?,
+        }
+        images += 1;
+        Ok(())
+    };
+
+    // The image at each step shows every frame received up to then
+    let step = 1_000_000.0 / fps as f64;
+    let mut next = first as f64;
+    for (timestamp, frame) in &frames {
+        while *timestamp as f64 > next {
+            write_image(&raster)?;
+            next += step;
+        }
+        if let Err(e) = raster.render(frame) {
+            eprintln!("Frame at {:.3}s: {}", (timestamp - first) as f64 / 1e6, e);
         }
     }
+    write_image(&raster)?;
+
+    println!("Wrote {} images from {} frames to {:?}", images, frames.len(), output);
+    Ok(())
 }
 
 // --- Web Server Handlers ---
@@ -955,6 +1704,15 @@ fn load_game(path: &str) -> Option<GameState> {
 #[derive(Deserialize)]
 struct WsParams {
     session: Option<String>,
+    protocol: Option<u16>,
+    // Comma-separated capability names
+    caps: Option<String>,
+    // Id of the zstd dictionary the client has loaded
+    dict: Option<u32>,
+    // Frames per second the client wants
+    fps: Option<f64>,
+    // Room to join, the main one if not given
+    room: Option<String>,
 }
 
 async fn ws_handler(
@@ -962,17 +1720,46 @@ async fn ws_handler(
     Query(params): Query<WsParams>,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, params.session))
+    ws.max_message_size(MAX_SOCKET_MESSAGE_LEN)
+        .on_upgrade(move |socket| handle_socket(socket, state, params))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_session: Option<String>) {
-    let session_id = requested_session.unwrap_or_else(|| Uuid::new_v4().to_string());
+async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, params: WsParams) {
+    let session_id = params.session.unwrap_or_else(|| Uuid::new_v4().to_string());
     println!("Client {} connecting via WebSocket...", session_id);
 
-    // 1. Send Handshake
+    // 1. Negotiate the protocol
+    let caps = match handshake::negotiate(params.protocol, params.caps.as_deref()) {
+        Ok(caps) => caps,
+        Err(reason) => {
+            println!("Rejecting client {}: {}", session_id, reason);
+            let reject = SignalMessage::REJECT { reason, protocol_version: PROTOCOL_VERSION };
+            let _ = socket.send(Message::Text(serde_json::to_string(&reject).unwrap())).await;
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+    let room_id = params.room.unwrap_or_else(|| MAIN_ROOM.to_string());
+    if !state.rooms.contains(&room_id) {
+        println!("Rejecting client {}: no room {}", session_id, room_id);
+        let reject = SignalMessage::REJECT {
+            reason: format!("There is no room '{}' on this server (any more).", room_id),
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let _ = socket.send(Message::Text(serde_json::to_string(&reject).unwrap())).await;
+        let _ = socket.send(Message::Close(None)).await;
+        return;
+    }
+    // Only a client holding the exact same dictionary can decode with it
+    let dictionary = state.dictionary.clone().filter(|dict| params.dict == Some(dict.id));
+
+    // 2. Send Handshake
     let handshake = SignalMessage::WELCOME {
         session_id: session_id.clone(),
-        server_instance_id: state.instance_id.clone()
+        server_instance_id: state.instance_id.clone(),
+        protocol_version: PROTOCOL_VERSION,
+        capabilities: caps.names().into_iter().map(String::from).collect(),
+        dictionary_id: dictionary.as_ref().map(|dict| dict.id),
     };
     if let Err(e) = socket.send(Message::Text(serde_json::to_string(&handshake).unwrap().into())).
     await {
@@ -980,21 +1767,30 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
         return;
     }
 
-    // 2. Prepare Game Loop Channels
-    let (tx_render, mut rx_render) = mpsc::channel::<bytes::Bytes>(30); // From Game -> Network
-    let (tx_input, rx_input) = mpsc::channel::<(u8, bool)>(100);       // From Network -> Game
-
-    // Push to Game Loop
-    {
-        let mut queue = state.new_clients.lock().unwrap();
-        queue.push(ClientConnection {
-            session_id: session_id.clone(),
-            tx_render,
-            rx_input,
-        });
+    // 3. Prepare Game Loop Channels
+    let (tx_render, mut rx_render) = mpsc::channel::<OutFrame>(30);    // From Game -> Network
+    let (tx_input, rx_input) = mpsc::channel::<ClientInput>(100);      // From Network -> Game
+    let (tx_ack, mut rx_ack) = mpsc::channel::<u32>(100);              // Frame acks -> Coordinator
+    let (tx_ws_sig, mut rx_ws_sig) = mpsc::channel::<Message>(100);    // Signaling & messages -> WebSocket
+
+    // Push to the Room's Game Loop
+    let client = ActiveClient::new(
+        session_id.clone(),
+        caps,
+        tx_render,
+        rx_input,
+        tx_ws_sig.clone(),
+        params
+            .fps
+            .filter(|fps| fps.is_finite())
+            .map_or(state.frame_rate, |fps| fps.round().clamp(1.0, MAX_FRAME_RATE as f64) as u32),
+    );
+    if state.rooms.join(&room_id, client).is_err() {
+        eprintln!("Room {} closed before {} could join", room_id, session_id);
+        return;
     }
 
-    // 3. Setup WebRTC API
+    // 4. Setup WebRTC API
     let mut m = MediaEngine::default();
     let registry = Registry::new();
     let registry = match register_default_interceptors(registry, &mut m) {
@@ -1028,15 +1824,19 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
         }
     };
 
-    // 4. Shared State for DataChannel
+    // 5. Shared State for DataChannel
     // We need to pass the DataChannel from the callback to the sender task.
     let active_dc: Arc<tokio::sync::Mutex<Option<Arc<webrtc::data_channel::RTCDataChannel>>>> = Arc::new(tokio::sync::Mutex::new(None));
     let active_dc_clone = active_dc.clone();
     let session_id_rtc = session_id.clone();
 
-    // 5. Handle Client-Initiated DataChannel
+    // 6. Handle Client-Initiated DataChannel
     // The client will create the DataChannel, ensuring the SDP Offer is valid.
     let tx_input_for_rtc = tx_input.clone();
+    let tx_ack_for_rtc = tx_ack.clone();
+    // Shared by both channels, so a client can't get twice the allowance
+    let message_limits = Arc::new(Mutex::new(MessageLimits::new(Instant::now())));
+    let message_limits_for_rtc = message_limits.clone();
     let session_id_for_dc = session_id_rtc.clone();
     peer_connection.on_data_channel(Box::new(move |dc: Arc<webrtc::data_channel::RTCDataChannel>| {
         let dc_label = dc.label().to_owned();
@@ -1045,6 +1845,8 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
 
         let active_dc_inner = active_dc_clone.clone();
         let tx_input_rtc = tx_input_for_rtc.clone();
+        let tx_ack_rtc = tx_ack_for_rtc.clone();
+        let message_limits_rtc = message_limits_for_rtc.clone();
 
         // Clone DC for use inside the on_open callback
         let dc_for_open = dc.clone();
@@ -1060,12 +1862,22 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
 
         dc.on_message(Box::new(move |msg: DataChannelMessage| {
             let tx = tx_input_rtc.clone();
+            let tx_ack = tx_ack_rtc.clone();
+            let message_limits = message_limits_rtc.clone();
             Box::pin(async move {
                 let data = msg.data;
-                if data.len() == 2 {
-                    let code = data[0];
-                    let active = data[1] != 0;
-                    let _ = tx.send((code, active)).await;
+                if msg.is_string {
+                    let message = message_limits.lock().unwrap().parse(&data, Instant::now());
+                    if let Some(SignalMessage::MESSAGE { payload }) = message {
+                        let _ = tx.send(ClientInput::Message(payload)).await;
+                    }
+                } else if let Some(id) = delta::decode_ack(&data) {
+                    let _ = tx_ack.send(id).await;
+                } else {
+                    let (stamp, packet) = Stamp::split(&data);
+                    if let Some(input) = Input::decode(packet) {
+                        let _ = tx.send(ClientInput::Input(input, stamp)).await;
+                    }
                 }
             })
         }));
@@ -1073,7 +1885,7 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
         Box::pin(async {{}})
     }));
 
-    // 6. WebSocket Signaling & Coordinator Loop
+    // 7. WebSocket Signaling & Coordinator Loop
     let (mut ws_sender, mut ws_receiver) = socket.split();
     let pc_clone = peer_connection.clone();
 
@@ -1083,36 +1895,49 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
     let (tx_ws_frame, mut rx_ws_frame) = mpsc::channel::<Vec<u8>>(30);
 
     let coordinator_handle = tokio::spawn(async move {
-        use std::io::Write;
-
-        while let Some(bytes) = rx_render.recv().await {
+        // Diffs each frame against the last one the client acknowledged
+        let mut frames = FrameEncoder::new();
+
+        loop {
+            let (bytes, reliable) = tokio::select! {
+                frame = rx_render.recv() => match frame {
+                    Some(frame) if caps.delta_frames => {
+                        if let Some(seq) = frame.input_ack {
+                            frames.ack_input(seq);
+                        }
+                        (frames.encode(frame.bytes), frame.reliable)
+                    },
+                    Some(frame) => (frame.bytes.to_vec(), frame.reliable),
+                    None => break,
+                },
+                Some(id) = rx_ack.recv() => {
+                    frames.ack(id);
+                    continue;
+                }
+            };
             // println!("Sending frame: {} bytes", bytes.len());
-            // Compress with Zstd (Standard, Level 0)
-            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 0).unwrap();
-            
-            if encoder.write_all(&bytes).is_ok() {
-                if let Ok(compressed) = encoder.finish() {
-                    let data = bytes::Bytes::from(compressed);
-                    
-                    // Check DC
-                    let dc_opt = active_dc_sender.lock().await.clone();
-                    let mut sent_via_udp = false;
-                    
-                    if let Some(dc) = dc_opt {
-                         // Only try if actually Open
-                         if dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
-                             if let Err(_e) = dc.send(&data).await {
-                                 // eprintln!("WebRTC Send Error: {}", _e);
-                             } else {
-                                 sent_via_udp = true;
-                             }
+            // Compress with Zstd (Level 0), using the game's dictionary if the client has it
+            if let Ok(compressed) = dictionary::compress(&bytes, dictionary.as_deref()) {
+                let data = bytes::Bytes::from(compressed);
+                
+                // Check DC
+                let dc_opt = if reliable { None } else { active_dc_sender.lock().await.clone() };
+                let mut sent_via_udp = false;
+                
+                if let Some(dc) = dc_opt {
+                     // Only try if actually Open
+                     if dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
+                         if let Err(_e) = dc.send(&data).await {
+                             // eprintln!("WebRTC Send Error: {}", _e);
+                         } else {
+                             sent_via_udp = true;
                          }
-                    } 
-                    
-                    if !sent_via_udp {
-                         // Fallback TCP
-                         let _ = tx_ws_frame.send(data.to_vec()).await;
-                    }
+                     }
+                } 
+                
+                if !sent_via_udp {
+                     // Fallback TCP
+                     let _ = tx_ws_frame.send(data.to_vec()).await;
                 }
             }
         }
@@ -1120,8 +1945,6 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
     });
 
     // Handle ICE Candidates from Local (Server) -> Remote (Client) via WebSocket
-    let (tx_ws_sig, mut rx_ws_sig) = mpsc::channel::<Message>(100);
-    
     peer_connection.on_ice_candidate(Box::new(move |c| {
         let tx = tx_ws_sig.clone();
         Box::pin(async move {
@@ -1175,14 +1998,26 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
                                     };
                                     let _ = pc_clone.add_ice_candidate(cand).await;
                                 },
+                                SignalMessage::VIEWPORT { width, height } => {
+                                    let _ = tx_input.send(ClientInput::Viewport { width, height }).await;
+                                },
+                                SignalMessage::MESSAGE { payload }
+                                    if message_limits.lock().unwrap().admit(text.len(), Instant::now()) => {
+                                    let _ = tx_input.send(ClientInput::Message(payload)).await;
+                                },
                                 _ => {} // Ignore other message types
                             }
                         }
                     },
                     Some(Ok(Message::Binary(data))) => {
                         // Fallback Input
-                        if data.len() == 2 {
-                            let _ = tx_input.send((data[0], data[1] != 0)).await;
+                        if let Some(id) = delta::decode_ack(&data) {
+                            let _ = tx_ack.send(id).await;
+                        } else {
+                            let (stamp, packet) = Stamp::split(&data);
+                            if let Some(input) = Input::decode(packet) {
+                                let _ = tx_input.send(ClientInput::Input(input, stamp)).await;
+                            }
                         }
                     },
                     Some(Err(_)) | None => break, // Disconnected
@@ -1197,7 +2032,7 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
                     }
                 }
             },
-            // 3. Outgoing Signaling
+            // 3. Outgoing Signaling & Messages
             sig = rx_ws_sig.recv() => {
                 if let Some(msg) = sig {
                     if ws_sender.send(msg).await.is_err() {
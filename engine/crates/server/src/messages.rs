@@ -0,0 +1,53 @@
+// Messages from clients for Lua's `on_message`: `{"type": "MESSAGE", "payload": ...}`,
+// as WebSocket text or as a string on the data channel. They carry what keys can't,
+// like names and chat, so each session is limited in how much it can send; messages
+// over the limits are dropped.
+
+use engine::protocol::MAX_MESSAGE_LEN;
+use serde::de::DeserializeOwned;
+use std::time::Instant;
+
+// Longest message a client may send over the WebSocket, so floods of huge texts are
+// dropped before they're parsed. Signalling needs more room than the 4096 bytes of a
+// MESSAGE: SDP offers run to a few KB.
+pub const MAX_SOCKET_MESSAGE_LEN: usize = 64 * 1024;
+
+// Messages a session may send per second, once its burst is spent
+pub const MESSAGE_RATE: f64 = 10.0;
+// Messages a session may send at once
+pub const MESSAGE_BURST: f64 = 20.0;
+
+/// One session's message allowance, refilled over time.
+pub struct MessageLimits {
+    tokens: f64,
+    last: Instant,
+}
+
+impl MessageLimits {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            tokens: MESSAGE_BURST,
+            last: now,
+        }
+    }
+
+    /// Whether a message of `len` bytes arriving at `now` goes to the game.
+    pub fn admit(&mut self, len: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * MESSAGE_RATE).min(MESSAGE_BURST);
+        if len > MAX_MESSAGE_LEN || self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+    /// Parses a string from the data channel, where every string is a message. The
+    /// limits apply first, so floods of junk are dropped without being parsed.
+    pub fn parse<T: DeserializeOwned>(&mut self, data: &[u8], now: Instant) -> Option<T> {
+        if !self.admit(data.len(), now) {
+            return None;
+        }
+        serde_json::from_slice(data).ok()
+    }
+}
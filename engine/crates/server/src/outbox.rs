@@ -0,0 +1,93 @@
+// Frames from the game loop to a session's coordinator. A client that falls behind
+// fills its queue and frames are dropped until it catches up, which is fine for the
+// drawing, since the next frame replaces it. But a client must not lose its setup from
+// `on_connect` (assets to load) or the sounds that started meanwhile, so the outbox keeps
+// those until they get through.
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// A frame from the game loop to a session's coordinator.
+pub struct OutFrame {
+    pub bytes: Bytes,
+    /// Setup data (asset loads, display lists) that must not be lost, so it goes over
+    /// the WebSocket rather than the lossy data channel.
+    pub reliable: bool,
+    /// Highest input sequence number handled before the frame was drawn.
+    pub input_ack: Option<u32>,
+}
+
+/// What a client is owed besides its next drawing.
+#[derive(Default)]
+pub struct Outbox {
+    // What on_connect sent, until the client's queue has room for it
+    setup: Option<Bytes>,
+    // Sounds from the updates since the client's last frame
+    events: Vec<u8>,
+}
+
+impl Outbox {
+    /// Queues setup from `on_connect`, replacing any not sent yet: after a hot reload,
+    /// only the new game's setup counts.
+    pub fn push_setup(&mut self, setup: Bytes) {
+        self.setup = Some(setup);
+    }
+
+    /// Whether setup is still waiting for room. Frames wait behind it, so nothing is
+    /// drawn with assets the client hasn't been told to load.
+    pub fn has_setup(&self) -> bool {
+        self.setup.is_some()
+    }
+
+    /// Sends the pending setup, returning it if it went out. On a full queue it stays
+    /// pending, for the next try.
+    pub fn send_setup(
+        &mut self,
+        tx: &mpsc::Sender<OutFrame>,
+        input_ack: Option<u32>,
+    ) -> Option<Bytes> {
+        let bytes = self.setup.take()?;
+        let frame = OutFrame {
+            bytes: bytes.clone(),
+            reliable: true,
+            input_ack,
+        };
+        match tx.try_send(frame) {
+            Ok(()) => Some(bytes),
+            Err(_) => {
+                self.setup = Some(bytes);
+                None
+            }
+        }
+    }
+
+    pub fn push_events(&mut self, events: &[u8]) {
+        self.events.extend_from_slice(events);
+    }
+
+    /// Sounds for the next frame. Hand them back to `send_frame` with it.
+    pub fn take_events(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Sends a frame carrying `events`. If the queue is full the frame is dropped, but
+    /// its sounds go out with the next one, ahead of any started since.
+    pub fn send_frame(
+        &mut self,
+        tx: &mpsc::Sender<OutFrame>,
+        frame: OutFrame,
+        mut events: Vec<u8>,
+    ) -> Result<Bytes, TrySendError<OutFrame>> {
+        let bytes = frame.bytes.clone();
+        match tx.try_send(frame) {
+            Ok(()) => Ok(bytes),
+            Err(e) => {
+                if let TrySendError::Full(_) = e {
+                    events.append(&mut self.events);
+                    self.events = events;
+                }
+                Err(e)
+            }
+        }
+    }
+}
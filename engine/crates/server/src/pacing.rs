@@ -0,0 +1,89 @@
+// When the game loop updates and when it sends each client a frame. Updates run at a
+// fixed rate with the same dt, however long the loop takes, so the simulation doesn't
+// depend on the machine. Frames go out at each client's own rate, in between updates,
+// with how far they are toward the next update for games that interpolate.
+
+use std::time::{Duration, Instant};
+
+// Updates run at most this many times in one pass of the loop; after a longer stall
+// (a hot reload, a slow update) the simulation slows down instead of spiralling
+pub const MAX_CATCH_UP: u32 = 5;
+
+/// Time owed to the simulation, paid in whole steps.
+pub struct FixedStep {
+    step: Duration,
+    accumulator: Duration,
+}
+
+impl FixedStep {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / rate as f64),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    /// The `dt` every update gets.
+    pub fn dt(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// Adds `elapsed` and returns how many updates are due now. Time beyond
+    /// `MAX_CATCH_UP` steps is dropped.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            if steps == MAX_CATCH_UP {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// How far the time since the last update is toward the next one, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+
+    /// Time until the next update is due.
+    pub fn until_next(&self) -> Duration {
+        self.step.saturating_sub(self.accumulator)
+    }
+}
+
+/// When one client's next frame is due.
+pub struct FramePacer {
+    interval: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    /// Frames at `rate` per second, the first one right away.
+    pub fn new(rate: u32, now: Instant) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate as f64),
+            next: now,
+        }
+    }
+
+    /// Whether a frame is due at `now`, scheduling the one after if so. A client
+    /// that fell behind skips the frames it missed rather than getting them in a burst.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        self.next += self.interval;
+        if self.next <= now {
+            self.next = now + self.interval;
+        }
+        true
+    }
+
+    pub fn next(&self) -> Instant {
+        self.next
+    }
+}
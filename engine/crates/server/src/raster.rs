@@ -0,0 +1,1021 @@
+// Software renderer for the command stream, behind MCP screenshots and offline tools.
+// It follows the browser client (client/main.js) command by command, including the
+// state that carries over between frames, so images match what players see up to
+// anti-aliasing details.
+
+use ab_glyph::{Font, FontArc, GlyphId, OutlineCurve, Point, PxScale, ScaleFont};
+use bytes::Bytes;
+use engine::protocol::{
+    Decoder, DrawCommand, LineCap, LineJoin, ScaleMode, TextAlign, TextBaseline, DEFAULT_FONT,
+    DEFAULT_FONT_SIZE, DEFAULT_HEIGHT, DEFAULT_WIDTH, MAX_LIST_DEPTH,
+};
+use engine::text::{asset_path, BUNDLED_FONT};
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+// Page background behind the canvas in index.html
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+// Canvas defaults
+const DEFAULT_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const DEFAULT_LINE_WIDTH: f32 = 1.0;
+const MITER_LIMIT: f32 = 10.0;
+
+// Samples per pixel row when filling; horizontal coverage is exact
+const SUBSAMPLES: usize = 5;
+
+// Largest distance, in pixels, between a curve and the segments that approximate it
+const TOLERANCE: f32 = 0.1;
+
+type Contour = Vec<(f32, f32)>;
+
+// 2D affine transform in canvas convention: x' = a*x + c*y + e, y' = b*x + d*y + f
+#[derive(Clone, Copy)]
+struct Affine {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+    f: f32,
+}
+
+impl Affine {
+    const IDENTITY: Affine = Affine {
+        a: 1.0,
+        b: 0.0,
+        c: 0.0,
+        d: 1.0,
+        e: 0.0,
+        f: 0.0,
+    };
+
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (
+            self.a * x + self.c * y + self.e,
+            self.b * x + self.d * y + self.f,
+        )
+    }
+
+    fn translate(&mut self, tx: f32, ty: f32) {
+        self.e += self.a * tx + self.c * ty;
+        self.f += self.b * tx + self.d * ty;
+    }
+
+    fn rotate(&mut self, angle: f32) {
+        let (sin, cos) = angle.sin_cos();
+        let Affine { a, b, c, d, .. } = *self;
+        self.a = a * cos + c * sin;
+        self.b = b * cos + d * sin;
+        self.c = c * cos - a * sin;
+        self.d = d * cos - b * sin;
+    }
+
+    fn scale(&mut self, sx: f32, sy: f32) {
+        self.a *= sx;
+        self.b *= sx;
+        self.c *= sy;
+        self.d *= sy;
+    }
+
+    fn inverse(&self) -> Option<Affine> {
+        let det = self.a * self.d - self.b * self.c;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        Some(Affine {
+            a: self.d / det,
+            b: -self.b / det,
+            c: -self.c / det,
+            d: self.a / det,
+            e: (self.c * self.f - self.d * self.e) / det,
+            f: (self.b * self.e - self.a * self.f) / det,
+        })
+    }
+
+    // Largest factor a length is stretched by, for picking curve detail
+    fn max_scale(&self) -> f32 {
+        (self.a * self.a + self.b * self.b)
+            .max(self.c * self.c + self.d * self.d)
+            .sqrt()
+    }
+}
+
+// What canvas save()/restore() cover
+#[derive(Clone)]
+struct State {
+    transform: Affine,
+    fill: Rgba<u8>,
+    stroke: Rgba<u8>,
+    font: String,
+    size: f32,
+    align: TextAlign,
+    baseline: TextBaseline,
+}
+
+pub struct Rasterizer {
+    // Directory served as `/assets`, where images and fonts are loaded from
+    assets_dir: PathBuf,
+    canvas: RgbaImage,
+    mode: ScaleMode,
+    // `None` for images that failed to load, which draw nothing, as in the browser
+    images: HashMap<String, Option<RgbaImage>>,
+    fonts: HashMap<String, FontArc>,
+    fallback: FontArc,
+    lists: HashMap<String, Bytes>,
+    state: State,
+    stack: Vec<State>,
+}
+
+impl Rasterizer {
+    /// A renderer in the state of a freshly connected client.
+    pub fn new(assets_dir: &Path) -> Self {
+        Self {
+            assets_dir: assets_dir.to_path_buf(),
+            canvas: RgbaImage::from_pixel(DEFAULT_WIDTH as u32, DEFAULT_HEIGHT as u32, BACKGROUND),
+            mode: ScaleMode::default(),
+            images: HashMap::new(),
+            fonts: HashMap::new(),
+            fallback: FontArc::try_from_slice(BUNDLED_FONT).expect("Bundled font is valid"),
+            lists: HashMap::new(),
+            state: State {
+                transform: Affine::IDENTITY,
+                fill: DEFAULT_COLOR,
+                stroke: DEFAULT_COLOR,
+                font: DEFAULT_FONT.to_string(),
+                size: DEFAULT_FONT_SIZE,
+                align: TextAlign::default(),
+                baseline: TextBaseline::default(),
+            },
+            stack: Vec::new(),
+        }
+    }
+
+    /// Draws one frame over the previous one. Like on the client, loaded images and
+    /// fonts, display lists, the resolution and colors carry over between frames; the
+    /// transform and text style don't. Stops at the first malformed command.
+    pub fn render(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        self.state.transform = Affine::IDENTITY;
+        self.state.font = DEFAULT_FONT.to_string();
+        self.state.size = DEFAULT_FONT_SIZE;
+        self.state.align = TextAlign::default();
+        self.state.baseline = TextBaseline::default();
+        let result = self.render_commands(frame, 0);
+
+        // Don't let an unbalanced frame leak its transform into the next one
+        self.restore_to(0);
+        result
+    }
+
+    /// The canvas, at the game's virtual resolution.
+    pub fn image(&self) -> &RgbaImage {
+        &self.canvas
+    }
+
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.canvas.write_to(&mut cursor, image::ImageFormat::Png)?;
+        Ok(cursor.into_inner())
+    }
+
+    // Plays a command stream; `level` is the number of display lists it is nested in.
+    fn render_commands(&mut self, data: &[u8], level: usize) -> anyhow::Result<()> {
+        // A list can't pop state pushed outside of it
+        let floor = self.stack.len();
+        for cmd in Decoder::new(data) {
+            match cmd? {
+                DrawCommand::Clear { r, g, b } => {
+                    // Like the client, this leaves the fill color set to the clear color
+                    self.state.fill = Rgba([r, g, b, 255]);
+                    let (width, height) = self.canvas.dimensions();
+                    self.fill_rect(0.0, 0.0, width as f32, height as f32);
+                }
+                DrawCommand::SetColor { r, g, b, a } => {
+                    self.state.fill = Rgba([r, g, b, a]);
+                    self.state.stroke = Rgba([r, g, b, a]);
+                }
+                DrawCommand::FillRect { x, y, w, h } => self.fill_rect(x, y, w, h),
+                DrawCommand::DrawLine {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    width,
+                } => {
+                    self.stroke(
+                        &[(x1, y1), (x2, y2)],
+                        false,
+                        width,
+                        LineJoin::default(),
+                        LineCap::default(),
+                    );
+                }
+                DrawCommand::DrawText { x, y, text } => self.draw_text(x, y, &text),
+                DrawCommand::LoadSound { .. }
+                | DrawCommand::PlaySound { .. }
+                | DrawCommand::StopSound { .. }
+                | DrawCommand::SetVolume { .. } => {}
+                DrawCommand::LoadImage { name, url } => self.load_image(name, &url),
+                DrawCommand::DrawImage {
+                    name,
+                    x,
+                    y,
+                    w,
+                    h,
+                    sx,
+                    sy,
+                    sw,
+                    sh,
+                    rotation,
+                    ox,
+                    oy,
+                } => {
+                    self.draw_image(&name, (x, y, w, h), (sx, sy, sw, sh), rotation, (ox, oy));
+                }
+                DrawCommand::FillPoly { points } => self.fill(&[points], self.state.fill),
+                DrawCommand::Push => self.stack.push(self.state.clone()),
+                DrawCommand::Pop => {
+                    if self.stack.len() > floor {
+                        self.restore_to(self.stack.len() - 1);
+                    }
+                }
+                DrawCommand::Translate { x, y } => self.state.transform.translate(x, y),
+                DrawCommand::Rotate { angle } => self.state.transform.rotate(angle),
+                DrawCommand::Scale { x, y } => self.state.transform.scale(x, y),
+                DrawCommand::FillCircle { x, y, r } => {
+                    let circle = self.arc(x, y, r.abs(), r.abs(), 0.0, 0.0, TAU);
+                    self.fill(&[circle], self.state.fill);
+                }
+                DrawCommand::StrokeCircle { x, y, r, width } => {
+                    let circle = self.arc(x, y, r.abs(), r.abs(), 0.0, 0.0, TAU);
+                    self.stroke(
+                        &circle,
+                        true,
+                        width,
+                        LineJoin::default(),
+                        LineCap::default(),
+                    );
+                }
+                DrawCommand::StrokeRect { x, y, w, h, width } => {
+                    let corners = [(x, y), (x + w, y), (x + w, y + h), (x, y + h)];
+                    self.stroke(
+                        &corners,
+                        true,
+                        width,
+                        LineJoin::default(),
+                        LineCap::default(),
+                    );
+                }
+                DrawCommand::Arc {
+                    x,
+                    y,
+                    r,
+                    start,
+                    end,
+                    width,
+                } => {
+                    let mut points = self.arc(
+                        x,
+                        y,
+                        r.abs(),
+                        r.abs(),
+                        0.0,
+                        start,
+                        start + arc_sweep(start, end),
+                    );
+                    if width > 0.0 {
+                        self.stroke(
+                            &points,
+                            false,
+                            width,
+                            LineJoin::default(),
+                            LineCap::default(),
+                        );
+                    } else {
+                        points.insert(0, (x, y));
+                        self.fill(&[points], self.state.fill);
+                    }
+                }
+                DrawCommand::Ellipse {
+                    x,
+                    y,
+                    rx,
+                    ry,
+                    rotation,
+                    width,
+                } => {
+                    let points = self.arc(x, y, rx.abs(), ry.abs(), rotation, 0.0, TAU);
+                    if width > 0.0 {
+                        self.stroke(
+                            &points,
+                            true,
+                            width,
+                            LineJoin::default(),
+                            LineCap::default(),
+                        );
+                    } else {
+                        self.fill(&[points], self.state.fill);
+                    }
+                }
+                DrawCommand::StrokePoly {
+                    width,
+                    join,
+                    cap,
+                    closed,
+                    points,
+                } => {
+                    self.stroke(&points, closed, width, join, cap);
+                }
+                DrawCommand::LoadFont { name, url } => self.load_font(name, &url),
+                DrawCommand::SetFont { name, size } => {
+                    self.state.font = name;
+                    self.state.size = size;
+                }
+                DrawCommand::SetTextAlign { align, baseline } => {
+                    self.state.align = align;
+                    self.state.baseline = baseline;
+                }
+                DrawCommand::DefineList { name, commands } => {
+                    self.lists.insert(name, commands.into());
+                }
+                DrawCommand::SetResolution {
+                    width,
+                    height,
+                    mode,
+                } => {
+                    self.mode = mode;
+                    // Resizing the canvas clears it
+                    if self.canvas.dimensions() != (width as u32, height as u32) {
+                        self.canvas =
+                            RgbaImage::from_pixel(width as u32, height as u32, BACKGROUND);
+                    }
+                }
+                DrawCommand::CallList {
+                    name,
+                    x,
+                    y,
+                    rotation,
+                    sx,
+                    sy,
+                } => {
+                    // Unknown lists draw nothing; so does nesting past the limit
+                    let Some(list) = self.lists.get(&name).cloned() else {
+                        continue;
+                    };
+                    if level >= MAX_LIST_DEPTH {
+                        continue;
+                    }
+                    let depth = self.stack.len();
+                    self.stack.push(self.state.clone());
+                    self.state.transform.translate(x, y);
+                    self.state.transform.rotate(rotation);
+                    self.state.transform.scale(sx, sy);
+                    let nested = self.render_commands(&list, level + 1);
+                    self.restore_to(depth);
+                    nested?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Pops saved states until `depth` are left.
+    fn restore_to(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            if let Some(state) = self.stack.pop() {
+                self.state = state;
+            }
+        }
+    }
+
+    // Curve detail for the current transform, in user units
+    fn tolerance(&self) -> f32 {
+        TOLERANCE / self.state.transform.max_scale().max(1e-3)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn arc(
+        &self,
+        cx: f32,
+        cy: f32,
+        rx: f32,
+        ry: f32,
+        rotation: f32,
+        start: f32,
+        end: f32,
+    ) -> Contour {
+        arc_points(cx, cy, rx, ry, rotation, start, end, self.tolerance())
+    }
+
+    fn fill_rect(&mut self, x: f32, y: f32, w: f32, h: f32) {
+        let corners = vec![(x, y), (x + w, y), (x + w, y + h), (x, y + h)];
+        self.fill(&[corners], self.state.fill);
+    }
+
+    // Fills contours given in user space with the nonzero rule.
+    fn fill(&mut self, contours: &[Contour], color: Rgba<u8>) {
+        let transform = self.state.transform;
+        let contours: Vec<Contour> = contours
+            .iter()
+            .map(|contour| {
+                contour
+                    .iter()
+                    .map(|&(x, y)| transform.apply(x, y))
+                    .collect()
+            })
+            .collect();
+        let alpha = color[3] as f32 / 255.0;
+        let canvas = &mut self.canvas;
+        fill_path(
+            canvas.width(),
+            canvas.height(),
+            &contours,
+            |x, y, coverage| {
+                blend(canvas.get_pixel_mut(x, y), color, alpha * coverage);
+            },
+        );
+    }
+
+    fn stroke(
+        &mut self,
+        points: &[(f32, f32)],
+        closed: bool,
+        width: f32,
+        join: LineJoin,
+        cap: LineCap,
+    ) {
+        // Canvas ignores widths that aren't positive, keeping the client's default
+        let width = if width > 0.0 && width.is_finite() {
+            width
+        } else {
+            DEFAULT_LINE_WIDTH
+        };
+        let outline = stroke_outline(points, closed, width / 2.0, join, cap, self.tolerance());
+        self.fill(&outline, self.state.stroke);
+    }
+
+    fn load_font(&mut self, name: String, url: &str) {
+        let font = asset_path(&self.assets_dir, url)
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| FontArc::try_from_vec(data).ok());
+        match font {
+            Some(font) => {
+                self.fonts.insert(name, font);
+            }
+            None => eprintln!("Renderer: can't load font '{}' from {}", name, url),
+        }
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str) {
+        let font = self
+            .fonts
+            .get(&self.state.font)
+            .unwrap_or(&self.fallback)
+            .clone();
+        // `size` is an em size, like CSS; ab_glyph scales by ascent - descent.
+        let units_per_em = font.units_per_em().unwrap_or(1000.0);
+        let scale = PxScale::from(self.state.size * font.height_unscaled() / units_per_em);
+        let scaled = font.as_scaled(scale);
+
+        let mut glyphs: Vec<(GlyphId, f32)> = Vec::new();
+        let mut pen = 0.0;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(&(prev, _)) = glyphs.last() {
+                pen += scaled.kern(prev, id);
+            }
+            glyphs.push((id, pen));
+            pen += scaled.h_advance(id);
+        }
+
+        let left = x - match self.state.align {
+            TextAlign::Left => 0.0,
+            TextAlign::Center => pen / 2.0,
+            TextAlign::Right => pen,
+        };
+        // Descent is negative
+        let baseline = y + match self.state.baseline {
+            TextBaseline::Top => scaled.ascent(),
+            TextBaseline::Middle => (scaled.ascent() + scaled.descent()) / 2.0,
+            TextBaseline::Bottom => scaled.descent(),
+            TextBaseline::Alphabetic => 0.0,
+        };
+
+        let (sx, sy) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+        let tolerance = self.tolerance();
+        let mut contours = Vec::new();
+        for (id, offset) in glyphs {
+            let Some(outline) = font.outline(id) else {
+                continue;
+            };
+            // Font units are y-up
+            let to_user = |p: Point| (left + offset + p.x * sx, baseline - p.y * sy);
+            let mut contour: Contour = Vec::new();
+            for curve in &outline.curves {
+                let (from, to) = match *curve {
+                    OutlineCurve::Line(from, to) => (from, to),
+                    OutlineCurve::Quad(from, _, to) => (from, to),
+                    OutlineCurve::Cubic(from, _, _, to) => (from, to),
+                };
+                if contour.last() != Some(&to_user(from)) {
+                    contours.push(std::mem::take(&mut contour));
+                    contour.push(to_user(from));
+                }
+                match *curve {
+                    OutlineCurve::Line(..) => contour.push(to_user(to)),
+                    OutlineCurve::Quad(p0, p1, p2) => {
+                        flatten_quad(
+                            &mut contour,
+                            [to_user(p0), to_user(p1), to_user(p2)],
+                            tolerance,
+                        );
+                    }
+                    OutlineCurve::Cubic(p0, p1, p2, p3) => {
+                        let points = [to_user(p0), to_user(p1), to_user(p2), to_user(p3)];
+                        flatten_cubic(&mut contour, points, tolerance);
+                    }
+                }
+            }
+            contours.push(contour);
+        }
+        contours.retain(|contour| contour.len() > 2);
+        self.fill(&contours, self.state.fill);
+    }
+
+    fn load_image(&mut self, name: String, url: &str) {
+        // The client only fetches each name once
+        if self.images.contains_key(&name) {
+            return;
+        }
+        let image = match asset_path(&self.assets_dir, url).map(image::open) {
+            Some(Ok(image)) => Some(image.to_rgba8()),
+            Some(Err(e)) => {
+                eprintln!("Renderer: can't load image '{}' from {}: {}", name, url, e);
+                None
+            }
+            None => None,
+        };
+        self.images.insert(name, image);
+    }
+
+    // Mirrors the client's drawImage call: translate to the position, rotate, flip
+    // for negative widths, then draw the source rect with its origin at (ox, oy).
+    fn draw_image(
+        &mut self,
+        name: &str,
+        (x, y, w, h): (f32, f32, f32, f32),
+        (sx, sy, sw, sh): (f32, f32, f32, f32),
+        rotation: f32,
+        (ox, oy): (f32, f32),
+    ) {
+        let Some(Some(image)) = self.images.get(name) else {
+            return;
+        };
+        let (iw, ih) = (image.width() as f32, image.height() as f32);
+        let or_own = |v: f32, own: f32| if v != -1.0 { v } else { own };
+        let (mut w, h) = (or_own(w, iw), or_own(h, ih));
+
+        let mut transform = self.state.transform;
+        transform.translate(x, y);
+        transform.rotate(rotation);
+        if w < 0.0 {
+            transform.scale(-1.0, 1.0);
+            w = -w;
+        }
+        // drawImage takes the rectangles' corners, whichever way round they are
+        let (dx, dw) = normalize(-ox, w);
+        let (dy, dh) = normalize(-oy, h);
+        let (sx, sw) = normalize(sx, or_own(sw, iw));
+        let (sy, sh) = normalize(sy, or_own(sh, ih));
+        let Some(inverse) = transform.inverse() else {
+            return;
+        };
+        if dw == 0.0 || dh == 0.0 || sw == 0.0 || sh == 0.0 {
+            return;
+        }
+
+        // Sampling stays inside the source rect
+        let bounds = (
+            sx.max(0.0) as u32,
+            sy.max(0.0) as u32,
+            ((sx + sw).min(iw).ceil() as u32).saturating_sub(1),
+            ((sy + sh).min(ih).ceil() as u32).saturating_sub(1),
+        );
+        if bounds.0 > bounds.2 || bounds.1 > bounds.3 {
+            return;
+        }
+        // `integer` scaling turns smoothing off on the client
+        let smooth = self.mode != ScaleMode::Integer;
+        let corners: Contour = [(dx, dy), (dx + dw, dy), (dx + dw, dy + dh), (dx, dy + dh)]
+            .iter()
+            .map(|&(px, py)| transform.apply(px, py))
+            .collect();
+        let canvas = &mut self.canvas;
+        fill_path(
+            canvas.width(),
+            canvas.height(),
+            &[corners],
+            |px, py, coverage| {
+                let (lx, ly) = inverse.apply(px as f32 + 0.5, py as f32 + 0.5);
+                let u = sx + (lx - dx) / dw * sw;
+                let v = sy + (ly - dy) / dh * sh;
+                let color = sample(image, u, v, bounds, smooth);
+                blend(
+                    canvas.get_pixel_mut(px, py),
+                    color,
+                    color[3] as f32 / 255.0 * coverage,
+                );
+            },
+        );
+    }
+}
+
+// Start and length of a span, with a negative length flipped round
+fn normalize(start: f32, len: f32) -> (f32, f32) {
+    if len < 0.0 {
+        (start + len, -len)
+    } else {
+        (start, len)
+    }
+}
+
+// Clockwise sweep from `start` to `end`, as canvas arc() draws it
+fn arc_sweep(start: f32, end: f32) -> f32 {
+    if end - start >= TAU {
+        TAU
+    } else {
+        (end - start).rem_euclid(TAU)
+    }
+}
+
+// Samples an elliptical arc into points no further than `tolerance` from the curve.
+#[allow(clippy::too_many_arguments)]
+fn arc_points(
+    cx: f32,
+    cy: f32,
+    rx: f32,
+    ry: f32,
+    rotation: f32,
+    start: f32,
+    end: f32,
+    tolerance: f32,
+) -> Contour {
+    let r = rx.max(ry);
+    let step = if r > tolerance {
+        2.0 * (1.0 - tolerance / r).acos()
+    } else {
+        TAU / 4.0
+    };
+    let sweep = end - start;
+    let segments = ((sweep.abs() / step).ceil() as usize).clamp(1, 1024);
+    let (sin_r, cos_r) = rotation.sin_cos();
+    (0..=segments)
+        .map(|i| {
+            let t = start + sweep * i as f32 / segments as f32;
+            let (ex, ey) = (rx * t.cos(), ry * t.sin());
+            (cx + ex * cos_r - ey * sin_r, cy + ex * sin_r + ey * cos_r)
+        })
+        .collect()
+}
+
+// Appends a quadratic Bézier from the contour's last point, as line segments.
+fn flatten_quad(contour: &mut Contour, [p0, p1, p2]: [(f32, f32); 3], tolerance: f32) {
+    let deviation = length((p0.0 - 2.0 * p1.0 + p2.0, p0.1 - 2.0 * p1.1 + p2.1)) / 4.0;
+    let segments = ((deviation / tolerance).sqrt().ceil() as usize).clamp(1, 64);
+    for i in 1..=segments {
+        let t = i as f32 / segments as f32;
+        let mt = 1.0 - t;
+        let (a, b, c) = (mt * mt, 2.0 * mt * t, t * t);
+        contour.push((
+            a * p0.0 + b * p1.0 + c * p2.0,
+            a * p0.1 + b * p1.1 + c * p2.1,
+        ));
+    }
+}
+
+fn flatten_cubic(contour: &mut Contour, [p0, p1, p2, p3]: [(f32, f32); 4], tolerance: f32) {
+    let deviation = length((p0.0 - 2.0 * p1.0 + p2.0, p0.1 - 2.0 * p1.1 + p2.1))
+        .max(length((p1.0 - 2.0 * p2.0 + p3.0, p1.1 - 2.0 * p2.1 + p3.1)))
+        * 0.75;
+    let segments = ((deviation / tolerance).sqrt().ceil() as usize).clamp(1, 64);
+    for i in 1..=segments {
+        let t = i as f32 / segments as f32;
+        let mt = 1.0 - t;
+        let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+        contour.push((
+            a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+            a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+        ));
+    }
+}
+
+fn length((x, y): (f32, f32)) -> f32 {
+    (x * x + y * y).sqrt()
+}
+
+fn unit((x, y): (f32, f32)) -> (f32, f32) {
+    let len = length((x, y));
+    if len > 0.0 {
+        (x / len, y / len)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+// `p` moved by `scale` times the vector `v`
+fn offset(p: (f32, f32), v: (f32, f32), scale: f32) -> (f32, f32) {
+    (p.0 + v.0 * scale, p.1 + v.1 * scale)
+}
+
+// Outline of a stroked polyline, as contours that all wind the same way so that a
+// nonzero fill covers their union: a quad per segment plus joins and caps.
+fn stroke_outline(
+    points: &[(f32, f32)],
+    closed: bool,
+    half_width: f32,
+    join: LineJoin,
+    cap: LineCap,
+    tolerance: f32,
+) -> Vec<Contour> {
+    let mut points: Contour = points.iter().fold(Vec::new(), |mut acc, &p| {
+        if acc.last() != Some(&p) {
+            acc.push(p);
+        }
+        acc
+    });
+    if closed && points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+
+    let mut outline = Vec::new();
+    let n = points.len();
+    if n == 1 {
+        // A zero-length line only shows its caps
+        let p = points[0];
+        let circle = arc_points(p.0, p.1, half_width, half_width, 0.0, 0.0, TAU, tolerance);
+        let square = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .iter()
+            .map(|&(dx, dy)| (p.0 + dx * half_width, p.1 + dy * half_width))
+            .collect();
+        match cap {
+            LineCap::Round => outline.push(circle),
+            LineCap::Square => outline.push(square),
+            LineCap::Butt => {}
+        }
+    }
+    if n >= 2 {
+        let segments = if closed { n } else { n - 1 };
+        for i in 0..segments {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            let d = unit((b.0 - a.0, b.1 - a.1));
+            let normal = (-d.1, d.0);
+            outline.push(vec![
+                offset(a, normal, half_width),
+                offset(b, normal, half_width),
+                offset(b, normal, -half_width),
+                offset(a, normal, -half_width),
+            ]);
+        }
+        let joins = if closed { 0..n } else { 1..n - 1 };
+        for i in joins {
+            let (prev, vertex, next) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            outline.extend(join_outline(
+                prev, vertex, next, half_width, join, tolerance,
+            ));
+        }
+        if !closed {
+            outline.extend(cap_outline(
+                points[1], points[0], half_width, cap, tolerance,
+            ));
+            outline.extend(cap_outline(
+                points[n - 2],
+                points[n - 1],
+                half_width,
+                cap,
+                tolerance,
+            ));
+        }
+    }
+
+    for contour in &mut outline {
+        if signed_area(contour) < 0.0 {
+            contour.reverse();
+        }
+    }
+    outline
+}
+
+// Fills the gap on the outside of the turn at `vertex`.
+fn join_outline(
+    prev: (f32, f32),
+    vertex: (f32, f32),
+    next: (f32, f32),
+    half_width: f32,
+    join: LineJoin,
+    tolerance: f32,
+) -> Option<Contour> {
+    let d1 = unit((vertex.0 - prev.0, vertex.1 - prev.1));
+    let d2 = unit((next.0 - vertex.0, next.1 - vertex.1));
+    let cross = d1.0 * d2.1 - d1.1 * d2.0;
+    if cross == 0.0 && d1.0 * d2.0 + d1.1 * d2.1 > 0.0 {
+        return None;
+    }
+    if join == LineJoin::Round {
+        let (x, y) = vertex;
+        return Some(arc_points(
+            x, y, half_width, half_width, 0.0, 0.0, TAU, tolerance,
+        ));
+    }
+
+    // Unit normals pointing to the outside of the turn
+    let side = if cross > 0.0 { -1.0 } else { 1.0 };
+    let n1 = (-d1.1 * side, d1.0 * side);
+    let n2 = (-d2.1 * side, d2.0 * side);
+    let (a, b) = (
+        offset(vertex, n1, half_width),
+        offset(vertex, n2, half_width),
+    );
+    if join == LineJoin::Miter {
+        // The tip is where the outer edges meet, unless that's past the miter limit
+        let bisector = unit((n1.0 + n2.0, n1.1 + n2.1));
+        let cos_half = bisector.0 * n1.0 + bisector.1 * n1.1;
+        if cos_half > 0.0 && 1.0 / cos_half <= MITER_LIMIT {
+            let tip = offset(vertex, bisector, half_width / cos_half);
+            return Some(vec![vertex, a, tip, b]);
+        }
+    }
+    Some(vec![vertex, a, b])
+}
+
+// The cap past `end`, for a line arriving from `from`.
+fn cap_outline(
+    from: (f32, f32),
+    end: (f32, f32),
+    half_width: f32,
+    cap: LineCap,
+    tolerance: f32,
+) -> Option<Contour> {
+    let d = unit((end.0 - from.0, end.1 - from.1));
+    let normal = (-d.1, d.0);
+    match cap {
+        LineCap::Butt => None,
+        LineCap::Round => Some(arc_points(
+            end.0, end.1, half_width, half_width, 0.0, 0.0, TAU, tolerance,
+        )),
+        LineCap::Square => {
+            let tip = offset(end, d, half_width);
+            Some(vec![
+                offset(end, normal, half_width),
+                offset(tip, normal, half_width),
+                offset(tip, normal, -half_width),
+                offset(end, normal, -half_width),
+            ])
+        }
+    }
+}
+
+fn signed_area(contour: &[(f32, f32)]) -> f32 {
+    let n = contour.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (contour[i], contour[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum::<f32>()
+        / 2.0
+}
+
+// Fills `contours` (device space, each implicitly closed) with the nonzero rule,
+// calling `paint` with the coverage of every pixel they touch.
+fn fill_path(width: u32, height: u32, contours: &[Contour], mut paint: impl FnMut(u32, u32, f32)) {
+    // Non-horizontal edges as (top, bottom, winding)
+    let mut edges = Vec::new();
+    for contour in contours {
+        for (i, &a) in contour.iter().enumerate() {
+            let b = contour[(i + 1) % contour.len()];
+            if a.1 == b.1
+                || !(a.0.is_finite() && a.1.is_finite() && b.0.is_finite() && b.1.is_finite())
+            {
+                continue;
+            }
+            edges.push(if a.1 < b.1 { (a, b, 1) } else { (b, a, -1) });
+        }
+    }
+    let top = edges
+        .iter()
+        .map(|e| e.0 .1)
+        .fold(f32::INFINITY, f32::min)
+        .max(0.0);
+    let bottom = edges
+        .iter()
+        .map(|e| e.1 .1)
+        .fold(f32::NEG_INFINITY, f32::max)
+        .min(height as f32);
+    if top >= bottom {
+        return;
+    }
+
+    let mut coverage = vec![0.0f32; width as usize];
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    let weight = 1.0 / SUBSAMPLES as f32;
+    for row in top as u32..bottom.ceil() as u32 {
+        let (mut first, mut last) = (usize::MAX, 0);
+        for sample in 0..SUBSAMPLES {
+            let y = row as f32 + (sample as f32 + 0.5) * weight;
+            crossings.clear();
+            for &((x0, y0), (x1, y1), winding) in &edges {
+                if y >= y0 && y < y1 {
+                    crossings.push((x0 + (y - y0) * (x1 - x0) / (y1 - y0), winding));
+                }
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                if winding == 0 {
+                    continue;
+                }
+                let (x0, x1) = (pair[0].0.max(0.0), pair[1].0.min(width as f32));
+                if x1 <= x0 {
+                    continue;
+                }
+                let (from, to) = (x0 as usize, (x1.ceil() as usize).min(width as usize));
+                for (px, cell) in coverage.iter_mut().enumerate().take(to).skip(from) {
+                    let overlap = x1.min(px as f32 + 1.0) - x0.max(px as f32);
+                    *cell += overlap * weight;
+                }
+                first = first.min(from);
+                last = last.max(to);
+            }
+        }
+        for (px, cell) in coverage.iter_mut().enumerate().take(last).skip(first) {
+            let cell = std::mem::take(cell);
+            if cell > 0.0 {
+                paint(px as u32, row, cell.min(1.0));
+            }
+        }
+    }
+}
+
+// Source-over of `color` at opacity `alpha` onto `pixel`.
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, alpha: f32) {
+    if alpha <= 0.0 {
+        return;
+    }
+    let below = pixel[3] as f32 / 255.0 * (1.0 - alpha);
+    let out = alpha + below;
+    for i in 0..3 {
+        pixel[i] = ((color[i] as f32 * alpha + pixel[i] as f32 * below) / out).round() as u8;
+    }
+    pixel[3] = (out * 255.0).round() as u8;
+}
+
+// The image's color at (u, v) in pixels, from texels inside `bounds` (inclusive).
+fn sample(
+    image: &RgbaImage,
+    u: f32,
+    v: f32,
+    bounds: (u32, u32, u32, u32),
+    smooth: bool,
+) -> Rgba<u8> {
+    let texel = |x: f32, y: f32| {
+        let x = (x.max(0.0) as u32).clamp(bounds.0, bounds.2);
+        let y = (y.max(0.0) as u32).clamp(bounds.1, bounds.3);
+        *image.get_pixel(x, y)
+    };
+    if !smooth {
+        return texel(u.floor(), v.floor());
+    }
+
+    // Bilinear, on premultiplied colors so transparent texels don't bleed their color
+    let (fx, fy) = (u - 0.5, v - 0.5);
+    let (x0, y0) = (fx.floor(), fy.floor());
+    let (tx, ty) = (fx - x0, fy - y0);
+    let mut sum = [0.0f32; 4];
+    for (x, y, w) in [
+        (x0, y0, (1.0 - tx) * (1.0 - ty)),
+        (x0 + 1.0, y0, tx * (1.0 - ty)),
+        (x0, y0 + 1.0, (1.0 - tx) * ty),
+        (x0 + 1.0, y0 + 1.0, tx * ty),
+    ] {
+        let Rgba([r, g, b, a]) = texel(x, y);
+        let a = a as f32 * w;
+        sum[0] += r as f32 * a;
+        sum[1] += g as f32 * a;
+        sum[2] += b as f32 * a;
+        sum[3] += a;
+    }
+    if sum[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (sum[0] / sum[3]).round() as u8,
+        (sum[1] / sum[3]).round() as u8,
+        (sum[2] / sum[3]).round() as u8,
+        sum[3].round() as u8,
+    ])
+}
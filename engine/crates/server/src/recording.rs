@@ -0,0 +1,111 @@
+// Recordings of what each session was sent, for `--record` and `render-recording`.
+//
+// A recording is one file per session, `<session id>.rec`, holding:
+//
+//   header  8 bytes   "CLEOREC" followed by the format version, 1
+//   frames  repeated  u64 LE  microseconds since the server started recording
+//                     u32 LE  length of the frame in bytes
+//                     bytes   the frame's commands
+//
+// Frames are the command streams the game loop handed to the session (connect setup,
+// then one per tick), before delta encoding and compression, so each one can be drawn
+// on top of the previous ones. Frames dropped because the client lagged are left out.
+// A session that reconnects continues the same file.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub const MAGIC: &[u8; 8] = b"CLEOREC\x01";
+pub const EXTENSION: &str = "rec";
+
+pub struct Recorder {
+    dir: PathBuf,
+    start: Instant,
+    // `None` once writing to a session's file has failed
+    files: HashMap<String, Option<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            start: Instant::now(),
+            files: HashMap::new(),
+        })
+    }
+
+    /// Appends `frame` to `session_id`'s recording. Errors are reported once, after
+    /// which the session is no longer recorded.
+    pub fn record(&mut self, session_id: &str, frame: &[u8]) {
+        let timestamp = self.start.elapsed().as_micros() as u64;
+        let dir = &self.dir;
+        let file = self.files.entry(session_id.to_string()).or_insert_with(|| {
+            let path = dir.join(format!("{}.{}", file_stem(session_id), EXTENSION));
+            match File::create(&path).and_then(|file| {
+                let mut file = BufWriter::new(file);
+                file.write_all(MAGIC)?;
+                Ok(file)
+            }) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("Recording: can't create {:?}: {}", path, e);
+                    None
+                }
+            }
+        });
+        let Some(writer) = file else {
+            return;
+        };
+        // Flushed every frame, so a crash loses nothing
+        if let Err(e) = write_frame(writer, timestamp, frame).and_then(|_| writer.flush()) {
+            eprintln!("Recording: stopped recording {}: {}", session_id, e);
+            *file = None;
+        }
+    }
+}
+
+// Session ids come from clients, so only keep characters that are safe in a file name
+fn file_stem(session_id: &str) -> String {
+    session_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn write_frame<W: Write>(out: &mut W, timestamp: u64, frame: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(frame.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "frame too large"))?;
+    out.write_all(&timestamp.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(frame)
+}
+
+/// The `(timestamp, frame)` pairs of a recording. A frame cut short at the end, as
+/// left by a server that was killed mid-write, is ignored.
+pub fn read(path: &Path) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+    let data = std::fs::read(path)?;
+    let Some(mut rest) = data.strip_prefix(MAGIC.as_slice()) else {
+        anyhow::bail!("{:?} is not a version 1 recording", path);
+    };
+    let mut frames = Vec::new();
+    while rest.len() >= 12 {
+        let timestamp = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        let Some(frame) = rest.get(12..12 + len) else {
+            break;
+        };
+        frames.push((timestamp, frame.to_vec()));
+        rest = &rest[12 + len..];
+    }
+    Ok(frames)
+}
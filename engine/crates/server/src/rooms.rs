@@ -0,0 +1,186 @@
+// Rooms: games running side by side, each with its own Lua state, game loop thread and
+// players. The server starts with the main room running its script; games open more
+// with `api.create_room`, players join one with `/ws?room=<id>`, and
+// `api.move_session` sends them from room to room. Rooms other than the main one
+// close once they have been empty for a while.
+//
+// The registry is generic over what a player is so it can be tested on its own; the
+// server keeps its game loop's clients in it.
+
+use engine::rooms::{RoomInfo, MAIN_ROOM};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Rooms open at once, the main one included, each being a thread
+pub const MAX_ROOMS: usize = 64;
+// How long a room other than the main one stays open without players
+pub const ROOM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Hex digits in a room id, short enough to type into a URL
+const ROOM_ID_LEN: usize = 6;
+
+pub struct Rooms<C> {
+    dir: PathBuf,
+    rooms: Mutex<HashMap<String, Room<C>>>,
+}
+
+struct Room<C> {
+    script: String,
+    params: Value,
+    players: usize,
+    // Players on their way in, new connections and from other rooms, until the
+    // room's loop takes them
+    joining: Vec<C>,
+    // Sessions `api.move_session` sends elsewhere, with the room each goes to
+    leaving: Vec<(String, String)>,
+}
+
+impl<C> Rooms<C> {
+    /// Rooms for the game in `dir`, starting with the main one running `main_script`.
+    pub fn new(dir: &Path, main_script: &str) -> Self {
+        let mut rooms = HashMap::new();
+        rooms.insert(MAIN_ROOM.to_string(), Room::new(main_script, Value::Null));
+        Self {
+            dir: dir.to_path_buf(),
+            rooms: Mutex::new(rooms),
+        }
+    }
+
+    /// The file `script` names, which has to be inside the game's directory.
+    pub fn script_path(&self, script: &str) -> anyhow::Result<PathBuf> {
+        let path = Path::new(script);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            anyhow::bail!(
+                "room script '{}' is not a path inside the game's directory",
+                script
+            );
+        }
+        let path = self.dir.join(path);
+        if !path.is_file() {
+            anyhow::bail!("room script '{}' not found", script);
+        }
+        Ok(path)
+    }
+
+    /// Adds a room running `script` and returns its new id.
+    pub fn open(&self, script: &str, params: Value) -> anyhow::Result<String> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.len() >= MAX_ROOMS {
+            anyhow::bail!("the server already has {} rooms open", MAX_ROOMS);
+        }
+        let id = loop {
+            let id = Uuid::new_v4().simple().to_string()[..ROOM_ID_LEN].to_string();
+            if !rooms.contains_key(&id) {
+                break id;
+            }
+        };
+        rooms.insert(id.clone(), Room::new(script, params));
+        Ok(id)
+    }
+
+    /// Removes a room along with anyone still joining it.
+    pub fn close(&self, id: &str) -> Vec<C> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .remove(id)
+            .map(|room| room.joining)
+            .unwrap_or_default()
+    }
+
+    /// Removes a room if nobody is joining it, returning whether it did.
+    pub fn close_if_empty(&self, id: &str) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get(id) {
+            Some(room) if room.joining.is_empty() => {
+                rooms.remove(id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.rooms.lock().unwrap().contains_key(id)
+    }
+
+    /// Queues `player` to join room `id`, or hands it back if there is no such room.
+    pub fn join(&self, id: &str, player: C) -> Result<(), C> {
+        match self.rooms.lock().unwrap().get_mut(id) {
+            Some(room) => {
+                room.joining.push(player);
+                Ok(())
+            }
+            None => Err(player),
+        }
+    }
+
+    /// Players waiting to join room `id`, in the order they came.
+    pub fn take_joining(&self, id: &str) -> Vec<C> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .map(|room| std::mem::take(&mut room.joining))
+            .unwrap_or_default()
+    }
+
+    /// Records that `session_id`, in room `from`, is to go to room `to`.
+    pub fn move_session(&self, from: &str, session_id: &str, to: &str) -> anyhow::Result<()> {
+        let mut rooms = self.rooms.lock().unwrap();
+        if !rooms.contains_key(to) {
+            anyhow::bail!("no room '{}'", to);
+        }
+        if let Some(room) = rooms.get_mut(from) {
+            room.leaving.push((session_id.to_string(), to.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Sessions to send from room `id` to other rooms, with the room each goes to.
+    pub fn take_leaving(&self, id: &str) -> Vec<(String, String)> {
+        self.rooms
+            .lock()
+            .unwrap()
+            .get_mut(id)
+            .map(|room| std::mem::take(&mut room.leaving))
+            .unwrap_or_default()
+    }
+
+    pub fn set_players(&self, id: &str, players: usize) {
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(id) {
+            room.players = players;
+        }
+    }
+
+    /// The open rooms, the main one first and the rest by id.
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut list: Vec<RoomInfo> = rooms
+            .iter()
+            .map(|(id, room)| RoomInfo {
+                id: id.clone(),
+                script: room.script.clone(),
+                players: room.players,
+                params: room.params.clone(),
+            })
+            .collect();
+        list.sort_by(|a, b| (a.id != MAIN_ROOM, &a.id).cmp(&(b.id != MAIN_ROOM, &b.id)));
+        list
+    }
+}
+
+impl<C> Room<C> {
+    fn new(script: &str, params: Value) -> Self {
+        Self {
+            script: script.to_string(),
+            params,
+            players: 0,
+            joining: Vec::new(),
+            leaving: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use engine::protocol::DrawCommand;
+
+/// A frame holding `cmds`, as the engine sends it.
+pub fn encode(cmds: &[DrawCommand]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for cmd in cmds {
+        cmd.encode(&mut buf);
+    }
+    buf
+}
@@ -0,0 +1,60 @@
+use cleoselene::games;
+use std::path::Path;
+
+fn write(dir: &Path, path: &str, contents: &str) {
+    let path = dir.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_games_are_the_directories_with_a_main_script() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    write(dir, "fighting/main.lua", "");
+    write(
+        dir,
+        "fighting/metadata.json",
+        r#"{"title": "Dragon <Fighters>"}"#,
+    );
+    write(dir, "astro-maze/main.lua", "");
+    write(dir, "astro-maze/metadata.json", "not json");
+    write(dir, "assets/logo.svg", "");
+    write(dir, ".git/main.lua", "");
+    write(dir, "two words/main.lua", "");
+    write(dir, "README.md", "");
+
+    let games = games::find(dir).unwrap();
+    let names: Vec<&str> = games.iter().map(|game| game.name.as_str()).collect();
+    assert_eq!(names, ["astro-maze", "fighting"]);
+    assert_eq!(
+        games[0].script_path,
+        dir.join("astro-maze").join("main.lua")
+    );
+    assert_eq!(games[0].title, "astro-maze");
+    assert_eq!(games[1].title, "Dragon <Fighters>");
+
+    assert!(games::find(&dir.join("missing")).is_err());
+}
+
+#[test]
+fn test_index_links_each_game_under_the_base_path() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    write(dir, "fighting/main.lua", "");
+    write(
+        dir,
+        "fighting/metadata.json",
+        r#"{"title": "Dragon <Fighters>"}"#,
+    );
+    let games = games::find(dir).unwrap();
+
+    let html = games::index_html(&games, "/");
+    assert!(
+        html.contains("<a href='/fighting/'>Dragon &lt;Fighters&gt;</a>"),
+        "{}",
+        html
+    );
+    let html = games::index_html(&games, "/arcade/");
+    assert!(html.contains("<a href='/arcade/fighting/'>"), "{}", html);
+}
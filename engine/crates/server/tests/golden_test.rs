@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn games_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../games")
+}
+
+fn test_golden(scenario: &Path, update: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_cleoselene"));
+    command.arg("test-golden").arg(scenario);
+    if update {
+        command.arg("--update");
+    }
+    command.output().expect("Failed to run cleoselene")
+}
+
+#[test]
+fn test_fighting_example_matches_its_goldens() {
+    let scenario = games_dir().join("fighting-example/golden/scenario.json");
+    let output = test_golden(&scenario, false);
+    assert!(
+        output.status.success(),
+        "Renders differ from the goldens (see games/fighting-example/golden/failures):\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_mismatch_fails_and_writes_a_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = games_dir().join("fighting-example/main.lua");
+    let scenario = dir.path().join("scenario.json");
+    let steps = r#"[
+        { "connect": "p1" },
+        { "connect": "p2" },
+        { "tick": 2 },
+        { "snapshot": { "session": "p1", "name": "start" } }
+    ]"#;
+    std::fs::write(
+        &scenario,
+        format!(
+            r#"{{ "script": {:?}, "steps": {} }}"#,
+            script.canonicalize().unwrap(),
+            steps
+        ),
+    )
+    .unwrap();
+
+    assert!(test_golden(&scenario, true).status.success());
+    let golden_path = dir.path().join("start.png");
+    assert!(test_golden(&scenario, false).status.success());
+
+    // Paint over part of the golden, as if the renderer had drawn it differently
+    let mut golden = image::open(&golden_path).unwrap().to_rgba8();
+    for y in 10..20 {
+        for x in 10..30 {
+            golden.put_pixel(x, y, image::Rgba([1, 2, 3, 255]));
+        }
+    }
+    golden.save(&golden_path).unwrap();
+
+    let output = test_golden(&scenario, false);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("200 pixels differ"));
+    let failures = dir.path().join("failures");
+    let actual = image::open(failures.join("start.png")).unwrap().to_rgba8();
+    assert_eq!(actual.dimensions(), golden.dimensions());
+    let diff = image::open(failures.join("start.diff.png"))
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(diff.get_pixel(15, 15).0, [255, 0, 0, 255]);
+    assert_ne!(diff.get_pixel(50, 50).0, [255, 0, 0, 255]);
+
+    // Matching again clears out the failure
+    assert!(test_golden(&scenario, true).status.success());
+    assert!(test_golden(&scenario, false).status.success());
+    assert!(!failures.join("start.diff.png").exists());
+}
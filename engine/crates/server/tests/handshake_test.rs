@@ -0,0 +1,32 @@
+use cleoselene::handshake::negotiate;
+use engine::protocol::{Capabilities, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+
+#[test]
+fn test_clients_without_a_version_are_rejected() {
+    let reason = negotiate(None, Some("delta_frames")).unwrap_err();
+    assert!(reason.contains("version 1 is not supported"), "{}", reason);
+    assert!(reason.contains(&format!("server speaks {}", PROTOCOL_VERSION)));
+}
+
+#[test]
+fn test_clients_older_than_the_minimum_are_rejected() {
+    assert!(negotiate(Some(MIN_PROTOCOL_VERSION - 1), None).is_err());
+    assert!(negotiate(Some(0), Some("delta_frames,display_lists")).is_err());
+}
+
+#[test]
+fn test_supported_versions_negotiate_capabilities() {
+    let caps = negotiate(
+        Some(PROTOCOL_VERSION),
+        Some("display_lists, teleport,delta_frames"),
+    )
+    .expect("Current clients are accepted");
+    assert_eq!(caps.names(), ["delta_frames", "display_lists"]);
+
+    // Nothing listed means the plain protocol
+    let caps = negotiate(Some(MIN_PROTOCOL_VERSION), None).unwrap();
+    assert_eq!(caps, Capabilities::default());
+
+    // Newer clients are accepted and spoken to at our version
+    assert!(negotiate(Some(PROTOCOL_VERSION + 1), Some("")).is_ok());
+}
@@ -0,0 +1,52 @@
+use cleoselene::messages::{MessageLimits, MESSAGE_BURST, MESSAGE_RATE};
+use engine::protocol::MAX_MESSAGE_LEN;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_messages_beyond_the_burst_wait_for_the_rate() {
+    let start = Instant::now();
+    let mut limits = MessageLimits::new(start);
+    for _ in 0..MESSAGE_BURST as usize {
+        assert!(limits.admit(10, start));
+    }
+    assert!(!limits.admit(10, start));
+
+    // The allowance refills at MESSAGE_RATE per second
+    let next = start + Duration::from_secs_f64(1.0 / MESSAGE_RATE);
+    assert!(limits.admit(10, next));
+    assert!(!limits.admit(10, next));
+
+    // ...up to the burst, however long the session was quiet
+    let later = next + Duration::from_secs(3600);
+    let admitted = (0..100).filter(|_| limits.admit(10, later)).count();
+    assert_eq!(admitted, MESSAGE_BURST as usize);
+}
+
+#[test]
+fn test_long_messages_are_dropped_without_using_the_allowance() {
+    let start = Instant::now();
+    let mut limits = MessageLimits::new(start);
+    assert!(limits.admit(MAX_MESSAGE_LEN, start));
+    for _ in 0..100 {
+        assert!(!limits.admit(MAX_MESSAGE_LEN + 1, start));
+    }
+    assert!(limits.admit(1, start));
+}
+
+#[test]
+fn test_junk_on_the_data_channel_uses_the_allowance() {
+    let start = Instant::now();
+    let mut limits = MessageLimits::new(start);
+    let message = json!({ "type": "MESSAGE", "payload": { "chat": "hi" } }).to_string();
+    assert_eq!(
+        limits.parse::<Value>(message.as_bytes(), start),
+        Some(json!({ "type": "MESSAGE", "payload": { "chat": "hi" } }))
+    );
+
+    // Unparseable strings count against the limits like any other message
+    for _ in 0..100 {
+        assert_eq!(limits.parse::<Value>(b"{not json", start), None);
+    }
+    assert_eq!(limits.parse::<Value>(message.as_bytes(), start), None);
+}
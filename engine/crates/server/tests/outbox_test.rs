@@ -0,0 +1,62 @@
+use bytes::Bytes;
+use cleoselene::outbox::{OutFrame, Outbox};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+fn frame(bytes: &'static [u8]) -> OutFrame {
+    OutFrame {
+        bytes: Bytes::from_static(bytes),
+        reliable: false,
+        input_ack: None,
+    }
+}
+
+#[test]
+fn test_setup_waits_for_room_in_a_full_queue() {
+    let (tx, mut rx) = mpsc::channel(1);
+    tx.try_send(frame(b"drawing")).unwrap();
+
+    let mut outbox = Outbox::default();
+    outbox.push_setup(Bytes::from_static(b"setup"));
+    assert_eq!(outbox.send_setup(&tx, Some(7)), None);
+    assert!(outbox.has_setup());
+    assert_eq!(outbox.send_setup(&tx, Some(7)), None);
+
+    // Once the coordinator takes a frame, the next try gets through
+    assert_eq!(&rx.try_recv().unwrap().bytes[..], b"drawing");
+    assert_eq!(
+        outbox.send_setup(&tx, Some(7)).as_deref(),
+        Some(&b"setup"[..])
+    );
+    assert!(!outbox.has_setup());
+    let setup = rx.try_recv().unwrap();
+    assert_eq!(&setup.bytes[..], b"setup");
+    assert!(setup.reliable);
+    assert_eq!(setup.input_ack, Some(7));
+
+    // Sent once only
+    assert_eq!(outbox.send_setup(&tx, None), None);
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn test_sounds_of_a_dropped_frame_go_out_with_the_next() {
+    let (tx, mut rx) = mpsc::channel(1);
+    tx.try_send(frame(b"drawing")).unwrap();
+
+    let mut outbox = Outbox::default();
+    outbox.push_events(b"boom");
+    let events = outbox.take_events();
+    assert!(matches!(
+        outbox.send_frame(&tx, frame(b"boom, drawing"), events),
+        Err(TrySendError::Full(_))
+    ));
+
+    // Kept ahead of the sounds started since
+    outbox.push_events(b"bang");
+    rx.try_recv().unwrap();
+    let events = outbox.take_events();
+    assert_eq!(events, b"boombang");
+    let sent = outbox.send_frame(&tx, frame(b"boombang, drawing"), events);
+    assert_eq!(&sent.unwrap()[..], b"boombang, drawing");
+    assert!(outbox.take_events().is_empty());
+}
@@ -0,0 +1,47 @@
+use cleoselene::pacing::{FixedStep, FramePacer, MAX_CATCH_UP};
+use std::time::{Duration, Instant};
+
+const STEP: Duration = Duration::from_millis(20);
+
+#[test]
+fn test_updates_run_in_whole_steps_with_the_rest_as_alpha() {
+    let mut clock = FixedStep::new(50);
+    assert_eq!(clock.dt(), 0.02);
+
+    assert_eq!(clock.advance(Duration::from_millis(5)), 0);
+    assert_eq!(clock.until_next(), Duration::from_millis(15));
+    assert_eq!(clock.advance(Duration::from_millis(45)), 2);
+    assert!((clock.alpha() - 0.5).abs() < 1e-6);
+    assert_eq!(clock.until_next(), Duration::from_millis(10));
+    // However the time is sliced, the number of updates is the same
+    let steps: u32 = (0..30)
+        .map(|_| clock.advance(Duration::from_millis(7)))
+        .sum();
+    assert_eq!(steps, 11);
+}
+
+#[test]
+fn test_a_stall_is_not_caught_up_in_full() {
+    let mut clock = FixedStep::new(50);
+    assert_eq!(clock.advance(STEP * 100), MAX_CATCH_UP);
+    assert_eq!(clock.alpha(), 0.0);
+    assert_eq!(clock.advance(STEP), 1);
+}
+
+#[test]
+fn test_frames_are_paced_per_client() {
+    let start = Instant::now();
+    let mut pacer = FramePacer::new(50, start);
+    assert!(pacer.due(start));
+    assert!(!pacer.due(start + STEP / 2));
+    assert_eq!(pacer.next(), start + STEP);
+
+    // Late frames keep the schedule...
+    assert!(pacer.due(start + STEP + Duration::from_millis(5)));
+    assert_eq!(pacer.next(), start + STEP * 2);
+    // ...unless whole frames were missed, which are skipped
+    let late = start + STEP * 10;
+    assert!(pacer.due(late));
+    assert!(!pacer.due(late + STEP / 2));
+    assert_eq!(pacer.next(), late + STEP);
+}
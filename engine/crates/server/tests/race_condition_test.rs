@@ -11,11 +11,15 @@ async fn test_handshake_arrives_first() {
     // vamos assumir que o usuário rodou ./run.sh OU vamos falhar se não conectar.
     // MELHOR: Vamos replicar a lógica do server::main em menor escala ou conectar no :3000 se já estiver rodando.
     
-    let uri = "ws://localhost:3000/ws";
+    // Clientes sem versão de protocolo são rejeitados
+    let uri = format!(
+        "ws://localhost:3000/ws?protocol={}",
+        engine::protocol::PROTOCOL_VERSION
+    );
     println!("Connecting to {}", uri);
 
     // Tenta conectar (assume que o servidor está rodando via ./run.sh)
-    let (mut socket, response) = match tokio_tungstenite::connect_async(uri).await {
+    let (mut socket, response) = match tokio_tungstenite::connect_async(uri.as_str()).await {
         Ok(v) => v,
         Err(e) => {
             panic!("Could not connect to server at {}. Is it running? Error: {}", uri, e);
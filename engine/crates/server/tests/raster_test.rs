@@ -0,0 +1,236 @@
+mod common;
+
+use cleoselene::raster::Rasterizer;
+use common::encode;
+use engine::protocol::{DrawCommand, LineCap, LineJoin, ScaleMode, TextAlign, TextBaseline};
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+fn canvas(raster: &Rasterizer) -> RgbaImage {
+    let png = image::load_from_memory(&raster.to_png().unwrap())
+        .unwrap()
+        .to_rgba8();
+    assert!(png == *raster.image(), "The PNG should hold the canvas as is");
+    png
+}
+
+fn render(cmds: &[DrawCommand]) -> RgbaImage {
+    let mut raster = Rasterizer::new(Path::new("."));
+    raster.render(&encode(cmds)).expect("Frame should render");
+    canvas(&raster)
+}
+
+fn resolution(width: u16, height: u16) -> DrawCommand {
+    DrawCommand::SetResolution {
+        width,
+        height,
+        mode: ScaleMode::Letterbox,
+    }
+}
+
+fn color(r: u8, g: u8, b: u8, a: u8) -> DrawCommand {
+    DrawCommand::SetColor { r, g, b, a }
+}
+
+fn assert_near(actual: Rgba<u8>, expected: [u8; 4]) {
+    let close = actual
+        .0
+        .iter()
+        .zip(expected)
+        .all(|(a, e)| a.abs_diff(e) <= 2);
+    assert!(close, "{:?} should be close to {:?}", actual.0, expected);
+}
+
+#[test]
+fn test_fills_blend_alpha_and_antialias_edges() {
+    let img = render(&[
+        resolution(20, 10),
+        DrawCommand::Clear { r: 0, g: 0, b: 255 },
+        color(255, 0, 0, 128),
+        DrawCommand::FillRect {
+            x: 2.5,
+            y: 0.0,
+            w: 5.0,
+            h: 10.0,
+        },
+    ]);
+    assert_eq!(img.dimensions(), (20, 10));
+    assert_near(*img.get_pixel(4, 5), [128, 0, 127, 255]);
+    // Half covered, at half opacity
+    assert_near(*img.get_pixel(2, 5), [64, 0, 191, 255]);
+    assert_near(*img.get_pixel(7, 5), [64, 0, 191, 255]);
+    assert_near(*img.get_pixel(9, 5), [0, 0, 255, 255]);
+}
+
+#[test]
+fn test_strokes_use_width_and_caps() {
+    let img = render(&[
+        resolution(40, 40),
+        color(255, 255, 255, 255),
+        DrawCommand::DrawLine {
+            x1: 5.0,
+            y1: 10.0,
+            x2: 35.0,
+            y2: 10.0,
+            width: 6.0,
+        },
+        DrawCommand::StrokePoly {
+            width: 4.0,
+            join: LineJoin::Miter,
+            cap: LineCap::Square,
+            closed: false,
+            points: vec![(10.0, 25.0), (30.0, 25.0)],
+        },
+    ]);
+    let white = [255, 255, 255, 255];
+    let black = [0, 0, 0, 255];
+    assert_near(*img.get_pixel(20, 7), white);
+    assert_near(*img.get_pixel(20, 12), white);
+    assert_near(*img.get_pixel(20, 14), black);
+    // Butt caps end at the points, square caps half a width past them
+    assert_near(*img.get_pixel(3, 10), black);
+    assert_near(*img.get_pixel(8, 24), white);
+    assert_near(*img.get_pixel(6, 24), black);
+}
+
+#[test]
+fn test_text_is_drawn_from_glyphs_at_its_anchor() {
+    let img = render(&[
+        resolution(100, 40),
+        color(255, 255, 255, 255),
+        DrawCommand::SetFont {
+            name: "monospace".to_string(),
+            size: 20.0,
+        },
+        DrawCommand::SetTextAlign {
+            align: TextAlign::Center,
+            baseline: TextBaseline::Middle,
+        },
+        DrawCommand::DrawText {
+            x: 50.0,
+            y: 20.0,
+            text: "HI".to_string(),
+        },
+    ]);
+    let lit: Vec<(u32, u32)> = img
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] > 128)
+        .map(|(x, y, _)| (x, y))
+        .collect();
+    let (min_x, max_x) = (
+        lit.iter().map(|p| p.0).min().unwrap(),
+        lit.iter().map(|p| p.0).max().unwrap(),
+    );
+    let (min_y, max_y) = (
+        lit.iter().map(|p| p.1).min().unwrap(),
+        lit.iter().map(|p| p.1).max().unwrap(),
+    );
+    assert!((min_x + max_x).abs_diff(100) <= 4, "{}..{}", min_x, max_x);
+    assert!((min_y + max_y).abs_diff(40) <= 6, "{}..{}", min_y, max_y);
+    // Glyph shapes, not a box: the middle of "H" above its crossbar is empty
+    assert!(lit.len() < ((max_x - min_x + 1) * (max_y - min_y + 1)) as usize / 2);
+}
+
+#[test]
+fn test_images_use_source_rect_flip_and_origin() {
+    let dir = tempfile::tempdir().unwrap();
+    // Red on the left half, green on the right
+    let image = RgbaImage::from_fn(4, 2, |x, _| {
+        if x < 2 {
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([0, 255, 0, 255])
+        }
+    });
+    image.save(dir.path().join("duo.png")).unwrap();
+
+    let image =
+        |name: &str, x: f32, w: f32, (sx, sw): (f32, f32), ox: f32| DrawCommand::DrawImage {
+            name: name.to_string(),
+            x,
+            y: 0.0,
+            w,
+            h: 4.0,
+            sx,
+            sy: 0.0,
+            sw,
+            sh: -1.0,
+            rotation: 0.0,
+            ox,
+            oy: 0.0,
+        };
+    let mut raster = Rasterizer::new(dir.path());
+    raster
+        .render(&encode(&[
+            resolution(40, 4),
+            DrawCommand::LoadImage {
+                name: "duo".to_string(),
+                url: "/assets/duo.png".to_string(),
+            },
+            DrawCommand::LoadImage {
+                name: "outside".to_string(),
+                url: "/assets/../duo.png".to_string(),
+            },
+            // The green half only
+            image("duo", 0.0, 4.0, (2.0, 2.0), 0.0),
+            // Flipped, so red ends up on the right
+            image("duo", 20.0, -8.0, (0.0, -1.0), 0.0),
+            // Anchored at its center
+            image("duo", 34.0, 8.0, (0.0, -1.0), 4.0),
+        ]))
+        .unwrap();
+    // Unloaded images draw nothing
+    raster
+        .render(&encode(&[image("outside", 0.0, 40.0, (0.0, -1.0), 0.0)]))
+        .unwrap();
+
+    let img = canvas(&raster);
+    let (red, green, black) = ([255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 0, 255]);
+    assert_near(*img.get_pixel(0, 2), green);
+    assert_near(*img.get_pixel(3, 2), green);
+    assert_near(*img.get_pixel(5, 2), black);
+    assert_near(*img.get_pixel(13, 2), green);
+    assert_near(*img.get_pixel(19, 2), red);
+    assert_near(*img.get_pixel(29, 2), black);
+    assert_near(*img.get_pixel(30, 2), red);
+    assert_near(*img.get_pixel(37, 2), green);
+}
+
+#[test]
+fn test_lists_and_colors_carry_over_between_frames() {
+    let mut raster = Rasterizer::new(Path::new("."));
+    let mut list = Vec::new();
+    DrawCommand::FillPoly {
+        points: vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)],
+    }
+    .encode(&mut list);
+    raster
+        .render(&encode(&[
+            resolution(20, 20),
+            DrawCommand::DefineList {
+                name: "square".to_string(),
+                commands: list,
+            },
+            color(255, 255, 0, 255),
+            // Left unbalanced; the next frame starts from the identity transform
+            DrawCommand::Push,
+            DrawCommand::Translate { x: 100.0, y: 100.0 },
+        ]))
+        .unwrap();
+    raster
+        .render(&encode(&[DrawCommand::CallList {
+            name: "square".to_string(),
+            x: 10.0,
+            y: 10.0,
+            rotation: 0.0,
+            sx: 2.0,
+            sy: 2.0,
+        }]))
+        .unwrap();
+
+    let img = canvas(&raster);
+    assert_near(*img.get_pixel(11, 11), [255, 255, 0, 255]);
+    assert_near(*img.get_pixel(17, 17), [255, 255, 0, 255]);
+    assert_near(*img.get_pixel(9, 9), [0, 0, 0, 255]);
+    assert_near(*img.get_pixel(18, 18), [0, 0, 0, 255]);
+}
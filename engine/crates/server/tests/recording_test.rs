@@ -0,0 +1,136 @@
+mod common;
+
+use cleoselene::recording::{self, Recorder};
+use common::encode;
+use engine::protocol::{DrawCommand, ScaleMode};
+use image::AnimationDecoder;
+use std::process::Command;
+
+// A square at `x`, on a 16x16 screen
+fn frame(x: f32) -> Vec<u8> {
+    encode(&[
+        DrawCommand::SetResolution {
+            width: 16,
+            height: 16,
+            mode: ScaleMode::Letterbox,
+        },
+        DrawCommand::Clear { r: 0, g: 0, b: 0 },
+        DrawCommand::SetColor {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        },
+        DrawCommand::FillRect {
+            x,
+            y: 4.0,
+            w: 4.0,
+            h: 4.0,
+        },
+    ])
+}
+
+// Two seconds of frames: one at 0s, 0.5s, 1s, 1.5s and 2s
+fn write_recording(path: &std::path::Path) {
+    let mut data = recording::MAGIC.to_vec();
+    for i in 0..5u64 {
+        recording::write_frame(&mut data, i * 500_000, &frame(i as f32 * 2.0)).unwrap();
+    }
+    std::fs::write(path, data).unwrap();
+}
+
+fn render_recording(recording: &std::path::Path, output: &std::path::Path, fps: &str) {
+    let status = Command::new(env!("CARGO_BIN_EXE_cleoselene"))
+        .arg("render-recording")
+        .arg(recording)
+        .arg("--output")
+        .arg(output)
+        .args(["--fps", fps])
+        .status()
+        .expect("Failed to run cleoselene");
+    assert!(status.success());
+}
+
+#[test]
+fn test_recorder_writes_one_readable_file_per_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut recorder = Recorder::new(dir.path()).unwrap();
+    recorder.record("alice", b"setup");
+    recorder.record("../bob", b"one");
+    recorder.record("alice", b"two");
+
+    let alice = recording::read(&dir.path().join("alice.rec")).unwrap();
+    assert_eq!(
+        alice.iter().map(|(_, f)| f.as_slice()).collect::<Vec<_>>(),
+        [b"setup".as_slice(), b"two"]
+    );
+    assert!(alice[0].0 <= alice[1].0, "Timestamps should not go back");
+
+    // Ids are kept inside the directory
+    let bob = recording::read(&dir.path().join("___bob.rec")).unwrap();
+    assert_eq!(bob.len(), 1);
+    assert_eq!(bob[0].1, b"one");
+
+    // A frame cut short by a crash is dropped, the ones before it kept
+    let path = dir.path().join("alice.rec");
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..data.len() - 2]).unwrap();
+    assert_eq!(recording::read(&path).unwrap().len(), 1);
+
+    std::fs::write(&path, b"not a recording").unwrap();
+    assert!(recording::read(&path).is_err());
+}
+
+#[test]
+fn test_render_recording_writes_gif_frames_at_the_requested_rate() {
+    let dir = tempfile::tempdir().unwrap();
+    let rec = dir.path().join("session.rec");
+    write_recording(&rec);
+
+    let gif = dir.path().join("out.gif");
+    render_recording(&rec, &gif, "4");
+
+    let decoder = image::codecs::gif::GifDecoder::new(std::io::BufReader::new(
+        std::fs::File::open(&gif).unwrap(),
+    ))
+    .unwrap();
+    let frames = decoder.into_frames().collect_frames().unwrap();
+    // Every 0.25s over 2s, plus the final state
+    assert_eq!(frames.len(), 9);
+    let first = frames[0].buffer();
+    let last = frames[8].buffer();
+    assert_eq!((first.width(), first.height()), (16, 16));
+    assert_eq!(first.get_pixel(1, 5).0, [255, 255, 255, 255]);
+    assert_eq!(last.get_pixel(1, 5).0, [0, 0, 0, 255]);
+    assert_eq!(last.get_pixel(9, 5).0, [255, 255, 255, 255]);
+}
+
+#[test]
+fn test_render_recording_writes_numbered_pngs() {
+    let dir = tempfile::tempdir().unwrap();
+    let rec = dir.path().join("session.rec");
+    write_recording(&rec);
+
+    let out = dir.path().join("frames");
+    render_recording(&rec, &out, "2");
+
+    let mut names: Vec<_> = std::fs::read_dir(&out)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "frame_00000.png",
+            "frame_00001.png",
+            "frame_00002.png",
+            "frame_00003.png",
+            "frame_00004.png"
+        ]
+    );
+    // The second image shows the frame sent at 0.5s
+    let img = image::open(out.join("frame_00001.png")).unwrap().to_rgba8();
+    assert_eq!(img.get_pixel(3, 5).0, [255, 255, 255, 255]);
+    assert_eq!(img.get_pixel(1, 5).0, [0, 0, 0, 255]);
+}
@@ -0,0 +1,96 @@
+use cleoselene::rooms::{Rooms, MAX_ROOMS};
+use engine::rooms::MAIN_ROOM;
+use serde_json::json;
+use tempfile::TempDir;
+
+// A game directory with empty scripts
+fn game_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for script in ["main.lua", "arena.lua", "modes/duel.lua"] {
+        let path = dir.path().join(script);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_room_scripts_stay_in_the_game_directory() {
+    let dir = game_dir();
+    let rooms: Rooms<()> = Rooms::new(dir.path(), "main.lua");
+
+    assert_eq!(
+        rooms.script_path("arena.lua").unwrap(),
+        dir.path().join("arena.lua")
+    );
+    assert!(rooms.script_path("modes/duel.lua").is_ok());
+    for script in [
+        "missing.lua",
+        "../main.lua",
+        "modes/../arena.lua",
+        "/etc/passwd",
+        "",
+    ] {
+        assert!(rooms.script_path(script).is_err(), "{}", script);
+    }
+}
+
+#[test]
+fn test_rooms_open_list_and_close() {
+    let dir = game_dir();
+    let rooms: Rooms<&str> = Rooms::new(dir.path(), "main.lua");
+    assert!(rooms.contains(MAIN_ROOM));
+
+    let arena = rooms.open("arena.lua", json!({"players": 2})).unwrap();
+    assert_eq!(arena.len(), 6);
+    assert!(rooms.contains(&arena));
+    rooms.set_players(&arena, 2);
+
+    let list = rooms.list();
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].id, MAIN_ROOM);
+    assert_eq!(list[0].script, "main.lua");
+    assert_eq!(list[1].id, arena);
+    assert_eq!(list[1].players, 2);
+    assert_eq!(list[1].params, json!({"players": 2}));
+
+    // A room someone is on their way into stays open
+    rooms.join(&arena, "sess_1").unwrap();
+    assert!(!rooms.close_if_empty(&arena));
+    assert_eq!(rooms.take_joining(&arena), ["sess_1"]);
+    assert!(rooms.close_if_empty(&arena));
+    assert!(!rooms.contains(&arena));
+    assert_eq!(rooms.join(&arena, "sess_2"), Err("sess_2"));
+
+    // Closing a room that failed to start hands back whoever was joining it
+    let broken = rooms.open("arena.lua", json!(null)).unwrap();
+    rooms.join(&broken, "sess_3").unwrap();
+    assert_eq!(rooms.close(&broken), ["sess_3"]);
+    assert!(!rooms.contains(&broken));
+
+    // There is a limit, the main room included
+    for _ in 1..MAX_ROOMS {
+        rooms.open("arena.lua", json!(null)).unwrap();
+    }
+    assert!(rooms.open("arena.lua", json!(null)).is_err());
+}
+
+#[test]
+fn test_sessions_move_between_rooms() {
+    let dir = game_dir();
+    let rooms: Rooms<&str> = Rooms::new(dir.path(), "main.lua");
+    let arena = rooms.open("arena.lua", json!(null)).unwrap();
+
+    rooms.move_session(MAIN_ROOM, "sess_1", &arena).unwrap();
+    rooms.move_session(MAIN_ROOM, "sess_2", &arena).unwrap();
+    assert!(rooms.move_session(MAIN_ROOM, "sess_3", "nowhere").is_err());
+    assert!(rooms.take_leaving(&arena).is_empty());
+    assert_eq!(
+        rooms.take_leaving(MAIN_ROOM),
+        [
+            ("sess_1".to_string(), arena.clone()),
+            ("sess_2".to_string(), arena.clone()),
+        ]
+    );
+    assert!(rooms.take_leaving(MAIN_ROOM).is_empty());
+}
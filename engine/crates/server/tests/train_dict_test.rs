@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn test_train_dict_writes_a_usable_dictionary() {
+    let script =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../games/fighting-example/main.lua");
+    let output = std::env::temp_dir().join(format!("cleoselene-{}.dict", std::process::id()));
+
+    let status = Command::new(env!("CARGO_BIN_EXE_cleoselene"))
+        .arg("train-dict")
+        .arg(&script)
+        .args(["--frames", "200", "--sessions", "2", "--output"])
+        .arg(&output)
+        .status()
+        .expect("Failed to run cleoselene");
+    assert!(status.success());
+
+    let dict = std::fs::read(&output).expect("Dictionary should be written");
+    let _ = std::fs::remove_file(&output);
+    assert!(zstd::zstd_safe::get_dict_id_from_dict(&dict).is_some());
+
+    // Frames compressed with it only decode with the same dictionary
+    let frame = b"\x02\x03\xff\x64\x00\x03\x10\x00\x00\x48\x42\x00\x00\x48\x42";
+    let mut compressor = zstd::bulk::Compressor::with_dictionary(0, &dict).unwrap();
+    let compressed = compressor.compress(frame).unwrap();
+    let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&dict).unwrap();
+    assert_eq!(
+        decompressor.decompress(&compressed, frame.len()).unwrap(),
+        frame
+    );
+    assert!(zstd::bulk::decompress(&compressed, frame.len()).is_err());
+}